@@ -0,0 +1,366 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, ErrorKind, Read, Write};
+
+use crate::fetch::{self, quarantining};
+use crate::packed::PackedHandle;
+use crate::repository::{pack_tree, unpack_tree};
+use crate::storage::{Key, key_bytes, key_from_bytes, storage};
+use crate::{Data, Handle, chunk, local};
+
+// An archive holds a Handle and the closure of every object reachable from it, so it can
+// be moved between machines as a single file:
+//
+//   MAGIC
+//   the Handle, packed (32 bytes)
+//   each object: kind (BLOB or TREE), key (24 bytes), length (u64), contents
+//   END
+//
+// Multi-byte fields are little-endian, and Tree contents are packed (as in a Repository).
+// Every object is checked against its key on import (as fetched objects are: see fetch).
+//
+// Between two parties that keep track of what the other holds (e.g. a coordinator and its
+// workers: see remote), an archive can leave out the part of the closure the receiver
+// already has, or (when the receiver can fetch what it turns out to need) what's only
+// reachable through Refs (see send and receive).
+const MAGIC: &[u8] = b"fix archive 1\n";
+const BLOB: u8 = 0;
+const TREE: u8 = 1;
+const END: u8 = 0xff;
+
+// Write `h` and everything reachable from it (canonicalizing any local objects first).
+pub(crate) fn export(h: Handle, out: impl Write) -> io::Result<()> {
+    send(h, out, &mut HashSet::new(), false)
+}
+
+// As export, but leaving out the objects in `held` (and what they reach), and adding those
+// it writes to `held`. If `lazy`, objects named by Refs are left out too (though not the
+// chunks of a Blob).
+pub(crate) fn send(
+    h: Handle,
+    mut out: impl Write,
+    held: &mut HashSet<(bool, Key)>,
+    lazy: bool,
+) -> io::Result<()> {
+    let root = local::canonical(PackedHandle::pack(h))?;
+    let storage = storage();
+    out.write_all(MAGIC)?;
+    out.write_all(root.as_bytes())?;
+    // (Each with whether it's a chunk.)
+    let mut work = vec![(root, false)];
+    while let Some((h, is_chunk)) = work.pop() {
+        let Some(name) = h.key() else {
+            continue;
+        };
+        if lazy && !is_chunk && matches!(h.try_unpack(), Some(Handle::Data(Data::Ref(_)))) {
+            continue;
+        }
+        let tree = chunk::stored_as_tree(&h);
+        if !held.insert((tree, name)) {
+            continue;
+        }
+        let missing = || io::Error::new(ErrorKind::NotFound, "object missing from storage");
+        if tree {
+            let elements = storage.get_tree(name)?.ok_or_else(missing)?;
+            record(&mut out, TREE, name, &pack_tree(&elements))?;
+            work.extend(elements.iter().map(|&x| (x, !h.is_tree())));
+        } else {
+            record(
+                &mut out,
+                BLOB,
+                name,
+                &storage.get_blob(name)?.ok_or_else(missing)?,
+            )?;
+        }
+    }
+    out.write_all(&[END])
+}
+
+fn record(out: &mut impl Write, kind: u8, name: Key, contents: &[u8]) -> io::Result<()> {
+    out.write_all(&[kind])?;
+    out.write_all(&key_bytes(name))?;
+    out.write_all(&(contents.len() as u64).to_le_bytes())?;
+    out.write_all(contents)
+}
+
+// Store every object in an archive, returning its Handle.
+// The objects are staged (in memory) until the whole archive has been read and checked, so
+// nothing is stored unless the import succeeds. Fails (with InvalidData) if any object
+// doesn't match its key, a Handle (the root, or an element of a Tree) isn't canonical, or the
+// closure is incomplete. Objects in the archive that the root doesn't reach aren't stored.
+pub(crate) fn import(input: impl Read) -> io::Result<Handle> {
+    Ok(receive(input, Closure::Complete)?.0)
+}
+
+// What of the closure an archive must hold.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Closure {
+    Complete,
+    // It may leave out objects the Storage already has (see send), and what they reach.
+    Held,
+    // It may leave out anything, which the receiver fetches if it needs it (see remote).
+    Lazy,
+}
+
+// As import, also returning the objects the archive names: those stored from it, and those
+// it leaves out (which it has to hold, as `closure` allows).
+pub(crate) fn receive(
+    mut input: impl Read,
+    closure: Closure,
+) -> io::Result<(Handle, Vec<(bool, Key)>)> {
+    let mut magic = [0; MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(invalid("not a fix archive"));
+    }
+    let mut root = [0; 32];
+    input.read_exact(&mut root)?;
+    let root = PackedHandle::from_bytes(root);
+    let handle = canonical(root)?;
+
+    let mut staged = Staged::default();
+    loop {
+        let mut kind = [0];
+        input.read_exact(&mut kind)?;
+        if kind[0] == END {
+            break;
+        }
+        let mut header = [0; 32];
+        input.read_exact(&mut header)?;
+        let name = key_from_bytes(&header[..24]).unwrap();
+        let length = u64::from_le_bytes(header[24..].try_into().unwrap());
+        let mut contents = Vec::new();
+        (&mut input).take(length).read_to_end(&mut contents)?;
+        if contents.len() as u64 != length {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        match kind[0] {
+            BLOB => {
+                quarantining(&*storage(), fetch::check_blob(name, &contents))?;
+                staged.blobs.insert(name, contents);
+            }
+            TREE => {
+                let tree = unpack_tree(&contents)?;
+                quarantining(&*storage(), fetch::check_tree(name, &tree))?;
+                staged.trees.insert(name, tree);
+            }
+            _ => return Err(invalid("unknown archive record")),
+        }
+    }
+
+    // Everything reachable from the root must have been in the archive.
+    let mut work = vec![root];
+    let mut reached = HashSet::new();
+    let mut left_out = Vec::new();
+    while let Some(h) = work.pop() {
+        let Some(name) = h.key() else {
+            continue;
+        };
+        let tree = chunk::stored_as_tree(&h);
+        if !reached.insert((tree, name)) {
+            continue;
+        }
+        let archived = match tree {
+            true => staged
+                .trees
+                .get(&name)
+                .inspect(|x| work.extend(x.iter().copied()))
+                .is_some(),
+            false => staged.blobs.contains_key(&name),
+        };
+        let held = || match tree {
+            true => storage().contains_tree(name),
+            false => storage().contains_blob(name),
+        };
+        if !archived {
+            let allowed = match closure {
+                Closure::Complete => false,
+                Closure::Held => held()?,
+                Closure::Lazy => true,
+            };
+            if !allowed {
+                return Err(invalid("archive is missing part of the closure"));
+            }
+            reached.remove(&(tree, name));
+            left_out.push((tree, name));
+        }
+    }
+
+    let storage = storage();
+    for &(tree, name) in &reached {
+        match tree {
+            true => storage.put_tree(name, staged.trees.remove(&name).unwrap().into())?,
+            false => storage.put_blob(name, staged.blobs.remove(&name).unwrap().into())?,
+        }
+    }
+    left_out.extend(reached);
+    Ok((handle, left_out))
+}
+
+// The objects read from an archive, before they're stored.
+#[derive(Default)]
+struct Staged {
+    blobs: HashMap<Key, Vec<u8>>,
+    trees: HashMap<Key, Vec<PackedHandle>>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+// An archived Handle, which must be in its one canonical packed form and name no local
+// object (see local).
+fn canonical(h: PackedHandle) -> io::Result<Handle> {
+    let is_local = h.key().and_then(local::local_id).is_some();
+    match h.try_unpack() {
+        Some(x) if h.is_canonical() && !is_local => Ok(x),
+        _ => Err(invalid("malformed Handle in archive")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::hash::{hash_blob, hash_tree};
+    use crate::storage::key;
+    use crate::{BlobName, Data, Object, Ref, TreeName};
+
+    fn blob(contents: &[u8]) -> Handle {
+        Handle::Data(Data::Object(Object::Blob(
+            BlobName::create(contents.to_vec()).ok().unwrap(),
+        )))
+    }
+
+    #[test]
+    fn round_trips_the_closure() {
+        let tree = TreeName::create(vec![blob(&[1; 100]), blob(b"small")])
+            .ok()
+            .unwrap();
+        let root = Handle::Data(Data::Ref(Ref::Tree(tree)));
+        let mut archive = Vec::new();
+        export(root, &mut archive).unwrap();
+        let imported = import(&archive[..]).unwrap();
+        assert!(PackedHandle::pack(imported) == PackedHandle::pack(root));
+    }
+
+    // An archive of a Tree of one Blob, whose Tree record is corrupt (it's empty).
+    fn corrupt(contents: &[u8]) -> (Key, Vec<u8>) {
+        let pointer = hash_blob(contents);
+        let element = PackedHandle::pack(Handle::Data(Data::Ref(Ref::Blob(BlobName::Name((
+            pointer,
+            contents.len(),
+        ))))));
+        let name = hash_tree(&[element]);
+        let root = TreeName::<Handle> {
+            name,
+            size: 1,
+            footprint: 1,
+            eq: true,
+            tag: false,
+        };
+        let mut archive = MAGIC.to_vec();
+        archive.extend(PackedHandle::pack(Handle::Data(Data::Ref(Ref::Tree(root)))).as_bytes());
+        record(&mut archive, BLOB, key(pointer), contents).unwrap();
+        record(&mut archive, TREE, key(name), &[]).unwrap();
+        archive.push(END);
+        (key(pointer), archive)
+    }
+
+    #[test]
+    fn leaves_out_what_the_receiver_holds() {
+        let held = blob(b"held by the receiver, so left out of the archive");
+        let tree = TreeName::create(vec![held, blob(&[3; 100])]).ok().unwrap();
+        let root = Handle::Data(Data::Object(Object::Tree(tree)));
+        let mut sent = HashSet::new();
+        send(held, io::sink(), &mut sent, false).unwrap();
+        let mut archive = Vec::new();
+        send(root, &mut archive, &mut sent, false).unwrap();
+        assert_eq!(sent.len(), 3);
+        // Only the Tree and its other Blob are in it...
+        let (imported, named) = receive(&archive[..], Closure::Held).unwrap();
+        assert!(PackedHandle::pack(imported) == PackedHandle::pack(root));
+        assert_eq!(named.len(), 3);
+        // ...so it's incomplete, unless the part it leaves out is held.
+        assert!(import(&archive[..]).is_err());
+        let missing = PackedHandle::pack(held).key().unwrap();
+        storage().delete_blob(missing).unwrap();
+        assert!(receive(&archive[..], Closure::Held).is_err());
+        assert!(receive(&archive[..], Closure::Lazy).is_ok());
+    }
+
+    #[test]
+    fn a_lazy_archive_leaves_out_what_refs_name() {
+        let large = BlobName::create(vec![4; 3 * crate::chunk::MAX_CHUNK])
+            .ok()
+            .unwrap();
+        let named = Handle::Data(Data::Ref(Ref::Blob(
+            BlobName::create(b"named by a Ref, so left out of a lazy archive".to_vec())
+                .ok()
+                .unwrap(),
+        )));
+        let object = Handle::Data(Data::Object(Object::Blob(large)));
+        let tree = TreeName::create(vec![named, object]).ok().unwrap();
+        let root = Handle::Data(Data::Object(Object::Tree(tree)));
+        let (mut lazy, mut complete) = (HashSet::new(), HashSet::new());
+        send(root, io::sink(), &mut lazy, true).unwrap();
+        send(root, io::sink(), &mut complete, false).unwrap();
+        // The Tree and the large Blob (with its chunks), but not the Blob the Ref names.
+        let key = |h| PackedHandle::pack(h).key().unwrap();
+        assert!(complete.contains(&(false, key(named))));
+        assert!(!lazy.contains(&(false, key(named))));
+        assert_eq!(lazy.len(), complete.len() - 1);
+    }
+
+    #[test]
+    fn stores_nothing_from_a_corrupt_archive() {
+        let contents = [2; 100];
+        let (blob, archive) = corrupt(&contents);
+        let error = import(&archive[..]).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(!storage().contains_blob(blob).unwrap());
+    }
+
+    #[test]
+    fn rejects_a_non_canonical_root() {
+        // A Literal with a nonzero unused byte.
+        let mut root = *PackedHandle::pack(blob(b"x")).as_bytes();
+        root[20] = 1;
+        let archive = [MAGIC, &root, &[END]].concat();
+        let error = import(&archive[..]).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    fn archive() -> Vec<u8> {
+        let tree = TreeName::create(vec![blob(&[7; 100]), blob(b"fuzzed")]);
+        let root = Handle::Data(Data::Object(Object::Tree(tree.ok().unwrap())));
+        let mut archive = Vec::new();
+        export(root, &mut archive).unwrap();
+        archive
+    }
+
+    proptest! {
+        // Whatever an archive holds, importing it fails or succeeds, without panicking.
+        #[test]
+        fn arbitrary_archives_are_imported_or_rejected(bytes in prop::collection::vec(any::<u8>(), 0..200)) {
+            let _ = import(&[MAGIC, &bytes].concat()[..]);
+        }
+
+        #[test]
+        fn damaged_archives_are_imported_or_rejected(
+            index in any::<prop::sample::Index>(),
+            byte in any::<u8>(),
+            truncate in any::<bool>(),
+        ) {
+            let mut archive = archive();
+            let i = index.index(archive.len());
+            if truncate {
+                archive.truncate(i);
+                prop_assert!(import(&archive[..]).is_err());
+            } else {
+                archive[i] = byte;
+                let _ = import(&archive[..]);
+            }
+        }
+    }
+}
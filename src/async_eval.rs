@@ -0,0 +1,182 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use tokio::task::{JoinSet, spawn_blocking};
+
+use crate::{
+    Context, Data, Encode, Execution, Handle, Object, Result, RuntimeValue, Thunk, TreeName, Value,
+    apply, memo, select, types,
+};
+
+// Async versions of eval, execute and think, for a tokio runtime.
+//
+// The elements of a Tree are evaluated as separate tasks, so sibling Encodes run
+// concurrently, and a pending Encode is a task rather than a thread. Anything that
+// blocks (loading and storing objects, apply, select) runs on tokio's blocking pool, so
+// slow fetches and procedures overlap. Results, and traps, are the same as the
+// synchronous versions': when several elements trap, the first one's trap is returned.
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+// Run blocking work off the async threads (resuming its panic, if it panics).
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    match spawn_blocking(f).await {
+        Ok(x) => x,
+        Err(e) => match e.try_into_panic() {
+            Ok(panic) => std::panic::resume_unwind(panic),
+            Err(e) => panic!("blocking task failed: {e}"),
+        },
+    }
+}
+
+// Evaluate every element of a Tree concurrently.
+async fn map(tree: TreeName, context: &Context) -> Result<TreeName<Value>> {
+    let elements = blocking(move || tree.try_load()).await?;
+    let mut values: Vec<Option<Result<Value>>> = vec![None; elements.len()];
+    let mut tasks = JoinSet::new();
+    for (i, h) in elements.into_iter().enumerate() {
+        let context = context.clone();
+        tasks.spawn(async move { (i, eval(h, context).await) });
+    }
+    // The first trap (in element order) is known once every element before it is done.
+    // Returning drops the JoinSet, which cancels whatever is still running.
+    let mut done = 0;
+    while let Some(finished) = tasks.join_next().await {
+        let (i, value) = finished.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+        values[i] = Some(value);
+        while let Some(Some(value)) = values.get(done) {
+            if let Err(trap) = value {
+                return Err(*trap);
+            }
+            done += 1;
+        }
+    }
+    let values = values
+        .into_iter()
+        .map(Option::unwrap)
+        .collect::<Result<_>>()?;
+    let tree = blocking(move || tree.mapped(values)).await?;
+    types::record(tree);
+    Ok(tree)
+}
+
+pub(crate) async fn think(thunk: Thunk, context: &Context) -> Result<RuntimeValue> {
+    match thunk {
+        Thunk::Application(combination) => {
+            let combination = map(combination, context).await?;
+            let context = context.clone();
+            blocking(move || apply(combination, &context)).await
+        }
+        Thunk::Selection(spec) => {
+            let context = context.clone();
+            blocking(move || select(spec, &context)).await
+        }
+        Thunk::Identification(x) => Ok(RuntimeValue::Data(x)),
+    }
+}
+
+pub(crate) async fn execute(e: Encode, context: &Context) -> Result<Data> {
+    let mut execution = Execution::new(e, context);
+    let data = loop {
+        let thunk = execution.thunk;
+        if let Some(x) = blocking(move || memo::get(thunk)).await {
+            context.hooks.on_cache_hit(thunk, x);
+            break x;
+        }
+        execution.think()?;
+        let thought = think(thunk, &execution.context).await;
+        if let Some(x) = execution.thought(thought)? {
+            break x;
+        }
+    };
+    blocking(move || execution.finish(data)).await
+}
+
+pub(crate) fn eval(h: Handle, context: Context) -> BoxFuture<Result<Value>> {
+    Box::pin(async move {
+        Ok(match h {
+            Handle::Encode(e) => {
+                let data = execute(e, &context).await?;
+                eval(Handle::Data(data), context).await?
+            }
+            Handle::Data(d) => Value::Data(match d {
+                Data::Object(Object::Tree(x)) => {
+                    Data::Object(Object::Tree(map(x, &context).await?))
+                }
+                Data::Object(Object::Blob(x)) => Data::Object(Object::Blob(x)),
+                Data::Ref(x) => Data::Ref(x),
+            }),
+            Handle::Thunk(thunk) => Value::Thunk(thunk),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::runtime::Builder;
+
+    use super::*;
+    use crate::packed::PackedHandle;
+    use crate::path::Path;
+    use crate::{BlobName, HandleType, local, trap};
+
+    fn selection(seed: &[u8], index: u64) -> Handle {
+        let blob = Data::Object(Object::Blob(BlobName::create(seed.to_vec()).ok().unwrap()));
+        let tree = TreeName::create(vec![Handle::Data(blob)]).ok().unwrap();
+        let thunk = Path::new()
+            .index(index)
+            .thunk(Data::Object(Object::Tree(tree)));
+        Handle::Encode(Encode {
+            thunk: thunk.ok().unwrap(),
+            accessibility: None,
+        })
+    }
+
+    fn tree(elements: Vec<Handle>) -> Handle {
+        Handle::Data(Data::Object(Object::Tree(
+            TreeName::create(elements).ok().unwrap(),
+        )))
+    }
+
+    fn both(h: Handle) -> (Result<Value>, Result<Value>) {
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let asynchronous = runtime.block_on(eval(h, Context::default()));
+        (asynchronous, crate::eval(h, &Context::default()))
+    }
+
+    #[test]
+    fn evaluates_as_eval_does() {
+        let h = tree(vec![
+            selection(b"async, first", 0),
+            tree(vec![selection(b"async, nested", 0)]),
+        ]);
+        let (asynchronous, synchronous) = both(h);
+        let packed = |x: Result<Value>| PackedHandle::pack(x.ok().unwrap().relax());
+        assert!(packed(asynchronous) == packed(synchronous));
+    }
+
+    #[test]
+    fn the_first_trap_in_order_is_returned() {
+        // A spec without an index is malformed: a different trap from out of range.
+        let malformed = TreeName::create(vec![Handle::Data(Data::Object(Object::Blob(
+            BlobName::from(1_u8),
+        )))]);
+        let malformed = Handle::Encode(Encode {
+            thunk: Thunk::Selection(malformed.ok().unwrap()),
+            accessibility: None,
+        });
+        let h = tree(vec![
+            selection(b"async, fine", 0),
+            selection(b"async, out of range", 4),
+            malformed,
+        ]);
+        let (asynchronous, synchronous) = both(h);
+        // (Traps are local Trees, so they're compared by canonical Name.)
+        let trap = |x: Result<Value>| {
+            local::canonical_name(PackedHandle::pack(Handle::Data(x.err().unwrap())))
+        };
+        assert!(trap(asynchronous) == trap(synchronous));
+        let message = trap::message(both(h).0.err().unwrap());
+        assert_eq!(message.as_deref(), Some("selection out of range"));
+    }
+}
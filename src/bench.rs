@@ -0,0 +1,322 @@
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::generate::{self, Shape};
+use crate::repository::Repository;
+use crate::storage::memory::MemoryStorage;
+use crate::storage::verify::{Verification, Verified};
+use crate::storage::{Storage, set_storage};
+use crate::stream::BlobReader;
+use crate::{
+    BlobName, Context, Data, Encode, Handle, Object, Thunk, TreeName, eval, local, metrics,
+};
+
+// Synthetic workloads, for measuring the evaluator's throughput on a repeatable basis:
+//
+//   store      a wide Tree of distinct small Blobs, stored and then loaded
+//   heavy      one large Blob, stored (chunked and hashed) and then read back
+//   deep       a chain of Encodes, each evaluating to a Tree holding the next
+//   friendly   a wide Tree of Encodes of the same Thunk (memo-friendly: one execution)
+//   hostile    a wide Tree of Encodes of distinct Thunks (memo-hostile: one each)
+//   apply      a wide Tree of applications of a trivial procedure (with the wasm feature)
+//   random     many small random graphs (see generate), each evaluated on its own (as some
+//              trap)
+//
+// They run in memory, or in a fresh Repository (see Options::store), optionally verifying
+// what's loaded (see Options::verify, to measure its overhead against a run without). Each is built with
+// contents seeded by the run's seed, so runs with different seeds share nothing in the
+// memo table.
+pub(crate) const WORKLOADS: &[&str] = &[
+    "store", "heavy", "deep", "friendly", "hostile", "apply", "random",
+];
+
+pub(crate) struct Options {
+    pub(crate) width: usize,
+    pub(crate) depth: usize,
+    pub(crate) blob_size: usize,
+    pub(crate) seed: u64,
+    // A directory to create a Repository in, to measure it rather than memory.
+    pub(crate) store: Option<String>,
+    // How to verify what's loaded from the store (see storage::verify).
+    pub(crate) verify: Option<Verification>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            width: 10_000,
+            depth: 1_000,
+            blob_size: 64 << 20,
+            seed: 0,
+            store: None,
+            verify: None,
+        }
+    }
+}
+
+// Run the named workloads, reporting each one's throughput as a line of `out`.
+pub(crate) fn run(workloads: &[&str], options: &Options, out: &mut impl Write) -> io::Result<()> {
+    let backing: Arc<dyn Storage> = match &options.store {
+        Some(dir) => Arc::new(Repository::create(dir)?),
+        None => Arc::new(MemoryStorage::default()),
+    };
+    match options.verify {
+        Some(verification) => set_storage(Arc::new(Verified::new(backing, verification))),
+        None if options.store.is_some() => set_storage(backing),
+        None => {}
+    }
+    for &workload in workloads {
+        let report = match workload {
+            "store" => store(options)?,
+            "heavy" => heavy(options)?,
+            "deep" => deep(options)?,
+            "friendly" => encodes(options, true)?,
+            "hostile" => encodes(options, false)?,
+            "apply" => applies(options)?,
+            "random" => random(options)?,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "no such workload",
+                ));
+            }
+        };
+        writeln!(out, "{workload:<10} {report}")?;
+    }
+    Ok(())
+}
+
+fn rate(n: usize, time: Duration) -> String {
+    let rate = n as f64 / time.as_secs_f64().max(1e-9);
+    match rate {
+        x if x >= 1e6 => format!("{:.1}M/s", x / 1e6),
+        x if x >= 1e3 => format!("{:.1}k/s", x / 1e3),
+        x => format!("{x:.1}/s"),
+    }
+}
+
+fn timed<T>(f: impl FnOnce() -> io::Result<T>) -> io::Result<(T, Duration)> {
+    let start = Instant::now();
+    let result = f()?;
+    Ok((result, start.elapsed()))
+}
+
+fn io_result<T>(result: crate::Result<T>) -> io::Result<T> {
+    result.map_err(io::Error::from)
+}
+
+// Distinct contents for the `i`th object of a workload.
+fn contents(options: &Options, i: usize, len: usize) -> Vec<u8> {
+    let mut state = options.seed ^ (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    (0..len)
+        .map(|_| {
+            // xorshift64*
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            (state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8
+        })
+        .collect()
+}
+
+fn data(blob: BlobName) -> Handle {
+    Handle::Data(Data::Object(Object::Blob(blob)))
+}
+
+fn identification(options: &Options, i: usize) -> Handle {
+    let blob = data(local::blob(contents(options, i, 64)));
+    let Handle::Data(data) = blob else {
+        unreachable!()
+    };
+    Handle::Encode(Encode {
+        thunk: Thunk::Identification(data),
+        accessibility: None,
+    })
+}
+
+fn store(options: &Options) -> io::Result<String> {
+    let n = options.width;
+    let (tree, put) = timed(|| {
+        let blobs = (0..n)
+            .map(|i| io_result(BlobName::create(contents(options, i, 100))).map(data))
+            .collect::<io::Result<Vec<_>>>()?;
+        io_result(TreeName::create(blobs))
+    })?;
+    let ((), load) = timed(|| {
+        for h in io_result(tree.try_load())? {
+            if let Handle::Data(Data::Object(Object::Blob(x))) = h {
+                io_result(x.try_load())?;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(format!(
+        "{n} Blobs stored in {put:.2?} ({}), loaded in {load:.2?} ({})",
+        rate(n, put),
+        rate(n, load)
+    ))
+}
+
+fn heavy(options: &Options) -> io::Result<String> {
+    let size = options.blob_size;
+    let contents = contents(options, 0, size);
+    let (blob, put) = timed(|| io_result(BlobName::create(contents)))?;
+    let (read, get) = timed(|| {
+        let mut read = Vec::with_capacity(size);
+        BlobReader::new(&blob)?.read_to_end(&mut read)?;
+        Ok(read.len())
+    })?;
+    let mib = |time: Duration| {
+        format!(
+            "{:.1} MiB/s",
+            size as f64 / (1 << 20) as f64 / time.as_secs_f64().max(1e-9)
+        )
+    };
+    Ok(format!(
+        "{read} bytes stored in {put:.2?} ({}), read in {get:.2?} ({})",
+        mib(put),
+        mib(get)
+    ))
+}
+
+fn deep(options: &Options) -> io::Result<String> {
+    let n = options.depth;
+    let mut h = identification(options, 0);
+    for _ in 0..n {
+        let tree = Data::Object(Object::Tree(local::tree(vec![h])));
+        h = Handle::Encode(Encode {
+            thunk: Thunk::Identification(tree),
+            accessibility: None,
+        });
+    }
+    let before = metrics::metrics();
+    let ((), time) = timed(|| io_result(eval(h, &Context::default())).map(drop))?;
+    let thinks = metrics::metrics().since(&before).thinks as usize;
+    Ok(format!(
+        "{n} nested Encodes evaluated in {time:.2?} ({thinks} thinks, {})",
+        rate(thinks, time)
+    ))
+}
+
+fn encodes(options: &Options, friendly: bool) -> io::Result<String> {
+    let n = options.width;
+    let encodes = (0..n)
+        .map(|i| identification(options, if friendly { 1 } else { 2 + i }))
+        .collect();
+    let tree = Handle::Data(Data::Object(Object::Tree(local::tree(encodes))));
+    let before = metrics::metrics();
+    let ((), time) = timed(|| io_result(eval(tree, &Context::default())).map(drop))?;
+    let work = metrics::metrics().since(&before);
+    Ok(format!(
+        "{n} Encodes evaluated in {time:.2?} ({}; {} thinks, {:.0}% memo hits)",
+        rate(n, time),
+        work.thinks,
+        100.0 * work.hit_ratio()
+    ))
+}
+
+// A module whose `apply` returns its combination.
+#[cfg(feature = "wasm")]
+const IDENTITY: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic and version
+    0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f, // type 0: (i32) -> i32
+    0x03, 0x02, 0x01, 0x00, // function 0 has type 0
+    0x05, 0x03, 0x01, 0x00, 0x01, // one memory of one page
+    0x07, 0x12, 0x02, // two exports:
+    0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00, // memory 0
+    0x05, b'a', b'p', b'p', b'l', b'y', 0x00, 0x00, // function 0
+    0x0a, 0x06, 0x01, 0x04, 0x00, 0x20, 0x00, 0x0b, // function 0: local.get 0
+];
+
+#[cfg(feature = "wasm")]
+fn applies(options: &Options) -> io::Result<String> {
+    let n = options.width;
+    let procedure = data(io_result(BlobName::create(IDENTITY.to_vec()))?);
+    let limits = data(local::blob(Vec::new()));
+    let encodes = (0..n)
+        .map(|i| {
+            let argument = data(local::blob(contents(options, i, 8)));
+            Handle::Encode(Encode {
+                thunk: Thunk::Application(local::tree(vec![limits, procedure, argument])),
+                accessibility: None,
+            })
+        })
+        .collect();
+    let tree = Handle::Data(Data::Object(Object::Tree(local::tree(encodes))));
+    let before = metrics::metrics();
+    let ((), time) = timed(|| io_result(eval(tree, &Context::default())).map(drop))?;
+    let applies = metrics::metrics().since(&before).applies as usize;
+    Ok(format!(
+        "{applies} applications evaluated in {time:.2?} ({})",
+        rate(applies, time)
+    ))
+}
+
+#[cfg(not(feature = "wasm"))]
+fn applies(_: &Options) -> io::Result<String> {
+    Ok("skipped: applying a procedure needs the wasm feature".to_string())
+}
+
+fn random(options: &Options) -> io::Result<String> {
+    let n = options.width;
+    let graphs: Vec<_> = (0..n)
+        .map(|i| {
+            let options = generate::Options {
+                seed: options.seed ^ (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15),
+                ..Default::default()
+            };
+            generate::build(&Shape::Encode(Box::new(generate::shape(&options)), None))
+        })
+        .collect();
+    let before = metrics::metrics();
+    let ((), time) = timed(|| {
+        for h in graphs {
+            // (A trap is as much a result as a Value.)
+            let _ = eval(h, &Context::default());
+        }
+        Ok(())
+    })?;
+    let work = metrics::metrics().since(&before);
+    Ok(format!(
+        "{n} random graphs evaluated in {time:.2?} ({}; {} thinks, {} traps)",
+        rate(n, time),
+        work.thinks,
+        work.traps
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_workload_runs() {
+        let options = Options {
+            width: 20,
+            depth: 20,
+            blob_size: 1 << 20,
+            seed: 0xbe4c,
+            store: None,
+            verify: None,
+        };
+        let mut out = Vec::new();
+        run(WORKLOADS, &options, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), WORKLOADS.len());
+        assert!(out.contains("1048576 bytes stored"));
+        assert!(run(&["nothing"], &options, &mut Vec::new()).is_err());
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn the_identity_module_is_as_written() {
+        let wat = r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "apply") (param i32) (result i32)
+                (local.get 0)))
+        "#;
+        assert_eq!(wat::parse_str(wat).unwrap(), IDENTITY);
+    }
+}
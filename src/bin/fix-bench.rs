@@ -0,0 +1,24 @@
+use std::process::{Command, ExitCode};
+
+// fix-bench: the bench command (see bench) as a binary of its own, for performance work, e.g.
+//   fix-bench --width 1000 --seed 7 store deep hostile
+// It runs the fixmodel binary installed beside it (`fixmodel bench ARGS...`), so the two
+// always measure the same evaluator.
+fn main() -> ExitCode {
+    let fixmodel = std::env::current_exe()
+        .map(|x| x.with_file_name(format!("fixmodel{}", std::env::consts::EXE_SUFFIX)));
+    let status = fixmodel.and_then(|fixmodel| {
+        Command::new(fixmodel)
+            .arg("bench")
+            .args(std::env::args_os().skip(1))
+            .status()
+    });
+    match status {
+        Ok(status) if status.success() => ExitCode::SUCCESS,
+        Ok(status) => ExitCode::from(status.code().map_or(1, |x| x as u8)),
+        Err(e) => {
+            eprintln!("fix-bench: can't run fixmodel: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
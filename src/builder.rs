@@ -0,0 +1,141 @@
+use std::io;
+use std::marker::PhantomData;
+
+use crate::gc::{self, Pin};
+use crate::hash::{TreeHasher, hash_tree};
+use crate::packed::{MAX_FOOTPRINT, PackedHandle};
+use crate::storage::{Key, key, storage};
+use crate::{Data, HANDLE_SIZE, Handle, HandleType, PAGE_SIZE, Ref, TreeName, local};
+
+// Building a Tree one element at a time, for when the elements don't come as a Vec.
+//
+// Each element is packed (and canonicalized, see local) as it's pushed, and the Name's
+// hash, size, footprint and eq are kept up to date, so finishing only has to store the
+// Tree. Once more than a threshold of elements are held, they're spilled to Storage as a
+// segment (a Tree of its own, pinned until the builder is dropped, and read back on
+// finishing), so building a very large Tree only holds its packed elements in memory once,
+// when it's stored. Segments are left for the next garbage collection.
+pub(crate) struct TreeBuilder<T: HandleType = Handle> {
+    elements: Vec<PackedHandle>,
+    segments: Vec<(Key, Pin)>,
+    spill_threshold: usize,
+    hasher: TreeHasher,
+    size: u32,
+    // The sum of the elements' footprints (saturating).
+    footprint: u32,
+    eq: bool,
+    _type: PhantomData<T>,
+}
+
+// Elements held in memory before they're spilled (32 MiB, packed).
+const SPILL_THRESHOLD: usize = 1 << 20;
+
+impl<T: HandleType> Default for TreeBuilder<T> {
+    fn default() -> Self {
+        TreeBuilder {
+            elements: Vec::new(),
+            segments: Vec::new(),
+            spill_threshold: SPILL_THRESHOLD,
+            hasher: TreeHasher::default(),
+            size: 0,
+            footprint: 0,
+            eq: true,
+            _type: PhantomData,
+        }
+    }
+}
+
+impl<T: HandleType> TreeBuilder<T> {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    // Spill once more than `threshold` elements are held in memory.
+    #[cfg(test)]
+    pub(crate) fn with_spill_threshold(mut self, threshold: usize) -> Self {
+        self.spill_threshold = threshold;
+        self
+    }
+
+    // Fails if the element's local objects (see local) can't be stored, or the elements
+    // can't be spilled.
+    pub(crate) fn push(&mut self, h: T) -> io::Result<()> {
+        self.size = self
+            .size
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("Tree too large to name"))?;
+        self.footprint = self.footprint.saturating_add(h.footprint());
+        self.eq &= h.is_eq();
+        let packed = local::canonical(PackedHandle::pack(h.relax()))?;
+        self.hasher.update(packed);
+        self.elements.push(packed);
+        if self.elements.len() > self.spill_threshold {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    // Store the elements held as a segment.
+    fn spill(&mut self) -> io::Result<()> {
+        let segment = std::mem::take(&mut self.elements);
+        let name = TreeName::<Handle> {
+            name: hash_tree(&segment),
+            size: segment.len() as u32,
+            footprint: 0,
+            eq: false,
+            tag: false,
+        };
+        // Pinned before it's stored, so no collection can delete it in between.
+        let pin = gc::pin(Handle::Data(Data::Ref(Ref::Tree(name))));
+        storage().put_tree(key(name.name), segment.into())?;
+        self.segments.push((key(name.name), pin));
+        Ok(())
+    }
+
+    // Store the Tree, and name it (as TreeName::create would).
+    pub(crate) fn finish(self) -> io::Result<TreeName<T>> {
+        let mut elements = Vec::with_capacity(self.size as usize);
+        for &(name, _) in &self.segments {
+            let segment = storage()
+                .get_tree(name)?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "segment missing"))?;
+            elements.extend_from_slice(&segment);
+        }
+        elements.extend(self.elements);
+        let footprint = (self.size as usize * HANDLE_SIZE).div_ceil(PAGE_SIZE) as u32;
+        let name = TreeName {
+            name: self.hasher.finish(),
+            size: self.size,
+            footprint: footprint.saturating_add(self.footprint).min(MAX_FOOTPRINT),
+            eq: self.eq,
+            tag: false,
+        };
+        storage().put_tree(key(name.name), elements.into())?;
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BlobName, Object};
+
+    fn element(i: usize) -> Handle {
+        let blob = BlobName::create(i.to_le_bytes().repeat(4)).ok().unwrap();
+        Handle::Data(Data::Object(Object::Blob(blob)))
+    }
+
+    #[test]
+    fn builds_what_create_would() {
+        let elements: Vec<_> = (0..10).map(element).collect();
+        let mut builder = TreeBuilder::new().with_spill_threshold(3);
+        for &h in &elements {
+            builder.push(h).unwrap();
+        }
+        assert_eq!(builder.segments.len(), 2);
+        let built = builder.finish().unwrap();
+        let created = TreeName::create(elements.clone()).ok().unwrap();
+        assert!(built == created);
+        assert!(built.try_load().ok().unwrap() == elements);
+    }
+}
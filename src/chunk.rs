@@ -0,0 +1,282 @@
+use std::io;
+
+use rayon::prelude::*;
+
+use crate::hash::{hash_blob, hash_blobs, hash_tree};
+use crate::packed::PackedHandle;
+use crate::storage::{SharedBlob, key, storage};
+use crate::{Blob, BlobName, Data, Handle, Pointer, Ref, Tree, local};
+
+// Content-defined chunking of large Blobs (FastCDC).
+//
+// A Blob larger than CHUNK_THRESHOLD is stored as chunks, each an ordinary Blob, plus a
+// "chunk list": a Tree of Refs to the chunks, in order. Chunk boundaries depend only on
+// the nearby contents, so an edit only changes the chunks around it and the rest are
+// shared (and needn't be transferred again).
+//
+// Readers don't see the chunking. A chunked Blob's Name is still a BlobName, whose Pointer
+// is the hash of its chunk list (a function of the contents, so each Blob still has exactly
+// one Name, but not the BLAKE3 hash of the contents that names smaller Blobs), and it loads
+// as the whole Blob. Local Blobs are never chunked.
+pub(crate) const CHUNK_THRESHOLD: usize = 1 << 20;
+
+const MIN_CHUNK: usize = 16 << 10;
+const AVERAGE_CHUNK: usize = 64 << 10;
+pub(crate) const MAX_CHUNK: usize = 256 << 10;
+
+// Normalized chunking: a cut is harder to find before the average size and easier after,
+// which narrows the spread of chunk sizes. The masks select the hash's top bits
+// (which depend on the last 64 bytes).
+const HARD_MASK: u64 = !0 << (64 - 18);
+const EASY_MASK: u64 = !0 << (64 - 14);
+
+static GEAR: [u64; 256] = gear();
+
+// Random values for the rolling hash (splitmix64, so they're fixed forever).
+const fn gear() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+pub(crate) fn is_chunked(size: usize) -> bool {
+    size > CHUNK_THRESHOLD
+}
+
+// Is the object a Handle names stored as a Tree? (Trees are, and so are chunked Blobs.)
+pub(crate) fn stored_as_tree(h: &PackedHandle) -> bool {
+    h.is_tree() || is_chunked(h.size())
+}
+
+// The length of the first chunk of `data` (which only depends on its first MAX_CHUNK bytes).
+pub(crate) fn cut(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK);
+    let normal = end.min(AVERAGE_CHUNK);
+    let mut hash = 0u64;
+    for (i, &byte) in data.iter().enumerate().take(end).skip(MIN_CHUNK) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        let mask = if i < normal { HARD_MASK } else { EASY_MASK };
+        if hash & mask == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+pub(crate) fn chunks(mut blob: &Blob) -> Vec<&Blob> {
+    let mut chunks = Vec::new();
+    while !blob.is_empty() {
+        let (chunk, rest) = blob.split_at(cut(blob));
+        chunks.push(chunk);
+        blob = rest;
+    }
+    chunks
+}
+
+// Refs to each chunk, in order (the chunks are hashed in parallel).
+pub(crate) fn chunk_list(chunks: &[&Blob]) -> Vec<PackedHandle> {
+    chunks
+        .iter()
+        .zip(hash_blobs(chunks))
+        .map(|(chunk, pointer)| chunk_ref(chunk, pointer))
+        .collect()
+}
+
+fn chunk_ref(chunk: &Blob, pointer: Pointer<Blob>) -> PackedHandle {
+    let name = BlobName::literal(chunk).unwrap_or(BlobName::Name((pointer, chunk.len())));
+    PackedHandle::pack(Handle::Data(Data::Ref(Ref::Blob(name))))
+}
+
+// Store chunks (hashing and storing them in parallel), returning their entries in the
+// chunk list.
+pub(crate) fn put_chunks(chunks: &[&Blob]) -> io::Result<Vec<PackedHandle>> {
+    let (storage, list) = (storage(), chunk_list(chunks));
+    chunks
+        .par_iter()
+        .zip(&list)
+        .try_for_each(|(chunk, h)| match h.key() {
+            Some(name) => storage.put_blob(name, chunk.to_vec().into()),
+            None => Ok(()),
+        })?;
+    Ok(list)
+}
+
+// Store a chunk list (whose chunks are already stored), returning the chunked Blob's Pointer.
+pub(crate) fn put_list(list: Vec<PackedHandle>) -> io::Result<Pointer<Blob>> {
+    let pointer = hash_tree(&list);
+    storage().put_tree(key(pointer), list.into())?;
+    Ok(pointer)
+}
+
+// The canonical Pointer of a Blob too long for a Literal.
+pub(crate) fn name(blob: &Blob) -> Pointer<Blob> {
+    if !is_chunked(blob.len()) {
+        return hash_blob(blob);
+    }
+    hash_tree(&chunk_list(&chunks(blob)))
+}
+
+// Store a Blob too long for a Literal (as chunks, if it's large), returning its canonical Pointer.
+pub(crate) fn put(blob: SharedBlob) -> io::Result<Pointer<Blob>> {
+    let storage = storage();
+    if !is_chunked(blob.len()) {
+        let pointer = hash_blob(&blob);
+        storage.put_blob(key(pointer), blob)?;
+        return Ok(pointer);
+    }
+    put_list(put_chunks(&chunks(&blob))?)
+}
+
+// Fetch a Blob by its Pointer and size, reassembling it if it's chunked.
+pub(crate) fn get(pointer: Pointer<Blob>, size: usize) -> io::Result<Option<SharedBlob>> {
+    if !is_chunked(size) || local::is_local(pointer) {
+        return local::storage_of(pointer).get_blob(key(pointer));
+    }
+    let Some(chunks) = list(pointer, size)? else {
+        return Ok(None);
+    };
+    let mut blob = Vec::with_capacity(size);
+    for chunk in &chunks {
+        let Some(chunk) = get_chunk(chunk)? else {
+            return Ok(None);
+        };
+        blob.extend_from_slice(&chunk);
+    }
+    Ok(Some(blob.into()))
+}
+
+// Bytes `start..end` of a Blob, loading only the chunks they overlap (if it's chunked).
+pub(crate) fn get_range(
+    pointer: Pointer<Blob>,
+    size: usize,
+    start: usize,
+    end: usize,
+) -> io::Result<Option<Vec<u8>>> {
+    let slice = |blob: &Blob, start, end| {
+        blob.get(start..end)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Blob range out of bounds"))
+    };
+    if !is_chunked(size) || local::is_local(pointer) {
+        let blob = local::storage_of(pointer).get_blob(key(pointer))?;
+        return blob.map(|blob| slice(&blob, start, end)).transpose();
+    }
+    let Some(chunks) = list(pointer, size)? else {
+        return Ok(None);
+    };
+    let mut range = Vec::with_capacity(end.saturating_sub(start));
+    let mut offset = 0;
+    for chunk in &chunks {
+        let (first, last) = (offset, offset + chunk.size());
+        offset = last;
+        if last <= start || first >= end {
+            continue;
+        }
+        let Some(bytes) = get_chunk(chunk)? else {
+            return Ok(None);
+        };
+        range.extend(slice(
+            &bytes,
+            start.max(first) - first,
+            end.min(last) - first,
+        )?);
+    }
+    Ok(Some(range))
+}
+
+// The chunks of a chunked Blob, in order.
+pub(crate) fn list(pointer: Pointer<Blob>, size: usize) -> io::Result<Option<Vec<BlobName>>> {
+    match storage().get_tree(key(pointer))? {
+        Some(list) => parse_list(&list, size).map(Some),
+        None => Ok(None),
+    }
+}
+
+// The chunks a stored chunk list names, checked against the size of the chunked Blob.
+pub(crate) fn parse_list(list: &Tree<PackedHandle>, size: usize) -> io::Result<Vec<BlobName>> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed chunk list");
+    let chunks = list
+        .iter()
+        .map(|h| match h.unpack() {
+            Handle::Data(Data::Ref(Ref::Blob(x))) => Ok(x),
+            _ => Err(malformed()),
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    if chunks.iter().map(BlobName::size).sum::<usize>() != size {
+        return Err(malformed());
+    }
+    Ok(chunks)
+}
+
+pub(crate) fn get_chunk(chunk: &BlobName) -> io::Result<Option<SharedBlob>> {
+    match chunk {
+        BlobName::Literal((bytes, length)) => Ok(Some(bytes[..*length as usize].to_vec().into())),
+        BlobName::Name((pointer, _)) => storage().get_blob(key(*pointer)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pseudo-random contents (so there are chunk boundaries to find).
+    fn contents(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chunks_are_bounded_and_cover_the_blob() {
+        let blob = contents(3 * CHUNK_THRESHOLD, 1);
+        let chunks = chunks(&blob);
+        assert!(chunks.len() > 1);
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(
+            rest.iter()
+                .all(|x| (MIN_CHUNK..=MAX_CHUNK).contains(&x.len()))
+        );
+        assert!(last.len() <= MAX_CHUNK);
+        assert_eq!(chunks.concat(), blob);
+    }
+
+    #[test]
+    fn an_edit_only_changes_nearby_chunks() {
+        let blob = contents(2 * CHUNK_THRESHOLD, 2);
+        let mut edited = blob.clone();
+        edited[CHUNK_THRESHOLD] ^= 1;
+        let (before, after) = (chunk_list(&chunks(&blob)), chunk_list(&chunks(&edited)));
+        let changed = after.iter().filter(|x| !before.contains(x)).count();
+        assert_eq!(before.len(), after.len());
+        assert!((1..=2).contains(&changed));
+    }
+
+    #[test]
+    fn a_stored_chunked_blob_reads_back() {
+        let blob = contents(CHUNK_THRESHOLD + 1000, 3);
+        let pointer = put(blob.clone().into()).unwrap();
+        assert!(pointer == name(&blob));
+        assert_eq!(&*get(pointer, blob.len()).unwrap().unwrap(), &blob[..]);
+        let (start, end) = (MAX_CHUNK - 10, 3 * MAX_CHUNK);
+        let range = get_range(pointer, blob.len(), start, end).unwrap().unwrap();
+        assert_eq!(range, &blob[start..end]);
+    }
+}
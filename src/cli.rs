@@ -0,0 +1,1062 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, IsTerminal, Read, Write};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "async")]
+use crate::async_eval;
+use crate::builder::TreeBuilder;
+use crate::collections::HandleSet;
+use crate::fetch::Fetched;
+#[cfg(feature = "git")]
+use crate::git::GitStorage;
+#[cfg(feature = "ipfs")]
+use crate::ipfs;
+use crate::packed::PackedHandle;
+use crate::path::Path;
+use crate::prefetch::{self, Arguments, Learned};
+use crate::pretty::pretty;
+use crate::progress::{Format, Progress};
+use crate::remote::{Coordinator, Worker};
+use crate::repository::Repository;
+use crate::schedule::Priority;
+use crate::script::Script;
+use crate::storage::cache::CachedStorage;
+#[cfg(feature = "sled")]
+use crate::storage::kv::KvStorage;
+#[cfg(feature = "git")]
+use crate::storage::memory::MemoryStorage;
+use crate::storage::quota::Quota;
+#[cfg(feature = "s3")]
+use crate::storage::s3::{S3Config, S3Storage};
+use crate::storage::tiered::TieredStorage;
+use crate::storage::verify::{Verification, Verified};
+use crate::storage::{Storage, set_storage, storage};
+use crate::stream::{BlobReader, BlobWriter};
+use crate::trace::Trace;
+use crate::{
+    BlobName, Context, Data, Handle, HandleType, Object, archive, bench, conformance, daemon,
+    directory, equivalence, eval, eval_shallow, eval_to_depth, fetch, fsck, gc, graph, local, memo,
+    metrics, reference, remote, stats, trace,
+};
+
+// The command line: `fixmodel [--repository DIR] COMMAND ...`, on the Repository in DIR
+// (by default $FIX_REPOSITORY, or `.fix`), which is the Storage (or its first tier) and
+// persists the memo table.
+//
+// A HANDLE argument is a packed Handle in hex (64 digits), or the name of a label. Handles
+// are printed the same way, canonically (so the objects they name are stored).
+const USAGE: &str = "\
+usage: fixmodel [--repository DIR] [--compress LEVEL] [--verify MODE] [--tier LOCATION]...
+                [--cache PAGES] COMMAND
+
+  --verify MODE                 check what's loaded from the repository: never (the default),
+                                first-load (re-hash each object once), always (re-hash every
+                                load) or paranoid (also check what each Tree's elements
+                                record of the objects they name; see storage::verify)
+  --tier LOCATION               load what the repository doesn't hold from LOCATION (in turn,
+                                if repeated), and write what's stored through to it: a
+                                repository's DIR, sled:PATH or s3://BUCKET[/PREFIX] (see
+                                storage::tiered)
+  --cache PAGES                 keep up to PAGES of recently loaded objects in memory (see
+                                storage::cache)
+
+  init                          create the repository
+  put [FILE]                    store a file (or stdin) as a Blob
+  put-dir DIR                   store a directory tree, in parallel (see directory)
+  tree [--unique] [FILE]        store a Tree of the HANDLEs in a file (or stdin), one per
+                                line (leaving out any equal to an earlier one)
+  get HANDLE                    write a Blob's contents to stdout
+  get --as TYPE HANDLE          print a Blob as a value of TYPE: u8, u16, u32, u64, i8, i16,
+                                i32, i64, f32, f64, bool or string (see literal)
+  show HANDLE [DEPTH]           print a Handle, and its closure DEPTH levels deep
+  select HANDLE PATH            select along a path (e.g. /3/bytes:0..10) from a Handle
+  graph [--order] HANDLE        print the Encodes evaluating a Handle executes, as dot (or
+                                one per line, each after those it depends on)
+  eval [OPTIONS] HANDLE         evaluate a Handle (Ctrl-C cancels it: it traps, cancelled)
+      --depth N                 only evaluate N Trees deep
+      --steps N                 the step budget of each Encode
+      --timeout MS              how long each Encode may take
+      --workers N               offload Encodes to N worker processes
+      --worker [TOKEN@]ADDRESS  offload Encodes to a worker listening at ADDRESS (repeatable),
+                                or to a daemon, as the tenant TOKEN names
+      --lease MS                give up on a worker not heard from for MS (by default, 10s)
+      --worker-timeout MS       give up on a worker that hasn't answered within MS, and
+                                execute the Encode locally
+      --priority PRIORITY       interactive, normal or batch
+      --prefetch PAGES          fetch the Refs (up to PAGES each) among each apply's arguments
+      --prefetch-trace TRACE    fetch what each procedure loaded when a trace (see --trace) was
+                                recorded, before applying it again
+      --read-ahead N            fetch the objects among the first N elements of each Tree loaded
+      --trace LABEL             label a trace of the evaluation
+      --chrome-trace FILE       write how the evaluation was scheduled (its steps and loads,
+                                per worker), for chrome://tracing or Perfetto
+      --metrics                 report the work done (on stderr)
+      --cross-check             evaluate again with the reference evaluator, failing if it
+                                differs (see reference; it's slow, so for small inputs)
+      --quiet                   don't report progress (by default it is, to a terminal)
+      --json-progress           report progress on stderr as JSON lines
+      --async                   evaluate on a tokio runtime, each Tree's elements as tasks
+                                (with the async feature; see async_eval)
+  replay HANDLE                 replay a trace, reporting where it diverges
+  repl [--script FILE]          run statements (see script), printing what each produces;
+                                from stdin, or (stopping at the first error) a file
+  label [NAME [HANDLE]]         list the labels, print one, or set it
+  label --remote [TOKEN@]ADDRESS NAME [HANDLE]
+                                print (storing its closure) or set a label a worker keeps,
+                                e.g. one of the tenant TOKEN names, on a daemon
+  label --if EXPECTED NAME HANDLE
+                                set a label only if it still names EXPECTED (failing if
+                                another process has since set it)
+  unlabel NAME                  delete a label
+  export HANDLE FILE            write a Handle and its closure to an archive
+  import FILE                   store everything in an archive
+  remote [NAME [LOCATION]]      list the remotes, print where one is, or add (or move) it
+                                (a LOCATION as --tier's)
+  unremote NAME                 delete a remote
+  fetch [--shallow] REMOTE HANDLE
+                                store a Handle's closure (or only its object) from the
+                                remote REMOTE (HANDLE can be one of its labels)
+  fsck [HANDLE]                 check every stored object (or only a Handle's closure)
+  gc                            delete every object no label reaches
+  stats                         describe what's stored
+  repack                        pack the stored objects
+  forget [--equivalences]       forget every remembered result (and every equivalence)
+  equate HANDLE HANDLE          assert that two Handles are interchangeable, so a result
+                                under one serves the other (unchecked: see equivalence)
+  equivalent HANDLE HANDLE      print whether two Handles have been equated
+  worker [OPTIONS]              execute Encodes for a coordinator, on stdin and stdout
+      --listen ADDRESS          or on each TCP connection to ADDRESS (e.g. 0.0.0.0:7070)
+      --steps N                 the most steps any Encode may take
+      --fuel N                  the most fuel every apply, together, may consume
+      --storage BYTES           the most bytes of objects to hold
+      --persistent              keep objects, results and labels in the repository
+      --exit-with-stdin         exit when stdin closes
+  daemon --listen ADDRESS --tenants FILE
+                                execute Encodes (and keep labels) for the tenants in FILE, each
+                                on a worker of its own, under its quotas, with a repository
+                                of its own, in the repository's directory (see daemon)
+  conformance [--reference COMMAND] DIR
+                                run the .fix conformance cases in DIR (or compare what
+                                they evaluate to with another implementation: see conformance)
+  bench [OPTIONS] [WORKLOAD...] measure throughput (in memory) on synthetic workloads:
+                                store, heavy, deep, friendly, hostile, apply or random
+                                (by default all)
+      --width N                 how many objects or Encodes wide workloads have
+      --depth N                 how many Encodes deep the chain is
+      --blob-size BYTES         the size of the heavy Blob
+      --seed N                  vary the objects (to share nothing with another run)
+      --store DIR               measure a new repository in DIR instead of memory
+      --verify MODE             verify what's loaded (as above), to measure what it costs
+";
+
+#[cfg(feature = "git")]
+const GIT_USAGE: &str = "\
+  git-import DIR [REVISION]     store the tree of a revision (by default HEAD) of the git
+                                repository in DIR (see git)
+";
+
+#[cfg(feature = "ipfs")]
+const IPFS_USAGE: &str = "\
+  cid HANDLE                    print the CID of a stored object
+  lookup-cid CID                print a Ref to the stored object a CID (of cid's) names
+  ipfs-import GATEWAY CID       store an IPFS DAG, fetched from a trustless gateway
+";
+
+fn usage() -> io::Error {
+    let usage = [
+        USAGE,
+        #[cfg(feature = "git")]
+        GIT_USAGE,
+        #[cfg(feature = "ipfs")]
+        IPFS_USAGE,
+    ];
+    io::Error::new(ErrorKind::InvalidInput, usage.concat())
+}
+
+pub(crate) fn run(args: Vec<String>) -> io::Result<()> {
+    let mut args = args.into_iter().peekable();
+    let mut root = std::env::var("FIX_REPOSITORY").unwrap_or_else(|_| ".fix".to_string());
+    if args.peek().map(String::as_str) == Some("--repository") {
+        args.next();
+        root = args.next().ok_or_else(usage)?;
+    }
+    let mut compression = None;
+    if args.peek().map(String::as_str) == Some("--compress") {
+        args.next();
+        compression = Some(number(&args.next().ok_or_else(usage)?)?);
+    }
+    let mut verify = None;
+    if args.peek().map(String::as_str) == Some("--verify") {
+        args.next();
+        verify = Some(verification(&args.next().ok_or_else(usage)?)?);
+    }
+    let mut tiers = Vec::new();
+    while args.peek().map(String::as_str) == Some("--tier") {
+        args.next();
+        tiers.push(open(&args.next().ok_or_else(usage)?)?.storage());
+    }
+    let mut cache = None;
+    if args.peek().map(String::as_str) == Some("--cache") {
+        args.next();
+        cache = Some(number(&args.next().ok_or_else(usage)?)?);
+    }
+    let command = args.next().ok_or_else(usage)?;
+    let args: Vec<String> = args.collect();
+    match (command.as_str(), &args[..]) {
+        ("worker", options) => return worker(&root, options),
+        ("daemon", [listen, address, tenants, file])
+            if listen == "--listen" && tenants == "--tenants" =>
+        {
+            let tenants = daemon::tenants(&std::fs::read_to_string(file)?)?;
+            let listener = std::net::TcpListener::bind(address)?;
+            return daemon::listen(listener, root.as_ref(), tenants);
+        }
+        ("init", []) => return Repository::create(&root).map(drop),
+        ("bench", options) => return bench(options),
+        // Cases are self-contained, so they're run in memory.
+        ("conformance", [dir]) => return conformance(dir, None, &mut io::stdout().lock()),
+        ("conformance", [option, reference, dir]) if option == "--reference" => {
+            return conformance(dir, Some(reference), &mut io::stdout().lock());
+        }
+        _ => {}
+    }
+
+    let mut repository = Repository::open(&root)?;
+    if let Some(level) = compression {
+        repository = repository.with_compression(level);
+    }
+    let repository = Arc::new(repository);
+    let mut layers: Arc<dyn Storage> = repository.clone();
+    if !tiers.is_empty() {
+        tiers.insert(0, layers);
+        layers = Arc::new(TieredStorage::new(tiers));
+    }
+    if let Some(pages) = cache {
+        layers = Arc::new(CachedStorage::new(layers, pages));
+    }
+    if let Some(verification) = verify {
+        layers = Arc::new(Verified::new(layers, verification));
+    }
+    set_storage(layers);
+    equivalence::persist(repository.clone())?;
+    memo::persist(repository.clone())?;
+    let parse = |arg: &str| handle(&repository, arg);
+    let mut out = io::stdout().lock();
+    match (command.as_str(), &args[..]) {
+        ("put", [file]) => put(File::open(file)?, &mut out)?,
+        ("put", []) => put(io::stdin().lock(), &mut out)?,
+        ("tree", []) => tree(&parse, io::stdin().lock(), false, &mut out)?,
+        ("tree", [unique]) if unique == "--unique" => {
+            tree(&parse, io::stdin().lock(), true, &mut out)?
+        }
+        ("tree", [file]) => tree(&parse, BufReader::new(File::open(file)?), false, &mut out)?,
+        ("tree", [unique, file]) if unique == "--unique" => {
+            tree(&parse, BufReader::new(File::open(file)?), true, &mut out)?
+        }
+        ("put-dir", [dir]) => {
+            let tree = directory::put(std::path::Path::new(dir))?;
+            writeln!(out, "{}", text(Handle::Data(Data::Ref(tree)))?)?;
+        }
+        ("get", [h]) => match parse(h)? {
+            Handle::Data(Data::Object(Object::Blob(x))) => {
+                io::copy(&mut BlobReader::new(&x)?, &mut out)?;
+            }
+            _ => return Err(invalid("not an accessible Blob")),
+        },
+        ("get", [option, kind, h]) if option == "--as" => match parse(h)? {
+            Handle::Data(Data::Object(Object::Blob(x))) => writeln!(out, "{}", value(x, kind)?)?,
+            _ => return Err(invalid("not an accessible Blob")),
+        },
+        ("show", [h]) => writeln!(out, "{}", pretty(parse(h)?, 1))?,
+        ("show", [h, depth]) => writeln!(out, "{}", pretty(parse(h)?, number(depth)?))?,
+        ("select", [h, path]) => {
+            let Handle::Data(target) = parse(h)? else {
+                return Err(invalid("not Data"));
+            };
+            let path: Path = path.parse()?;
+            let selected = match path.select(target, &Context::default())? {
+                crate::RuntimeValue::Data(x) => Handle::Data(x),
+                crate::RuntimeValue::Thunk(x) => Handle::Thunk(x),
+            };
+            writeln!(out, "{}", text(selected)?)?;
+        }
+        ("graph", [h]) => write!(out, "{}", graph::graph(parse(h)?)?.dot())?,
+        ("graph", [order, h]) if order == "--order" => {
+            let graph = graph::graph(parse(h)?)?;
+            for node in graph.order() {
+                writeln!(out, "{}", text(Handle::Encode(graph.encode(node)))?)?;
+            }
+        }
+        ("eval", [options @ .., h]) => {
+            let h = parse(h)?;
+            evaluate(&repository, h, options, &mut out)?;
+        }
+        ("replay", [h]) => {
+            let Handle::Data(Data::Object(Object::Tree(x)) | Data::Ref(crate::Ref::Tree(x))) =
+                parse(h)?
+            else {
+                return Err(invalid("not a trace"));
+            };
+            match trace::replay(x, &Context::default())? {
+                None => writeln!(out, "replayed")?,
+                Some(divergence) => {
+                    writeln!(
+                        out,
+                        "step {} ({}) diverged\nexpected: {}\nactual:   {}",
+                        divergence.index,
+                        divergence.step,
+                        pretty(divergence.expected, 1),
+                        pretty(divergence.actual, 1)
+                    )?;
+                    return Err(invalid("trace diverged"));
+                }
+            }
+        }
+        ("repl", []) => {
+            let mut script = Script::new(&parse);
+            let prompt = || {
+                if io::stdin().is_terminal() {
+                    eprint!("> ");
+                }
+            };
+            prompt();
+            for line in io::stdin().lock().lines() {
+                match script.run(&line?) {
+                    Ok(Some(h)) => writeln!(out, "{}", text(h)?)?,
+                    Ok(None) => {}
+                    Err(e) => eprintln!("{e}"),
+                }
+                prompt();
+            }
+        }
+        ("repl", [flag, file]) if flag == "--script" => {
+            let source = std::fs::read_to_string(file)?;
+            for h in Script::new(&parse).run_all(&source)? {
+                writeln!(out, "{}", text(h)?)?;
+            }
+        }
+        ("label", []) => {
+            for (name, h) in repository.labels() {
+                writeln!(out, "{name} {}", text(h)?)?;
+            }
+        }
+        ("label", [name]) => {
+            let h = repository
+                .label(name)
+                .ok_or_else(|| invalid("no such label"))?;
+            writeln!(out, "{}", text(h)?)?;
+        }
+        ("label", [remote, address, name]) if remote == "--remote" => {
+            let h = connect(address)?
+                .label(name)
+                .map_err(closed)?
+                .ok_or_else(|| invalid("no such label"))?;
+            writeln!(out, "{}", text(h)?)?;
+        }
+        ("label", [remote, address, name, h]) if remote == "--remote" => {
+            if !connect(address)?
+                .set_label(name, parse(h)?)
+                .map_err(closed)?
+            {
+                return Err(invalid("the worker keeps no such label"));
+            }
+        }
+        ("label", [option, expected, name, h]) if option == "--if" => {
+            let (expected, h) = (parse(expected)?, parse(h)?);
+            if !repository.compare_and_set_label(name, Some(expected), Some(h))? {
+                return Err(invalid("the label no longer names what was expected"));
+            }
+        }
+        ("label", [name, h]) => repository.set_label(name, parse(h)?)?,
+        ("unlabel", [name]) => repository.delete_label(name)?,
+        ("export", [h, file]) => {
+            let mut file = BufWriter::new(File::create(file)?);
+            archive::export(parse(h)?, &mut file)?;
+            file.flush()?;
+        }
+        ("import", [file]) => {
+            let h = archive::import(BufReader::new(File::open(file)?))?;
+            writeln!(out, "{}", text(h)?)?;
+        }
+        ("remote", []) => {
+            for (name, location) in repository.remotes()? {
+                writeln!(out, "{name} {location}")?;
+            }
+        }
+        ("remote", [name]) => {
+            let location = repository
+                .remote(name)?
+                .ok_or_else(|| invalid("no such remote"))?;
+            writeln!(out, "{location}")?;
+        }
+        ("remote", [name, location]) => repository.set_remote(name, &absolute(location)?)?,
+        ("unremote", [name]) => repository.delete_remote(name)?,
+        ("fetch", [remote, h]) => fetch(&repository, remote, h, false, &mut out)?,
+        ("fetch", [shallow, remote, h]) if shallow == "--shallow" => {
+            fetch(&repository, remote, h, true, &mut out)?
+        }
+        ("fsck", []) | ("fsck", [_]) => {
+            let problems = match args.first() {
+                Some(h) => fsck::check(handle(&repository, h)?)?,
+                None => fsck::check_all()?,
+            };
+            for problem in &problems {
+                writeln!(out, "{problem:?}")?;
+            }
+            if !problems.is_empty() {
+                return Err(invalid("the repository has problems"));
+            }
+        }
+        ("gc", []) => {
+            let collected = gc::collect()?;
+            writeln!(
+                out,
+                "deleted {} Blobs and {} Trees",
+                collected.blobs, collected.trees
+            )?;
+        }
+        ("stats", []) => {
+            let stats = stats::stats()?;
+            writeln!(
+                out,
+                "{} objects ({} Blobs, {} Trees)",
+                stats.objects(),
+                stats.blobs,
+                stats.trees
+            )?;
+            writeln!(out, "{} bytes stored", stats.bytes())?;
+            writeln!(
+                out,
+                "{:.1}% Literal elements",
+                100.0 * stats.literal_ratio()
+            )?;
+            writeln!(
+                out,
+                "{} bytes saved by deduplication",
+                stats.deduplication_savings()
+            )?;
+        }
+        ("repack", []) => repository.repack()?,
+        ("forget", []) => memo::clear()?,
+        ("forget", [flag]) if flag == "--equivalences" => equivalence::clear()?,
+        ("equate", [a, b]) => equivalence::equate(parse(a)?, parse(b)?)?,
+        ("equivalent", [a, b]) => {
+            writeln!(out, "{}", equivalence::equivalent(parse(a)?, parse(b)?))?
+        }
+        #[cfg(feature = "git")]
+        ("git-import", [dir]) => git_import(dir, "HEAD", &mut out)?,
+        #[cfg(feature = "git")]
+        ("git-import", [dir, revision]) => git_import(dir, revision, &mut out)?,
+        #[cfg(feature = "ipfs")]
+        ("cid", [h]) => {
+            let cid = match local::canonicalize(parse(h)?)? {
+                Handle::Data(Data::Object(Object::Blob(x)) | Data::Ref(crate::Ref::Blob(x))) => {
+                    ipfs::blob_cid(&x)
+                }
+                Handle::Data(Data::Object(Object::Tree(x)) | Data::Ref(crate::Ref::Tree(x))) => {
+                    ipfs::tree_cid(&x)
+                }
+                _ => return Err(invalid("not a Blob or a Tree")),
+            };
+            writeln!(out, "{}", cid.unwrap())?;
+        }
+        #[cfg(feature = "ipfs")]
+        ("lookup-cid", [cid]) => {
+            let cid = ipfs::Cid::parse(cid).ok_or_else(|| invalid("not a CID"))?;
+            let found = ipfs::lookup(&cid)?.ok_or_else(|| invalid("no such object"))?;
+            writeln!(out, "{}", text(Handle::Data(Data::Ref(found)))?)?;
+        }
+        #[cfg(feature = "ipfs")]
+        ("ipfs-import", [gateway, cid]) => {
+            let cid = ipfs::Cid::parse(cid).ok_or_else(|| invalid("not a CID"))?;
+            let imported = ipfs::Importer::new(ipfs::Gateway::new(gateway)).import(&cid)?;
+            writeln!(out, "{}", text(Handle::Data(Data::Ref(imported)))?)?;
+        }
+        _ => return Err(usage()),
+    }
+    // (Every layer, so what's put reaches every tier.)
+    storage().flush()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, message)
+}
+
+fn number<T: std::str::FromStr>(arg: &str) -> io::Result<T> {
+    arg.parse().map_err(|_| invalid("not a number"))
+}
+
+// A --verify MODE.
+fn verification(arg: &str) -> io::Result<Verification> {
+    match arg {
+        "never" => Ok(Verification::Never),
+        "first-load" => Ok(Verification::FirstLoad),
+        "always" => Ok(Verification::Always),
+        "paranoid" => Ok(Verification::Paranoid),
+        _ => Err(invalid("no such verification")),
+    }
+}
+
+// A HANDLE argument.
+fn handle(repository: &Repository, arg: &str) -> io::Result<Handle> {
+    match repository.label(arg) {
+        Some(h) => Ok(h),
+        None => packed(arg),
+    }
+}
+
+// A HANDLE argument that can't be a label.
+fn packed(arg: &str) -> io::Result<Handle> {
+    let bytes = (arg.len() == 64 && arg.is_ascii())
+        .then(|| {
+            (0..32)
+                .map(|i| u8::from_str_radix(&arg[2 * i..2 * i + 2], 16).ok())
+                .collect::<Option<Vec<u8>>>()
+        })
+        .flatten()
+        .ok_or_else(|| invalid("not a Handle or a label"))?;
+    PackedHandle::from_bytes(bytes.try_into().unwrap())
+        .try_unpack()
+        .ok_or_else(|| invalid("not a valid Handle"))
+}
+
+// A Storage a LOCATION names: a Repository's directory, `sled:PATH` (a sled database, with
+// the sled feature) or `s3://BUCKET[/PREFIX]` (with the s3 feature, at $AWS_ENDPOINT_URL, in
+// $AWS_REGION, with the credentials in $AWS_ACCESS_KEY_ID and $AWS_SECRET_ACCESS_KEY).
+enum Opened {
+    Repository(Arc<Repository>),
+    #[cfg(any(feature = "sled", feature = "s3"))]
+    Storage(Arc<dyn Storage>),
+}
+
+impl Opened {
+    fn storage(self) -> Arc<dyn Storage> {
+        match self {
+            Opened::Repository(x) => x,
+            #[cfg(any(feature = "sled", feature = "s3"))]
+            Opened::Storage(x) => x,
+        }
+    }
+}
+
+fn open(location: &str) -> io::Result<Opened> {
+    if let Some(path) = location.strip_prefix("sled:") {
+        #[cfg(feature = "sled")]
+        return Ok(Opened::Storage(Arc::new(KvStorage::open(path)?)));
+        #[cfg(not(feature = "sled"))]
+        return Err(invalid(&format!("{path}: built without the sled feature")));
+    }
+    if let Some(bucket) = location.strip_prefix("s3://") {
+        #[cfg(feature = "s3")]
+        return Ok(Opened::Storage(Arc::new(S3Storage::new(s3_config(
+            bucket,
+        )?))));
+        #[cfg(not(feature = "s3"))]
+        return Err(invalid(&format!("{bucket}: built without the s3 feature")));
+    }
+    Ok(Opened::Repository(Arc::new(Repository::open(location)?)))
+}
+
+#[cfg(feature = "s3")]
+fn s3_config(location: &str) -> io::Result<S3Config> {
+    let variable = |name: &str| {
+        std::env::var(name).map_err(|_| invalid(&format!("s3://{location} needs ${name}")))
+    };
+    let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+    let region = variable("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+    Ok(S3Config {
+        endpoint: variable("AWS_ENDPOINT_URL")
+            .unwrap_or_else(|_| format!("https://s3.{region}.amazonaws.com")),
+        bucket: bucket.to_string(),
+        prefix: match prefix {
+            "" => String::new(),
+            prefix => format!("{}/", prefix.trim_end_matches('/')),
+        },
+        region,
+        access_key: variable("AWS_ACCESS_KEY_ID")?,
+        secret_key: variable("AWS_SECRET_ACCESS_KEY")?,
+    })
+}
+
+// A LOCATION with its path made absolute (so it's the same wherever fixmodel runs).
+fn absolute(location: &str) -> io::Result<String> {
+    if location.starts_with("s3://") {
+        return Ok(location.to_string());
+    }
+    let (scheme, path) = match location.strip_prefix("sled:") {
+        Some(path) => ("sled:", path),
+        None => ("", location),
+    };
+    let path = std::path::absolute(path)?;
+    let path = path
+        .to_str()
+        .ok_or_else(|| invalid("the location isn't UTF-8"))?;
+    Ok(format!("{scheme}{path}"))
+}
+
+// A Handle as printed (canonicalized, so the objects it names are stored).
+fn text(h: Handle) -> io::Result<String> {
+    let packed = local::canonical(PackedHandle::pack(h))?;
+    Ok(packed
+        .as_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+fn put(mut input: impl Read, out: &mut impl Write) -> io::Result<()> {
+    let mut writer = BlobWriter::new();
+    io::copy(&mut input, &mut writer)?;
+    let blob = writer.finish()?;
+    writeln!(
+        out,
+        "{}",
+        text(Handle::Data(Data::Object(Object::Blob(blob))))?
+    )
+}
+
+// A Blob as a value of the type named (trapping if it isn't one).
+fn value(x: BlobName, kind: &str) -> io::Result<String> {
+    Ok(match kind {
+        "u8" => x.as_u8()?.to_string(),
+        "u16" => x.as_u16()?.to_string(),
+        "u32" => x.as_u32()?.to_string(),
+        "u64" => x.as_u64()?.to_string(),
+        "i8" => x.as_i8()?.to_string(),
+        "i16" => x.as_i16()?.to_string(),
+        "i32" => x.as_i32()?.to_string(),
+        "i64" => x.as_i64()?.to_string(),
+        "f32" => x.as_f32()?.to_string(),
+        "f64" => x.as_f64()?.to_string(),
+        "bool" => x.as_bool()?.to_string(),
+        "string" => x.as_string()?,
+        _ => return Err(invalid("not a type")),
+    })
+}
+
+// Store a Tree of the HANDLEs read, one per line (however many: see builder), leaving out any
+// equal to one before it (see collections) if `unique`.
+fn tree(
+    parse: &dyn Fn(&str) -> io::Result<Handle>,
+    input: impl BufRead,
+    unique: bool,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let mut builder = TreeBuilder::new();
+    let mut seen = HandleSet::new();
+    for line in input.lines() {
+        let h = parse(line?.trim())?;
+        // (A Handle that isn't eq is equal to nothing, so it's always kept.)
+        if !unique || seen.insert(h).unwrap_or(true) {
+            builder.push(h)?;
+        }
+    }
+    let tree = builder.finish()?;
+    writeln!(
+        out,
+        "{}",
+        text(Handle::Data(Data::Object(Object::Tree(tree))))?
+    )
+}
+
+// Fetch from one of `repository`'s remotes. A fetched HANDLE can also be the name of one of
+// the remote's labels.
+fn fetch(
+    repository: &Repository,
+    remote: &str,
+    h: &str,
+    shallow: bool,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let location = repository
+        .remote(remote)?
+        .ok_or_else(|| invalid("no such remote (see remote)"))?;
+    let (remote, h) = match open(&location)? {
+        Opened::Repository(remote) => {
+            let h = handle(&remote, h)?;
+            (remote as Arc<dyn Storage>, h)
+        }
+        // (Only a Repository has labels.)
+        #[cfg(any(feature = "sled", feature = "s3"))]
+        Opened::Storage(remote) => (remote, packed(h)?),
+    };
+    fetch_from(&*remote, h, shallow, out)
+}
+
+// Fetch a Handle's closure (or only its object) from `remote`, reporting progress.
+fn fetch_from(
+    remote: &dyn Storage,
+    h: Handle,
+    shallow: bool,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let report = |fetched: &Fetched| {
+        eprint!(
+            "\rfetched {} Blobs and {} Trees ({} bytes); {} already stored",
+            fetched.blobs, fetched.trees, fetched.bytes, fetched.present
+        );
+    };
+    let fetched = fetch::fetch(remote, h, shallow, report);
+    eprintln!();
+    fetched?;
+    writeln!(out, "{}", text(h)?)
+}
+
+// Store the tree of a revision of the git repository in `dir` (see git), fetched from the git
+// objects as from a remote (so each is checked as it's stored).
+#[cfg(feature = "git")]
+fn git_import(dir: &str, revision: &str, out: &mut impl Write) -> io::Result<()> {
+    let git = GitStorage::new(MemoryStorage::default(), std::path::Path::new(dir))?;
+    let tree = git.tree(revision)?;
+    fetch_from(&git, Handle::Data(Data::Ref(tree)), false, out)
+}
+
+fn conformance(dir: &str, reference: Option<&str>, out: &mut impl Write) -> io::Result<()> {
+    let scratch = std::env::temp_dir().join(format!("fixmodel-conformance-{}", std::process::id()));
+    if reference.is_some() {
+        std::fs::create_dir(&scratch)?;
+    }
+    let outcomes =
+        conformance::check_all(std::path::Path::new(dir), |name, source| match reference {
+            Some(reference) => conformance::differ(source, reference, &scratch.join(name)),
+            None => conformance::check(source),
+        });
+    if reference.is_some() {
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+    let outcomes = outcomes?;
+    let mut failed = 0;
+    for (name, outcome) in &outcomes {
+        match outcome {
+            Ok(()) => writeln!(out, "ok      {name}")?,
+            Err(why) => {
+                failed += 1;
+                writeln!(out, "FAILED  {name}: {why}")?;
+            }
+        }
+    }
+    writeln!(out, "{} passed, {failed} failed", outcomes.len() - failed)?;
+    match failed {
+        0 => Ok(()),
+        _ => Err(io::Error::other("conformance cases failed")),
+    }
+}
+
+// A worker keeps its objects in memory (they come with each request, or are fetched), or
+// with --persistent, in the repository (creating it), with its results and labels.
+fn worker(root: &str, args: &[String]) -> io::Result<()> {
+    let (mut listen, mut quota, mut persistent) = (None, None, false);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(usage);
+        match arg.as_str() {
+            "--listen" => listen = Some(std::net::TcpListener::bind(value()?)?),
+            "--steps" => remote::set_step_limit(number(value()?)?),
+            "--fuel" => remote::set_fuel_limit(number(value()?)?),
+            "--storage" => quota = Some(number(value()?)?),
+            "--persistent" => persistent = true,
+            // (For a daemon's workers, which end with it.)
+            "--exit-with-stdin" => {
+                std::thread::spawn(|| {
+                    let _ = io::copy(&mut io::stdin(), &mut io::sink());
+                    std::process::exit(0)
+                });
+            }
+            _ => return Err(usage()),
+        }
+    }
+    if persistent {
+        let repository = match Repository::open(root) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Repository::create(root)?,
+            repository => repository?,
+        };
+        let repository = Arc::new(repository);
+        set_storage(repository.clone());
+        equivalence::persist(repository.clone())?;
+        memo::persist(repository.clone())?;
+        remote::keep_labels(repository);
+    }
+    if let Some(bytes) = quota {
+        set_storage(Arc::new(Quota::new(storage(), bytes)?));
+    }
+    set_storage(Arc::new(remote::Demand(storage())));
+    match listen {
+        Some(listener) => {
+            // (Where it listens, for one asked to pick a port, as a daemon's are.)
+            println!("{}", listener.local_addr()?);
+            remote::listen(listener)
+        }
+        None => remote::serve(io::stdin(), io::stdout()),
+    }
+}
+
+// A worker ending the connection before it answers (as a daemon does for a token that isn't
+// a tenant's).
+fn closed(e: io::Error) -> io::Error {
+    match e.kind() {
+        ErrorKind::UnexpectedEof => invalid("the worker closed the connection"),
+        _ => e,
+    }
+}
+
+// A worker listening at an address, or a daemon's, as the tenant a token names (TOKEN@ADDRESS).
+fn connect(address: &str) -> io::Result<Worker> {
+    match address.split_once('@') {
+        Some((token, address)) => Worker::connect_as(address, token),
+        None => Worker::connect(address),
+    }
+}
+
+// The write end of the pipe interrupted signals down.
+#[cfg(unix)]
+static INTERRUPTS: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(-1);
+
+#[cfg(unix)]
+extern "C" fn interrupted(_: libc::c_int) {
+    let fd = INTERRUPTS.load(std::sync::atomic::Ordering::Relaxed);
+    // SAFETY: write is async-signal-safe, and the byte outlives the call.
+    unsafe { libc::write(fd, [0u8].as_ptr().cast(), 1) };
+}
+
+// Cancel an evaluation on the first Ctrl-C (so it traps, cancelled, keeping the results of
+// the Encodes it finished), and exit on the second. (The handler only writes to a pipe: the
+// cancelling is done on a thread of its own.)
+#[cfg(unix)]
+fn cancel_on_interrupt(cancellation: crate::Cancellation) -> io::Result<()> {
+    use std::os::fd::FromRawFd;
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for both ends.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    INTERRUPTS.store(fds[1], std::sync::atomic::Ordering::Relaxed);
+    // SAFETY: the read end is ours alone.
+    let mut signals = unsafe { File::from_raw_fd(fds[0]) };
+    std::thread::spawn(move || {
+        let mut byte = [0];
+        if signals.read_exact(&mut byte).is_ok() {
+            eprintln!("\ncancelling (interrupt again to exit)");
+            cancellation.cancel();
+        }
+        if signals.read_exact(&mut byte).is_ok() {
+            std::process::exit(130);
+        }
+    });
+    let handler = interrupted as extern "C" fn(libc::c_int);
+    // SAFETY: the handler only does what's async-signal-safe.
+    if unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) } == libc::SIG_ERR {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn bench(args: &[String]) -> io::Result<()> {
+    let mut options = bench::Options::default();
+    let mut workloads = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(usage);
+        match arg.as_str() {
+            "--width" => options.width = number(value()?)?,
+            "--depth" => options.depth = number(value()?)?,
+            "--blob-size" => options.blob_size = number(value()?)?,
+            "--seed" => options.seed = number(value()?)?,
+            "--store" => options.store = Some(value()?.clone()),
+            "--verify" => options.verify = Some(verification(value()?)?),
+            workload => workloads.push(workload),
+        }
+    }
+    if workloads.is_empty() {
+        workloads = bench::WORKLOADS.to_vec();
+    }
+    bench::run(&workloads, &options, &mut io::stdout().lock())
+}
+
+fn evaluate(
+    repository: &Repository,
+    h: Handle,
+    options: &[String],
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let mut context = Context::default();
+    let (mut depth, mut traced, mut report, mut cross_check) = (None, None, false, false);
+    #[cfg(feature = "async")]
+    let mut on_tokio = false;
+    let (mut chrome, mut workers) = (None, Vec::new());
+    let (mut lease, mut worker_timeout) = (None, None);
+    let mut progress = io::stderr().is_terminal().then_some(Format::Text);
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let mut value = || options.next().ok_or_else(usage);
+        match option.as_str() {
+            "--depth" => depth = Some(number(value()?)?),
+            "--steps" => context.step_budget = Some(number(value()?)?),
+            "--timeout" => context.timeout = Some(Duration::from_millis(number(value()?)?)),
+            "--workers" => {
+                for _ in 0..number::<usize>(value()?)? {
+                    let mut command = Command::new(std::env::current_exe()?);
+                    workers.push(Worker::spawn(command.arg("worker"))?);
+                }
+            }
+            "--worker" => workers.push(connect(value()?)?),
+            "--lease" => lease = Some(Duration::from_millis(number(value()?)?)),
+            "--worker-timeout" => worker_timeout = Some(Duration::from_millis(number(value()?)?)),
+            "--priority" => {
+                context.priority = match value()?.as_str() {
+                    "interactive" => Priority::Interactive,
+                    "normal" => Priority::Normal,
+                    "batch" => Priority::Batch,
+                    _ => return Err(usage()),
+                }
+            }
+            "--prefetch" => {
+                context.prefetch = Arc::new(Arguments {
+                    max_footprint: number(value()?)?,
+                })
+            }
+            "--prefetch-trace" => {
+                let (Handle::Data(Data::Object(Object::Tree(trace)))
+                | Handle::Data(Data::Ref(crate::Ref::Tree(trace)))) = handle(repository, value()?)?
+                else {
+                    return Err(invalid("not a trace"));
+                };
+                context.prefetch = Arc::new(Learned::from_trace(trace)?);
+            }
+            "--read-ahead" => prefetch::set_read_ahead(number(value()?)?),
+            "--trace" => traced = Some(value()?.clone()),
+            "--chrome-trace" => chrome = Some(value()?.clone()),
+            "--metrics" => report = true,
+            "--cross-check" => cross_check = true,
+            #[cfg(feature = "async")]
+            "--async" => on_tokio = true,
+            "--quiet" => progress = None,
+            "--json-progress" => progress = Some(Format::Json),
+            _ => return Err(usage()),
+        }
+    }
+    if lease.is_some_and(|x| x <= remote::PULSE) {
+        return Err(invalid(
+            "a lease must be longer than a worker's heartbeat (1s)",
+        ));
+    }
+    if !workers.is_empty() {
+        let mut coordinator = Coordinator::new(workers);
+        if let Some(lease) = lease {
+            coordinator = coordinator.with_lease(lease);
+        }
+        if let Some(timeout) = worker_timeout {
+            coordinator = coordinator.with_timeout(timeout);
+        }
+        context.offload = Some(Arc::new(coordinator));
+    }
+    #[cfg(unix)]
+    cancel_on_interrupt(context.cancellation.clone())?;
+    if cross_check && depth.is_some() {
+        return Err(invalid("a cross-check evaluates fully, so has no --depth"));
+    }
+    #[cfg(feature = "async")]
+    if on_tokio && (cross_check || depth.is_some()) {
+        return Err(invalid("--async evaluates fully, without a cross-check"));
+    }
+    let trace = Arc::new(Trace::default());
+    if traced.is_some() || chrome.is_some() {
+        context.hooks = trace.clone();
+    }
+    let recording = chrome.is_some().then(|| trace.record_loads());
+    let progress = progress
+        .map(|format| -> io::Result<_> {
+            let total = graph::graph(h)?.len();
+            Ok(Arc::new(Progress::new(
+                format,
+                total,
+                Box::new(io::stderr()),
+            )))
+        })
+        .transpose()?;
+    if let Some(progress) = &progress {
+        context.hooks = Arc::new((context.hooks.clone(), progress.clone()));
+    }
+    let before = metrics::metrics();
+    let result = match depth {
+        None if cross_check => match reference::cross_check(h, &context) {
+            Ok(x) => x.map(|x| x.relax()),
+            Err(difference) => {
+                let (engine, reference) = *difference;
+                writeln!(
+                    out,
+                    "eval:      {}\nreference: {}",
+                    pretty(engine, 1),
+                    pretty(reference, 1)
+                )?;
+                return Err(invalid("the evaluators diverged"));
+            }
+        },
+        #[cfg(feature = "async")]
+        None if on_tokio => tokio::runtime::Builder::new_current_thread()
+            .build()?
+            .block_on(async_eval::eval(h, context.clone()))
+            .map(|x| x.relax()),
+        None => eval(h, &context).map(|x| x.relax()),
+        Some(0) => eval_shallow(h, &context),
+        Some(depth) => eval_to_depth(h, depth, &context),
+    };
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+    drop(recording);
+    if let Some(file) = chrome {
+        let mut file = BufWriter::new(File::create(file)?);
+        trace.chrome(&mut file)?;
+        file.flush()?;
+    }
+    if let Some(label) = traced {
+        repository.set_label(
+            &label,
+            Handle::Data(Data::Object(Object::Tree(trace.tree()?))),
+        )?;
+    }
+    if report {
+        let work = metrics::metrics().since(&before);
+        eprintln!(
+            "{} thinks, {} applies, {} selections, {} traps; {:.0}% memo hits, {} bytes loaded",
+            work.thinks,
+            work.applies,
+            work.selections,
+            work.traps,
+            100.0 * work.hit_ratio(),
+            work.bytes_loaded
+        );
+    }
+    match result {
+        Ok(value) => writeln!(out, "{}", text(value)?),
+        Err(trap) => {
+            writeln!(out, "{}", pretty(Handle::Data(trap), 2))?;
+            Err(io::Error::from(trap))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn handles_print_and_parse_back() {
+        let dir = TempDir::new().unwrap();
+        let repository = Repository::create(dir.path().join("repository")).unwrap();
+        let blob = BlobName::create(b"printed and parsed back".to_vec())
+            .ok()
+            .unwrap();
+        let h = Handle::Data(Data::Object(Object::Blob(blob)));
+        let parsed = handle(&repository, &text(h).unwrap()).unwrap();
+        assert!(PackedHandle::pack(parsed) == PackedHandle::pack(h));
+        repository.set_label("printed", h).unwrap();
+        let labelled = handle(&repository, "printed").unwrap();
+        assert!(PackedHandle::pack(labelled) == PackedHandle::pack(h));
+        assert!(handle(&repository, "neither").is_err());
+    }
+}
@@ -0,0 +1,305 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::hash::{Hash, Hasher};
+
+use crate::packed::PackedHandle;
+use crate::storage::{Key, key};
+use crate::{BlobName, Data, Handle, Ref, Result, TreeName, local, trap};
+
+// Eq Handles as keys, for maps and sets of Values (e.g. dedup, or indexes by content).
+//
+// Handle equality is by content (see PartialEq for BlobName and TreeName), and ignores both
+// accessibility (an Object equals its Ref, at any depth) and where an object is held (a local
+// object equals its canonical copy). So a key is the Handle's normal form: Data lowered to a
+// Ref, with a local Blob named by its hash, and a Tree by the hash of its elements' normal
+// forms. Two eq Handles are equal iff their normal forms are, so they hash and order by it:
+// by its packed bytes, a total order (though not a meaningful one). Finding the normal form
+// of a Tree loads it, and every Tree within it whose Pointer isn't already one.
+//
+// Only eq Handles (Data all of whose elements are Data, at any depth) have one: a Thunk or
+// Encode isn't equal to anything, not even itself.
+#[derive(Copy, Clone)]
+pub(crate) struct EqHandle {
+    #[cfg(test)]
+    handle: Handle,
+    normal: PackedHandle,
+}
+
+impl EqHandle {
+    // Traps if the Handle isn't eq, or a Tree within it can't be loaded.
+    pub(crate) fn new(handle: Handle) -> Result<EqHandle> {
+        let Handle::Data(x) = handle else {
+            return Err(trap::type_error("a Thunk or Encode is not eq"));
+        };
+        if !x.is_eq() {
+            return Err(trap::type_error("Data is not eq"));
+        }
+        let normal = Normalize::default().data(x)?;
+        Ok(EqHandle {
+            #[cfg(test)]
+            handle,
+            normal,
+        })
+    }
+
+    // The Handle, as given (not normalized).
+    #[cfg(test)]
+    pub(crate) fn handle(&self) -> Handle {
+        self.handle
+    }
+}
+
+impl PartialEq for EqHandle {
+    fn eq(&self, other: &Self) -> bool {
+        self.normal == other.normal
+    }
+}
+
+impl Eq for EqHandle {}
+
+impl Hash for EqHandle {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.normal.hash(state);
+    }
+}
+
+impl PartialOrd for EqHandle {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for EqHandle {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.normal.as_bytes().cmp(other.normal.as_bytes())
+    }
+}
+
+// Normal forms of the Trees seen, by Pointer (shared subtrees are only loaded once).
+#[derive(Default)]
+struct Normalize(HashMap<Key, TreeName>);
+
+impl Normalize {
+    fn data(&mut self, x: Data) -> Result<PackedHandle> {
+        let normal = match x.lower() {
+            Ref::Blob(x) => Ref::Blob(self.blob(x)?),
+            Ref::Tree(x) => Ref::Tree(self.tree(x)?),
+        };
+        Ok(PackedHandle::pack(Handle::Data(Data::Ref(normal))))
+    }
+
+    fn blob(&mut self, x: BlobName) -> Result<BlobName> {
+        Ok(match x {
+            BlobName::Name((pointer, _)) if local::is_local(pointer) => {
+                BlobName::name(&x.try_load()?)
+            }
+            x => x,
+        })
+    }
+
+    fn tree(&mut self, x: TreeName) -> Result<TreeName> {
+        if let Some(&normal) = self.0.get(&key(x.name)) {
+            return Ok(normal);
+        }
+        let elements = x
+            .try_load()?
+            .into_iter()
+            .map(|h| match h {
+                Handle::Data(x) => Ok(self.data(x)?.unpack()),
+                _ => unreachable!("eq Tree contains a Thunk or Encode"),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let normal = TreeName {
+            tag: x.tag,
+            ..TreeName::name(&elements)
+        };
+        self.0.insert(key(x.name), normal);
+        Ok(normal)
+    }
+}
+
+// A map with eq Handles as keys (see EqHandle). Inserting a Handle that isn't eq traps; looking
+// one up finds nothing.
+pub(crate) struct HandleMap<V> {
+    entries: HashMap<EqHandle, V>,
+}
+
+impl<V> Default for HandleMap<V> {
+    fn default() -> Self {
+        HandleMap {
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl<V> HandleMap<V> {
+    #[cfg(test)]
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // The value previously held under an equal key, if any (whose key is kept).
+    pub(crate) fn insert(&mut self, key: Handle, value: V) -> Result<Option<V>> {
+        Ok(match self.entries.entry(EqHandle::new(key)?) {
+            Entry::Occupied(mut entry) => Some(std::mem::replace(entry.get_mut(), value)),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+                None
+            }
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn get(&self, key: Handle) -> Option<&V> {
+        self.entries.get(&EqHandle::new(key).ok()?)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn get_mut(&mut self, key: Handle) -> Option<&mut V> {
+        self.entries.get_mut(&EqHandle::new(key).ok()?)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn contains_key(&self, key: Handle) -> bool {
+        self.get(key).is_some()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn remove(&mut self, key: Handle) -> Option<V> {
+        self.entries.remove(&EqHandle::new(key).ok()?)
+    }
+
+    // In no particular order, each under the key it was first inserted with.
+    #[cfg(test)]
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (Handle, &V)> {
+        self.entries.iter().map(|(k, v)| (k.handle(), v))
+    }
+}
+
+// A set of eq Handles (see HandleMap).
+#[derive(Default)]
+pub(crate) struct HandleSet(HandleMap<()>);
+
+impl HandleSet {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // Was no equal Handle already in the set?
+    pub(crate) fn insert(&mut self, h: Handle) -> Result<bool> {
+        Ok(self.0.insert(h, ())?.is_none())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn contains(&self, h: Handle) -> bool {
+        self.0.contains_key(h)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn remove(&mut self, h: Handle) -> bool {
+        self.0.remove(h).is_some()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn iter(&self) -> impl Iterator<Item = Handle> {
+        self.0.iter().map(|(h, _)| h)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Object, Thunk};
+
+    fn object(tree: TreeName) -> Handle {
+        Handle::Data(Data::Object(Object::Tree(tree)))
+    }
+
+    #[test]
+    fn keys_are_equal_up_to_accessibility_and_locality() {
+        let contents = b"a Blob too long to be a Literal".to_vec();
+        let local_blob = Handle::Data(Data::Object(Object::Blob(local::blob(contents.clone()))));
+        let blob = Handle::Data(Data::Object(Object::Blob(
+            BlobName::create(contents).ok().unwrap(),
+        )));
+        let local_tree = local::tree(vec![local_blob]);
+        let tree = TreeName::create(vec![blob]).ok().unwrap();
+
+        let mut map = HandleMap::new();
+        assert!(map.insert(object(local_tree), 1).ok().unwrap().is_none());
+        assert_eq!(map.get(object(tree)), Some(&1));
+        assert_eq!(map.get(Handle::Data(Data::Ref(Ref::Tree(tree)))), Some(&1));
+        assert_eq!(map.insert(object(tree), 2).ok().unwrap(), Some(1));
+        assert_eq!(map.len(), 1);
+        // The key first inserted is kept.
+        let (key, _) = map.iter().next().unwrap();
+        assert!(PackedHandle::pack(key) == PackedHandle::pack(object(local_tree)));
+
+        let tagged = TreeName { tag: true, ..tree };
+        assert!(!map.contains_key(object(tagged)));
+        *map.get_mut(object(tree)).unwrap() += 1;
+        assert_eq!(map.remove(blob), None);
+        assert_eq!(map.remove(object(local_tree)), Some(3));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn only_eq_handles_are_keys() {
+        let blob = Data::Object(Object::Blob(BlobName::literal(b"eq").unwrap()));
+        let thunk = Handle::Thunk(Thunk::Identification(blob));
+        let holding_thunk = object(TreeName::create(vec![thunk]).ok().unwrap());
+        let mut set = HandleSet::new();
+        for h in [thunk, holding_thunk] {
+            let trap = set.insert(h).err().unwrap();
+            assert!(trap::is(trap, trap::Kind::TypeError));
+            assert!(!set.contains(h) && !set.remove(h));
+        }
+        assert!(set.insert(Handle::Data(blob)).ok().unwrap());
+        assert!(
+            !set.insert(Handle::Data(Data::Ref(blob.lower())))
+                .ok()
+                .unwrap()
+        );
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.iter().count(), 1);
+        assert!(set.remove(Handle::Data(blob)) && set.is_empty());
+    }
+
+    #[test]
+    fn keys_order_by_normal_form() {
+        let (a, b) = (
+            EqHandle::new(Handle::Data(Data::Object(Object::Blob(BlobName::from(
+                1_u8,
+            ))))),
+            EqHandle::new(Handle::Data(Data::Ref(Ref::Blob(BlobName::from(1_u8))))),
+        );
+        let c = EqHandle::new(Handle::Data(Data::Object(Object::Blob(BlobName::from(
+            2_u8,
+        )))));
+        let (a, b, c) = (a.ok().unwrap(), b.ok().unwrap(), c.ok().unwrap());
+        assert_eq!(a.cmp(&b), Ordering::Equal);
+        assert_eq!(a.cmp(&c), c.cmp(&a).reverse());
+        assert_ne!(a.cmp(&c), Ordering::Equal);
+    }
+}
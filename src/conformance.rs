@@ -0,0 +1,229 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use std::process::Command;
+
+use crate::packed::PackedHandle;
+use crate::repository::Repository;
+use crate::script::Script;
+use crate::storage::storage;
+use crate::{Context, Data, Handle, HandleType, Object, eval, fetch, local, trap};
+
+// Conformance cases: declarative tests of the Fix semantics, independent of any
+// implementation, each a script (see script) in a `.fix` file that binds
+//   input      the Handle to evaluate
+//   expected   what it evaluates to (compared by canonical Name), or
+//   trap       the kind of trap it evaluates to (a string, e.g. "bad-selection")
+// and optionally
+//   steps      the step budget of each Encode (a number)
+//
+// Cases are self-contained: they name no labels or stored Handles, only what they create.
+
+// A case, as bound by its script.
+struct Case {
+    input: Handle,
+    expected: Option<Handle>,
+    trap: Option<String>,
+    steps: Option<u64>,
+}
+
+fn parse(source: &str) -> Result<Case, String> {
+    let lookup = |_: &str| -> io::Result<Handle> {
+        Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "conformance cases are self-contained",
+        ))
+    };
+    let mut script = Script::new(&lookup);
+    script.run_all(source).map_err(|e| e.to_string())?;
+    Ok(Case {
+        input: script.variable("input").ok_or("no input")?,
+        expected: script.variable("expected"),
+        trap: script.variable("trap").map(string).transpose()?,
+        steps: script.variable("steps").map(number).transpose()?,
+    })
+}
+
+fn evaluate(case: &Case) -> Result<Handle, Data> {
+    let context = Context {
+        step_budget: case.steps,
+        ..Context::default()
+    };
+    eval(case.input, &context).map(HandleType::relax)
+}
+
+// Run one case, returning why it failed, if it did.
+pub(crate) fn check(source: &str) -> Result<(), String> {
+    let case = parse(source)?;
+    match (case.expected, &case.trap, evaluate(&case)) {
+        (Some(expected), None, Ok(actual)) => {
+            let name = |h| local::canonical_name(PackedHandle::pack(h));
+            match name(expected) == name(actual) {
+                true => Ok(()),
+                false => Err("evaluated to something else".to_string()),
+            }
+        }
+        (Some(_), None, Err(trap)) => Err(format!("trapped: {}", describe(trap))),
+        (None, Some(kind), Err(trap)) => match trap::kind(trap).map(trap::Kind::name) {
+            Some(actual) if actual == kind => Ok(()),
+            _ => Err(format!("trapped otherwise: {}", describe(trap))),
+        },
+        (None, Some(_), Ok(_)) => Err("didn't trap".to_string()),
+        _ => Err("a case binds one of expected and trap".to_string()),
+    }
+}
+
+// Differential testing: run a case's input with another implementation of Fix (e.g. the C++
+// runtime) as well, through the repository format, and compare what they evaluate to. The
+// `reference` command is run as
+//   COMMAND REPOSITORY HANDLE [STEPS]
+// with the input's closure stored in a new repository (under `scratch`), and prints what the
+// Handle (in hex) evaluates to: a Handle in hex, or `trap KIND`.
+pub(crate) fn differ(source: &str, reference: &str, scratch: &Path) -> Result<(), String> {
+    let case = parse(source)?;
+    let failed = |e: io::Error| format!("couldn't run the reference: {e}");
+    let input = local::canonicalize(case.input).map_err(failed)?;
+    let repository = Repository::create(scratch).map_err(failed)?;
+    fetch::fetch_into(&repository, &*storage(), input, false, |_| ()).map_err(failed)?;
+    let mut command = Command::new(reference);
+    command.arg(scratch).arg(hex(input));
+    command.args(case.steps.map(|x| x.to_string()));
+    let output = command.output().map_err(failed)?;
+    if !output.status.success() {
+        return Err(format!("the reference failed ({})", output.status));
+    }
+    let theirs = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let ours = match evaluate(&case) {
+        Ok(h) => hex(local::canonical_name(PackedHandle::pack(h)).unpack()),
+        Err(trap) => match trap::kind(trap) {
+            Some(kind) => format!("trap {}", kind.name()),
+            None => "something other than a trap".to_string(),
+        },
+    };
+    match ours == theirs {
+        true => Ok(()),
+        false => Err(format!("diverged: {ours} here, {theirs} in the reference")),
+    }
+}
+
+fn hex(h: Handle) -> String {
+    PackedHandle::pack(h)
+        .as_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn describe(trap: Data) -> String {
+    match (trap::kind(trap), trap::message(trap)) {
+        (Some(kind), Some(message)) => format!("{} ({message})", kind.name()),
+        _ => "something other than a trap".to_string(),
+    }
+}
+
+fn blob(h: Handle) -> Result<crate::BlobName, String> {
+    match h {
+        Handle::Data(Data::Object(Object::Blob(x))) => Ok(x),
+        _ => Err("not a Blob".to_string()),
+    }
+}
+
+fn string(h: Handle) -> Result<String, String> {
+    blob(h)?.as_string().map_err(describe)
+}
+
+fn number(h: Handle) -> Result<u64, String> {
+    blob(h)?.as_u64().map_err(describe)
+}
+
+// Run every case in a directory (in order of file name), returning each one's name and
+// outcome (by `run`, i.e. check or differ).
+pub(crate) fn check_all(
+    dir: &Path,
+    mut run: impl FnMut(&str, &str) -> Result<(), String>,
+) -> io::Result<Vec<(String, Result<(), String>)>> {
+    let mut files: Vec<_> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|x| x.path()))
+        .collect::<io::Result<_>>()?;
+    files.retain(|x| x.extension().is_some_and(|x| x == "fix"));
+    files.sort();
+    files
+        .into_iter()
+        .map(|file| {
+            let name = file.file_stem().unwrap().to_string_lossy().into_owned();
+            let outcome = run(&name, &fs::read_to_string(&file)?);
+            Ok((name, outcome))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_corpus_passes() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("conformance");
+        let outcomes = check_all(&dir, |_, source| check(source)).unwrap();
+        assert!(outcomes.len() >= 8);
+        for (name, outcome) in outcomes {
+            assert_eq!(outcome, Ok(()), "{name}");
+        }
+    }
+
+    #[test]
+    fn failures_say_why() {
+        for (source, why) in [
+            ("x = 1", "no input"),
+            ("input = 1\nexpected = 2", "evaluated to something else"),
+            ("input = 1\ntrap = \"cycle\"", "didn't trap"),
+            ("input = 1", "a case binds one of expected and trap"),
+            (
+                "input = select(tree(), \"/0\")\ntrap = \"cycle\"",
+                "trapped otherwise: bad-selection (selection out of range)",
+            ),
+        ] {
+            assert_eq!(check(source), Err(why.to_string()), "{source}");
+        }
+    }
+
+    // Skipped unless FIXMODEL_REFERENCE names a reference implementation (see differ).
+    #[test]
+    fn the_reference_agrees() {
+        let Ok(reference) = std::env::var("FIXMODEL_REFERENCE") else {
+            return;
+        };
+        let scratch = tempfile::TempDir::new().unwrap();
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("conformance");
+        let outcomes = check_all(&dir, |name, source| {
+            differ(source, &reference, &scratch.path().join(name))
+        })
+        .unwrap();
+        for (name, outcome) in outcomes {
+            assert_eq!(outcome, Ok(()), "{name}");
+        }
+    }
+
+    #[test]
+    fn divergence_is_reported() {
+        let scratch = tempfile::TempDir::new().unwrap();
+        let source = "input = identify(\"stored for a reference that differs\")\nexpected = \"stored for a reference that differs\"";
+        let failed = differ(source, "false", &scratch.path().join("failing")).unwrap_err();
+        assert!(failed.starts_with("the reference failed"), "{failed}");
+        // `echo` prints its arguments, as no implementation would.
+        let diverged = differ(source, "echo", &scratch.path().join("echoing")).unwrap_err();
+        assert!(diverged.starts_with("diverged: "), "{diverged}");
+        let repository = Repository::open(scratch.path().join("echoing")).unwrap();
+        let input = parse(source).unwrap().input;
+        let stored = fetch::fetch_into(
+            &crate::storage::memory::MemoryStorage::default(),
+            &repository,
+            local::canonicalize(input).unwrap(),
+            false,
+            |_| (),
+        );
+        // (The input's closure was stored for the reference.)
+        assert_eq!(stored.unwrap().blobs, 1);
+    }
+}
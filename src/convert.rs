@@ -0,0 +1,290 @@
+use crate::{
+    Data, Encode, Handle, HandleType, Object, Ref, Result, RuntimeValue, Thunk, Value, trap, types,
+};
+
+// Conversions through the lattice of Handle types:
+//
+//   Value ⊂ RuntimeValue ⊂ Handle, and Data, Thunk ⊂ RuntimeValue, Encode ⊂ Handle
+//
+// Widening is From. Narrowing is TryFrom, and fails with a trap (type-error, see trap):
+// a Handle that's an Encode isn't a RuntimeValue, nor is one of the wrong variant its part.
+// Narrowing to a Value also checks that no accessible Tree holds an Encode, at any depth
+// (see types::value, which may load the Trees).
+
+impl From<Value> for Handle {
+    fn from(x: Value) -> Handle {
+        x.relax()
+    }
+}
+
+impl From<Value> for RuntimeValue {
+    fn from(x: Value) -> RuntimeValue {
+        match x.relax() {
+            Handle::Data(x) => RuntimeValue::Data(x),
+            Handle::Thunk(x) => RuntimeValue::Thunk(x),
+            Handle::Encode(_) => unreachable!("Value is an Encode"),
+        }
+    }
+}
+
+impl From<RuntimeValue> for Handle {
+    fn from(x: RuntimeValue) -> Handle {
+        match x {
+            RuntimeValue::Data(x) => Handle::Data(x),
+            RuntimeValue::Thunk(x) => Handle::Thunk(x),
+        }
+    }
+}
+
+impl From<Data> for Handle {
+    fn from(x: Data) -> Handle {
+        Handle::Data(x)
+    }
+}
+
+impl From<Data> for RuntimeValue {
+    fn from(x: Data) -> RuntimeValue {
+        RuntimeValue::Data(x)
+    }
+}
+
+impl From<Data<Value>> for Data {
+    fn from(x: Data<Value>) -> Data {
+        match x {
+            Data::Ref(x) => Data::Ref(x),
+            Data::Object(x) => Data::Object(x.relax()),
+        }
+    }
+}
+
+impl From<Thunk> for Handle {
+    fn from(x: Thunk) -> Handle {
+        Handle::Thunk(x)
+    }
+}
+
+impl From<Thunk> for RuntimeValue {
+    fn from(x: Thunk) -> RuntimeValue {
+        RuntimeValue::Thunk(x)
+    }
+}
+
+impl From<Thunk> for Value {
+    fn from(x: Thunk) -> Value {
+        Value::Thunk(x)
+    }
+}
+
+impl From<Encode> for Handle {
+    fn from(x: Encode) -> Handle {
+        Handle::Encode(x)
+    }
+}
+
+impl From<Object> for Data {
+    fn from(x: Object) -> Data {
+        Data::Object(x)
+    }
+}
+
+impl From<Ref> for Data {
+    fn from(x: Ref) -> Data {
+        Data::Ref(x)
+    }
+}
+
+// Refs are Values whatever they name (their contents aren't accessible).
+impl From<Ref> for Value {
+    fn from(x: Ref) -> Value {
+        Value::Data(Data::Ref(x))
+    }
+}
+
+fn mismatch(message: &str) -> Data {
+    trap::type_error(message)
+}
+
+impl TryFrom<Handle> for RuntimeValue {
+    type Error = Data;
+
+    fn try_from(h: Handle) -> Result<RuntimeValue> {
+        match h {
+            Handle::Data(x) => Ok(RuntimeValue::Data(x)),
+            Handle::Thunk(x) => Ok(RuntimeValue::Thunk(x)),
+            Handle::Encode(_) => Err(mismatch("an Encode is not a RuntimeValue")),
+        }
+    }
+}
+
+impl TryFrom<Handle> for Value {
+    type Error = Data;
+
+    fn try_from(h: Handle) -> Result<Value> {
+        RuntimeValue::try_from(h)?.try_into()
+    }
+}
+
+impl TryFrom<RuntimeValue> for Value {
+    type Error = Data;
+
+    fn try_from(x: RuntimeValue) -> Result<Value> {
+        Ok(match x {
+            RuntimeValue::Thunk(x) => Value::Thunk(x),
+            RuntimeValue::Data(Data::Ref(x)) => Value::Data(Data::Ref(x)),
+            RuntimeValue::Data(Data::Object(Object::Blob(x))) => {
+                Value::Data(Data::Object(Object::Blob(x)))
+            }
+            RuntimeValue::Data(Data::Object(Object::Tree(x))) => {
+                Value::Data(Data::Object(Object::Tree(types::value(x)?)))
+            }
+        })
+    }
+}
+
+impl TryFrom<Handle> for Data {
+    type Error = Data;
+
+    fn try_from(h: Handle) -> Result<Data> {
+        match h {
+            Handle::Data(x) => Ok(x),
+            _ => Err(mismatch("Handle is not Data")),
+        }
+    }
+}
+
+impl TryFrom<Handle> for Thunk {
+    type Error = Data;
+
+    fn try_from(h: Handle) -> Result<Thunk> {
+        match h {
+            Handle::Thunk(x) => Ok(x),
+            _ => Err(mismatch("Handle is not a Thunk")),
+        }
+    }
+}
+
+impl TryFrom<Handle> for Encode {
+    type Error = Data;
+
+    fn try_from(h: Handle) -> Result<Encode> {
+        match h {
+            Handle::Encode(x) => Ok(x),
+            _ => Err(mismatch("Handle is not an Encode")),
+        }
+    }
+}
+
+impl TryFrom<RuntimeValue> for Data {
+    type Error = Data;
+
+    fn try_from(x: RuntimeValue) -> Result<Data> {
+        match x {
+            RuntimeValue::Data(x) => Ok(x),
+            RuntimeValue::Thunk(_) => Err(mismatch("a Thunk is not Data")),
+        }
+    }
+}
+
+impl TryFrom<RuntimeValue> for Thunk {
+    type Error = Data;
+
+    fn try_from(x: RuntimeValue) -> Result<Thunk> {
+        match x {
+            RuntimeValue::Thunk(x) => Ok(x),
+            RuntimeValue::Data(_) => Err(mismatch("Data is not a Thunk")),
+        }
+    }
+}
+
+impl TryFrom<Data> for Object {
+    type Error = Data;
+
+    fn try_from(x: Data) -> Result<Object> {
+        match x {
+            Data::Object(x) => Ok(x),
+            Data::Ref(_) => Err(mismatch("a Ref is not an Object")),
+        }
+    }
+}
+
+impl TryFrom<Data> for Ref {
+    type Error = Data;
+
+    fn try_from(x: Data) -> Result<Ref> {
+        match x {
+            Data::Ref(x) => Ok(x),
+            Data::Object(_) => Err(mismatch("an Object is not a Ref")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packed::PackedHandle;
+    use crate::{BlobName, TreeName};
+
+    fn same(a: impl Into<Handle>, b: impl Into<Handle>) -> bool {
+        PackedHandle::pack(a.into()) == PackedHandle::pack(b.into())
+    }
+
+    fn type_error<T>(x: Result<T>) -> bool {
+        x.err().is_some_and(|x| trap::is(x, trap::Kind::TypeError))
+    }
+
+    #[test]
+    fn narrowing_inverts_widening() {
+        let data = Data::Object(Object::Blob(BlobName::literal(b"lattice").unwrap()));
+        let thunk = Thunk::Identification(data);
+        let encode = Encode {
+            thunk,
+            accessibility: None,
+        };
+        let tree = TreeName::create(vec![Handle::Data(data), Handle::Thunk(thunk)]);
+        let tree = Data::Object(Object::Tree(tree.ok().unwrap()));
+
+        for h in [Handle::Data(data), Handle::Thunk(thunk), Handle::Data(tree)] {
+            let value = Value::try_from(h).ok().unwrap();
+            assert!(same(value, h));
+            assert!(same(RuntimeValue::from(value), h));
+            assert!(same(RuntimeValue::try_from(h).ok().unwrap(), h));
+        }
+        assert!(same(Data::try_from(Handle::Data(data)).ok().unwrap(), data));
+        assert!(same(
+            Thunk::try_from(Handle::Thunk(thunk)).ok().unwrap(),
+            thunk
+        ));
+        assert!(same(
+            Encode::try_from(Handle::Encode(encode)).ok().unwrap(),
+            encode
+        ));
+        let lowered = Data::Ref(data.lower());
+        assert!(same(
+            Data::from(Ref::try_from(lowered).ok().unwrap()),
+            lowered
+        ));
+        assert!(same(Data::from(Object::try_from(data).ok().unwrap()), data));
+    }
+
+    #[test]
+    fn narrowing_to_the_wrong_type_traps() {
+        let data = Data::Object(Object::Blob(BlobName::literal(b"lattice").unwrap()));
+        let thunk = Thunk::Identification(data);
+        let encode = Handle::Encode(Encode {
+            thunk,
+            accessibility: None,
+        });
+        assert!(type_error(RuntimeValue::try_from(encode)));
+        assert!(type_error(Data::try_from(Handle::Thunk(thunk))));
+        assert!(type_error(Thunk::try_from(RuntimeValue::Data(data))));
+        assert!(type_error(Encode::try_from(Handle::Data(data))));
+        assert!(type_error(Ref::try_from(data)));
+        assert!(type_error(Object::try_from(Data::Ref(data.lower()))));
+
+        // Only accessible Trees are searched for Encodes.
+        let holding = |h| Data::Object(Object::Tree(TreeName::create(vec![h]).ok().unwrap()));
+        let nested = holding(Handle::Data(holding(encode)));
+        assert!(type_error(Value::try_from(Handle::Data(nested))));
+        assert!(Value::try_from(Handle::Data(Data::Ref(nested.lower()))).is_ok());
+    }
+}
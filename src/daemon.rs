@@ -0,0 +1,296 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Serving several tenants from one machine: a daemon listening on TCP for coordinators (see
+// remote), each of which names its tenant by the tenant's token, on a line of its own, before
+// its first request. Each tenant has a worker process of its own (started on its first
+// connection, and again if it ends), which serves every connection of the tenant's, and
+// keeps its objects, the results its Encodes produced and its labels in a repository of its
+// own (`tenants/NAME`, in the daemon's --repository directory). So a tenant never sees
+// another's objects, results or labels (nor can it fetch from another's coordinator). Each
+// tenant is held to its quotas: how many connections it may have open at once (and so how
+// many Encodes it may have executing), the step budget each of its Encodes is held to, the
+// fuel all its applies may consume (over its worker's life), and how many bytes of objects
+// its repository may hold. A connection that isn't admitted is closed, and its coordinator
+// executes locally, as it would if the worker had failed.
+//
+// The tenants are listed one per line, as
+//   NAME TOKEN [connections=N] [steps=N] [fuel=N] [storage=BYTES]
+// with `#` starting a comment.
+pub(crate) struct Tenant {
+    name: String,
+    token: String,
+    connections: Option<usize>,
+    steps: Option<u64>,
+    fuel: Option<u64>,
+    storage: Option<u64>,
+    // How many of its connections are open.
+    open: AtomicUsize,
+    // Its worker, and where it listens.
+    worker: Mutex<Option<(Child, SocketAddr)>>,
+}
+
+const TENANTS: &str = "tenants";
+
+// The longest a token may be.
+const MAX_TOKEN: u64 = 1024;
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, message)
+}
+
+pub(crate) fn tenants(text: &str) -> io::Result<Vec<Tenant>> {
+    let mut tenants: Vec<Tenant> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let mut words = line.split_whitespace();
+        let Some(name) = words.next() else {
+            continue;
+        };
+        let error = |message: &str| invalid(format!("tenants, line {}: {message}", i + 1));
+        if !name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"_-".contains(&b))
+        {
+            return Err(error("a name is letters, digits, '_' and '-'"));
+        }
+        if tenants.iter().any(|x| x.name == name) {
+            return Err(error("a name can only be one tenant's"));
+        }
+        let token = words.next().ok_or_else(|| error("no token"))?;
+        if tenants.iter().any(|x| x.token == token) {
+            return Err(error("a token can only name one tenant"));
+        }
+        let mut tenant = Tenant {
+            name: name.to_string(),
+            token: token.to_string(),
+            connections: None,
+            steps: None,
+            fuel: None,
+            storage: None,
+            open: AtomicUsize::new(0),
+            worker: Mutex::new(None),
+        };
+        for quota in words {
+            let (key, value) = quota.split_once('=').ok_or_else(|| error("not a quota"))?;
+            let number = |_| error("not a number");
+            match key {
+                "connections" => tenant.connections = Some(value.parse().map_err(number)?),
+                "steps" => tenant.steps = Some(value.parse().map_err(number)?),
+                "fuel" => tenant.fuel = Some(value.parse().map_err(number)?),
+                "storage" => tenant.storage = Some(value.parse().map_err(number)?),
+                _ => return Err(error("unknown quota")),
+            }
+        }
+        tenants.push(tenant);
+    }
+    Ok(tenants)
+}
+
+impl Tenant {
+    // The options of the worker its connections are handed to.
+    fn worker_options(&self) -> Vec<String> {
+        let mut options = Vec::new();
+        if let Some(steps) = self.steps {
+            options.extend(["--steps".to_string(), steps.to_string()]);
+        }
+        if let Some(fuel) = self.fuel {
+            options.extend(["--fuel".to_string(), fuel.to_string()]);
+        }
+        if let Some(bytes) = self.storage {
+            options.extend(["--storage".to_string(), bytes.to_string()]);
+        }
+        options
+    }
+
+    // Where its worker listens (starting it, unless it's running), with its repository in
+    // `dir`.
+    fn worker(&self, dir: &Path) -> io::Result<SocketAddr> {
+        let mut worker = self.worker.lock().unwrap();
+        if let Some((child, address)) = &mut *worker
+            && child.try_wait()?.is_none()
+        {
+            return Ok(*address);
+        }
+        let mut child = Command::new(std::env::current_exe()?)
+            .arg("--repository")
+            .arg(dir.join(&self.name))
+            .args(["worker", "--listen", "127.0.0.1:0"])
+            .args(["--persistent", "--exit-with-stdin"])
+            .args(self.worker_options())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        // (It says where it listens once it does.)
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap()).read_line(&mut line)?;
+        let Ok(address) = line.trim().parse() else {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::other(format!(
+                "{}'s worker didn't start",
+                self.name
+            )));
+        };
+        // (Replacing one that's ended.)
+        *worker = Some((child, address));
+        Ok(address)
+    }
+}
+
+impl Drop for Tenant {
+    fn drop(&mut self) {
+        if let Some((child, _)) = &mut *self.worker.lock().unwrap() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+// A connection admitted for a tenant, counted among its open ones while it lives.
+struct Admitted(Arc<Tenant>);
+
+impl Drop for Admitted {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Whether two tokens are the same, taking as long whichever bytes differ (so how long it
+// takes says nothing of a token but its length).
+fn same(a: &str, b: &str) -> bool {
+    let difference = a
+        .bytes()
+        .zip(b.bytes())
+        .fold(0, |difference, (a, b)| difference | (a ^ b));
+    std::hint::black_box(difference) == 0 && a.len() == b.len()
+}
+
+// The tenant `token` names, if it has a connection to spare. (Every tenant's token is
+// compared, so how long it takes doesn't say which, if any, it matched.)
+fn admit(tenants: &[Arc<Tenant>], token: &str) -> io::Result<Admitted> {
+    let matched = tenants
+        .iter()
+        .fold(None, |matched, x| match same(&x.token, token) {
+            true => Some(x),
+            false => matched,
+        });
+    let Some(tenant) = matched else {
+        return Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            "unknown tenant",
+        ));
+    };
+    let open = tenant.open.fetch_add(1, Ordering::Relaxed);
+    let admitted = Admitted(tenant.clone());
+    match tenant.connections.is_none_or(|x| open < x) {
+        true => Ok(admitted),
+        false => Err(io::Error::new(
+            ErrorKind::QuotaExceeded,
+            format!("{} has all its connections open", tenant.name),
+        )),
+    }
+}
+
+// Serve each connection to `listener` (each on a thread of its own) with its tenant's worker,
+// keeping the tenants' repositories in `root`'s TENANTS.
+pub(crate) fn listen(listener: TcpListener, root: &Path, tenants: Vec<Tenant>) -> io::Result<()> {
+    let dir: Arc<PathBuf> = Arc::new(root.join(TENANTS));
+    fs::create_dir_all(&*dir)?;
+    let tenants: Arc<[Arc<Tenant>]> = tenants.into_iter().map(Arc::new).collect();
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let (dir, tenants) = (dir.clone(), tenants.clone());
+        // (A connection that fails only ends itself.)
+        thread::spawn(move || serve(stream, &dir, &tenants));
+    }
+    Ok(())
+}
+
+fn serve(stream: TcpStream, dir: &Path, tenants: &[Arc<Tenant>]) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut requests = BufReader::new(stream.try_clone()?);
+    let mut token = String::new();
+    (&mut requests).take(MAX_TOKEN).read_line(&mut token)?;
+    let worker = admit(tenants, token.trim_end_matches('\n'))
+        .and_then(|admitted| Ok((admitted.0.worker(dir)?, admitted)))
+        .and_then(|(address, admitted)| Ok((TcpStream::connect(address)?, admitted)));
+    let (worker, _admitted) = worker.inspect_err(|_| drop(stream.shutdown(Shutdown::Both)))?;
+    worker.set_nodelay(true)?;
+    let mut upstream = worker.try_clone()?;
+    // Until either side ends (the coordinator closing, or the worker failing).
+    let forwarding = thread::spawn(move || forward(&mut requests, &mut upstream));
+    let _ = forward(&mut &worker, &mut &stream);
+    let _ = worker.shutdown(Shutdown::Both);
+    let _ = stream.shutdown(Shutdown::Both);
+    let _ = forwarding.join();
+    Ok(())
+}
+
+// Copy what's read to `to` as it arrives. (Not with io::copy, which on Linux splices between
+// sockets and pipes, and reads nothing where the kernel doesn't support that, as in some
+// sandboxes.)
+fn forward(from: &mut impl Read, to: &mut impl Write) -> io::Result<()> {
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        match from.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(n) => to.write_all(&buffer[..n])?,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_tenants_and_their_quotas() {
+        let tenants = tenants(
+            "# name, token and quotas\n\
+             builds  b-token  connections=2 steps=1000 fuel=50000\n\
+             \n\
+             tests   t-token  storage=65536  # no limit on connections\n",
+        )
+        .unwrap();
+        assert_eq!(tenants.len(), 2);
+        assert_eq!(
+            tenants[0].worker_options(),
+            ["--steps", "1000", "--fuel", "50000"]
+        );
+        assert_eq!(tenants[1].worker_options(), ["--storage", "65536"]);
+        assert!(super::tenants("alone").is_err());
+        assert!(super::tenants("a token\nb token").is_err());
+        assert!(super::tenants("a token\na other").is_err());
+        assert!(super::tenants("../a token").is_err());
+        assert!(super::tenants("a token memory=3").is_err());
+    }
+
+    #[test]
+    fn admits_a_tenant_up_to_its_connections() {
+        let tenants: Vec<Arc<Tenant>> = tenants("a a-token connections=1\nb b-token")
+            .unwrap()
+            .into_iter()
+            .map(Arc::new)
+            .collect();
+        let error = admit(&tenants, "unknown").err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+        assert!(admit(&tenants, "a-tokens").is_err() && admit(&tenants, "a-toke").is_err());
+        let first = admit(&tenants, "a-token").unwrap();
+        assert_eq!(first.0.name, "a");
+        let error = admit(&tenants, "a-token").err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::QuotaExceeded);
+        let others: Vec<_> = (0..3).map(|_| admit(&tenants, "b-token")).collect();
+        assert!(others.iter().all(Result::is_ok));
+        drop(first);
+        assert!(admit(&tenants, "a-token").is_ok());
+    }
+}
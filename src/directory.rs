@@ -0,0 +1,118 @@
+use std::fs::{self, File};
+use std::io::{self, ErrorKind};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use rayon::prelude::*;
+
+use crate::stream::BlobWriter;
+use crate::{BlobName, Data, Handle, Object, Ref, TreeName};
+
+// Putting a directory tree in the Storage, as IPFS directories are imported (see ipfs):
+// a file is a Blob of its contents, and a directory is a Tree of its entries, in order of
+// name, each a Tree of its name (a Blob) and a Ref to what it holds.
+//
+// The entries of each directory are put in parallel, on rayon's pool, and each file is
+// streamed into the Storage as it's read (see BlobWriter), so a process holds at most a
+// few chunks per thread, whatever the size of the files. Anything that isn't a file or a
+// directory (e.g. a symbolic link) fails the put.
+
+// Put the directory at `path` (and everything in it), returning a Ref to its Tree.
+pub(crate) fn put(path: &Path) -> io::Result<Ref> {
+    if !fs::symlink_metadata(path)?.is_dir() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "not a directory"));
+    }
+    entry(path)
+}
+
+fn entry(path: &Path) -> io::Result<Ref> {
+    let kind = fs::symlink_metadata(path)?.file_type();
+    if kind.is_file() {
+        let mut writer = BlobWriter::new();
+        io::copy(&mut File::open(path)?, &mut writer)?;
+        return Ok(Ref::Blob(writer.finish()?));
+    }
+    if !kind.is_dir() {
+        return Err(io::Error::new(
+            ErrorKind::Unsupported,
+            format!("{} is neither a file nor a directory", path.display()),
+        ));
+    }
+    let mut names = fs::read_dir(path)?
+        .map(|x| Ok(x?.file_name()))
+        .collect::<io::Result<Vec<_>>>()?;
+    names.sort_unstable();
+    let entries = names
+        .par_iter()
+        .map(|name| {
+            let child = entry(&path.join(name))?;
+            let name = BlobName::create(name.as_bytes().to_vec())?;
+            let entry = TreeName::create(vec![
+                Handle::Data(Data::Object(Object::Blob(name))),
+                Handle::Data(Data::Ref(child)),
+            ])?;
+            Ok(Handle::Data(Data::Object(Object::Tree(entry))))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(Ref::Tree(TreeName::create(entries)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packed::PackedHandle;
+
+    // A directory of a few files (one large enough to be chunked) and a subdirectory.
+    fn directory() -> tempfile::TempDir {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::write(dir.path().join("small"), b"a small file").unwrap();
+        let large: Vec<u8> = (0..3_000_000u32).map(|i| (i * 7 / 3) as u8).collect();
+        fs::write(dir.path().join("large"), large).unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/nested"), b"put from a directory").unwrap();
+        fs::create_dir(dir.path().join("sub/empty")).unwrap();
+        dir
+    }
+
+    fn elements(x: Ref) -> Vec<Handle> {
+        let Ref::Tree(x) = x else {
+            panic!("not a Tree")
+        };
+        x.try_load().ok().unwrap()
+    }
+
+    // The name and Ref of a directory entry.
+    fn entry(h: Handle) -> (Vec<u8>, Ref) {
+        let Handle::Data(Data::Object(Object::Tree(x))) = h else {
+            panic!("not an entry")
+        };
+        match &x.try_load().ok().unwrap()[..] {
+            [
+                Handle::Data(Data::Object(Object::Blob(name))),
+                Handle::Data(Data::Ref(x)),
+            ] => (name.try_load().ok().unwrap().to_vec(), *x),
+            _ => panic!("not an entry"),
+        }
+    }
+
+    #[test]
+    fn a_directory_is_a_tree_of_named_entries() {
+        let dir = directory();
+        let root = put(dir.path()).unwrap();
+        let entries: Vec<_> = elements(root).into_iter().map(entry).collect();
+        let names: Vec<_> = entries.iter().map(|(name, _)| &name[..]).collect();
+        assert_eq!(names, [&b"large"[..], b"small", b"sub"]);
+        let Ref::Blob(large) = entries[0].1 else {
+            panic!("not a Blob")
+        };
+        assert_eq!(large.size(), 3_000_000);
+        let sub: Vec<_> = elements(entries[2].1).into_iter().map(entry).collect();
+        assert_eq!(sub[0].0, b"empty");
+        assert!(elements(sub[0].1).is_empty());
+        // Putting it again (in whatever order the threads finish) gives the same Tree.
+        let again = put(dir.path()).unwrap();
+        let name = |x| PackedHandle::pack(Handle::Data(Data::Ref(x)));
+        assert!(name(again) == name(root));
+        assert!(put(&dir.path().join("small")).is_err());
+    }
+}
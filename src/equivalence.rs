@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, LazyLock, RwLock};
+
+use crate::packed::PackedHandle;
+use crate::repository::Repository;
+use crate::{Handle, Thunk, TreeName, local, memo};
+
+// Blessed equivalences: an administrator can assert that two Names are interchangeable
+// (e.g. a slow reference implementation of a procedure and a fast one), so a result
+// computed under one satisfies requests for the other.
+//
+// Equated Names form classes, each represented by its least Name (by packed bytes, so the
+// representative doesn't depend on the order things were equated in). The memo table keys
+// Thunks by their representative Thunk (see memo::name): the Thunk's own class, after
+// replacing each element of an application's combination (or a selection's spec) by its
+// representative. (The substituted Tree is only named, not stored: the memo table keeps each
+// Thunk as well, to substitute again when more Names are equated.) So equating two procedures
+// makes every application of one equivalent to the same application of the other,
+// including those already remembered.
+//
+// Only the combination's own elements are substituted, not Names nested deeper within them:
+// equating two procedures doesn't make a combination whose element is a Tree holding one
+// equivalent to the same with the other. (Equate the Trees themselves for that.) This keeps
+// finding a representative to loading the one Tree.
+//
+// Nothing checks an equivalence: equating Names that aren't equivalent makes evaluations
+// return wrong results. And unlike the memo table, equivalences survive garbage collection.
+//
+// The table can be persisted in a Repository (as the memo table can), so equivalences
+// outlive the process: each is recorded there as it's asserted, and the next process to
+// persist the table in the same Repository asserts them again.
+#[derive(Default)]
+struct Classes {
+    // The representative of every equated Name (other than representatives themselves).
+    representatives: HashMap<PackedHandle, PackedHandle>,
+    // The other members of each class, by representative.
+    members: HashMap<PackedHandle, Vec<PackedHandle>>,
+}
+
+static EQUIVALENCES: LazyLock<RwLock<Classes>> = LazyLock::new(RwLock::default);
+
+static PERSISTENT: RwLock<Option<Arc<Repository>>> = RwLock::new(None);
+
+impl Classes {
+    fn representative(&self, h: PackedHandle) -> PackedHandle {
+        self.representatives.get(&h).copied().unwrap_or(h)
+    }
+
+    // Merge the classes of two canonical Names.
+    fn merge(&mut self, a: PackedHandle, b: PackedHandle) {
+        let (a, b) = (self.representative(a), self.representative(b));
+        if a == b {
+            return;
+        }
+        let (keep, merge) = if a.as_bytes() < b.as_bytes() {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        let mut merged = self.members.remove(&merge).unwrap_or_default();
+        merged.push(merge);
+        for &member in &merged {
+            self.representatives.insert(member, keep);
+        }
+        self.members.entry(keep).or_default().extend(merged);
+    }
+}
+
+// Persist the table in a Repository: assert every equivalence recorded there, and record
+// every one asserted from now on. (Before the memo table is persisted, so the results it
+// loads are keyed by them.)
+pub(crate) fn persist(repository: Arc<Repository>) -> io::Result<()> {
+    let equated = repository.equated()?;
+    let mut classes = EQUIVALENCES.write().unwrap();
+    for (a, b) in equated {
+        classes.merge(a, b);
+    }
+    drop(classes);
+    memo::rekey();
+    *PERSISTENT.write().unwrap() = Some(repository);
+    Ok(())
+}
+
+// Assert that two Handles are equivalent (and so is everything already equated with either).
+// Fails if the table is persisted and the equivalence can't be recorded.
+pub(crate) fn equate(a: Handle, b: Handle) -> io::Result<()> {
+    let (a, b) = (canonical(a), canonical(b));
+    if let Some(repository) = &*PERSISTENT.read().unwrap() {
+        repository.equate(a, b)?;
+    }
+    EQUIVALENCES.write().unwrap().merge(a, b);
+    memo::rekey();
+    Ok(())
+}
+
+// Have two Handles been equated (directly, or through others)?
+pub(crate) fn equivalent(a: Handle, b: Handle) -> bool {
+    let classes = EQUIVALENCES.read().unwrap();
+    classes.representative(canonical(a)) == classes.representative(canonical(b))
+}
+
+// Forget every equivalence, here and in the persisted table (and the memo table, which is
+// keyed by them).
+pub(crate) fn clear() -> io::Result<()> {
+    let mut classes = EQUIVALENCES.write().unwrap();
+    classes.representatives.clear();
+    classes.members.clear();
+    drop(classes);
+    if let Some(repository) = &*PERSISTENT.read().unwrap() {
+        repository.forget_equivalences()?;
+    }
+    memo::clear()
+}
+
+fn canonical(h: Handle) -> PackedHandle {
+    local::canonical_name(PackedHandle::pack(h))
+}
+
+// The representative of a Thunk (given its Name, which may name local objects), as described
+// above. Nothing is stored (only the Thunk's Tree is loaded, if anything has been equated).
+pub(crate) fn representative(thunk: PackedHandle) -> PackedHandle {
+    let name = local::canonical_name(thunk);
+    let classes = EQUIVALENCES.read().unwrap();
+    if classes.representatives.is_empty() {
+        return name;
+    }
+    let substituted = |tree: TreeName| -> Option<TreeName> {
+        let elements: Vec<_> = tree.try_load().ok()?.into_iter().map(canonical).collect();
+        let substituted: Vec<_> = elements
+            .iter()
+            .map(|&h| classes.representative(h))
+            .collect();
+        if substituted == elements {
+            return None;
+        }
+        let substituted: Vec<Handle> = substituted.iter().map(PackedHandle::unpack).collect();
+        Some(TreeName {
+            tag: tree.tag,
+            ..TreeName::name(&substituted)
+        })
+    };
+    let substituted = match thunk.unpack() {
+        Handle::Thunk(Thunk::Application(x)) => substituted(x).map(Thunk::Application),
+        Handle::Thunk(Thunk::Selection(x)) => substituted(x).map(Thunk::Selection),
+        _ => None,
+    };
+    let name = substituted.map_or(name, |x| PackedHandle::pack(Handle::Thunk(x)));
+    classes.representative(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::storage;
+    use crate::{BlobName, Data, Object};
+
+    fn blob(contents: &[u8]) -> Handle {
+        Handle::Data(Data::Object(Object::Blob(
+            BlobName::create(contents.to_vec()).ok().unwrap(),
+        )))
+    }
+
+    fn application(elements: Vec<Handle>) -> Thunk {
+        Thunk::Application(TreeName::create(elements).ok().unwrap())
+    }
+
+    #[test]
+    fn equated_procedures_share_results() {
+        let (slow, fast) = (blob(&[1; 64]), blob(&[2; 64]));
+        let argument = blob(b"argument");
+        let result = Data::Object(Object::Blob(BlobName::literal(b"result").unwrap()));
+        memo::put(&[application(vec![slow, argument])], result).unwrap();
+        assert!(memo::get(application(vec![fast, argument])).is_none());
+
+        equate(slow, fast).unwrap();
+        assert!(equivalent(fast, slow));
+        let found = memo::get(application(vec![fast, argument]));
+        assert!(found.is_some_and(|x| x == result));
+
+        // A combination with the other procedure is substituted, but only named, not stored.
+        let other = match canonical(slow).as_bytes() < canonical(fast).as_bytes() {
+            true => fast,
+            false => slow,
+        };
+        let thunk = application(vec![other, blob(b"another argument")]);
+        let name = memo::name(thunk);
+        assert!(name != canonical(Handle::Thunk(thunk)));
+        assert!(!storage().contains_tree(name.key().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn nested_names_are_not_substituted() {
+        let (slow, fast) = (blob(&[3; 64]), blob(&[4; 64]));
+        equate(slow, fast).unwrap();
+        let nested = |procedure| {
+            let tree = TreeName::create(vec![procedure]).ok().unwrap();
+            application(vec![Handle::Data(Data::Object(Object::Tree(tree)))])
+        };
+        let result = Data::Object(Object::Blob(BlobName::literal(b"nested").unwrap()));
+        memo::put(&[nested(slow)], result).unwrap();
+        assert!(memo::get(nested(fast)).is_none());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::generate::{Shape, build};
+use crate::packed::PackedHandle;
+use crate::path::Path;
+use crate::{Context, Data, Encode, Handle, HandleType, Object, Value, local, reference};
+
+// A bounded exhaustive check of the model's core claims: every object graph up to a small
+// bound (see shapes) is built and evaluated, and each must satisfy
+//
+//   confluence    eval gives the same outcome (Value or trap) the first time, again (from
+//                 the memo table), and with the reference evaluator (which has no memo)
+//   idempotence   evaluating what eval gave gives it back
+//   commutation   each element of an evaluated Tree is its element, evaluated, and is what
+//                 a Selection of it from the evaluated Tree executes to
+//
+// Random graphs (see generate, and the laws) go further; this covers every small one.
+
+// The Shapes at most `depth` deep: Trees and Applications of up to two elements, Selections
+// of the first two elements, and Encodes of each accessibility, down to three leaves (two
+// Literals and a named Blob).
+fn shapes(depth: usize) -> Vec<Shape> {
+    let leaves = vec![
+        Shape::Blob(vec![]),
+        Shape::Blob(b"fix".to_vec()),
+        Shape::Blob(vec![7; 31]),
+    ];
+    if depth == 0 {
+        return leaves;
+    }
+    let below = shapes(depth - 1);
+    let mut shapes = leaves;
+    let mut sequences = vec![vec![]];
+    for x in &below {
+        sequences.push(vec![x.clone()]);
+        for y in &below {
+            sequences.push(vec![x.clone(), y.clone()]);
+        }
+    }
+    for x in &below {
+        let boxed = || Box::new(x.clone());
+        shapes.push(Shape::Ref(boxed()));
+        shapes.push(Shape::Identification(boxed()));
+        shapes.extend((0..2).map(|i| Shape::Selection(boxed(), i)));
+        shapes.extend([None, Some(true), Some(false)].map(|a| Shape::Encode(boxed(), a)));
+    }
+    for x in sequences {
+        shapes.push(Shape::Tree(x.clone(), false));
+        shapes.push(Shape::Application(x));
+    }
+    shapes
+}
+
+fn outcome(x: crate::Result<Value>) -> PackedHandle {
+    let h = x.map_or_else(Handle::Data, HandleType::relax);
+    local::canonical_name(PackedHandle::pack(h))
+}
+
+fn check(shape: &Shape) -> Result<(), String> {
+    let context = Context::default();
+    let h = build(shape);
+    let first = crate::eval(h, &context);
+    let expected = outcome(first);
+    if outcome(crate::eval(h, &context)) != expected {
+        return Err("eval differs when memoized".into());
+    }
+    if outcome(reference::eval(h, &context)) != expected {
+        return Err("eval differs from the reference evaluator".into());
+    }
+    let Ok(result) = first else {
+        return Ok(());
+    };
+    let result = result.relax();
+    if outcome(crate::eval(result, &context)) != expected {
+        return Err("evaluating the result changes it".into());
+    }
+    let (
+        Handle::Data(Data::Object(Object::Tree(tree))),
+        Handle::Data(Data::Object(Object::Tree(evaluated))),
+    ) = (h, result)
+    else {
+        return Ok(());
+    };
+    let elements = tree.try_load().ok().unwrap();
+    let values = evaluated.try_load().ok().unwrap();
+    for (i, (element, value)) in elements.into_iter().zip(values).enumerate() {
+        let name = |h| local::canonical_name(PackedHandle::pack(h));
+        if outcome(crate::eval(element, &context)) != name(value) {
+            return Err(format!(
+                "element {i} of the result isn't the element evaluated"
+            ));
+        }
+        let Handle::Data(data) = value else {
+            continue;
+        };
+        let selection = Path::new()
+            .index(i as u64)
+            .thunk(Data::Object(Object::Tree(evaluated)))
+            .ok()
+            .unwrap();
+        let selected = crate::eval(
+            Handle::Encode(Encode {
+                thunk: selection,
+                accessibility: None,
+            }),
+            &context,
+        );
+        if outcome(selected) != name(Handle::Data(data)) {
+            return Err(format!(
+                "selecting element {i} of the result doesn't give it"
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn every_small_graph_behaves() {
+    let shapes = shapes(2);
+    assert!(shapes.len() > 5000);
+    for shape in &shapes {
+        if let Err(e) = check(shape) {
+            panic!("{e}: {shape:?}");
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+
+use crate::hash::{hash_blob, hash_tree};
+use crate::packed::PackedHandle;
+use crate::repository::{hex, pack_tree};
+use crate::storage::{Key, Storage, key, storage};
+use crate::{Handle, Tree, chunk, local};
+
+// Fetching a Handle's closure from a remote Storage (e.g. another Repository) into the
+// process-wide one. The remote isn't trusted: every object is checked against its key, and
+// every Handle in a Tree must be canonical, before anything is stored. Objects already
+// stored are not fetched again (though a stored Tree's elements are still followed, as an
+// earlier shallow fetch may have left them behind).
+//
+// An object that fails its check is a Mismatch: the fetch fails with it (an InvalidData
+// error carrying it), and it's quarantined in the Storage fetched into (see
+// Storage::quarantine), never stored. Every path that takes objects from elsewhere (S3,
+// archives and so workers' answers, IPFS gateways) checks them the same way.
+//
+// A shallow fetch stops at the Handle's own object: for a Tree, its elements' Names, but
+// not their objects.
+//
+// On the command line, a remote is named: a repository's remotes are kept in its config
+// (see Repository::set_remote), each the location of another Repository, a sled database or
+// an S3 bucket.
+
+// What a fetch has done so far.
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct Fetched {
+    pub(crate) blobs: usize,
+    pub(crate) trees: usize,
+    // The Blob contents and Tree elements (packed) fetched.
+    pub(crate) bytes: u64,
+    // Objects reached that were already stored.
+    pub(crate) present: usize,
+}
+
+// What was received that doesn't match what was asked for.
+#[derive(Debug)]
+pub(crate) struct Mismatch {
+    pub(crate) requested: Requested,
+    pub(crate) problem: Problem,
+    // What was received (a Tree's elements packed, as in a Repository).
+    pub(crate) contents: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Requested {
+    Blob(Key),
+    Tree(Key),
+    // An IPFS block, by CID.
+    #[cfg_attr(
+        not(feature = "ipfs"),
+        allow(dead_code, reason = "only IPFS imports request blocks")
+    )]
+    Block(String),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Problem {
+    // Its hash isn't the one asked for.
+    Hash,
+    // The element at this index isn't a canonical Handle (or is local).
+    MalformedElement(usize),
+}
+
+impl Mismatch {
+    // The Mismatch an error carries, if it's one.
+    pub(crate) fn of(e: &io::Error) -> Option<&Mismatch> {
+        e.get_ref()?.downcast_ref()
+    }
+
+    // A file name for what was received (unique to what was asked for).
+    pub(crate) fn file_name(&self) -> String {
+        match &self.requested {
+            Requested::Blob(name) => format!("blob-{}", hex(*name)),
+            Requested::Tree(name) => format!("tree-{}", hex(*name)),
+            Requested::Block(cid) => format!("block-{cid}"),
+        }
+    }
+}
+
+impl From<Mismatch> for io::Error {
+    fn from(mismatch: Mismatch) -> Self {
+        io::Error::new(ErrorKind::InvalidData, mismatch)
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (requested, pointer) = match &self.requested {
+            Requested::Blob(name) => (format!("Blob {}", hex(*name)), "Pointer"),
+            Requested::Tree(name) => (format!("Tree {}", hex(*name)), "Pointer"),
+            Requested::Block(cid) => (format!("block {cid}"), "CID"),
+        };
+        match self.problem {
+            Problem::Hash => write!(f, "received {requested} does not match its {pointer}"),
+            Problem::MalformedElement(index) => {
+                write!(f, "received {requested} has a malformed Handle at {index}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+// Check a Blob received from elsewhere against the key it was asked for by.
+pub(crate) fn check_blob(name: Key, blob: &[u8]) -> io::Result<()> {
+    if key(hash_blob(blob)) != name {
+        return Err(Mismatch {
+            requested: Requested::Blob(name),
+            problem: Problem::Hash,
+            contents: blob.to_vec(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+// Check a Tree received from elsewhere against the key it was asked for by, and that its
+// elements are canonical (and not local) Handles.
+pub(crate) fn check_tree(name: Key, tree: &Tree<PackedHandle>) -> io::Result<()> {
+    let mismatch = |problem| Mismatch {
+        requested: Requested::Tree(name),
+        problem,
+        contents: pack_tree(tree),
+    };
+    if key(hash_tree::<()>(tree)) != name {
+        return Err(mismatch(Problem::Hash).into());
+    }
+    for (index, h) in tree.iter().enumerate() {
+        let is_local = h.key().and_then(local::local_id).is_some();
+        if !h.is_canonical() || is_local || h.try_unpack().is_none() {
+            return Err(mismatch(Problem::MalformedElement(index)).into());
+        }
+    }
+    Ok(())
+}
+
+// Quarantine the Mismatch a result failed with, if it did (in `storage`).
+pub(crate) fn quarantining<T>(storage: &dyn Storage, result: io::Result<T>) -> io::Result<T> {
+    result.map_err(|e| {
+        let quarantined = Mismatch::of(&e).map_or(Ok(()), |x| storage.quarantine(x));
+        quarantined.err().unwrap_or(e)
+    })
+}
+
+// Fetch `h` (and, unless shallow, everything reachable from it), reporting progress after
+// each object reached.
+pub(crate) fn fetch(
+    remote: &dyn Storage,
+    h: Handle,
+    shallow: bool,
+    progress: impl FnMut(&Fetched),
+) -> io::Result<Fetched> {
+    fetch_into(&*storage(), remote, h, shallow, progress)
+}
+
+pub(crate) fn fetch_into(
+    storage: &dyn Storage,
+    remote: &dyn Storage,
+    h: Handle,
+    shallow: bool,
+    progress: impl FnMut(&Fetched),
+) -> io::Result<Fetched> {
+    let fetched = fetch_all(storage, remote, h, shallow, progress);
+    quarantining(storage, fetched)
+}
+
+fn fetch_all(
+    storage: &dyn Storage,
+    remote: &dyn Storage,
+    h: Handle,
+    shallow: bool,
+    mut progress: impl FnMut(&Fetched),
+) -> io::Result<Fetched> {
+    let root = PackedHandle::pack(h);
+    if root.key().and_then(local::local_id).is_some() {
+        return Err(invalid("a local Handle can't be fetched"));
+    }
+    let mut fetched = Fetched::default();
+    let mut seen = HashSet::new();
+    let mut work = vec![root];
+    while let Some(h) = work.pop() {
+        let Some(name) = h.key() else {
+            continue;
+        };
+        let tree = chunk::stored_as_tree(&h);
+        if !seen.insert((tree, name)) {
+            continue;
+        }
+        if tree {
+            let elements = match storage.get_tree(name)? {
+                Some(elements) => {
+                    fetched.present += 1;
+                    elements
+                }
+                None => {
+                    let elements = fetch_tree(remote, name)?;
+                    storage.put_tree(name, elements.clone())?;
+                    fetched.trees += 1;
+                    fetched.bytes += (elements.len() * crate::HANDLE_SIZE) as u64;
+                    elements
+                }
+            };
+            // A chunked Blob's chunks are part of the Blob, so a shallow fetch gets them too.
+            if !shallow || !h.is_tree() {
+                work.extend(elements.iter().copied());
+            }
+        } else if storage.contains_blob(name)? {
+            fetched.present += 1;
+        } else {
+            let blob = remote.get_blob(name)?.ok_or_else(missing)?;
+            check_blob(name, &blob)?;
+            fetched.bytes += blob.len() as u64;
+            storage.put_blob(name, blob)?;
+            fetched.blobs += 1;
+        }
+        progress(&fetched);
+    }
+    storage.flush()?;
+    Ok(fetched)
+}
+
+fn fetch_tree(remote: &dyn Storage, name: Key) -> io::Result<Arc<Tree<PackedHandle>>> {
+    let elements = remote.get_tree(name)?.ok_or_else(missing)?;
+    check_tree(name, &elements)?;
+    Ok(elements)
+}
+
+fn missing() -> io::Error {
+    io::Error::new(ErrorKind::NotFound, "object missing from the remote")
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+    use crate::{BlobName, Data, Object, Ref, TreeName};
+
+    fn blob(contents: &[u8]) -> Handle {
+        Handle::Data(Data::Object(Object::Blob(
+            BlobName::create(contents.to_vec()).ok().unwrap(),
+        )))
+    }
+
+    // A Tree of a Blob and a Tree of another Blob, on a remote of its own.
+    fn remote() -> (MemoryStorage, Handle) {
+        let inner = TreeName::create(vec![blob(&[2; 100])]).ok().unwrap();
+        let inner = Handle::Data(Data::Ref(Ref::Tree(inner)));
+        let root = TreeName::create(vec![blob(&[1; 100]), inner]).ok().unwrap();
+        let root = Handle::Data(Data::Ref(Ref::Tree(root)));
+        let remote = MemoryStorage::default();
+        fetch_into(&remote, &*storage(), root, false, |_| {}).unwrap();
+        (remote, root)
+    }
+
+    #[test]
+    fn fetches_the_closure_once() {
+        let (remote, root) = remote();
+        let local = MemoryStorage::default();
+        let shallow = fetch_into(&local, &remote, root, true, |_| {}).unwrap();
+        assert_eq!((shallow.trees, shallow.blobs, shallow.present), (1, 0, 0));
+        let mut reports = 0;
+        let deep = fetch_into(&local, &remote, root, false, |_| reports += 1).unwrap();
+        assert_eq!((deep.trees, deep.blobs, deep.present), (1, 2, 1));
+        assert_eq!(deep.bytes, 200 + 32);
+        assert_eq!(reports, 4);
+        assert_eq!(local.len(), remote.len());
+    }
+
+    #[test]
+    fn corrupt_objects_are_not_stored() {
+        let (_, root) = remote();
+        // A remote whose copy of the first Blob is corrupt (fetching into it skips the Blob).
+        let name = key(hash_blob(&[1; 100]));
+        let corrupt = MemoryStorage::default();
+        corrupt.put_blob(name, vec![3; 100].into()).unwrap();
+        fetch_into(&corrupt, &*storage(), root, false, |_| {}).unwrap();
+        let local = MemoryStorage::default();
+        let error = fetch_into(&local, &corrupt, root, false, |_| {})
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let mismatch = Mismatch::of(&error).unwrap();
+        assert_eq!(mismatch.requested, Requested::Blob(name));
+        assert_eq!(mismatch.problem, Problem::Hash);
+        assert!(!local.contains_blob(name).unwrap());
+    }
+
+    #[test]
+    fn mismatches_are_quarantined() {
+        let (_, root) = remote();
+        // A remote whose copy of the root's Tree has a malformed element.
+        let Some(name) = PackedHandle::pack(root).key() else {
+            unreachable!()
+        };
+        let mut elements = storage().get_tree(name).unwrap().unwrap().to_vec();
+        elements[0] = PackedHandle::from_bytes([0xff; crate::HANDLE_SIZE]);
+        let corrupt = MemoryStorage::default();
+        corrupt.put_tree(name, elements.clone().into()).unwrap();
+        let dir = tempfile::TempDir::new().unwrap();
+        let local = crate::repository::Repository::create(dir.path().join("local")).unwrap();
+        let error = fetch_into(&local, &corrupt, root, false, |_| {})
+            .err()
+            .unwrap();
+        let mismatch = Mismatch::of(&error).unwrap();
+        assert_eq!(mismatch.problem, Problem::Hash);
+        assert!(!local.contains_tree(name).unwrap());
+        let quarantined = dir
+            .path()
+            .join("local/quarantine")
+            .join(mismatch.file_name());
+        assert!(std::fs::read(quarantined).unwrap() == pack_tree(&elements));
+
+        // A Tree that matches its Pointer, but holds a malformed Handle, is quarantined too.
+        let tree: Arc<Tree<PackedHandle>> = elements.into();
+        let name = key(hash_tree::<()>(&tree));
+        corrupt.put_tree(name, tree).unwrap();
+        let error = fetch_tree(&corrupt, name).err().unwrap();
+        assert_eq!(
+            Mismatch::of(&error).unwrap().problem,
+            Problem::MalformedElement(0)
+        );
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::HashSet;
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+
+use crate::hash::{hash_blob, hash_tree};
+use crate::packed::PackedHandle;
+use crate::storage::{Key, Storage, key, storage};
+use crate::{Handle, Tree, TreeName, chunk, local};
+
+// Integrity checking of the process-wide Storage: every object is re-hashed, every Tree
+// element must be a valid, canonical Handle, and every Name's recorded metadata (a Blob's
+// size, a Tree's size, footprint and eq) must match the object it names.
+//
+// Problems are collected rather than returned as errors, so one check reports all of them.
+// Only I/O failures (other than undecodable objects, which are Corrupt) end a check early.
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum Kind {
+    Blob,
+    Tree,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Problem {
+    // An object is named, but not stored.
+    Missing(Kind, Key),
+    // An object's contents don't match its key (or can't be decoded).
+    Corrupt(Kind, Key),
+    // A Tree element is not a valid Handle (or not in canonical form, or names a local object).
+    MalformedElement {
+        tree: Key,
+        index: usize,
+    },
+    // A Name's recorded metadata doesn't match the object it names.
+    Metadata {
+        kind: Kind,
+        name: Key,
+        field: &'static str,
+    },
+}
+
+struct Checker<'a> {
+    storage: &'a dyn Storage,
+    problems: Vec<Problem>,
+    // The objects already re-hashed, and the Names already checked against them.
+    objects: HashSet<(Kind, Key)>,
+    names: HashSet<PackedHandle>,
+    work: Vec<PackedHandle>,
+}
+
+// Check every stored object, and every Name in every stored Tree.
+pub(crate) fn check_all() -> io::Result<Vec<Problem>> {
+    check_all_in(&*storage())
+}
+
+pub(crate) fn check_all_in(storage: &dyn Storage) -> io::Result<Vec<Problem>> {
+    let mut checker = Checker::new(storage);
+    for name in storage.list_blobs()? {
+        checker.blob(name)?;
+    }
+    for name in storage.list_trees()? {
+        checker.tree(name)?;
+    }
+    checker.run()?;
+    Ok(checker.problems)
+}
+
+// Check the closure of a Handle (canonicalizing any local objects first).
+pub(crate) fn check(h: Handle) -> io::Result<Vec<Problem>> {
+    let storage = storage();
+    let mut checker = Checker::new(&*storage);
+    checker.work.push(local::canonical(PackedHandle::pack(h))?);
+    checker.run()?;
+    Ok(checker.problems)
+}
+
+impl<'a> Checker<'a> {
+    fn new(storage: &'a dyn Storage) -> Self {
+        Checker {
+            storage,
+            problems: Vec::new(),
+            objects: HashSet::new(),
+            names: HashSet::new(),
+            work: Vec::new(),
+        }
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(h) = self.work.pop() {
+            self.name(h)?;
+        }
+        Ok(())
+    }
+
+    // Check a Name against the object it names (and, through it, everything below).
+    fn name(&mut self, h: PackedHandle) -> io::Result<()> {
+        let Some(name) = h.key() else {
+            return Ok(());
+        };
+        if !self.names.insert(h) {
+            return Ok(());
+        }
+        let metadata = |field| Problem::Metadata {
+            kind: if h.is_tree() { Kind::Tree } else { Kind::Blob },
+            name,
+            field,
+        };
+        if let Some((size, footprint, eq)) = h.tree_metadata() {
+            let Some(tree) = self.tree(name)? else {
+                return Ok(());
+            };
+            // (If any element is malformed, that's reported, and the metadata can't be checked.)
+            let Some(tree) = tree
+                .iter()
+                .map(|h| h.try_unpack())
+                .collect::<Option<Vec<_>>>()
+            else {
+                return Ok(());
+            };
+            let (actual_size, actual_footprint, actual_eq) = TreeName::metadata(&tree);
+            if actual_size != size {
+                self.problems.push(metadata("size"));
+            }
+            if actual_footprint != footprint {
+                self.problems.push(metadata("footprint"));
+            }
+            if actual_eq != eq {
+                self.problems.push(metadata("eq"));
+            }
+        } else if chunk::is_chunked(h.size()) {
+            let Some(list) = self.tree(name)? else {
+                return Ok(());
+            };
+            match chunk::parse_list(&list, h.size()) {
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    self.problems.push(metadata("chunk list"))
+                }
+                Err(e) => return Err(e),
+            }
+        } else if let Some(blob) = self.blob(name)?
+            && blob != h.size()
+        {
+            self.problems.push(metadata("size"));
+        }
+        Ok(())
+    }
+
+    // Re-hash a Blob (once), returning its size if it is stored and intact.
+    fn blob(&mut self, name: Key) -> io::Result<Option<usize>> {
+        let first = self.objects.insert((Kind::Blob, name));
+        let blob = match self.storage.get_blob(name) {
+            Ok(Some(blob)) => blob,
+            Ok(None) => {
+                if first {
+                    self.problems.push(Problem::Missing(Kind::Blob, name));
+                }
+                return Ok(None);
+            }
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                if first {
+                    self.problems.push(Problem::Corrupt(Kind::Blob, name));
+                }
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        if key(hash_blob(&blob)) != name {
+            if first {
+                self.problems.push(Problem::Corrupt(Kind::Blob, name));
+            }
+            return Ok(None);
+        }
+        Ok(Some(blob.len()))
+    }
+
+    // Re-hash a Tree and check its elements (once), returning it if it is stored and intact.
+    fn tree(&mut self, name: Key) -> io::Result<Option<Arc<Tree<PackedHandle>>>> {
+        let first = self.objects.insert((Kind::Tree, name));
+        let tree = match self.storage.get_tree(name) {
+            Ok(Some(tree)) => tree,
+            Ok(None) => {
+                if first {
+                    self.problems.push(Problem::Missing(Kind::Tree, name));
+                }
+                return Ok(None);
+            }
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                if first {
+                    self.problems.push(Problem::Corrupt(Kind::Tree, name));
+                }
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        if key(hash_tree::<()>(&tree)) != name {
+            if first {
+                self.problems.push(Problem::Corrupt(Kind::Tree, name));
+            }
+            return Ok(None);
+        }
+        if first {
+            for (index, h) in tree.iter().enumerate() {
+                let valid = h.try_unpack().is_some()
+                    && h.is_canonical()
+                    && h.key()
+                        .is_none_or(|x| !local::is_local(crate::storage::pointer::<()>(x)));
+                if valid {
+                    self.work.push(*h);
+                } else {
+                    self.problems
+                        .push(Problem::MalformedElement { tree: name, index });
+                }
+            }
+        }
+        Ok(Some(tree))
+    }
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use crate::repository::hex;
+        match self {
+            Problem::Missing(kind, name) => write!(f, "{kind:?} {} is missing", hex(*name)),
+            Problem::Corrupt(kind, name) => write!(f, "{kind:?} {} is corrupt", hex(*name)),
+            Problem::MalformedElement { tree, index } => {
+                write!(f, "element {index} of Tree {} is malformed", hex(*tree))
+            }
+            Problem::Metadata { kind, name, field } => {
+                write!(f, "a Name of {kind:?} {} has the wrong {field}", hex(*name))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+    use crate::{BlobName, Data, Ref};
+
+    fn name(contents: &[u8], size: usize) -> PackedHandle {
+        PackedHandle::pack(Handle::Data(Data::Ref(Ref::Blob(BlobName::Name((
+            hash_blob(contents),
+            size,
+        ))))))
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let storage = MemoryStorage::default();
+        let (intact, corrupt, missing) = ([1; 100], [2; 100], [3; 100]);
+        storage
+            .put_blob(key(hash_blob(&intact)), intact.to_vec().into())
+            .unwrap();
+        storage
+            .put_blob(key(hash_blob(&corrupt)), vec![4; 100].into())
+            .unwrap();
+        let tree = [
+            name(&intact, 99),
+            PackedHandle::from_bytes([0xff; 32]),
+            name(&missing, 100),
+            name(&corrupt, 100),
+            name(&intact, 100),
+        ];
+        let tree_name = key(hash_tree::<()>(&tree));
+        storage.put_tree(tree_name, Arc::new(tree)).unwrap();
+
+        let problems = check_all_in(&storage).unwrap();
+        let expected = [
+            Problem::Metadata {
+                kind: Kind::Blob,
+                name: key(hash_blob(&intact)),
+                field: "size",
+            },
+            Problem::MalformedElement {
+                tree: tree_name,
+                index: 1,
+            },
+            Problem::Missing(Kind::Blob, key(hash_blob(&missing))),
+            Problem::Corrupt(Kind::Blob, key(hash_blob(&corrupt))),
+        ];
+        assert_eq!(problems.len(), expected.len(), "{problems:?}");
+        assert!(
+            expected.iter().all(|x| problems.contains(x)),
+            "{problems:?}"
+        );
+    }
+
+    #[test]
+    fn an_intact_storage_has_no_problems() {
+        let storage = MemoryStorage::default();
+        let blob = [1; 100];
+        storage
+            .put_blob(key(hash_blob(&blob)), blob.to_vec().into())
+            .unwrap();
+        let tree = [name(&blob, 100)];
+        storage
+            .put_tree(key(hash_tree::<()>(&tree)), Arc::new(tree))
+            .unwrap();
+        assert!(check_all_in(&storage).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::{LazyLock, Mutex};
+
+use crate::packed::PackedHandle;
+use crate::storage::{Key, Storage, storage};
+use crate::{Handle, chunk, memo, types};
+
+// Garbage collection of the process-wide Storage.
+//
+// The roots are pinned Handles: anything that has to survive a collection (labels,
+// Encodes being executed) holds a Pin on it for as long as it needs it. A collection
+// traces every object reachable from the roots, through Trees (whether or not their
+// Handles are accessible), and deletes every stored object it did not reach.
+//
+// Objects only held in local variables are not roots, and objects stored after a
+// collection starts are never deleted by it. Remembered results (see memo) aren't roots
+// either: a collection forgets those whose Thunk or Data it deleted. Neither are Trees known
+// to be Values (see types).
+
+struct Roots {
+    pins: HashMap<PackedHandle, usize>,
+    // Pinned while a collection is marking (None when there is no collection).
+    late: Option<Vec<PackedHandle>>,
+}
+
+static ROOTS: LazyLock<Mutex<Roots>> = LazyLock::new(|| {
+    Mutex::new(Roots {
+        pins: HashMap::new(),
+        late: None,
+    })
+});
+
+// One collection at a time.
+static COLLECTING: Mutex<()> = Mutex::new(());
+
+// Keeps a Handle (and everything reachable from it) alive until dropped.
+pub(crate) struct Pin(PackedHandle);
+
+pub(crate) fn pin(h: Handle) -> Pin {
+    let h = PackedHandle::pack(h);
+    let mut roots = ROOTS.lock().unwrap();
+    *roots.pins.entry(h).or_default() += 1;
+    if let Some(late) = &mut roots.late {
+        late.push(h);
+    }
+    Pin(h)
+}
+
+impl Drop for Pin {
+    fn drop(&mut self) {
+        let mut roots = ROOTS.lock().unwrap();
+        let count = roots.pins.get_mut(&self.0).expect("unbalanced Pin");
+        *count -= 1;
+        if *count == 0 {
+            roots.pins.remove(&self.0);
+        }
+    }
+}
+
+// What a collection deleted.
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct Collected {
+    pub(crate) blobs: usize,
+    pub(crate) trees: usize,
+}
+
+#[derive(Default)]
+struct Marks {
+    blobs: HashSet<Key>,
+    trees: HashSet<Key>,
+}
+
+// The objects a sweep deleted.
+#[derive(Default)]
+struct Deleted {
+    blobs: HashSet<Key>,
+    trees: HashSet<Key>,
+}
+
+impl Deleted {
+    // Was the object a Handle names deleted? (If not, neither was anything it reaches,
+    // as the object was marked, or stored after the collection started.)
+    fn names(&self, h: PackedHandle) -> bool {
+        match h.key() {
+            Some(name) if chunk::stored_as_tree(&h) => self.trees.contains(&name),
+            Some(name) => self.blobs.contains(&name),
+            None => false,
+        }
+    }
+}
+
+impl Marks {
+    // Mark everything reachable from `roots`.
+    fn trace(&mut self, storage: &dyn Storage, roots: Vec<PackedHandle>) -> io::Result<()> {
+        let mut work = roots;
+        while let Some(h) = work.pop() {
+            let Some(name) = h.key() else {
+                continue;
+            };
+            if !chunk::stored_as_tree(&h) {
+                self.blobs.insert(name);
+            } else if self.trees.insert(name)
+                && let Some(tree) = storage.get_tree(name)?
+            {
+                work.extend(tree.iter().copied());
+            }
+        }
+        Ok(())
+    }
+
+    // Delete the unmarked objects among `blobs` and `trees`.
+    fn sweep(
+        &self,
+        storage: &dyn Storage,
+        blobs: Vec<Key>,
+        trees: Vec<Key>,
+    ) -> io::Result<Deleted> {
+        let mut deleted = Deleted::default();
+        for name in blobs.into_iter().filter(|x| !self.blobs.contains(x)) {
+            storage.delete_blob(name)?;
+            deleted.blobs.insert(name);
+        }
+        for name in trees.into_iter().filter(|x| !self.trees.contains(x)) {
+            storage.delete_tree(name)?;
+            deleted.trees.insert(name);
+        }
+        Ok(deleted)
+    }
+}
+
+// Delete every stored object not reachable from a pinned Handle.
+pub(crate) fn collect() -> io::Result<Collected> {
+    collect_in(&*storage())
+}
+
+fn collect_in(storage: &dyn Storage) -> io::Result<Collected> {
+    let _collecting = COLLECTING.lock().unwrap();
+    // Only objects already stored are candidates, so concurrent writes are left alone.
+    let (blobs, trees) = (storage.list_blobs()?, storage.list_trees()?);
+
+    let mut work = {
+        let mut roots = ROOTS.lock().unwrap();
+        roots.late = Some(Vec::new());
+        roots.pins.keys().copied().collect()
+    };
+    let mut marks = Marks::default();
+    // Anything pinned while marking is traced too. The last check and the sweep
+    // happen with the roots locked, so nothing can be pinned in between.
+    let (mut roots, result) = loop {
+        let traced = marks.trace(storage, work);
+        let mut roots = ROOTS.lock().unwrap();
+        let late = std::mem::take(roots.late.as_mut().unwrap());
+        match traced {
+            Err(e) => break (roots, Err(e)),
+            Ok(()) if late.is_empty() => break (roots, marks.sweep(storage, blobs, trees)),
+            Ok(()) => work = late,
+        }
+    };
+    roots.late = None;
+    drop(roots);
+    let deleted = result?;
+    if !deleted.trees.is_empty() {
+        types::forget(|x| deleted.trees.contains(x));
+    }
+    if !deleted.blobs.is_empty() || !deleted.trees.is_empty() {
+        memo::retain(|h| !deleted.names(h))?;
+    }
+    Ok(Collected {
+        blobs: deleted.blobs.len(),
+        trees: deleted.trees.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::hash_blob;
+    use crate::storage::key;
+    use crate::storage::memory::MemoryStorage;
+    use crate::{BlobName, Data, Ref, Thunk};
+
+    fn blob(storage: &MemoryStorage, byte: u8) -> Data {
+        let contents = vec![byte; 100];
+        let pointer = hash_blob(&contents);
+        storage.put_blob(key(pointer), contents.into()).unwrap();
+        Data::Ref(Ref::Blob(BlobName::Name((pointer, 100))))
+    }
+
+    #[test]
+    fn collects_unreachable_objects_and_forgets_their_results() {
+        let storage = MemoryStorage::default();
+        let (kept, collected) = (blob(&storage, 1), blob(&storage, 2));
+        let _pin = pin(Handle::Data(kept));
+        let (keep, forget) = (
+            Thunk::Identification(kept),
+            Thunk::Identification(collected),
+        );
+        memo::put(&[keep], kept).unwrap();
+        memo::put(&[forget], collected).unwrap();
+
+        let result = collect_in(&storage).unwrap();
+        assert_eq!((result.blobs, result.trees), (1, 0));
+        assert_eq!(storage.len(), 1);
+        assert!(memo::get(keep).is_some());
+        assert!(memo::get(forget).is_none());
+    }
+}
@@ -0,0 +1,188 @@
+use crate::path::Path;
+use crate::{BlobName, Data, Encode, Handle, Object, Thunk, TreeName};
+
+// Random object graphs for tests and benchmarks: a Shape describes one, and build makes it
+// (storing its objects). Every Shape builds a well-formed Handle, however it's drawn: what
+// can't be a Thunk's or an Encode's target is adjusted (see build), so generators (seeded
+// here, or proptest's strategies) can pick shapes freely.
+#[derive(Clone, Debug)]
+pub(crate) enum Shape {
+    Blob(Vec<u8>),
+    // Elements, and the tag.
+    Tree(Vec<Shape>, bool),
+    Ref(Box<Shape>),
+    Identification(Box<Shape>),
+    // A Selection of an element (possibly out of range).
+    Selection(Box<Shape>, u64),
+    // An Application of a combination (which traps without a procedure).
+    Application(Vec<Shape>),
+    Encode(Box<Shape>, Option<bool>),
+}
+
+pub(crate) struct Options {
+    // How many Shapes deep, at most.
+    pub(crate) depth: usize,
+    // How many elements a Tree (or combination) has, at most.
+    pub(crate) branching: usize,
+    // How large a Blob is, at most (those over 30 bytes are named, not literals).
+    pub(crate) blob_size: usize,
+    // The percentage of shapes (above the leaves) that are Thunks or Encodes.
+    pub(crate) thunks: u64,
+    pub(crate) seed: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            depth: 4,
+            branching: 4,
+            blob_size: 100,
+            thunks: 30,
+            seed: 0,
+        }
+    }
+}
+
+// A Shape drawn at random (the same one for the same Options).
+pub(crate) fn shape(options: &Options) -> Shape {
+    let mut state = options.seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    let mut next = move || {
+        // xorshift64*
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    };
+    draw(options, options.depth, &mut next)
+}
+
+fn draw(options: &Options, depth: usize, next: &mut impl FnMut() -> u64) -> Shape {
+    let below = |next: &mut _| Box::new(draw(options, depth - 1, next));
+    if depth == 0 || next().is_multiple_of(4) {
+        let len = next() as usize % (options.blob_size + 1);
+        return Shape::Blob((0..len).map(|_| next() as u8).collect());
+    }
+    if next() % 100 < options.thunks {
+        return match next() % 5 {
+            0 => Shape::Identification(below(next)),
+            1 => Shape::Selection(below(next), next() % 3),
+            2 => {
+                let len = next() as usize % (options.branching + 1);
+                Shape::Application((0..len).map(|_| *below(next)).collect())
+            }
+            _ => {
+                let accessibility = [None, Some(true), Some(false)][next() as usize % 3];
+                Shape::Encode(below(next), accessibility)
+            }
+        };
+    }
+    match next() % 4 {
+        0 => Shape::Ref(below(next)),
+        _ => {
+            let len = next() as usize % (options.branching + 1);
+            let elements = (0..len).map(|_| *below(next)).collect();
+            Shape::Tree(elements, next().is_multiple_of(8))
+        }
+    }
+}
+
+// The Handle of a Shape, with its objects stored. (A Selection of a Thunk or Encode
+// selects it from a Tree; an Encode of Data identifies it; anything else that can't be
+// a Thunk's or an Encode's target is left as it is.)
+pub(crate) fn build(shape: &Shape) -> Handle {
+    match shape {
+        Shape::Blob(x) => Handle::Data(Data::Object(Object::Blob(
+            BlobName::create(x.clone()).ok().unwrap(),
+        ))),
+        Shape::Tree(elements, tag) => {
+            let tree = TreeName::create(elements.iter().map(build).collect());
+            Handle::Data(Data::Object(Object::Tree(TreeName {
+                tag: *tag,
+                ..tree.ok().unwrap()
+            })))
+        }
+        Shape::Ref(x) => match build(x) {
+            Handle::Data(x) => Handle::Data(Data::Ref(x.lower())),
+            h => h,
+        },
+        Shape::Identification(x) => match build(x) {
+            Handle::Data(x) => Handle::Thunk(Thunk::Identification(x)),
+            h => h,
+        },
+        Shape::Selection(x, index) => {
+            let target = match build(x) {
+                Handle::Data(x) => x,
+                h => Data::Object(Object::Tree(TreeName::create(vec![h]).ok().unwrap())),
+            };
+            match Path::new().index(*index).thunk(target) {
+                Ok(thunk) => Handle::Thunk(thunk),
+                Err(_) => Handle::Data(target),
+            }
+        }
+        Shape::Application(elements) => Handle::Thunk(Thunk::Application(
+            TreeName::create(elements.iter().map(build).collect())
+                .ok()
+                .unwrap(),
+        )),
+        Shape::Encode(x, accessibility) => {
+            let thunk = match build(x) {
+                Handle::Thunk(x) => x,
+                Handle::Data(x) => Thunk::Identification(x),
+                h => return h,
+            };
+            Handle::Encode(Encode {
+                thunk,
+                accessibility: *accessibility,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, reference};
+
+    // How deep and wide a Shape is, and how many Thunks and Encodes it has.
+    fn size(shape: &Shape) -> (usize, usize, usize) {
+        let (children, thunk): (Vec<&Shape>, bool) = match shape {
+            Shape::Blob(_) => (vec![], false),
+            Shape::Tree(x, _) => (x.iter().collect(), false),
+            Shape::Application(x) => (x.iter().collect(), true),
+            Shape::Ref(x) => (vec![x], false),
+            Shape::Identification(x) | Shape::Selection(x, _) | Shape::Encode(x, _) => {
+                (vec![x], true)
+            }
+        };
+        let (depth, width, thunks) = children.iter().map(|x| size(x)).fold(
+            (0, children.len(), usize::from(thunk)),
+            |(d, w, t), (x, y, z)| (d.max(x), w.max(y), t + z),
+        );
+        (depth + usize::from(!children.is_empty()), width, thunks)
+    }
+
+    #[test]
+    fn shapes_keep_to_their_options_and_evaluate() {
+        let mut total = 0;
+        for seed in 0..50 {
+            let options = Options {
+                depth: 3,
+                branching: 3,
+                thunks: 50,
+                seed,
+                ..Options::default()
+            };
+            let shape = shape(&options);
+            let (depth, width, thunks) = size(&shape);
+            assert!(depth <= 3 && width <= 3, "seed {seed}");
+            assert_eq!(
+                format!("{shape:?}"),
+                format!("{:?}", super::shape(&options))
+            );
+            total += thunks;
+            let h = build(&Shape::Encode(Box::new(shape), None));
+            assert!(reference::cross_check(h, &Context::default()).is_ok());
+        }
+        assert!(total > 0);
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::fetch::Mismatch;
+use crate::packed::PackedHandle;
+use crate::storage::{Key, SharedBlob, Storage, key};
+use crate::{Blob, BlobName, Data, Handle, Object, Ref, Tree, TreeName, chunk, hash};
+
+mod odb;
+
+pub(crate) use odb::Oid;
+use odb::{Kind, ObjectDatabase, from_hex, invalid};
+
+// Git objects as Fix data, without copying their contents.
+//
+// A git blob is a Blob. A git tree is a Tree of entries, in git's order, each a Tree of
+// the entry's name (a Blob), its mode (a Blob of git's octal text, e.g. "100644"), and a
+// Ref to the entry's object; a submodule's entry has a Blob of the commit id instead.
+//
+// GitStorage serves the Blobs mapped this way by reading them from the git objects on
+// demand (so only their Names are kept); everything else (the Trees, and objects put by
+// the program) goes to the wrapped Storage. Each git object is only hashed once. (The
+// command line's git-import fetches from it, to store a revision's tree.)
+pub(crate) struct GitStorage<S> {
+    inner: S,
+    db: ObjectDatabase,
+    // Where each mapped Blob (or chunk of a chunked Blob) is: a git blob, and a range of it.
+    mapped: Mutex<HashMap<Key, (Oid, usize, usize)>>,
+    imported: Mutex<HashMap<Oid, Ref>>,
+    // The git blob read last (a chunked Blob's chunks are usually read in order).
+    last: Mutex<Option<(Oid, Arc<Vec<u8>>)>>,
+}
+
+impl<S: Storage> GitStorage<S> {
+    pub(crate) fn new(inner: S, path: &Path) -> io::Result<Self> {
+        Ok(GitStorage {
+            inner,
+            db: ObjectDatabase::open(path)?,
+            mapped: Mutex::new(HashMap::new()),
+            imported: Mutex::new(HashMap::new()),
+            last: Mutex::new(None),
+        })
+    }
+
+    // The Tree of a commit (or tag, or tree) named by a revision, e.g. "HEAD" or "main".
+    pub(crate) fn tree(&self, revision: &str) -> io::Result<Ref> {
+        let mut oid = self
+            .db
+            .resolve(revision)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such revision"))?;
+        loop {
+            let (kind, contents) = self.read(&oid)?;
+            // A commit starts with "tree <id>", and a tag with "object <id>".
+            let field = match kind {
+                Kind::Tree => return self.import(&oid),
+                Kind::Commit => "tree ",
+                Kind::Tag => "object ",
+                Kind::Blob => return Err(invalid("revision names a blob")),
+            };
+            oid = std::str::from_utf8(&contents)
+                .ok()
+                .and_then(|x| x.lines().next()?.strip_prefix(field))
+                .and_then(from_hex)
+                .ok_or_else(|| invalid("malformed commit or tag"))?;
+        }
+    }
+
+    // A Ref to a git blob or tree (mapping it, and a tree's contents, if not yet mapped).
+    pub(crate) fn import(&self, oid: &Oid) -> io::Result<Ref> {
+        if let Some(&imported) = self.imported.lock().unwrap().get(oid) {
+            return Ok(imported);
+        }
+        let (kind, contents) = self.read(oid)?;
+        let imported = match kind {
+            Kind::Blob => Ref::Blob(self.map_blob(oid, &contents)?),
+            Kind::Tree => Ref::Tree(self.map_tree(&contents)?),
+            _ => return Err(invalid("not a blob or tree")),
+        };
+        self.imported.lock().unwrap().insert(*oid, imported);
+        Ok(imported)
+    }
+
+    fn read(&self, oid: &Oid) -> io::Result<(Kind, Vec<u8>)> {
+        self.db
+            .read(oid)?
+            .ok_or_else(|| invalid("missing git object"))
+    }
+
+    // Name a git blob's contents (as BlobName::create would), recording where the
+    // stored parts are.
+    fn map_blob(&self, oid: &Oid, blob: &Blob) -> io::Result<BlobName> {
+        if let Some(literal) = BlobName::literal(blob) {
+            return Ok(literal);
+        }
+        let pointer = if !chunk::is_chunked(blob.len()) {
+            let pointer = hash::hash_blob(blob);
+            self.mapped
+                .lock()
+                .unwrap()
+                .insert(key(pointer), (*oid, 0, blob.len()));
+            pointer
+        } else {
+            let chunks = chunk::chunks(blob);
+            let list = chunk::chunk_list(&chunks);
+            let mut mapped = self.mapped.lock().unwrap();
+            let mut offset = 0;
+            for (chunk, h) in chunks.iter().zip(&list) {
+                if let Some(name) = h.key() {
+                    mapped.insert(name, (*oid, offset, offset + chunk.len()));
+                }
+                offset += chunk.len();
+            }
+            drop(mapped);
+            let pointer = hash::hash_tree(&list);
+            self.inner.put_tree(key(pointer), list.into())?;
+            pointer
+        };
+        Ok(BlobName::Name((pointer, blob.len())))
+    }
+
+    // A git tree is a sequence of "<mode> <name>\0<20-byte id>".
+    fn map_tree(&self, mut tree: &[u8]) -> io::Result<TreeName> {
+        let malformed = || invalid("malformed git tree");
+        let mut entries = Vec::new();
+        while !tree.is_empty() {
+            let space = tree.iter().position(|&b| b == b' ').ok_or_else(malformed)?;
+            let nul = tree.iter().position(|&b| b == 0).ok_or_else(malformed)?;
+            let (mode, name) = (
+                &tree[..space],
+                tree.get(space + 1..nul).ok_or_else(malformed)?,
+            );
+            let oid: Oid = tree
+                .get(nul + 1..nul + 21)
+                .ok_or_else(malformed)?
+                .try_into()
+                .unwrap();
+            tree = &tree[nul + 21..];
+
+            let object = if mode == b"160000" {
+                Data::Object(Object::Blob(BlobName::literal(&oid).unwrap()))
+            } else {
+                Data::Ref(self.import(&oid)?)
+            };
+            let entry = vec![self.blob(name)?, self.blob(mode)?, Handle::Data(object)];
+            entries.push(Handle::Data(Data::Object(Object::Tree(
+                self.put_tree(entry)?,
+            ))));
+        }
+        self.put_tree(entries)
+    }
+
+    // Small Blobs (names and modes) are stored in the wrapped Storage.
+    fn blob(&self, blob: &Blob) -> io::Result<Handle> {
+        let name = match BlobName::literal(blob) {
+            Some(literal) => literal,
+            None => {
+                let pointer = hash::hash_blob(blob);
+                self.inner.put_blob(key(pointer), blob.to_vec().into())?;
+                BlobName::Name((pointer, blob.len()))
+            }
+        };
+        Ok(Handle::Data(Data::Object(Object::Blob(name))))
+    }
+
+    fn put_tree(&self, tree: Vec<Handle>) -> io::Result<TreeName> {
+        let packed = TreeName::pack(&tree);
+        let name = TreeName::name_packed(&tree, &packed);
+        self.inner.put_tree(key(name.name), packed.into())?;
+        Ok(name)
+    }
+
+    fn get_mapped(&self, name: Key) -> io::Result<Option<SharedBlob>> {
+        let Some((oid, start, end)) = self.mapped.lock().unwrap().get(&name).copied() else {
+            return Ok(None);
+        };
+        let mut last = self.last.lock().unwrap();
+        let contents = match &*last {
+            Some((last_oid, contents)) if *last_oid == oid => contents.clone(),
+            _ => {
+                let (_, contents) = self.read(&oid)?;
+                let contents = Arc::new(contents);
+                *last = Some((oid, contents.clone()));
+                contents
+            }
+        };
+        let part = contents
+            .get(start..end)
+            .ok_or_else(|| invalid("git blob changed since it was mapped"))?;
+        Ok(Some(part.to_vec().into()))
+    }
+}
+
+impl<S: Storage> Storage for GitStorage<S> {
+    fn get_blob(&self, name: Key) -> io::Result<Option<SharedBlob>> {
+        match self.inner.get_blob(name)? {
+            Some(blob) => Ok(Some(blob)),
+            None => self.get_mapped(name),
+        }
+    }
+
+    fn put_blob(&self, name: Key, blob: SharedBlob) -> io::Result<()> {
+        if self.mapped.lock().unwrap().contains_key(&name) {
+            return Ok(());
+        }
+        self.inner.put_blob(name, blob)
+    }
+
+    fn contains_blob(&self, name: Key) -> io::Result<bool> {
+        Ok(self.mapped.lock().unwrap().contains_key(&name) || self.inner.contains_blob(name)?)
+    }
+
+    // A mapped Blob is only forgotten (the git object is left alone).
+    fn delete_blob(&self, name: Key) -> io::Result<()> {
+        if self.mapped.lock().unwrap().remove(&name).is_some() {
+            self.imported.lock().unwrap().clear();
+        }
+        self.inner.delete_blob(name)
+    }
+
+    fn get_tree(&self, name: Key) -> io::Result<Option<Arc<Tree<PackedHandle>>>> {
+        self.inner.get_tree(name)
+    }
+
+    fn put_tree(&self, name: Key, tree: Arc<Tree<PackedHandle>>) -> io::Result<()> {
+        self.inner.put_tree(name, tree)
+    }
+
+    fn contains_tree(&self, name: Key) -> io::Result<bool> {
+        self.inner.contains_tree(name)
+    }
+
+    fn delete_tree(&self, name: Key) -> io::Result<()> {
+        self.imported.lock().unwrap().clear();
+        self.inner.delete_tree(name)
+    }
+
+    fn list_blobs(&self) -> io::Result<Vec<Key>> {
+        let mut names: BTreeSet<Key> = self.inner.list_blobs()?.into_iter().collect();
+        names.extend(self.mapped.lock().unwrap().keys().copied());
+        Ok(names.into_iter().collect())
+    }
+
+    fn list_trees(&self) -> io::Result<Vec<Key>> {
+        self.inner.list_trees()
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn quarantine(&self, mismatch: &Mismatch) -> io::Result<()> {
+        self.inner.quarantine(mismatch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::odb::tests::{git, repository};
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+
+    fn elements(storage: &impl Storage, tree: TreeName) -> Vec<Handle> {
+        let tree = storage.get_tree(key(tree.name)).unwrap().unwrap();
+        tree.iter().map(PackedHandle::unpack).collect()
+    }
+
+    fn blob(storage: &impl Storage, h: Handle) -> Vec<u8> {
+        let (Handle::Data(Data::Object(Object::Blob(x))) | Handle::Data(Data::Ref(Ref::Blob(x)))) =
+            h
+        else {
+            panic!("not a Blob");
+        };
+        match x {
+            BlobName::Literal(..) => x.try_load().ok().unwrap().to_vec(),
+            BlobName::Name((pointer, _)) => {
+                storage.get_blob(key(pointer)).unwrap().unwrap().to_vec()
+            }
+        }
+    }
+
+    #[test]
+    fn maps_a_commit_to_a_tree_of_entries() {
+        let dir = repository();
+        let storage = GitStorage::new(MemoryStorage::default(), dir.path()).unwrap();
+        let Ref::Tree(root) = storage.tree("HEAD").unwrap() else {
+            panic!("not a Tree");
+        };
+        let entries = elements(&storage, root);
+        let entries: Vec<_> = entries
+            .into_iter()
+            .map(|entry| {
+                let Handle::Data(Data::Object(Object::Tree(entry))) = entry else {
+                    panic!("entry is not a Tree");
+                };
+                elements(&storage, entry)
+            })
+            .collect();
+        let names: Vec<_> = entries.iter().map(|x| blob(&storage, x[0])).collect();
+        assert_eq!(names, [&b"dir"[..], b"large.txt", b"small.txt"]);
+        let modes: Vec<_> = entries.iter().map(|x| blob(&storage, x[1])).collect();
+        assert_eq!(modes, [&b"40000"[..], b"100644", b"100644"]);
+        assert_eq!(
+            blob(&storage, entries[1][2]),
+            fs::read(dir.path().join("large.txt")).unwrap()
+        );
+        assert_eq!(blob(&storage, entries[2][2]), b"small");
+
+        let Handle::Data(Data::Ref(Ref::Tree(nested))) = entries[0][2] else {
+            panic!("directory is not a Tree");
+        };
+        assert_eq!(elements(&storage, nested).len(), 1);
+    }
+
+    #[test]
+    fn maps_a_large_blob_to_chunks() {
+        let dir = repository();
+        let path = dir.path();
+        let contents: Vec<u8> = (0..3 << 20)
+            .map(|i: u32| (i.wrapping_mul(2654435761) >> 24) as u8)
+            .collect();
+        fs::write(path.join("huge.bin"), &contents).unwrap();
+        git(path, &["add", "huge.bin"]);
+        git(path, &["commit", "-q", "-m", "huge"]);
+        let oid = String::from_utf8(git(path, &["rev-parse", "HEAD:huge.bin"])).unwrap();
+
+        let storage = GitStorage::new(MemoryStorage::default(), path).unwrap();
+        let Ref::Blob(BlobName::Name((pointer, size))) =
+            storage.import(&from_hex(oid.trim()).unwrap()).unwrap()
+        else {
+            panic!("not a stored Blob");
+        };
+        assert_eq!(size, contents.len());
+        let chunks = storage.get_tree(key(pointer)).unwrap().unwrap();
+        assert!(chunks.len() > 1);
+        let mut read = Vec::new();
+        for chunk in chunks.iter() {
+            read.extend(blob(&storage, chunk.unpack()));
+        }
+        assert_eq!(read, contents);
+    }
+}
@@ -0,0 +1,689 @@
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+use flate2::read::ZlibDecoder;
+use memmap2::Mmap;
+
+// Reading a git object database: loose objects, and packs (with their deltas), plus
+// enough of refs to resolve a revision. Only SHA-1 repositories are supported.
+
+pub(crate) type Oid = [u8; 20];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Kind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+impl Kind {
+    fn parse(name: &[u8]) -> Option<Kind> {
+        Some(match name {
+            b"commit" => Kind::Commit,
+            b"tree" => Kind::Tree,
+            b"blob" => Kind::Blob,
+            b"tag" => Kind::Tag,
+            _ => return None,
+        })
+    }
+
+    fn packed(code: u8) -> Option<Kind> {
+        Some(match code {
+            1 => Kind::Commit,
+            2 => Kind::Tree,
+            3 => Kind::Blob,
+            4 => Kind::Tag,
+            _ => return None,
+        })
+    }
+}
+
+pub(crate) fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+pub(crate) fn hex(oid: &Oid) -> String {
+    oid.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub(crate) fn from_hex(s: &str) -> Option<Oid> {
+    let s = s.as_bytes();
+    if s.len() != 40 {
+        return None;
+    }
+    let mut oid = [0; 20];
+    for (i, byte) in oid.iter_mut().enumerate() {
+        let pair = std::str::from_utf8(&s[2 * i..2 * i + 2]).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(oid)
+}
+
+pub(crate) struct ObjectDatabase {
+    // The git directory (".git", or a bare repository).
+    dir: PathBuf,
+    packs: Vec<Pack>,
+}
+
+struct Pack {
+    map: Mmap,
+    index: Mmap,
+    // The number of objects, and of entries in the index's table of 8-byte offsets.
+    count: usize,
+    large: usize,
+}
+
+// Where an object is: at an offset in a pack, or loose (and already read).
+enum Location<'a> {
+    Packed(&'a Pack, usize),
+    Loose(Kind, Vec<u8>),
+}
+
+// An entry of a pack: an object, or a delta against a base elsewhere.
+enum Entry {
+    Object(Kind, Vec<u8>),
+    OffsetDelta(usize, Vec<u8>),
+    RefDelta(Oid, Vec<u8>),
+}
+
+// The longest chain of deltas followed to rebuild an object (git's own limit on the depth it
+// writes), so a malformed pack whose deltas form a cycle is rejected.
+const MAX_DELTA_DEPTH: usize = 4095;
+
+// The index: a header, a fan-out table of 256 counts, then for each object its id, CRC and
+// 4-byte offset, then the 8-byte offsets, then the checksums of the pack and of the index.
+const FAN_OUT: usize = 8;
+const IDS: usize = FAN_OUT + 256 * 4;
+
+impl ObjectDatabase {
+    // Open the repository at `path` (a work tree or a git directory).
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let mut dir = path.to_path_buf();
+        let dot_git = path.join(".git");
+        if dot_git.is_dir() {
+            dir = dot_git;
+        } else if dot_git.is_file() {
+            // A linked work tree or submodule: "gitdir: <path>".
+            let link = fs::read_to_string(&dot_git)?;
+            let target = link
+                .trim()
+                .strip_prefix("gitdir: ")
+                .ok_or_else(|| invalid("malformed .git file"))?;
+            dir = path.join(target);
+        }
+        if !dir.join("objects").is_dir() {
+            return Err(io::Error::new(ErrorKind::NotFound, "not a git repository"));
+        }
+
+        let mut packs = Vec::new();
+        let pack_dir = dir.join("objects/pack");
+        if pack_dir.is_dir() {
+            for entry in fs::read_dir(pack_dir)? {
+                let path = entry?.path();
+                if path.extension().is_none_or(|x| x != "idx") {
+                    continue;
+                }
+                packs.push(Pack::open(&path)?);
+            }
+        }
+        Ok(ObjectDatabase { dir, packs })
+    }
+
+    pub(crate) fn read(&self, oid: &Oid) -> io::Result<Option<(Kind, Vec<u8>)>> {
+        Ok(match self.locate(oid)? {
+            Some(Location::Packed(pack, offset)) => Some(pack.read(offset, self)?),
+            Some(Location::Loose(kind, data)) => Some((kind, data)),
+            None => None,
+        })
+    }
+
+    fn locate(&self, oid: &Oid) -> io::Result<Option<Location<'_>>> {
+        for pack in &self.packs {
+            if let Some(offset) = pack.find(oid)? {
+                return Ok(Some(Location::Packed(pack, offset)));
+            }
+        }
+        Ok(self
+            .read_loose(oid)?
+            .map(|(kind, data)| Location::Loose(kind, data)))
+    }
+
+    fn read_loose(&self, oid: &Oid) -> io::Result<Option<(Kind, Vec<u8>)>> {
+        let hex = hex(oid);
+        let path = self.dir.join("objects").join(&hex[..2]).join(&hex[2..]);
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut object = Vec::new();
+        ZlibDecoder::new(file).read_to_end(&mut object)?;
+        // "<kind> <size>\0<contents>"
+        let malformed = || invalid("malformed loose object");
+        let nul = object.iter().position(|&b| b == 0).ok_or_else(malformed)?;
+        let (kind, size) = std::str::from_utf8(&object[..nul])
+            .ok()
+            .and_then(|header| header.split_once(' '))
+            .ok_or_else(malformed)?;
+        let kind = Kind::parse(kind.as_bytes()).ok_or_else(malformed)?;
+        if size.parse::<usize>().ok() != Some(object.len() - nul - 1) {
+            return Err(malformed());
+        }
+        object.drain(..=nul);
+        Ok(Some((kind, object)))
+    }
+
+    // The object a revision names: a full object id, or a ref (as git resolves a short name,
+    // so "main" finds refs/heads/main).
+    pub(crate) fn resolve(&self, revision: &str) -> io::Result<Option<Oid>> {
+        if let Some(oid) = from_hex(revision) {
+            return Ok(Some(oid));
+        }
+        for candidate in [
+            revision.to_string(),
+            format!("refs/{revision}"),
+            format!("refs/tags/{revision}"),
+            format!("refs/heads/{revision}"),
+            format!("refs/remotes/{revision}"),
+            format!("refs/remotes/{revision}/HEAD"),
+        ] {
+            if let Some(oid) = self.resolve_ref(&candidate, 0)? {
+                return Ok(Some(oid));
+            }
+        }
+        Ok(None)
+    }
+
+    fn resolve_ref(&self, name: &str, depth: usize) -> io::Result<Option<Oid>> {
+        if depth > 8 {
+            return Err(invalid("symbolic ref loop"));
+        }
+        if name.split('/').any(|part| part.is_empty() || part == "..") {
+            return Ok(None);
+        }
+        match fs::read_to_string(self.dir.join(name)) {
+            Ok(contents) => {
+                let contents = contents.trim();
+                return match contents.strip_prefix("ref: ") {
+                    Some(target) => self.resolve_ref(target, depth + 1),
+                    None => from_hex(contents)
+                        .map(Some)
+                        .ok_or_else(|| invalid("malformed ref")),
+                };
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::IsADirectory) => {}
+            Err(e) => return Err(e),
+        }
+        let packed = match fs::read_to_string(self.dir.join("packed-refs")) {
+            Ok(packed) => packed,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(packed
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .find(|&(_, ref_name)| ref_name == name)
+            .and_then(|(oid, _)| from_hex(oid)))
+    }
+}
+
+impl Pack {
+    // Open a pack by its index, checking the index is complete (so looking objects up in it
+    // stays in bounds).
+    fn open(path: &Path) -> io::Result<Pack> {
+        // SAFETY: git never modifies a pack or its index once written.
+        let index = unsafe { Mmap::map(&File::open(path)?)? };
+        let map = unsafe { Mmap::map(&File::open(path.with_extension("pack"))?)? };
+        if index.get(..8) != Some(b"\xfftOc\0\0\0\x02") || map.get(..4) != Some(b"PACK") {
+            return Err(invalid("unsupported pack format"));
+        }
+        let truncated = || invalid("truncated pack index");
+        let word = |i: usize| {
+            let bytes = index.get(FAN_OUT + 4 * i..FAN_OUT + 4 * (i + 1));
+            bytes.map(|x| u32::from_be_bytes(x.try_into().unwrap()))
+        };
+        let fan_out = (0..256).map(word).collect::<Option<Vec<_>>>();
+        let fan_out = fan_out.ok_or_else(truncated)?;
+        if fan_out.windows(2).any(|x| x[0] > x[1]) {
+            return Err(invalid("malformed pack index"));
+        }
+        let count = fan_out[255] as usize;
+        let fixed = count
+            .checked_mul(28)
+            .and_then(|x| x.checked_add(IDS + 40))
+            .ok_or_else(truncated)?;
+        let tables = index.len().checked_sub(fixed).ok_or_else(truncated)?;
+        if !tables.is_multiple_of(8) {
+            return Err(truncated());
+        }
+        Ok(Pack {
+            map,
+            index,
+            count,
+            large: tables / 8,
+        })
+    }
+
+    fn word(&self, i: usize) -> u32 {
+        u32::from_be_bytes(
+            self.index[FAN_OUT + 4 * i..FAN_OUT + 4 * (i + 1)]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    // The offset of an object in the pack (via the index's fan-out table and sorted ids).
+    fn find(&self, oid: &Oid) -> io::Result<Option<usize>> {
+        let start = match oid[0] {
+            0 => 0,
+            x => self.word(x as usize - 1) as usize,
+        };
+        let end = self.word(oid[0] as usize) as usize;
+        let id = |i: usize| &self.index[IDS + 20 * i..IDS + 20 * (i + 1)];
+        let (mut low, mut high) = (start, end);
+        while low < high {
+            let middle = (low + high) / 2;
+            match id(middle).cmp(oid) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => {
+                    // After the ids come a CRC per object, then 4-byte offsets, whose top bit
+                    // selects an entry in the table of 8-byte offsets instead.
+                    let offsets = IDS + 24 * self.count;
+                    let at = |i: usize, n: usize| &self.index[i..i + n];
+                    let offset =
+                        u32::from_be_bytes(at(offsets + 4 * middle, 4).try_into().unwrap());
+                    if offset & 1 << 31 == 0 {
+                        return Ok(Some(offset as usize));
+                    }
+                    let entry = (offset & !(1 << 31)) as usize;
+                    if entry >= self.large {
+                        return Err(invalid("malformed pack index"));
+                    }
+                    let large = offsets + 4 * self.count + 8 * entry;
+                    let offset = u64::from_be_bytes(at(large, 8).try_into().unwrap());
+                    return Ok(Some(offset as usize));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // Read the object at an offset, following its chain of deltas (within this pack, or by id
+    // to any object) back to a whole object, then applying them.
+    fn read(&self, offset: usize, db: &ObjectDatabase) -> io::Result<(Kind, Vec<u8>)> {
+        let mut deltas = Vec::new();
+        let (mut pack, mut offset) = (self, offset);
+        let (kind, mut data) = loop {
+            if deltas.len() > MAX_DELTA_DEPTH {
+                return Err(invalid("delta chain too long"));
+            }
+            match pack.entry(offset)? {
+                Entry::Object(kind, data) => break (kind, data),
+                Entry::OffsetDelta(base, delta) => {
+                    deltas.push(delta);
+                    offset = base;
+                }
+                Entry::RefDelta(oid, delta) => {
+                    deltas.push(delta);
+                    match db.locate(&oid)? {
+                        Some(Location::Packed(base, at)) => (pack, offset) = (base, at),
+                        Some(Location::Loose(kind, data)) => break (kind, data),
+                        None => return Err(invalid("missing delta base")),
+                    }
+                }
+            }
+        };
+        while let Some(delta) = deltas.pop() {
+            data = apply_delta(&data, &delta)?;
+        }
+        Ok((kind, data))
+    }
+
+    fn entry(&self, offset: usize) -> io::Result<Entry> {
+        let malformed = || invalid("malformed pack entry");
+        let mut at = self.map.get(offset..).ok_or_else(malformed)?;
+        // A varint header: the type in bits 4-6 of the first byte, then the size.
+        let (&first, rest) = at.split_first().ok_or_else(malformed)?;
+        at = rest;
+        let (code, mut size, mut shift) = ((first >> 4) & 7, (first & 15) as usize, 4);
+        let mut more = first & 0x80 != 0;
+        while more {
+            let (&byte, rest) = at.split_first().ok_or_else(malformed)?;
+            at = rest;
+            size |= ((byte & 0x7f) as usize)
+                .checked_shl(shift)
+                .ok_or_else(malformed)?;
+            shift += 7;
+            more = byte & 0x80 != 0;
+        }
+        let base = match code {
+            // OFS_DELTA: the base is earlier in this pack.
+            6 => {
+                let (&byte, rest) = at.split_first().ok_or_else(malformed)?;
+                at = rest;
+                let mut distance = (byte & 0x7f) as usize;
+                let mut more = byte & 0x80 != 0;
+                while more {
+                    let (&byte, rest) = at.split_first().ok_or_else(malformed)?;
+                    at = rest;
+                    distance = distance
+                        .checked_add(1)
+                        .and_then(|x| x.checked_mul(128))
+                        .ok_or_else(malformed)?
+                        | (byte & 0x7f) as usize;
+                    more = byte & 0x80 != 0;
+                }
+                if distance == 0 {
+                    return Err(malformed());
+                }
+                Some(Ok(offset.checked_sub(distance).ok_or_else(malformed)?))
+            }
+            // REF_DELTA: the base is named by id (and may be anywhere).
+            7 => {
+                let (oid, rest) = at.split_at_checked(20).ok_or_else(malformed)?;
+                at = rest;
+                Some(Err(oid.try_into().unwrap()))
+            }
+            _ => None,
+        };
+        // The size is only trusted as far as the input could hold it, and no more is inflated.
+        let mut data = Vec::with_capacity(size.min(at.len()));
+        ZlibDecoder::new(at)
+            .take(size as u64 + 1)
+            .read_to_end(&mut data)?;
+        if data.len() != size {
+            return Err(malformed());
+        }
+        Ok(match base {
+            Some(Ok(base)) => Entry::OffsetDelta(base, data),
+            Some(Err(oid)) => Entry::RefDelta(oid, data),
+            None => Entry::Object(Kind::packed(code).ok_or_else(malformed)?, data),
+        })
+    }
+}
+
+// Rebuild an object from its delta base: the sizes of the base and the result, then a
+// sequence of copies (from the base) and inserts (of literal bytes).
+fn apply_delta(base: &[u8], mut delta: &[u8]) -> io::Result<Vec<u8>> {
+    let malformed = || invalid("malformed delta");
+    let varint = |delta: &mut &[u8]| {
+        let (mut x, mut shift) = (0usize, 0);
+        loop {
+            let (&byte, rest) = delta.split_first().ok_or_else(malformed)?;
+            *delta = rest;
+            x |= ((byte & 0x7f) as usize)
+                .checked_shl(shift)
+                .ok_or_else(malformed)?;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok::<_, io::Error>(x);
+            }
+        }
+    };
+    if varint(&mut delta)? != base.len() {
+        return Err(malformed());
+    }
+    let size = varint(&mut delta)?;
+    // (Each byte of a delta copies at most 2^16 bytes of the base, or inserts one of its own.)
+    let mut result = Vec::with_capacity(size.min(delta.len().saturating_mul(1 << 16)));
+    while let Some((&command, rest)) = delta.split_first() {
+        if result.len() > size {
+            return Err(malformed());
+        }
+        delta = rest;
+        if command & 0x80 != 0 {
+            // Which bytes of the offset (bits 0-3) and size (bits 4-6) are present.
+            let mut field = |bits: std::ops::Range<u8>| {
+                let mut x = 0;
+                for (i, bit) in bits.enumerate() {
+                    if command & 1 << bit != 0 {
+                        let (&byte, rest) = delta.split_first().ok_or_else(malformed)?;
+                        delta = rest;
+                        x |= (byte as usize) << (8 * i);
+                    }
+                }
+                Ok::<_, io::Error>(x)
+            };
+            let offset = field(0..4)?;
+            let length = match field(4..7)? {
+                0 => 0x10000,
+                x => x,
+            };
+            let copy = offset
+                .checked_add(length)
+                .and_then(|end| base.get(offset..end))
+                .ok_or_else(malformed)?;
+            result.extend_from_slice(copy);
+        } else if command != 0 {
+            let (insert, rest) = delta
+                .split_at_checked(command as usize)
+                .ok_or_else(malformed)?;
+            delta = rest;
+            result.extend_from_slice(insert);
+        } else {
+            return Err(malformed());
+        }
+    }
+    if result.len() != size {
+        return Err(malformed());
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Write;
+    use std::process::Command;
+
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    // Run git in `dir`, returning its output.
+    pub(crate) fn git(dir: &Path, args: &[&str]) -> Vec<u8> {
+        let output = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(["-c", "init.defaultBranch=main", "-c", "gc.auto=0"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed");
+        output.stdout
+    }
+
+    // A repository of two commits, the second a small change to a large file of the first.
+    pub(crate) fn repository() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path();
+        git(path, &["init", "-q"]);
+        let lines: String = (0..2000).map(|i| format!("line {i}\n")).collect();
+        fs::write(path.join("large.txt"), &lines).unwrap();
+        fs::write(path.join("small.txt"), "small").unwrap();
+        fs::create_dir(path.join("dir")).unwrap();
+        fs::write(path.join("dir/nested.txt"), "a file in a directory\n").unwrap();
+        git(path, &["add", "-A"]);
+        git(path, &["commit", "-q", "-m", "first"]);
+        fs::write(
+            path.join("large.txt"),
+            lines.replace("line 1000\n", "changed\n"),
+        )
+        .unwrap();
+        git(path, &["commit", "-q", "-a", "-m", "second"]);
+        dir
+    }
+
+    // Every object in the repository is read as git reads it.
+    fn check_objects(path: &Path) {
+        let db = ObjectDatabase::open(path).unwrap();
+        let objects = String::from_utf8(git(path, &["rev-list", "--objects", "--all"])).unwrap();
+        for line in objects.lines() {
+            let id = &line[..40];
+            let oid = from_hex(id).unwrap();
+            let (kind, contents) = db.read(&oid).unwrap().unwrap();
+            let name = String::from_utf8(git(path, &["cat-file", "-t", id])).unwrap();
+            assert_eq!(Some(kind), Kind::parse(name.trim().as_bytes()));
+            assert_eq!(contents, git(path, &["cat-file", name.trim(), id]));
+        }
+        assert_eq!(db.read(&[0xab; 20]).unwrap(), None);
+    }
+
+    #[test]
+    fn reads_loose_objects() {
+        let dir = repository();
+        check_objects(dir.path());
+    }
+
+    #[test]
+    fn reads_packed_objects_and_deltas() {
+        let dir = repository();
+        git(dir.path(), &["repack", "-a", "-d", "-f", "-q"]);
+        git(dir.path(), &["prune-packed"]);
+        assert!(
+            dir.path()
+                .join(".git/objects/pack")
+                .read_dir()
+                .unwrap()
+                .next()
+                .is_some()
+        );
+        check_objects(dir.path());
+    }
+
+    #[test]
+    fn resolves_revisions() {
+        let dir = repository();
+        let path = dir.path();
+        let head = String::from_utf8(git(path, &["rev-parse", "HEAD"])).unwrap();
+        let head = from_hex(head.trim()).unwrap();
+        let db = ObjectDatabase::open(path).unwrap();
+        assert_eq!(db.resolve("HEAD").unwrap(), Some(head));
+        assert_eq!(db.resolve("main").unwrap(), Some(head));
+        git(path, &["pack-refs", "--all"]);
+        assert_eq!(db.resolve("main").unwrap(), Some(head));
+        assert_eq!(db.resolve("no-such-branch").unwrap(), None);
+    }
+
+    fn compressed(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    // A repository of one pack holding the given raw entries, by id.
+    fn pack(entries: &[(Oid, Vec<u8>)]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let packs = dir.path().join("objects/pack");
+        fs::create_dir_all(&packs).unwrap();
+        let mut entries = entries.to_vec();
+        entries.sort();
+        let mut pack = b"PACK\0\0\0\x02".to_vec();
+        pack.extend((entries.len() as u32).to_be_bytes());
+        let mut offsets = Vec::new();
+        for (_, entry) in &entries {
+            offsets.push(pack.len() as u32);
+            pack.extend(entry);
+        }
+        pack.extend([0; 20]);
+        let mut index = b"\xfftOc\0\0\0\x02".to_vec();
+        for byte in 0..256 {
+            let count = entries
+                .iter()
+                .filter(|(oid, _)| oid[0] as usize <= byte)
+                .count();
+            index.extend((count as u32).to_be_bytes());
+        }
+        entries.iter().for_each(|(oid, _)| index.extend(oid));
+        entries.iter().for_each(|_| index.extend([0; 4]));
+        offsets.iter().for_each(|x| index.extend(x.to_be_bytes()));
+        index.extend([0; 40]);
+        fs::write(packs.join("pack-test.pack"), pack).unwrap();
+        fs::write(packs.join("pack-test.idx"), index).unwrap();
+        dir
+    }
+
+    // An entry's header: its type and size.
+    fn header(code: u8, mut size: u64) -> Vec<u8> {
+        let mut header = vec![code << 4 | (size & 15) as u8];
+        size >>= 4;
+        while size != 0 {
+            *header.last_mut().unwrap() |= 0x80;
+            header.push((size & 0x7f) as u8);
+            size >>= 7;
+        }
+        header
+    }
+
+    #[test]
+    fn reads_a_packed_object() {
+        let mut entry = header(3, 5);
+        entry.extend(compressed(b"hello"));
+        let dir = pack(&[([1; 20], entry)]);
+        let db = ObjectDatabase::open(dir.path()).unwrap();
+        let object = db.read(&[1; 20]).unwrap();
+        assert_eq!(object, Some((Kind::Blob, b"hello".to_vec())));
+    }
+
+    #[test]
+    fn rejects_an_offset_delta_of_distance_zero() {
+        let mut entry = header(6, 0);
+        entry.push(0);
+        entry.extend(compressed(b""));
+        let dir = pack(&[([1; 20], entry)]);
+        let db = ObjectDatabase::open(dir.path()).unwrap();
+        assert!(db.read(&[1; 20]).is_err());
+    }
+
+    #[test]
+    fn rejects_a_cycle_of_deltas() {
+        let delta = |base: Oid| {
+            let mut entry = header(7, 2);
+            entry.extend(base);
+            entry.extend(compressed(&[0, 0]));
+            entry
+        };
+        let dir = pack(&[([1; 20], delta([2; 20])), ([2; 20], delta([1; 20]))]);
+        let db = ObjectDatabase::open(dir.path()).unwrap();
+        assert!(db.read(&[1; 20]).is_err());
+    }
+
+    #[test]
+    fn does_not_trust_the_size_of_an_entry() {
+        let mut entry = header(3, 1 << 60);
+        entry.extend(compressed(b"hello"));
+        let dir = pack(&[([1; 20], entry)]);
+        let db = ObjectDatabase::open(dir.path()).unwrap();
+        assert!(db.read(&[1; 20]).is_err());
+    }
+
+    #[test]
+    fn rejects_a_truncated_index() {
+        let mut entry = header(3, 5);
+        entry.extend(compressed(b"hello"));
+        let dir = pack(&[([1; 20], entry.clone()), ([2; 20], entry)]);
+        let index = dir.path().join("objects/pack/pack-test.idx");
+        let full = fs::read(&index).unwrap();
+        for len in [0, 100, IDS, IDS + 20, full.len() - 41, full.len() - 1] {
+            fs::write(&index, &full[..len]).unwrap();
+            assert!(ObjectDatabase::open(dir.path()).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_a_malformed_delta() {
+        let base = b"0123456789";
+        // Sizes, then a copy past the end of the base.
+        let delta = [10, 4, 0x91, 8, 4];
+        assert!(apply_delta(base, &delta).is_err());
+        // A result larger than its stated size.
+        let delta = [10, 1, 0x91, 0, 4];
+        assert!(apply_delta(base, &delta).is_err());
+        let delta = [10, 4, 0x91, 2, 4];
+        assert_eq!(apply_delta(base, &delta).unwrap(), b"2345");
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tempfile::TempDir;
+
+use crate::fsck;
+use crate::packed::PackedHandle;
+use crate::path::Path as Selection;
+use crate::repository::Repository;
+use crate::storage::{Storage, storage};
+use crate::{BlobName, Data, Encode, Handle, Object, Ref, Thunk, TreeName, archive, fetch};
+
+// Golden fixtures: the formats a repository's users depend on (packed Handles, archives, and
+// a Repository's files: loose objects, packs, labels and the memo log), checked in under
+// `golden/` for a fixed set of objects. Each is decoded and checked against what it should
+// hold, then encoded again and compared byte for byte, so a change to any format (or to how
+// objects are named) shows up here before it strands anyone's repository.
+//
+// After a deliberate change of format, regenerate the fixtures with
+//   FIXMODEL_BLESS=1 cargo test golden
+// (and bump the format's version).
+
+fn golden() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("golden")
+}
+
+fn blessing() -> bool {
+    std::env::var_os("FIXMODEL_BLESS").is_some()
+}
+
+fn data(h: Handle) -> Data {
+    match h {
+        Handle::Data(x) => x,
+        _ => unreachable!("not Data"),
+    }
+}
+
+fn blob(contents: Vec<u8>) -> Handle {
+    Handle::Data(Data::Object(Object::Blob(
+        BlobName::create(contents).ok().unwrap(),
+    )))
+}
+
+fn tree(elements: Vec<Handle>) -> TreeName {
+    TreeName::create(elements).ok().unwrap()
+}
+
+// Bytes that don't compress, from a fixed seed.
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x0060_1de4_u64;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+// The fixed objects: one Handle of each kind.
+fn corpus() -> Vec<(&'static str, Handle)> {
+    let literal = Handle::Data(Data::Object(Object::Blob(
+        BlobName::literal(b"a literal").unwrap(),
+    )));
+    let small = blob(b"a Blob too large to be a literal, so it's named by its hash".to_vec());
+    let compressible = blob(b"compressible ".repeat(200));
+    let large = blob(noise(70_000));
+    let pair = tree(vec![literal, small]);
+    let tagged = TreeName { tag: true, ..pair };
+    let objects = Handle::Data(Data::Object(Object::Tree(tree(vec![
+        compressible,
+        large,
+        Handle::Data(Data::Ref(Ref::Tree(pair))),
+    ]))));
+    let identification = Thunk::Identification(data(small));
+    let selection = Selection::new()
+        .index(1)
+        .thunk(Data::Object(Object::Tree(pair)))
+        .ok()
+        .unwrap();
+    let application = Thunk::Application(tree(vec![literal, small, objects]));
+    let encode = |thunk, accessibility| {
+        Handle::Encode(Encode {
+            thunk,
+            accessibility,
+        })
+    };
+    vec![
+        ("literal", literal),
+        ("blob", small),
+        ("compressible-blob", compressible),
+        ("large-blob", large),
+        ("tree", Handle::Data(Data::Object(Object::Tree(pair)))),
+        (
+            "tagged-tree",
+            Handle::Data(Data::Object(Object::Tree(tagged))),
+        ),
+        ("ref", Handle::Data(Data::Ref(Ref::Tree(pair)))),
+        ("objects", objects),
+        ("identification", Handle::Thunk(identification)),
+        ("selection", Handle::Thunk(selection)),
+        ("application", Handle::Thunk(application)),
+        ("encode", encode(selection, None)),
+        ("object-encode", encode(selection, Some(true))),
+        ("ref-encode", encode(identification, Some(false))),
+    ]
+}
+
+// A Ref to a Tree of the whole corpus.
+fn root() -> Handle {
+    let elements = corpus().into_iter().map(|(_, h)| h).collect();
+    Handle::Data(Data::Ref(Ref::Tree(tree(elements))))
+}
+
+// The memo records of the fixture repository: of its Thunks, what they produce.
+fn remembered() -> Vec<(Handle, Handle)> {
+    let corpus: BTreeMap<_, _> = corpus().into_iter().collect();
+    vec![
+        (corpus["identification"], corpus["blob"]),
+        (corpus["selection"], corpus["blob"]),
+    ]
+}
+
+fn hex(h: Handle) -> String {
+    PackedHandle::pack(h)
+        .as_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn unhex(hex: &str) -> [u8; 32] {
+    let bytes: Vec<u8> = (0..hex.len() / 2)
+        .map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap())
+        .collect();
+    bytes.try_into().unwrap()
+}
+
+// Check a fixture file against what it should hold (or, when blessing, write it).
+fn compare(path: &Path, expected: &[u8]) {
+    if blessing() {
+        fs::write(path, expected).unwrap();
+    } else {
+        let golden = fs::read(path).unwrap();
+        assert!(golden == expected, "{} changed", path.display());
+    }
+}
+
+#[test]
+fn golden_handles() {
+    let lines: String = corpus()
+        .into_iter()
+        .map(|(name, h)| format!("{name} {}\n", hex(h)))
+        .collect();
+    compare(&golden().join("handles"), lines.as_bytes());
+    for line in fs::read_to_string(golden().join("handles"))
+        .unwrap()
+        .lines()
+    {
+        let (name, hex) = line.split_once(' ').unwrap();
+        let decoded = PackedHandle::from_bytes(unhex(hex)).try_unpack();
+        let h = decoded.unwrap_or_else(|| panic!("{name} doesn't decode"));
+        assert_eq!(hex, self::hex(h), "{name}");
+    }
+}
+
+#[test]
+fn golden_archive() {
+    let mut exported = Vec::new();
+    archive::export(root(), &mut exported).unwrap();
+    let path = golden().join("archive");
+    compare(&path, &exported);
+    let imported = archive::import(&fs::read(&path).unwrap()[..]).unwrap();
+    assert_eq!(hex(imported), hex(root()));
+    let mut again = Vec::new();
+    archive::export(imported, &mut again).unwrap();
+    assert!(again == fs::read(&path).unwrap());
+}
+
+// A Repository holding the corpus: its closure (repacked, so some objects are in a pack
+// and the large one is loose), a label and memo records.
+fn build(dir: &Path, source: &dyn Storage) -> io::Result<()> {
+    let repository = Repository::create(dir)?.with_compression(3);
+    fetch::fetch_into(&repository, source, root(), false, |_| ())?;
+    for (thunk, result) in remembered() {
+        repository.remember(PackedHandle::pack(thunk), PackedHandle::pack(result));
+    }
+    repository.set_label("golden", root())?;
+    repository.repack()
+}
+
+// Every file under a directory, by path relative to it (packs by extension alone, as their
+// names are made up when they're written).
+fn files(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    let mut files = BTreeMap::new();
+    let mut work = vec![dir.to_path_buf()];
+    while let Some(path) = work.pop() {
+        if path.is_dir() {
+            work.extend(fs::read_dir(&path).unwrap().map(|x| x.unwrap().path()));
+            continue;
+        }
+        let mut relative = path.strip_prefix(dir).unwrap().to_path_buf();
+        if relative.starts_with("objects/pack") {
+            relative = relative.with_file_name(relative.extension().unwrap());
+        }
+        files.insert(relative, fs::read(&path).unwrap());
+    }
+    files
+}
+
+#[test]
+fn golden_repository() {
+    let path = golden().join("repository");
+    if blessing() {
+        let _ = fs::remove_dir_all(&path);
+        build(&path, &*storage()).unwrap();
+        // (Git keeps no empty directories, so opening recreates them: see below.)
+    }
+    // Decode: open a copy, and check it holds the corpus, intact.
+    let scratch = TempDir::new().unwrap();
+    let copy = scratch.path().join("copy");
+    for (relative, contents) in files(&path) {
+        let target = match relative.starts_with("objects/pack") {
+            true => copy.join(relative.with_file_name(format!(
+                "golden.{}",
+                relative.file_name().unwrap().to_str().unwrap()
+            ))),
+            false => copy.join(relative),
+        };
+        fs::create_dir_all(target.parent().unwrap()).unwrap();
+        fs::write(target, contents).unwrap();
+    }
+    for dir in ["objects/blob", "objects/tree", "objects/pack"] {
+        fs::create_dir_all(copy.join(dir)).unwrap();
+    }
+    let repository = Repository::open(&copy).unwrap();
+    assert_eq!(fsck::check_all_in(&repository).unwrap(), []);
+    assert_eq!(hex(repository.label("golden").unwrap()), hex(root()));
+    let records: Vec<_> = repository
+        .remembered()
+        .unwrap()
+        .into_iter()
+        .map(|(thunk, result)| (hex(thunk.unpack()), hex(result.unpack())))
+        .collect();
+    let expected: Vec<_> = remembered()
+        .into_iter()
+        .map(|(thunk, result)| (hex(thunk), hex(result)))
+        .collect();
+    assert_eq!(records, expected);
+
+    // Encode: build it again from what was decoded, and compare every file.
+    let rebuilt = scratch.path().join("rebuilt");
+    build(&rebuilt, &repository).unwrap();
+    let (golden, rebuilt) = (files(&path), files(&rebuilt));
+    assert_eq!(
+        golden.keys().collect::<Vec<_>>(),
+        rebuilt.keys().collect::<Vec<_>>()
+    );
+    for (relative, contents) in golden {
+        assert!(
+            rebuilt[&relative] == contents,
+            "{} changed",
+            relative.display()
+        );
+    }
+}
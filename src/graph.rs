@@ -0,0 +1,238 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::packed::PackedHandle;
+use crate::repository::hex;
+use crate::{Data, Encode, Handle, Object, Result, Thunk, memo};
+
+// The Encodes that evaluating a Handle executes, found without evaluating anything.
+//
+// Each node is an Encode (identified by its Thunk's canonical Name, so Encodes of the same
+// Thunk share a node). Executing an Application evaluates its combination first, so a node
+// depends on the Encodes accessible in its combination: through accessible Trees, but not
+// through Refs or Thunks. The Data an Encode produces can hold more Encodes, which can't be
+// known before it runs; a Graph can be extended with them once it has.
+#[derive(Default)]
+pub(crate) struct Graph {
+    nodes: HashMap<PackedHandle, usize>,
+    encodes: Vec<Encode>,
+    dependencies: Vec<Vec<usize>>,
+    roots: Vec<usize>,
+    // Trees already searched for the Encodes a node (or nothing, for None) depends on.
+    searched: HashSet<(PackedHandle, Option<usize>)>,
+}
+
+// The Graph of everything `h` depends on.
+pub(crate) fn graph(h: Handle) -> Result<Graph> {
+    let mut graph = Graph::default();
+    graph.extend(h, |_| true)?;
+    Ok(graph)
+}
+
+impl Graph {
+    // Add the Encodes that evaluating `h` executes, returning the new nodes.
+    // The dependencies of an Encode whose Thunk doesn't satisfy `expand` are left out.
+    pub(crate) fn extend(
+        &mut self,
+        h: Handle,
+        expand: impl Fn(Thunk) -> bool,
+    ) -> Result<Vec<usize>> {
+        let mut new = Vec::new();
+        let mut work = vec![(h, None)];
+        while let Some((h, owner)) = work.pop() {
+            match h {
+                Handle::Encode(e) => {
+                    let name = memo::name(e.thunk);
+                    let node = match self.nodes.get(&name) {
+                        Some(&node) => node,
+                        None => {
+                            let node = self.add(name, e);
+                            new.push(node);
+                            if let Thunk::Application(combination) = e.thunk
+                                && expand(e.thunk)
+                            {
+                                let combination = Data::Object(Object::Tree(combination));
+                                work.push((Handle::Data(combination), Some(node)));
+                            }
+                            node
+                        }
+                    };
+                    match owner {
+                        Some(owner) => self.dependencies[owner].push(node),
+                        None => self.roots.push(node),
+                    }
+                }
+                Handle::Data(Data::Object(Object::Tree(tree)))
+                    if self.searched.insert((PackedHandle::pack(h), owner)) =>
+                {
+                    work.extend(tree.try_load()?.into_iter().map(|x| (x, owner)));
+                }
+                _ => {}
+            }
+        }
+        // Only new nodes gain dependencies.
+        for &node in &new {
+            self.dependencies[node].sort_unstable();
+            self.dependencies[node].dedup();
+        }
+        self.roots.sort_unstable();
+        self.roots.dedup();
+        Ok(new)
+    }
+
+    fn add(&mut self, name: PackedHandle, e: Encode) -> usize {
+        let node = self.encodes.len();
+        self.nodes.insert(name, node);
+        self.encodes.push(e);
+        self.dependencies.push(Vec::new());
+        node
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.encodes.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.encodes.is_empty()
+    }
+
+    pub(crate) fn encode(&self, node: usize) -> Encode {
+        self.encodes[node]
+    }
+
+    pub(crate) fn dependencies(&self, node: usize) -> &[usize] {
+        &self.dependencies[node]
+    }
+
+    // The nodes reached from the Handles themselves, rather than from a combination.
+    pub(crate) fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    // The node of an Encode (or of another Encode of the same Thunk), if it's in the Graph.
+    #[cfg(test)]
+    pub(crate) fn node(&self, e: Encode) -> Option<usize> {
+        self.nodes.get(&memo::name(e.thunk)).copied()
+    }
+
+    // Every node, each after all of its dependencies.
+    pub(crate) fn order(&self) -> Vec<usize> {
+        let mut pending: Vec<usize> = self.dependencies.iter().map(Vec::len).collect();
+        let mut dependents = vec![Vec::new(); self.len()];
+        for (node, dependencies) in self.dependencies.iter().enumerate() {
+            for &dependency in dependencies {
+                dependents[dependency].push(node);
+            }
+        }
+        let mut order: Vec<usize> = (0..self.len()).filter(|&x| pending[x] == 0).collect();
+        let mut i = 0;
+        while let Some(&node) = order.get(i) {
+            for &dependent in &dependents[node] {
+                pending[dependent] -= 1;
+                if pending[dependent] == 0 {
+                    order.push(dependent);
+                }
+            }
+            i += 1;
+        }
+        order
+    }
+
+    // The Graph in Graphviz's dot language, with edges from each Encode to its dependencies
+    // (and the roots drawn with a double border).
+    pub(crate) fn dot(&self) -> String {
+        let mut dot = String::from("digraph {\n");
+        for (node, e) in self.encodes.iter().enumerate() {
+            let kind = match e.thunk {
+                Thunk::Application(_) => "application",
+                Thunk::Selection(_) => "selection",
+                Thunk::Identification(_) => "identification",
+            };
+            let name = memo::name(e.thunk)
+                .key()
+                .map_or_else(|| "literal".into(), |x| hex(x)[..12].to_string());
+            let border = match self.roots().contains(&node) {
+                true => ", peripheries=2",
+                false => "",
+            };
+            writeln!(dot, "  {node} [label=\"{kind} {name}\"{border}];").unwrap();
+            for dependency in &self.dependencies[node] {
+                writeln!(dot, "  {node} -> {dependency};").unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BlobName, TreeName};
+
+    fn encode(thunk: Thunk) -> Handle {
+        Handle::Encode(Encode {
+            thunk,
+            accessibility: None,
+        })
+    }
+
+    fn tree(elements: Vec<Handle>) -> TreeName {
+        TreeName::create(elements).ok().unwrap()
+    }
+
+    // An Application of a combination holding a shared Selection (twice, once inside a
+    // Tree) and an inner Application, whose combination holds an Identification.
+    fn sample() -> (Handle, [Encode; 4]) {
+        let blob = Data::Object(Object::Blob(BlobName::create(vec![5; 64]).ok().unwrap()));
+        let identification = encode(Thunk::Identification(blob));
+        let inner = encode(Thunk::Application(tree(vec![identification])));
+        let selection = encode(Thunk::Selection(tree(vec![Handle::Data(blob)])));
+        let nested = Handle::Data(Data::Object(Object::Tree(tree(vec![selection]))));
+        let outer = encode(Thunk::Application(tree(vec![
+            Handle::Data(blob),
+            selection,
+            nested,
+            inner,
+        ])));
+        let unwrap = |h| match h {
+            Handle::Encode(e) => e,
+            _ => unreachable!(),
+        };
+        let encodes = [outer, selection, inner, identification].map(unwrap);
+        (outer, encodes)
+    }
+
+    #[test]
+    fn encodes_depend_on_those_in_their_combinations() {
+        let (outer, [a, s, i, x]) = sample();
+        let graph = graph(outer).ok().unwrap();
+        assert_eq!(graph.len(), 4);
+        let node = |e| graph.node(e).unwrap();
+        assert_eq!(graph.roots(), [node(a)]);
+        let mut dependencies = vec![node(s), node(i)];
+        dependencies.sort();
+        assert_eq!(graph.dependencies(node(a)), dependencies);
+        assert_eq!(graph.dependencies(node(i)), [node(x)]);
+        assert!(graph.dependencies(node(s)).is_empty());
+
+        let order = graph.order();
+        let position = |e| order.iter().position(|&n| n == node(e)).unwrap();
+        assert_eq!(order.len(), 4);
+        assert!(position(x) < position(i) && position(i) < position(a));
+        assert!(position(s) < position(a));
+        assert_eq!(graph.dot().matches("->").count(), 3);
+    }
+
+    #[test]
+    fn unexpanded_applications_have_no_dependencies() {
+        let (outer, [a, ..]) = sample();
+        let mut graph = Graph::default();
+        let new = graph.extend(outer, |_| false).ok().unwrap();
+        assert_eq!(new, [0]);
+        assert!(memo::name(graph.encode(0).thunk) == memo::name(a.thunk));
+        assert!(graph.dependencies(0).is_empty());
+        // Extending with the same Handle again adds nothing.
+        assert!(graph.extend(outer, |_| true).ok().unwrap().is_empty());
+    }
+}
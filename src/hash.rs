@@ -0,0 +1,96 @@
+use std::marker::PhantomData;
+
+use rayon::prelude::*;
+
+use crate::packed::PackedHandle;
+use crate::{Blob, Pointer, Tree};
+
+// Inputs at least this large are hashed with BLAKE3's internal (multithreaded) tree parallelism.
+const PARALLEL_THRESHOLD: usize = 1 << 20;
+
+// A canonical Pointer is the BLAKE3 hash of the contents, truncated to 192 bits. (Except for
+// a chunked Blob, whose Pointer is its chunk list's: see chunk. That's still a function of
+// the contents, but not BLAKE3 of them, so that every stored Tree, chunk lists included,
+// can be checked against its Pointer without knowing what it is.)
+pub(crate) fn hash_blob(blob: &Blob) -> Pointer<Blob> {
+    let mut hasher = blake3::Hasher::new();
+    if blob.len() >= PARALLEL_THRESHOLD {
+        hasher.update_rayon(blob);
+    } else {
+        hasher.update(blob);
+    }
+    pointer(hasher.finalize())
+}
+
+// Hash many Blobs at once, spread across worker threads. The results are in input order.
+pub(crate) fn hash_blobs(batch: &[&Blob]) -> Vec<Pointer<Blob>> {
+    batch.par_iter().map(|blob| hash_blob(blob)).collect()
+}
+
+fn pointer<T: ?Sized>(hash: blake3::Hash) -> Pointer<T> {
+    let bytes = hash.as_bytes();
+    let word = |i: usize| u64::from_le_bytes(bytes[8 * i..8 * (i + 1)].try_into().unwrap());
+    (word(0), word(1), word(2), PhantomData)
+}
+
+// A Tree's canonical Pointer is the hash of its elements in packed form.
+pub(crate) fn hash_tree<T: ?Sized>(tree: &Tree<PackedHandle>) -> Pointer<T> {
+    let mut hasher = TreeHasher::default();
+    for &h in tree {
+        hasher.update(h);
+    }
+    hasher.finish()
+}
+
+// Hashing a Tree one element at a time.
+#[derive(Default)]
+pub(crate) struct TreeHasher(blake3::Hasher);
+
+impl TreeHasher {
+    pub(crate) fn update(&mut self, h: PackedHandle) {
+        self.0.update(h.as_bytes());
+    }
+
+    pub(crate) fn finish<T: ?Sized>(&self) -> Pointer<T> {
+        pointer(self.0.finalize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{key, key_bytes};
+
+    #[test]
+    fn pointers_are_truncated_blake3() {
+        let blob = vec![7; PARALLEL_THRESHOLD + 1];
+        for blob in [&b""[..], b"abc", &blob] {
+            let hash = blake3::hash(blob);
+            assert_eq!(key_bytes(key(hash_blob(blob))), hash.as_bytes()[..24]);
+        }
+    }
+
+    #[test]
+    fn batches_hash_in_input_order() {
+        let blobs: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i; 100 * i as usize]).collect();
+        let batch: Vec<&Blob> = blobs.iter().map(Vec::as_slice).collect();
+        let hashed: Vec<_> = hash_blobs(&batch).into_iter().map(key).collect();
+        let expected: Vec<_> = batch.iter().map(|x| key(hash_blob(x))).collect();
+        assert_eq!(hashed, expected);
+    }
+
+    #[test]
+    fn trees_hash_their_packed_elements() {
+        let tree: Vec<PackedHandle> = (0..3u8)
+            .map(|i| PackedHandle::from_bytes([i; crate::HANDLE_SIZE]))
+            .collect();
+        let bytes: Vec<u8> = tree.iter().flat_map(|x| *x.as_bytes()).collect();
+        assert_eq!(
+            key_bytes(key(hash_tree::<()>(&tree))),
+            blake3::hash(&bytes).as_bytes()[..24]
+        );
+        let mut hasher = TreeHasher::default();
+        tree.iter().for_each(|&x| hasher.update(x));
+        assert_eq!(key(hasher.finish::<()>()), key(hash_tree::<()>(&tree)));
+    }
+}
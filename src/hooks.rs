@@ -0,0 +1,198 @@
+use std::time::Duration;
+
+use crate::{Data, Result, RuntimeValue, Thunk, TreeName, Value};
+
+// Hooks for watching an evaluation as it runs (e.g. to show its progress), set in its
+// Context. They're called by whichever thread does the work, so Encodes executing in
+// parallel call them concurrently. Each does nothing unless it's implemented.
+pub(crate) trait Hooks: Send + Sync {
+    // An execution is about to think about `thunk`.
+    fn on_think(&self, _thunk: Thunk) {}
+
+    // Thinking about `thunk` produced `thought` (or trapped).
+    fn on_thought(&self, _thunk: Thunk, _thought: &Result<RuntimeValue>, _usage: Usage) {}
+
+    // The memo table already knew what `thunk` produces, so it isn't thought about.
+    fn on_cache_hit(&self, _thunk: Thunk, _result: Data) {}
+
+    // A procedure is about to be applied to an evaluated combination.
+    fn on_apply_start(&self, _combination: TreeName<Value>) {}
+
+    // A procedure has finished, with a result or a trap.
+    fn on_apply_finish(
+        &self,
+        _combination: TreeName<Value>,
+        _result: &Result<RuntimeValue>,
+        _usage: Usage,
+    ) {
+    }
+
+    // A selection is about to be made.
+    fn on_select_start(&self, _spec: TreeName) {}
+
+    fn on_select_finish(&self, _spec: TreeName, _result: &Result<RuntimeValue>, _usage: Usage) {}
+}
+
+// No hooks.
+impl Hooks for () {}
+
+impl<T: Hooks + ?Sized> Hooks for std::sync::Arc<T> {
+    fn on_think(&self, thunk: Thunk) {
+        (**self).on_think(thunk);
+    }
+
+    fn on_thought(&self, thunk: Thunk, thought: &Result<RuntimeValue>, usage: Usage) {
+        (**self).on_thought(thunk, thought, usage);
+    }
+
+    fn on_cache_hit(&self, thunk: Thunk, result: Data) {
+        (**self).on_cache_hit(thunk, result);
+    }
+
+    fn on_apply_start(&self, combination: TreeName<Value>) {
+        (**self).on_apply_start(combination);
+    }
+
+    fn on_apply_finish(
+        &self,
+        combination: TreeName<Value>,
+        result: &Result<RuntimeValue>,
+        usage: Usage,
+    ) {
+        (**self).on_apply_finish(combination, result, usage);
+    }
+
+    fn on_select_start(&self, spec: TreeName) {
+        (**self).on_select_start(spec);
+    }
+
+    fn on_select_finish(&self, spec: TreeName, result: &Result<RuntimeValue>, usage: Usage) {
+        (**self).on_select_finish(spec, result, usage);
+    }
+}
+
+// Both sets of hooks, the first called first.
+impl<A: Hooks, B: Hooks> Hooks for (A, B) {
+    fn on_think(&self, thunk: Thunk) {
+        self.0.on_think(thunk);
+        self.1.on_think(thunk);
+    }
+
+    fn on_thought(&self, thunk: Thunk, thought: &Result<RuntimeValue>, usage: Usage) {
+        self.0.on_thought(thunk, thought, usage);
+        self.1.on_thought(thunk, thought, usage);
+    }
+
+    fn on_cache_hit(&self, thunk: Thunk, result: Data) {
+        self.0.on_cache_hit(thunk, result);
+        self.1.on_cache_hit(thunk, result);
+    }
+
+    fn on_apply_start(&self, combination: TreeName<Value>) {
+        self.0.on_apply_start(combination);
+        self.1.on_apply_start(combination);
+    }
+
+    fn on_apply_finish(
+        &self,
+        combination: TreeName<Value>,
+        result: &Result<RuntimeValue>,
+        usage: Usage,
+    ) {
+        self.0.on_apply_finish(combination, result, usage);
+        self.1.on_apply_finish(combination, result, usage);
+    }
+
+    fn on_select_start(&self, spec: TreeName) {
+        self.0.on_select_start(spec);
+        self.1.on_select_start(spec);
+    }
+
+    fn on_select_finish(&self, spec: TreeName, result: &Result<RuntimeValue>, usage: Usage) {
+        self.0.on_select_finish(spec, result, usage);
+        self.1.on_select_finish(spec, result, usage);
+    }
+}
+
+// The resources a step used: its wall-clock time, and the fuel (for an apply; a think's
+// fuel is 0, as its apply is a step of its own).
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct Usage {
+    pub(crate) time: Duration,
+    pub(crate) fuel: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::path::Path;
+    use crate::{BlobName, Context, Encode, Handle, Object, eval};
+
+    #[derive(Default)]
+    struct Counts(Mutex<[usize; 5]>);
+
+    impl Counts {
+        fn count(&self, which: usize) {
+            self.0.lock().unwrap()[which] += 1;
+        }
+    }
+
+    impl Hooks for Counts {
+        fn on_think(&self, _: Thunk) {
+            self.count(0);
+        }
+
+        fn on_thought(&self, _: Thunk, _: &Result<RuntimeValue>, _: Usage) {
+            self.count(1);
+        }
+
+        fn on_cache_hit(&self, _: Thunk, _: Data) {
+            self.count(2);
+        }
+
+        fn on_select_start(&self, _: TreeName) {
+            self.count(3);
+        }
+
+        fn on_select_finish(&self, _: TreeName, _: &Result<RuntimeValue>, _: Usage) {
+            self.count(4);
+        }
+    }
+
+    #[test]
+    fn steps_are_reported_and_remembered_results_hit() {
+        let blob = BlobName::create(b"watched by hooks".to_vec()).ok().unwrap();
+        let tree = TreeName::create(vec![Handle::Data(Data::Object(Object::Blob(blob)))]);
+        let thunk = Path::new()
+            .index(0)
+            .thunk(Data::Object(Object::Tree(tree.ok().unwrap())))
+            .ok()
+            .unwrap();
+        let encode = Handle::Encode(Encode {
+            thunk,
+            accessibility: None,
+        });
+
+        let counts = Arc::new(Counts::default());
+        let context = Context {
+            hooks: counts.clone(),
+            ..Default::default()
+        };
+        assert!(eval(encode, &context).is_ok());
+        let [thinks, thoughts, _, selects, selected] = *counts.0.lock().unwrap();
+        assert_eq!((thinks, thoughts), (1, 1));
+        assert_eq!((selects, selected), (1, 1));
+
+        let counts = Arc::new(Counts::default());
+        let context = Context {
+            hooks: counts.clone(),
+            ..Default::default()
+        };
+        assert!(eval(encode, &context).is_ok());
+        let [thinks, thoughts, hits, selects, selected] = *counts.0.lock().unwrap();
+        assert_eq!((thinks, thoughts, selects, selected), (0, 0, 0, 0));
+        assert!(hits >= 1);
+    }
+}
@@ -0,0 +1,530 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+
+use sha2::{Digest, Sha256};
+
+use crate::fetch::{Mismatch, Problem, Requested};
+use crate::packed::PackedHandle;
+use crate::storage::{key_bytes, key_from_bytes, pointer, storage};
+use crate::stream::BlobWriter;
+use crate::{BlobName, Data, Handle, Object, Ref, TreeName, chunk, local};
+
+mod cid;
+
+pub(crate) use cid::Cid;
+use cid::{BLAKE3, DAG_CBOR, DAG_PB, FIX_TREE, IDENTITY, RAW, SHA2_256, get_varint};
+
+// Interoperation with IPFS (IPLD).
+//
+// A canonical Fix Name converts to a CID directly: a Blob is "raw" data with a BLAKE3
+// multihash truncated to 24 bytes (Literals use the identity multihash), and a Tree (or
+// a chunked Blob) uses a private-use codec for its packed elements. Such CIDs convert back
+// to Names of the stored objects.
+//
+// Other IPFS data (UnixFS files and directories, dag-cbor) is imported from a BlockSource,
+// which maps each DAG onto Blobs and Trees. Every block is checked against its CID.
+
+// A chunked Blob's chunk list (also in the private-use range).
+const FIX_CHUNKED: u64 = 0x3f_1001;
+
+pub(crate) fn blob_cid(name: &BlobName) -> Option<Cid> {
+    Some(match name {
+        BlobName::Literal((bytes, length)) => Cid {
+            codec: RAW,
+            hash: IDENTITY,
+            digest: bytes[..*length as usize].to_vec(),
+        },
+        BlobName::Name((p, size)) if !local::is_local(*p) => Cid {
+            codec: if chunk::is_chunked(*size) {
+                FIX_CHUNKED
+            } else {
+                RAW
+            },
+            hash: BLAKE3,
+            digest: key_bytes(crate::storage::key(*p)).to_vec(),
+        },
+        BlobName::Name(_) => return None,
+    })
+}
+
+pub(crate) fn tree_cid(name: &TreeName) -> Option<Cid> {
+    (!local::is_local(name.name)).then(|| Cid {
+        codec: FIX_TREE,
+        hash: BLAKE3,
+        digest: key_bytes(crate::storage::key(name.name)).to_vec(),
+    })
+}
+
+// A Ref to the stored object a CID names, if it is a Fix name (and the object is stored).
+pub(crate) fn lookup(cid: &Cid) -> io::Result<Option<Ref>> {
+    if (cid.codec, cid.hash) == (RAW, IDENTITY) {
+        return Ok(Some(Ref::Blob(BlobName::create(cid.digest.clone())?)));
+    }
+    let Some(name) = (cid.hash == BLAKE3)
+        .then(|| key_from_bytes(&cid.digest))
+        .flatten()
+    else {
+        return Ok(None);
+    };
+    let storage = storage();
+    Ok(match cid.codec {
+        RAW => storage
+            .get_blob(name)?
+            .map(|blob| Ref::Blob(BlobName::Name((pointer(name), blob.len())))),
+        FIX_CHUNKED => storage.get_tree(name)?.map(|list| {
+            let size = list.iter().map(PackedHandle::size).sum();
+            Ref::Blob(BlobName::Name((pointer(name), size)))
+        }),
+        FIX_TREE => storage.get_tree(name)?.map(|tree| {
+            let tree: Vec<Handle> = tree.iter().map(PackedHandle::unpack).collect();
+            let (size, footprint, eq) = TreeName::metadata(&tree);
+            Ref::Tree(TreeName {
+                name: pointer(name),
+                size,
+                footprint,
+                eq,
+                tag: false,
+            })
+        }),
+        _ => None,
+    })
+}
+
+// Where IPFS blocks come from.
+pub(crate) trait BlockSource {
+    fn get(&self, cid: &Cid) -> io::Result<Vec<u8>>;
+}
+
+// Blocks fetched from an IPFS HTTP gateway, as raw blocks (per the trustless gateway spec).
+pub(crate) struct Gateway {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl Gateway {
+    pub(crate) fn new(url: &str) -> Self {
+        Gateway {
+            url: url.trim_end_matches('/').to_string(),
+            agent: ureq::Agent::new(),
+        }
+    }
+}
+
+impl BlockSource for Gateway {
+    fn get(&self, cid: &Cid) -> io::Result<Vec<u8>> {
+        let response = self
+            .agent
+            .get(&format!("{}/ipfs/{cid}?format=raw", self.url))
+            .set("accept", "application/vnd.ipld.raw")
+            .call()
+            .map_err(io::Error::other)?;
+        let mut block = Vec::new();
+        response.into_reader().read_to_end(&mut block)?;
+        Ok(block)
+    }
+}
+
+impl BlockSource for HashMap<Cid, Vec<u8>> {
+    fn get(&self, cid: &Cid) -> io::Result<Vec<u8>> {
+        self.get(cid)
+            .cloned()
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "no such block"))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+// Imports IPFS DAGs as Fix data:
+// - a raw block, or a UnixFS file (or raw node, or symlink), is a Blob of its contents
+// - a UnixFS directory is a Tree of entries, each a Tree of its name (a Blob) and a Ref to it
+// - dag-cbor bytes and strings are Blobs, lists are Trees, maps are Trees of [key, value]
+//   Trees, links are Refs, and any other value is a Blob of its CBOR encoding.
+pub(crate) struct Importer<S> {
+    source: S,
+    imported: HashMap<Cid, Ref>,
+}
+
+impl<S: BlockSource> Importer<S> {
+    pub(crate) fn new(source: S) -> Self {
+        Importer {
+            source,
+            imported: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn import(&mut self, cid: &Cid) -> io::Result<Ref> {
+        if let Some(&imported) = self.imported.get(cid) {
+            return Ok(imported);
+        }
+        let block = self.block(cid)?;
+        let imported = match cid.codec {
+            RAW => Ref::Blob(blob(&block)?),
+            DAG_PB => {
+                let node = PbNode::parse(&block)?;
+                let unixfs = UnixFs::parse(&node.data)?;
+                if unixfs.kind == UNIXFS_DIRECTORY {
+                    let mut entries = Vec::new();
+                    for link in &node.links {
+                        let child = self.import(&link.cid)?;
+                        let name = Handle::Data(Data::Object(Object::Blob(blob(&link.name)?)));
+                        let entry = TreeName::create(vec![name, Handle::Data(Data::Ref(child))])?;
+                        entries.push(Handle::Data(Data::Object(Object::Tree(entry))));
+                    }
+                    Ref::Tree(TreeName::create(entries)?)
+                } else {
+                    let mut writer = BlobWriter::new();
+                    self.write_file(&node, &unixfs, &mut writer)?;
+                    Ref::Blob(writer.finish()?)
+                }
+            }
+            DAG_CBOR => {
+                let mut rest = &block[..];
+                let h = self.cbor(&mut rest)?;
+                if !rest.is_empty() {
+                    return Err(invalid("trailing bytes after dag-cbor value"));
+                }
+                match h {
+                    Handle::Data(d) => d.lower(),
+                    _ => unreachable!(),
+                }
+            }
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    "unsupported IPLD codec",
+                ));
+            }
+        };
+        self.imported.insert(cid.clone(), imported);
+        Ok(imported)
+    }
+
+    // Fetch a block and check it against its CID.
+    fn block(&self, cid: &Cid) -> io::Result<Vec<u8>> {
+        if cid.hash == IDENTITY {
+            return Ok(cid.digest.clone());
+        }
+        let block = self.source.get(cid)?;
+        let digest = match cid.hash {
+            SHA2_256 => Sha256::digest(&block).to_vec(),
+            BLAKE3 => {
+                let mut digest = vec![0; cid.digest.len()];
+                blake3::Hasher::new()
+                    .update(&block)
+                    .finalize_xof()
+                    .fill(&mut digest);
+                digest
+            }
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    "unsupported multihash",
+                ));
+            }
+        };
+        if digest != cid.digest {
+            let mismatch = Mismatch {
+                requested: Requested::Block(cid.to_string()),
+                problem: Problem::Hash,
+                contents: block,
+            };
+            storage().quarantine(&mismatch)?;
+            return Err(mismatch.into());
+        }
+        Ok(block)
+    }
+
+    // Append a UnixFS file's contents: its own data, then each child's, in order.
+    fn write_file(&self, node: &PbNode, unixfs: &UnixFs, out: &mut BlobWriter) -> io::Result<()> {
+        if ![UNIXFS_RAW, UNIXFS_FILE, UNIXFS_SYMLINK].contains(&unixfs.kind) {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "unsupported UnixFS node",
+            ));
+        }
+        out.write_all(&unixfs.data)?;
+        for link in &node.links {
+            let block = self.block(&link.cid)?;
+            match link.cid.codec {
+                RAW => out.write_all(&block)?,
+                DAG_PB => {
+                    let child = PbNode::parse(&block)?;
+                    self.write_file(&child, &UnixFs::parse(&child.data)?, out)?;
+                }
+                _ => return Err(invalid("unexpected codec in a UnixFS file")),
+            }
+        }
+        Ok(())
+    }
+
+    // Import one dag-cbor value.
+    fn cbor(&mut self, input: &mut &[u8]) -> io::Result<Handle> {
+        let start = *input;
+        let (major, argument) = cbor_head(input)?;
+        let object = |name| Ok(Handle::Data(Data::Object(name)));
+        match major {
+            2 | 3 => {
+                let (bytes, rest) = input
+                    .split_at_checked(argument as usize)
+                    .ok_or_else(|| invalid("truncated dag-cbor"))?;
+                *input = rest;
+                object(Object::Blob(blob(bytes)?))
+            }
+            4 | 5 => {
+                let mut elements = Vec::new();
+                for _ in 0..argument {
+                    if major == 4 {
+                        elements.push(self.cbor(input)?);
+                    } else {
+                        let pair = vec![self.cbor(input)?, self.cbor(input)?];
+                        elements.push(Handle::Data(Data::Object(Object::Tree(TreeName::create(
+                            pair,
+                        )?))));
+                    }
+                }
+                object(Object::Tree(TreeName::create(elements)?))
+            }
+            6 if argument == 42 => {
+                let (2, length) = cbor_head(input)? else {
+                    return Err(invalid("malformed dag-cbor link"));
+                };
+                let (bytes, rest) = input
+                    .split_at_checked(length as usize)
+                    .ok_or_else(|| invalid("truncated dag-cbor"))?;
+                *input = rest;
+                // The CID's bytes follow a multibase identity prefix.
+                let cid = bytes
+                    .strip_prefix(&[0])
+                    .and_then(Cid::from_bytes)
+                    .ok_or_else(|| invalid("malformed dag-cbor link"))?;
+                Ok(Handle::Data(Data::Ref(self.import(&cid)?)))
+            }
+            6 => self.cbor(input),
+            _ => {
+                // Integers and simple values (the head is the whole value), and floats.
+                let length = start.len() - input.len()
+                    + match (major, argument) {
+                        (7, 25) => 2,
+                        (7, 26) => 4,
+                        (7, 27) => 8,
+                        _ => 0,
+                    };
+                let length = length.min(start.len());
+                *input = &start[length..];
+                object(Object::Blob(blob(&start[..length])?))
+            }
+        }
+    }
+}
+
+fn blob(bytes: &[u8]) -> io::Result<BlobName> {
+    let mut writer = BlobWriter::new();
+    writer.write_all(bytes)?;
+    writer.finish()
+}
+
+// The head of a CBOR item: its major type and argument. (dag-cbor has no indefinite lengths.)
+// For floats (major type 7), the argument is the additional info and the value follows.
+fn cbor_head(input: &mut &[u8]) -> io::Result<(u8, u64)> {
+    let truncated = || invalid("truncated dag-cbor");
+    let (&initial, rest) = input.split_first().ok_or_else(truncated)?;
+    *input = rest;
+    let (major, info) = (initial >> 5, initial & 31);
+    if major == 7 && (25..=27).contains(&info) {
+        return Ok((7, info as u64));
+    }
+    let length = match info {
+        0..=23 => return Ok((major, info as u64)),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => return Err(invalid("unsupported dag-cbor item")),
+    };
+    let (bytes, rest) = input.split_at_checked(length).ok_or_else(truncated)?;
+    *input = rest;
+    Ok((major, bytes.iter().fold(0, |x, &b| x << 8 | b as u64)))
+}
+
+// Just enough protobuf for dag-pb and UnixFS: (field number, value) pairs, where a value
+// is a varint or length-delimited bytes.
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+fn fields(mut message: &[u8]) -> io::Result<Vec<(u64, Field<'_>)>> {
+    let malformed = || invalid("malformed protobuf");
+    let mut fields = Vec::new();
+    while !message.is_empty() {
+        let tag = get_varint(&mut message).ok_or_else(malformed)?;
+        let value = match tag & 7 {
+            0 => Field::Varint(get_varint(&mut message).ok_or_else(malformed)?),
+            2 => {
+                let length = get_varint(&mut message).ok_or_else(malformed)? as usize;
+                let (bytes, rest) = message.split_at_checked(length).ok_or_else(malformed)?;
+                message = rest;
+                Field::Bytes(bytes)
+            }
+            _ => return Err(malformed()),
+        };
+        fields.push((tag >> 3, value));
+    }
+    Ok(fields)
+}
+
+struct PbLink {
+    cid: Cid,
+    name: Vec<u8>,
+}
+
+struct PbNode {
+    links: Vec<PbLink>,
+    data: Vec<u8>,
+}
+
+impl PbNode {
+    fn parse(block: &[u8]) -> io::Result<PbNode> {
+        let mut node = PbNode {
+            links: Vec::new(),
+            data: Vec::new(),
+        };
+        for (number, field) in fields(block)? {
+            match (number, field) {
+                (1, Field::Bytes(data)) => node.data = data.to_vec(),
+                (2, Field::Bytes(link)) => {
+                    let (mut cid, mut name) = (None, Vec::new());
+                    for (number, field) in fields(link)? {
+                        match (number, field) {
+                            (1, Field::Bytes(hash)) => cid = Cid::from_bytes(hash),
+                            (2, Field::Bytes(x)) => name = x.to_vec(),
+                            _ => {}
+                        }
+                    }
+                    let cid = cid.ok_or_else(|| invalid("dag-pb link without a CID"))?;
+                    node.links.push(PbLink { cid, name });
+                }
+                _ => {}
+            }
+        }
+        Ok(node)
+    }
+}
+
+const UNIXFS_RAW: u64 = 0;
+const UNIXFS_DIRECTORY: u64 = 1;
+const UNIXFS_FILE: u64 = 2;
+const UNIXFS_SYMLINK: u64 = 4;
+
+struct UnixFs {
+    kind: u64,
+    data: Vec<u8>,
+}
+
+impl UnixFs {
+    fn parse(message: &[u8]) -> io::Result<UnixFs> {
+        let mut unixfs = UnixFs {
+            kind: u64::MAX,
+            data: Vec::new(),
+        };
+        for (number, field) in fields(message)? {
+            match (number, field) {
+                (1, Field::Varint(kind)) => unixfs.kind = kind,
+                (2, Field::Bytes(data)) => unixfs.data = data.to_vec(),
+                _ => {}
+            }
+        }
+        if unixfs.kind == u64::MAX {
+            return Err(invalid("dag-pb node without UnixFS data"));
+        }
+        Ok(unixfs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha2_cid(codec: u64, block: &[u8]) -> Cid {
+        Cid {
+            codec,
+            hash: SHA2_256,
+            digest: Sha256::digest(block).to_vec(),
+        }
+    }
+
+    #[test]
+    fn fix_names_round_trip_through_cids() {
+        let literal = BlobName::literal(b"short").unwrap();
+        let cid = blob_cid(&literal).unwrap();
+        assert_eq!((cid.codec, cid.hash), (RAW, IDENTITY));
+        let Some(Ref::Blob(found)) = lookup(&cid).unwrap() else {
+            panic!("no Blob");
+        };
+        assert!(
+            PackedHandle::pack(Handle::Data(Data::Ref(Ref::Blob(found))))
+                == PackedHandle::pack(Handle::Data(Data::Ref(Ref::Blob(literal))))
+        );
+
+        let local = local::blob(vec![3; 100]);
+        assert!(blob_cid(&local).is_none());
+        let tree = TreeName::create(vec![Handle::Data(Data::Object(Object::Blob(local)))])
+            .ok()
+            .unwrap();
+        let tree = Handle::Data(Data::Ref(Ref::Tree(tree)));
+        let Handle::Data(Data::Ref(Ref::Tree(canonical))) = local::canonicalize(tree).unwrap()
+        else {
+            panic!("not a Tree Ref");
+        };
+        let Some(Ref::Tree(found)) = lookup(&tree_cid(&canonical).unwrap()).unwrap() else {
+            panic!("no Tree");
+        };
+        assert!(
+            PackedHandle::pack(Handle::Data(Data::Ref(Ref::Tree(found))))
+                == PackedHandle::pack(Handle::Data(Data::Ref(Ref::Tree(canonical))))
+        );
+        let unknown = Cid {
+            digest: vec![0; 24],
+            ..tree_cid(&canonical).unwrap()
+        };
+        assert!(lookup(&unknown).unwrap().is_none());
+    }
+
+    #[test]
+    fn imports_dag_cbor_with_links() {
+        let raw = b"linked from dag-cbor, and long enough to be stored".to_vec();
+        let raw_cid = sha2_cid(RAW, &raw);
+        // [h'6162', 42(h'00' || raw_cid)]
+        let link = [&[0][..], &raw_cid.to_bytes()].concat();
+        let mut cbor = vec![0x82, 0x42, b'a', b'b', 0xd8, 0x2a, 0x58, link.len() as u8];
+        cbor.extend(link);
+        let cbor_cid = sha2_cid(DAG_CBOR, &cbor);
+        let source = HashMap::from([(raw_cid, raw.clone()), (cbor_cid.clone(), cbor)]);
+
+        let Ref::Tree(imported) = Importer::new(source).import(&cbor_cid).unwrap() else {
+            panic!("not a Tree");
+        };
+        let elements = imported.try_load().ok().unwrap();
+        let [
+            Handle::Data(Data::Object(Object::Blob(ab))),
+            Handle::Data(Data::Ref(Ref::Blob(x))),
+        ] = elements[..]
+        else {
+            panic!("unexpected elements");
+        };
+        assert_eq!(&*ab.try_load().ok().unwrap(), b"ab");
+        assert_eq!(&*x.try_load().ok().unwrap(), &raw[..]);
+    }
+
+    #[test]
+    fn blocks_are_checked_against_their_cids() {
+        let cid = sha2_cid(RAW, b"expected");
+        let source = HashMap::from([(cid.clone(), b"tampered".to_vec())]);
+        let error = Importer::new(source).import(&cid).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let missing = Importer::new(HashMap::new()).import(&cid).err().unwrap();
+        assert_eq!(missing.kind(), ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,200 @@
+use std::fmt;
+
+// A Content IDentifier (CIDv1, or a CIDv0 once parsed): a codec saying how to interpret
+// a block, and a multihash of it.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub(crate) struct Cid {
+    pub(crate) codec: u64,
+    pub(crate) hash: u64,
+    pub(crate) digest: Vec<u8>,
+}
+
+// Multicodec codes.
+pub(crate) const RAW: u64 = 0x55;
+pub(crate) const DAG_PB: u64 = 0x70;
+pub(crate) const DAG_CBOR: u64 = 0x71;
+// Packed Fix Trees (in the multicodec private-use range).
+pub(crate) const FIX_TREE: u64 = 0x3f_1000;
+
+pub(crate) const IDENTITY: u64 = 0x00;
+pub(crate) const SHA2_256: u64 = 0x12;
+pub(crate) const BLAKE3: u64 = 0x1e;
+
+impl Cid {
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for x in [1, self.codec, self.hash, self.digest.len() as u64] {
+            put_varint(&mut bytes, x);
+        }
+        bytes.extend_from_slice(&self.digest);
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<Cid> {
+        // A CIDv0 is a bare sha2-256 multihash (of a dag-pb block).
+        if bytes.len() == 34 && bytes[..2] == [0x12, 0x20] {
+            return Some(Cid {
+                codec: DAG_PB,
+                hash: SHA2_256,
+                digest: bytes[2..].to_vec(),
+            });
+        }
+        let mut rest = bytes;
+        let mut next = || get_varint(&mut rest);
+        let (version, codec, hash, length) = (next()?, next()?, next()?, next()?);
+        if version != 1 || rest.len() as u64 != length {
+            return None;
+        }
+        Some(Cid {
+            codec,
+            hash,
+            digest: rest.to_vec(),
+        })
+    }
+
+    // Parse a CIDv1 in base32 ("b...") or a CIDv0 ("Qm...", base58btc).
+    pub(crate) fn parse(s: &str) -> Option<Cid> {
+        if s.len() == 46 && s.starts_with("Qm") {
+            return Cid::from_bytes(&base58_decode(s)?);
+        }
+        Cid::from_bytes(&base32_decode(s.strip_prefix('b')?)?)
+    }
+}
+
+// Multibase base32 (lowercase, unpadded), the usual form of a CIDv1.
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "b{}", base32_encode(&self.to_bytes()))
+    }
+}
+
+pub(crate) fn put_varint(bytes: &mut Vec<u8>, mut x: u64) {
+    while x >= 0x80 {
+        bytes.push(x as u8 | 0x80);
+        x >>= 7;
+    }
+    bytes.push(x as u8);
+}
+
+pub(crate) fn get_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut x = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        x |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(x);
+        }
+    }
+    None
+}
+
+const BASE32: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut s = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = buffer << 8 | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            s.push(BASE32[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        s.push(BASE32[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    s
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in s.bytes() {
+        let value = BASE32.iter().position(|&x| x == c.to_ascii_lowercase())? as u32;
+        buffer = buffer << 5 | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+const BASE58: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+fn base58_decode(s: &str) -> Option<Vec<u8>> {
+    // Big-endian base-256 digits, least significant last.
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in s.bytes() {
+        let mut carry = BASE58.iter().position(|&x| x == c)? as u32;
+        for byte in bytes.iter_mut().rev() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.insert(0, carry as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = s.bytes().take_while(|&c| c == b'1').count();
+    Some([vec![0; zeros], bytes].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cids_print_and_parse() {
+        let empty = Cid {
+            codec: RAW,
+            hash: IDENTITY,
+            digest: Vec::new(),
+        };
+        assert_eq!(empty.to_string(), "bafkqaaa");
+        let cid = Cid {
+            codec: FIX_TREE,
+            hash: BLAKE3,
+            digest: (0..24).collect(),
+        };
+        assert_eq!(Cid::parse(&cid.to_string()), Some(cid.clone()));
+        assert_eq!(Cid::from_bytes(&cid.to_bytes()), Some(cid));
+        for malformed in ["", "b", "bafkqaa!", "afkqaaa", "bafkqaaaaa"] {
+            assert_eq!(Cid::parse(malformed), None, "{malformed:?}");
+        }
+    }
+
+    #[test]
+    fn a_cidv0_is_a_sha2_256_multihash() {
+        // The dag-pb CIDv0 of sha2-256 of nothing.
+        let cid = Cid::parse("QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n").unwrap();
+        assert_eq!((cid.codec, cid.hash), (DAG_PB, SHA2_256));
+        assert_eq!(
+            cid.digest[..4],
+            [0xe3, 0xb0, 0xc4, 0x42],
+            "{:x?}",
+            cid.digest
+        );
+        assert_eq!(cid.digest.len(), 32);
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for x in [0, 1, 127, 128, 300, u64::MAX] {
+            let mut bytes = Vec::new();
+            put_varint(&mut bytes, x);
+            let mut rest = &bytes[..];
+            assert_eq!(get_varint(&mut rest), Some(x));
+            assert!(rest.is_empty());
+        }
+        let mut bytes = Vec::new();
+        put_varint(&mut bytes, 300);
+        assert_eq!(bytes, [0xac, 0x02]);
+        assert_eq!(get_varint(&mut &[0x80][..]), None);
+        assert_eq!(get_varint(&mut &[0xff; 10][..]), None);
+    }
+}
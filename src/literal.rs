@@ -0,0 +1,124 @@
+use crate::{BlobName, Result, local, trap};
+
+// Blobs of small typed values, as procedure interfaces use them (e.g. resource limits, or a
+// selection's range):
+//   - integers and floats: their bytes, little-endian, at the type's width (so a u64 is 8 bytes)
+//   - bool: one byte, 0 or 1
+//   - strings: their UTF-8 bytes
+// Numbers and bools are always Literals, so they're named without storing anything, and a
+// longer string is a local Blob (see local), stored with the first Tree created around it. The
+// accessors trap (type-error) if the Blob isn't one of the type: the wrong size, a byte other
+// than 0 or 1, or invalid UTF-8. Nothing tags a Blob with its type, so any Blob of the right
+// size is a number.
+macro_rules! numbers {
+    ($($type:ident => $accessor:ident),* $(,)?) => {
+        $(
+            impl From<$type> for BlobName {
+                fn from(x: $type) -> BlobName {
+                    BlobName::literal(&x.to_le_bytes()).unwrap()
+                }
+            }
+        )*
+
+        impl BlobName {
+            $(
+                pub(crate) fn $accessor(&self) -> Result<$type> {
+                    let bytes = self.try_load()?;
+                    let bytes = (*bytes).try_into().map_err(|_| {
+                        trap::type_error(concat!("Blob is not a ", stringify!($type)))
+                    })?;
+                    Ok($type::from_le_bytes(bytes))
+                }
+            )*
+        }
+    };
+}
+
+numbers! {
+    u8 => as_u8,
+    u16 => as_u16,
+    u32 => as_u32,
+    u64 => as_u64,
+    i8 => as_i8,
+    i16 => as_i16,
+    i32 => as_i32,
+    i64 => as_i64,
+    f32 => as_f32,
+    f64 => as_f64,
+}
+
+impl From<bool> for BlobName {
+    fn from(x: bool) -> BlobName {
+        BlobName::literal(&[x as u8]).unwrap()
+    }
+}
+
+impl From<&str> for BlobName {
+    fn from(x: &str) -> BlobName {
+        local::blob(x.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for BlobName {
+    fn from(x: String) -> BlobName {
+        local::blob(x.into_bytes())
+    }
+}
+
+impl BlobName {
+    pub(crate) fn as_bool(&self) -> Result<bool> {
+        match *self.try_load()? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(trap::type_error("Blob is not a bool")),
+        }
+    }
+
+    // (Loads the Blob, if it isn't a Literal.)
+    pub(crate) fn as_string(&self) -> Result<String> {
+        String::from_utf8(self.try_load()?.to_vec())
+            .map_err(|_| trap::type_error("Blob is not UTF-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip() {
+        assert_eq!(
+            BlobName::from(0xdead_beef_u32).as_u32().ok(),
+            Some(0xdead_beef)
+        );
+        assert_eq!(BlobName::from(-2_i64).as_i64().ok(), Some(-2));
+        assert_eq!(BlobName::from(1.5_f64).as_f64().ok(), Some(1.5));
+        assert_eq!(BlobName::from(true).as_bool().ok(), Some(true));
+        assert_eq!(BlobName::from(false).as_bool().ok(), Some(false));
+        let long = "a string longer than a Literal can hold";
+        assert_eq!(BlobName::from(long).as_string().ok().as_deref(), Some(long));
+        assert_eq!(
+            BlobName::from(String::from("short"))
+                .as_string()
+                .ok()
+                .as_deref(),
+            Some("short")
+        );
+    }
+
+    #[test]
+    fn numbers_are_literals_of_their_width() {
+        assert!(matches!(BlobName::from(7_u64), BlobName::Literal((_, 8))));
+        assert!(matches!(BlobName::from(7_u8), BlobName::Literal((_, 1))));
+        assert_eq!(BlobName::from(7_u16).as_i16().ok(), Some(7));
+    }
+
+    #[test]
+    fn the_wrong_type_traps() {
+        let type_error = |x: Result<_>| x.err().is_some_and(|x| trap::is(x, trap::Kind::TypeError));
+        assert!(type_error(BlobName::from(7_u32).as_u64().map(drop)));
+        assert!(type_error(BlobName::from(2_u8).as_bool().map(drop)));
+        let invalid = BlobName::literal(&[0xff, 0xfe]).unwrap();
+        assert!(type_error(invalid.as_string().map(drop)));
+    }
+}
@@ -0,0 +1,397 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+
+use crate::hash::hash_tree;
+use crate::packed::PackedHandle;
+use crate::storage::memory::MemoryStorage;
+use crate::storage::{Key, Storage, key, pointer, storage};
+use crate::{BlobName, Handle, HandleType, Pointer, Tree, TreeName, chunk};
+
+// A local Pointer names an object held in this process's memory, without hashing it:
+// (id, 0, LOCAL) for a fresh id. (A canonical hash takes that form with probability 2^-128.)
+//
+// Local objects are never written to the Storage, and a canonical object never contains
+// a local Pointer: naming or creating a Tree canonicalizes its elements first.
+// Local objects stay in memory until the process exits, unless they're created in an Arena.
+const LOCAL: u64 = u64::MAX;
+
+static NEXT: AtomicU64 = AtomicU64::new(0);
+static OBJECTS: LazyLock<Arc<MemoryStorage>> = LazyLock::new(Arc::default);
+
+pub(crate) fn is_local<T: ?Sized>(pointer: Pointer<T>) -> bool {
+    local_key(key(pointer))
+}
+
+fn local_key((_, b, c): Key) -> bool {
+    (b, c) == (0, LOCAL)
+}
+
+// The id of a local Pointer's key.
+pub(crate) fn local_id(name: Key) -> Option<u64> {
+    local_key(name).then_some(name.0)
+}
+
+fn fresh<T: ?Sized>() -> Pointer<T> {
+    (NEXT.fetch_add(1, Ordering::Relaxed), 0, LOCAL, PhantomData)
+}
+
+// Where the object a Pointer names is kept.
+pub(crate) fn storage_of<T: ?Sized>(pointer: Pointer<T>) -> Arc<dyn Storage> {
+    if is_local(pointer) {
+        OBJECTS.clone()
+    } else {
+        storage()
+    }
+}
+
+// Create a Blob in memory (Blobs that fit in a Literal are still Literals).
+pub(crate) fn blob(blobdata: Vec<u8>) -> BlobName {
+    BlobName::literal(&blobdata).unwrap_or_else(|| {
+        let (name, size) = (fresh(), blobdata.len());
+        OBJECTS.put_blob(key(name), blobdata.into()).unwrap();
+        BlobName::Name((name, size))
+    })
+}
+
+// Create a Tree in memory. Its elements may name local objects.
+//
+// Small Trees are interned: one with the same elements as a Tree created before is that
+// Tree (by Name), so repeated small Trees (tags, pairs, empty combinations) are held once,
+// and compare equal by Name without being hashed.
+pub(crate) fn tree<T: HandleType>(treedata: Vec<T>) -> TreeName<T> {
+    if treedata.len() > INTERNED {
+        return unique_tree(treedata);
+    }
+    let packed: Vec<_> = treedata
+        .iter()
+        .map(|h| PackedHandle::pack(h.relax()))
+        .collect();
+    let mut interned = INTERN.lock().unwrap();
+    let name = *interned.entry(packed).or_insert_with_key(|packed| {
+        let name = key(fresh::<()>());
+        OBJECTS.put_tree(name, packed.clone().into()).unwrap();
+        name
+    });
+    named(&treedata, pointer(name))
+}
+
+// The most elements an interned Tree has.
+const INTERNED: usize = 8;
+
+static INTERN: LazyLock<Mutex<HashMap<Vec<PackedHandle>, Key>>> = LazyLock::new(Mutex::default);
+
+// Create a Tree in memory that's never interned (so it can be freed: see Arena).
+fn unique_tree<T: HandleType>(treedata: Vec<T>) -> TreeName<T> {
+    let name = fresh();
+    let packed: Vec<_> = treedata
+        .iter()
+        .map(|h| PackedHandle::pack(h.relax()))
+        .collect();
+    OBJECTS.put_tree(key(name), packed.into()).unwrap();
+    named(&treedata, name)
+}
+
+fn named<T: HandleType>(treedata: &[T], name: Pointer<Tree<T>>) -> TreeName<T> {
+    let (size, footprint, eq) = TreeName::metadata(treedata);
+    TreeName {
+        name,
+        size,
+        footprint,
+        eq,
+        tag: false,
+    }
+}
+
+// The transient objects of one step of an evaluation (e.g. everything a procedure creates
+// while it's applied): local objects, freed when the step is done unless its result
+// reaches them (see release). Those it reaches stay local, so they're only stored if the
+// result is canonicalized (e.g. when it's put in a Tree, or a persisted memo table).
+//
+// An Arena is the nursery of a generational scheme: what survives its step is promoted
+// (it's never freed, as a memo record or a later step may name it), and small Trees are
+// interned as they're promoted. Objects can only name older ones, so nothing older than
+// the nursery reaches into it, and releasing it walks only what it created: however large
+// the result's older objects, a step costs what it creates.
+#[cfg_attr(
+    not(feature = "wasm"),
+    allow(
+        dead_code,
+        reason = "only procedures (with the wasm feature) create in an Arena"
+    )
+)]
+#[derive(Default)]
+pub(crate) struct Arena {
+    created: Vec<(bool, Key)>,
+}
+
+#[cfg_attr(
+    not(feature = "wasm"),
+    allow(
+        dead_code,
+        reason = "only procedures (with the wasm feature) create in an Arena"
+    )
+)]
+impl Arena {
+    pub(crate) fn blob(&mut self, blobdata: Vec<u8>) -> BlobName {
+        let blob = blob(blobdata);
+        if let BlobName::Name((name, _)) = blob {
+            self.created.push((false, key(name)));
+        }
+        blob
+    }
+
+    pub(crate) fn tree<T: HandleType>(&mut self, treedata: Vec<T>) -> TreeName<T> {
+        let tree = unique_tree(treedata);
+        self.created.push((true, key(tree.name)));
+        tree
+    }
+
+    // Free every object created in the arena that none of `kept` reaches (promoting the
+    // rest), returning how many were freed.
+    pub(crate) fn release(self, kept: &[Handle]) -> usize {
+        let nursery: HashSet<_> = self.created.iter().copied().collect();
+        let mut reached = HashSet::new();
+        let mut work: Vec<_> = kept.iter().map(|&h| PackedHandle::pack(h)).collect();
+        while let Some(h) = work.pop() {
+            let Some(name) = h.key().filter(|&k| nursery.contains(&(h.is_tree(), k))) else {
+                continue;
+            };
+            if reached.insert((h.is_tree(), name))
+                && h.is_tree()
+                && let Some(tree) = OBJECTS.get_tree(name).unwrap()
+            {
+                work.extend(tree.iter().copied());
+            }
+        }
+        let mut freed = 0;
+        for (tree, name) in self.created {
+            let survived = reached.contains(&(tree, name));
+            match (survived, tree) {
+                (true, true) => promote(name),
+                (true, false) => {}
+                (false, true) => OBJECTS.delete_tree(name).unwrap(),
+                (false, false) => OBJECTS.delete_blob(name).unwrap(),
+            }
+            freed += usize::from(!survived);
+        }
+        freed
+    }
+}
+
+// Intern a small Tree that's survived its Arena (unless one with its elements already is).
+fn promote(name: Key) {
+    if let Some(tree) = OBJECTS.get_tree(name).unwrap()
+        && tree.len() <= INTERNED
+    {
+        INTERN.lock().unwrap().entry(tree.to_vec()).or_insert(name);
+    }
+}
+
+// The same Handle with every local Pointer reachable from it replaced by the object's
+// canonical hash (storing the objects as it goes).
+pub(crate) fn canonicalize(h: Handle) -> io::Result<Handle> {
+    Ok(canonical(PackedHandle::pack(h))?.unpack())
+}
+
+pub(crate) fn canonical(h: PackedHandle) -> io::Result<PackedHandle> {
+    Canonicalize::new(true).packed(h)
+}
+
+// The Handle canonicalize would give, without storing anything (so it can't fail).
+pub(crate) fn canonical_name(h: PackedHandle) -> PackedHandle {
+    Canonicalize::new(false)
+        .packed(h)
+        .expect("naming reads only local objects")
+}
+
+// The same Handle with every object reachable from it copied into memory and named locally.
+// Fails (with NotFound) if an object is missing from the Storage.
+#[cfg(test)]
+pub(crate) fn localize(h: Handle) -> io::Result<Handle> {
+    Ok(Localize::default().packed(PackedHandle::pack(h))?.unpack())
+}
+
+// The canonical keys of the local objects seen, and whether they're stored.
+struct Canonicalize {
+    store: bool,
+    keys: HashMap<(bool, Key), Key>,
+}
+
+impl Canonicalize {
+    fn new(store: bool) -> Self {
+        Canonicalize {
+            store,
+            keys: HashMap::new(),
+        }
+    }
+
+    fn packed(&mut self, h: PackedHandle) -> io::Result<PackedHandle> {
+        let Some(name) = h.key().filter(|&k| local_key(k)) else {
+            return Ok(h);
+        };
+        if let Some(&canonical) = self.keys.get(&(h.is_tree(), name)) {
+            return Ok(h.with_key(canonical));
+        }
+        let canonical = if h.is_tree() {
+            let tree = OBJECTS.get_tree(name)?.expect("unknown local Tree");
+            let tree = tree
+                .iter()
+                .map(|&x| self.packed(x))
+                .collect::<io::Result<Vec<_>>>()?;
+            let canonical = key(hash_tree::<()>(&tree));
+            if self.store {
+                storage().put_tree(canonical, tree.into())?;
+            }
+            canonical
+        } else {
+            let blob = OBJECTS.get_blob(name)?.expect("unknown local Blob");
+            match self.store {
+                true => key(chunk::put(blob)?),
+                false => key(chunk::name(&blob)),
+            }
+        };
+        self.keys.insert((h.is_tree(), name), canonical);
+        Ok(h.with_key(canonical))
+    }
+}
+
+#[cfg(test)]
+fn missing() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "object missing from storage")
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct Localize(HashMap<(bool, Key), Key>);
+
+#[cfg(test)]
+impl Localize {
+    fn packed(&mut self, h: PackedHandle) -> io::Result<PackedHandle> {
+        let Some(name) = h.key().filter(|&k| !local_key(k)) else {
+            return Ok(h);
+        };
+        if let Some(&local) = self.0.get(&(h.is_tree(), name)) {
+            return Ok(h.with_key(local));
+        }
+        let local = key(fresh::<()>());
+        if h.is_tree() {
+            let tree = storage().get_tree(name)?.ok_or_else(missing)?;
+            let tree = tree
+                .iter()
+                .map(|&x| self.packed(x))
+                .collect::<io::Result<Vec<_>>>()?;
+            OBJECTS.put_tree(local, tree.into()).unwrap();
+        } else {
+            let blob = chunk::get(pointer(name), h.size())?.ok_or_else(missing)?;
+            OBJECTS.put_blob(local, blob).unwrap();
+        }
+        self.0.insert((h.is_tree(), name), local);
+        Ok(h.with_key(local))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Data, Object, Ref};
+
+    // A local Tree holding a local Blob, twice.
+    fn local_tree(byte: u8) -> Handle {
+        let blob = Handle::Data(Data::Object(Object::Blob(blob(vec![byte; 100]))));
+        Handle::Data(Data::Ref(Ref::Tree(tree(vec![blob, blob]))))
+    }
+
+    #[test]
+    fn canonicalizing_stores_what_naming_only_hashes() {
+        let h = PackedHandle::pack(local_tree(1));
+        assert!(local_id(h.key().unwrap()).is_some());
+        let named = canonical_name(h);
+        assert!(local_id(named.key().unwrap()).is_none());
+        assert!(!storage().contains_tree(named.key().unwrap()).unwrap());
+
+        let canonical = canonical(h).unwrap();
+        assert!(canonical == named);
+        let stored = storage()
+            .get_tree(canonical.key().unwrap())
+            .unwrap()
+            .unwrap();
+        assert!(stored[0] == stored[1] && local_id(stored[0].key().unwrap()).is_none());
+        assert!(storage().contains_blob(stored[0].key().unwrap()).unwrap());
+        // The same contents, created separately, have the same canonical Name.
+        assert!(canonical_name(PackedHandle::pack(local_tree(1))) == named);
+        assert!(canonical_name(PackedHandle::pack(local_tree(2))) != named);
+    }
+
+    #[test]
+    fn localizing_inverts_canonicalizing() {
+        let canonical = canonical(PackedHandle::pack(local_tree(3))).unwrap();
+        let localized = PackedHandle::pack(localize(canonical.unpack()).unwrap());
+        assert!(local_id(localized.key().unwrap()).is_some());
+        assert!(canonical_name(localized) == canonical);
+
+        let missing = canonical.with_key((1, 2, 3));
+        let error = localize(missing.unpack()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn an_arena_frees_what_the_result_does_not_reach() {
+        let mut arena = Arena::default();
+        let blob = |arena: &mut Arena, byte| {
+            Handle::Data(Data::Object(Object::Blob(arena.blob(vec![byte; 100]))))
+        };
+        let (kept, freed) = (blob(&mut arena, 1), blob(&mut arena, 2));
+        let inner = arena.tree(vec![kept]);
+        let result = arena.tree(vec![Handle::Data(Data::Ref(Ref::Tree(inner)))]);
+        // (A Literal isn't an object, so isn't freed.)
+        arena.blob(vec![3; 10]);
+        let result = Handle::Data(Data::Object(Object::Tree(result)));
+        assert_eq!(arena.release(&[result]), 1);
+        let Handle::Data(Data::Object(Object::Blob(BlobName::Name((name, _))))) = freed else {
+            unreachable!()
+        };
+        assert!(OBJECTS.get_blob(key(name)).unwrap().is_none());
+        // What the result reaches is still there (through a Ref, too).
+        assert!(canonicalize(result).is_ok());
+    }
+
+    #[test]
+    fn an_arena_promotes_what_survives() {
+        let old = tree(vec![Handle::Data(Data::Object(Object::Blob(blob(vec![
+            4;
+            50
+        ]))))]);
+        let old = Handle::Data(Data::Object(Object::Tree(old)));
+        let mut arena = Arena::default();
+        let elements = vec![
+            old,
+            Handle::Data(Data::Object(Object::Blob(arena.blob(vec![5; 50])))),
+        ];
+        let survivor = arena.tree(elements.clone());
+        arena.tree(vec![old]);
+        // Only the nursery is freed (the older Tree the result names isn't).
+        let result = Handle::Data(Data::Object(Object::Tree(survivor)));
+        assert_eq!(arena.release(&[result]), 1);
+        assert!(canonicalize(old).is_ok());
+        // A promoted small Tree is interned.
+        assert!(tree(elements).name == survivor.name);
+    }
+
+    #[test]
+    fn small_trees_are_interned() {
+        let elements = |n: u8| -> Vec<Handle> {
+            let blob = Handle::Data(Data::Object(Object::Blob(blob(vec![n; 40]))));
+            vec![blob; n as usize]
+        };
+        let pair = elements(2);
+        assert!(tree(pair.clone()).name == tree(pair.clone()).name);
+        assert!(tree(pair.clone()).name != tree(elements(3)).name);
+        let large = elements(INTERNED as u8 + 1);
+        assert!(tree(large.clone()).name != tree(large).name);
+        // An Arena's Trees are its own (so they can be freed).
+        assert!(Arena::default().tree(pair.clone()).name != tree(pair).name);
+    }
+}
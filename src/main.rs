@@ -1,4 +1,73 @@
+use std::collections::HashSet;
+use std::io::{self, ErrorKind};
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+
+mod archive;
+#[cfg(feature = "async")]
+mod async_eval;
+mod bench;
+mod builder;
+mod chunk;
+mod cli;
+mod collections;
+mod conformance;
+mod convert;
+mod daemon;
+mod directory;
+mod equivalence;
+#[cfg(test)]
+mod exhaustive;
+mod fetch;
+mod fsck;
+mod gc;
+mod generate;
+#[cfg(feature = "git")]
+mod git;
+#[cfg(test)]
+mod golden;
+mod graph;
+mod hash;
+mod hooks;
+#[cfg(feature = "ipfs")]
+mod ipfs;
+mod literal;
+mod local;
+mod memo;
+mod metrics;
+mod packed;
+mod path;
+mod prefetch;
+mod pretty;
+mod progress;
+mod reference;
+mod remote;
+mod repository;
+mod schedule;
+mod script;
+mod selection;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(test)]
+mod simulation;
+mod stats;
+mod storage;
+mod stream;
+mod sync;
+mod trace;
+mod trap;
+mod types;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+use hooks::{Hooks, Usage};
+use metrics::Counter;
+use packed::PackedHandle;
+use prefetch::Prefetch;
+use rayon::prelude::*;
+use storage::{BlobData, key, storage};
 
 // A physical "object" is either a Blob (an immutable vector of bytes)
 // or a Tree (an immutable vector of "Handles", defined below).
@@ -6,11 +75,14 @@ type Blob = [u8];
 type Tree<T> = [T];
 
 // A Pointer is an opaque pointer to an object (in this case, a 192-bit number).
-// In practice this is either a pointer to the object in memory or a canonical hash of the contents.
+// In practice this is either a pointer to the object in memory or a canonical hash of the contents
+// (see local::canonicalize and local::localize for converting between the two).
 type Pointer<T> = (u64, u64, u64, PhantomData<T>);
 
 // A Blob "Name" identifies a Blob and its length, either by containing
 // its contents directly in the Name (a Literal) or via Pointer.
+// Blobs of up to 30 bytes are always named by Literal, and longer ones always by Pointer,
+// so each Blob has exactly one Name.
 #[derive(Copy, Clone)]
 enum BlobName {
     Literal(([u8; 30], u8)),
@@ -20,6 +92,10 @@ enum BlobName {
 const PAGE_SIZE: usize = 65536; // Units of estimated memory "footprint" (64 KiB)
 const HANDLE_SIZE: usize = 32; // Size of a Handle in memory (256 bits)
 
+// With the `strict-invariants` feature, Name metadata (size, footprint, eq, tag, literal length)
+// is checked against what it describes at every construction and transition.
+const STRICT_INVARIANTS: bool = cfg!(feature = "strict-invariants");
+
 // A Tree "Name" identifies a Tree, its length, and an estimate of its memory "footprint"
 // (number of pages consumed by the Tree itself plus the footprint of its accessible Refs).
 // It also records whether the Name is "eq" (can be compared against other Tree Names for equality)
@@ -41,7 +117,7 @@ struct TreeName<T: HandleType = Handle> {
 // A Ref is a reference to an inaccessible physical object (Blob or Tree).
 // Only the Name metadata (e.g. size, footprint, tag, eq) can be accessed.
 // A Ref's Tree may never have been looked at after creation, so it must be
-// the general type (in a system without runtime Tree types).
+// the general type (though at runtime it may be known to be a Value: see types).
 #[derive(Copy, Clone)]
 enum Ref {
     Blob(BlobName),
@@ -98,7 +174,8 @@ struct Encode {
     accessibility: Option<bool>,
 }
 
-// A Handle is the element type of a Tree, intended to be storable in a 256-bit register.
+// A Handle is the element type of a Tree, intended to be storable in a 256-bit register
+// (see packed::PackedHandle for that representation).
 // There are three variants: Data, Thunk, and Encode.
 #[derive(Copy, Clone)]
 enum Handle {
@@ -111,103 +188,606 @@ enum Handle {
 // or a fatal trap (expressed as Fix data).
 type Result<T> = std::result::Result<T, Data>;
 
+// An object fetched from Storage. A missing or corrupt object traps, as does any other
+// storage failure.
+fn stored<T>(result: io::Result<Option<T>>, what: &str) -> Result<T> {
+    match result {
+        Ok(Some(x)) => Ok(x),
+        Ok(None) => Err(trap::missing(what)),
+        Err(e) if e.kind() == ErrorKind::InvalidData => Err(trap::corruption()),
+        Err(e) => Err(trap::storage_failed(&e)),
+    }
+}
+
+// The result of putting objects into Storage (a failure traps).
+fn put<T>(result: io::Result<T>) -> Result<T> {
+    result.map_err(|e| trap::storage_failed(&e))
+}
+
 // Fix operations: apply, select, think, execute, and eval.
 
+// The settings of an evaluation, which apply to every Encode it executes:
+// - the step budget: how many times executing an Encode may think before it traps
+//   (resource-exhausted, for "steps": see trap), or None for no limit.
+// - the timeout: how long executing an Encode may take (in wall-clock time, however much
+//   fuel it has) before it traps (timed-out), or None for no limit.
+// - the cancellation: once it's cancelled, the evaluation traps (cancelled) before
+//   its next think step, and any procedure it's running is interrupted.
+// Both are checked before each think step, and while a procedure runs.
+// - the hooks, which are told about each step (see hooks).
+// - the prefetch policy, which picks the Refs to fetch before each apply (see prefetch).
+// - the priority of its Encodes, relative to other evaluations' (see schedule::Priority).
+// - the workers to offload the Encodes it schedules to, if any (see remote).
+#[derive(Clone)]
+struct Context {
+    step_budget: Option<u64>,
+    timeout: Option<Duration>,
+    cancellation: Cancellation,
+    hooks: Arc<dyn Hooks>,
+    prefetch: Arc<dyn Prefetch>,
+    priority: schedule::Priority,
+    offload: Option<Arc<remote::Coordinator>>,
+    // When the Encode being executed times out.
+    deadline: Option<Instant>,
+}
+
+// A token for cancelling evaluations from another thread (clones share the token).
+#[derive(Clone, Default)]
+struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+        #[cfg(feature = "wasm")]
+        wasm::interrupt();
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl Context {
+    // Traps if the evaluation has been cancelled, or the Encode being executed has timed out.
+    fn check(&self) -> Result<()> {
+        let trap = if self.cancellation.is_cancelled() {
+            trap::cancelled()
+        } else {
+            match (self.deadline, self.timeout) {
+                (Some(deadline), Some(timeout)) if Instant::now() >= deadline => {
+                    trap::timed_out(timeout)
+                }
+                _ => return Ok(()),
+            }
+        };
+        metrics::count(Counter::Traps);
+        Err(trap)
+    }
+
+    // The context for executing an Encode, which has a deadline of its own.
+    fn execution(&self) -> Context {
+        Context {
+            deadline: self.timeout.map(|x| Instant::now() + x),
+            ..self.clone()
+        }
+    }
+}
+
+// The default step budget, from the FIX_STEP_BUDGET environment variable.
+static STEP_BUDGET: LazyLock<Option<u64>> = LazyLock::new(|| {
+    let budget = std::env::var("FIX_STEP_BUDGET").ok();
+    budget.and_then(|x| x.parse().ok())
+});
+
+fn step_budget() -> Option<u64> {
+    *STEP_BUDGET
+}
+
+// The default settings.
+impl Default for Context {
+    fn default() -> Self {
+        Context {
+            step_budget: step_budget(),
+            timeout: None,
+            cancellation: Cancellation::default(),
+            hooks: Arc::new(()),
+            prefetch: Arc::new(()),
+            priority: schedule::Priority::default(),
+            offload: None,
+            deadline: None,
+        }
+    }
+}
+
 // Apply a function to arguments, as described by an evaluated "combination":
 // a tree that includes the resource limits, the function, and the arguments/environment.
-// The evaluated combination (the input) will never contain any accessible Encodes.
+// The evaluated combination (the input) will never contain any accessible Encodes
+// (checked, with the strict-invariants feature, by its runtime type: see types).
 // The function can return any Value it wants (it can't return an Encode,
 // but it can return a Tree containing accessible Encodes).
-fn apply(_evaluated_combination: TreeName<Value>) -> Result<RuntimeValue> {
-    // must enforce that the type returned by a Fix procedure actually is a RuntimeValue
-    unimplemented!("apply")
+fn apply(evaluated_combination: TreeName<Value>, context: &Context) -> Result<RuntimeValue> {
+    if STRICT_INVARIANTS {
+        assert!(
+            types::is_value(evaluated_combination.relax()),
+            "combination is not known to be a Value"
+        );
+    }
+    prefetch::start(&*context.prefetch, evaluated_combination);
+    metrics::count(Counter::Applies);
+    context.hooks.on_apply_start(evaluated_combination);
+    let start = Instant::now();
+    let mut fuel = 0;
+    let result = call(evaluated_combination, context, &mut fuel);
+    if result.is_err() {
+        metrics::count(Counter::Traps);
+    }
+    let usage = Usage {
+        time: start.elapsed(),
+        fuel,
+    };
+    context
+        .hooks
+        .on_apply_finish(evaluated_combination, &result, usage);
+    result
+}
+
+// Procedures are Wasm modules; see wasm.
+#[cfg(feature = "wasm")]
+fn call(
+    evaluated_combination: TreeName<Value>,
+    context: &Context,
+    fuel: &mut u64,
+) -> Result<RuntimeValue> {
+    wasm::apply(evaluated_combination.relax(), context, fuel)
+}
+
+#[cfg(not(feature = "wasm"))]
+fn call(
+    _evaluated_combination: TreeName<Value>,
+    _context: &Context,
+    _fuel: &mut u64,
+) -> Result<RuntimeValue> {
+    Err(trap::unsupported(
+        "applying a procedure needs the wasm feature",
+    ))
 }
 
-// Select data as specified, without loading or evaluating anything not needed.
-// The specification language is TBD, but will permit:
+// Select data as specified, without loading or evaluating anything not needed:
 // - fetching a byte range of a Blob
 // - fetching a single element of a Tree
 // - fetching a subrange of a Tree
 // - truncating the output elements to be empty
 //   (to permit discovery of element types without unnecessary accessible data)
-fn select(_spec: TreeName) -> Result<RuntimeValue> {
-    // must enforce that the type returned by a Fix procedure actually is a RuntimeValue
-    unimplemented!("select")
+// See selection for the specification.
+fn select(spec: TreeName, context: &Context) -> Result<RuntimeValue> {
+    metrics::count(Counter::Selections);
+    context.hooks.on_select_start(spec);
+    let start = Instant::now();
+    let result = selection::select(spec);
+    if result.is_err() {
+        metrics::count(Counter::Traps);
+    }
+    let usage = Usage {
+        time: start.elapsed(),
+        fuel: 0,
+    };
+    context.hooks.on_select_finish(spec, &result, usage);
+    result
 }
 
 // Execute one step of the evaluation of a Thunk. This might produce another Thunk.
-fn think(thunk: Thunk) -> Result<RuntimeValue> {
+fn think(thunk: Thunk, context: &Context) -> Result<RuntimeValue> {
     match thunk {
-        Thunk::Application(combination) => apply(combination.try_map(eval)?),
-        Thunk::Selection(spec) => select(spec),
+        // The combination is evaluated as a Tree (so this doesn't recurse per Encode within).
+        Thunk::Application(combination) => {
+            let combination = Handle::Data(Data::Object(Object::Tree(combination)));
+            let Value::Data(Data::Object(Object::Tree(combination))) = eval(combination, context)?
+            else {
+                unreachable!("a Tree evaluated to something else");
+            };
+            apply(combination, context)
+        }
+        Thunk::Selection(spec) => select(spec, context),
         Thunk::Identification(x) => Ok(RuntimeValue::Data(x)),
     }
 }
 
+// An Encode being executed: its latest thought, and every thought so far.
+struct Execution {
+    thunk: Thunk,
+    accessibility: Option<bool>,
+    thoughts: Vec<Thunk>,
+    // The canonical names of the thoughts (see memo::name), to detect cycles.
+    seen: HashSet<PackedHandle>,
+    context: Context,
+    // The thinks so far (which the step budget limits).
+    steps: u64,
+    // When thinking about the latest thought started (while it's being thought about).
+    thinking: Option<Instant>,
+    // The Encode and its latest thought are GC roots while it runs.
+    _encode: gc::Pin,
+    _thought: Option<gc::Pin>,
+}
+
+impl Execution {
+    fn new(e: Encode, context: &Context) -> Self {
+        Execution {
+            thunk: e.thunk,
+            accessibility: e.accessibility,
+            thoughts: vec![e.thunk],
+            seen: HashSet::from([memo::name(e.thunk)]),
+            context: context.execution(),
+            steps: 0,
+            thinking: None,
+            _encode: gc::pin(Handle::Encode(e)),
+            _thought: None,
+        }
+    }
+
+    // Start thinking about the latest thought. Traps if the execution was cancelled or timed
+    // out, or has already taken as many steps as its budget.
+    fn think(&mut self) -> Result<()> {
+        self.context.check()?;
+        if let Some(budget) = self.context.step_budget
+            && self.steps >= budget
+        {
+            metrics::count(Counter::Traps);
+            return Err(trap::resource_exhausted("steps", budget));
+        }
+        self.steps += 1;
+        metrics::count(Counter::Thinks);
+        self.context.hooks.on_think(self.thunk);
+        self.thinking = Some(Instant::now());
+        Ok(())
+    }
+
+    // Thinking about the latest thought trapped.
+    fn trapped(&mut self, trap: Data) {
+        if let Some(start) = self.thinking.take() {
+            let usage = Usage {
+                time: start.elapsed(),
+                fuel: 0,
+            };
+            self.context.hooks.on_thought(self.thunk, &Err(trap), usage);
+        }
+    }
+
+    // The next thought, or the Data the execution produced.
+    // Traps if thinking trapped, or if the thought repeats an earlier one.
+    fn thought(&mut self, x: Result<RuntimeValue>) -> Result<Option<Data>> {
+        let usage = Usage {
+            time: self.thinking.take().map_or(Duration::ZERO, |x| x.elapsed()),
+            fuel: 0,
+        };
+        self.context.hooks.on_thought(self.thunk, &x, usage);
+        match x? {
+            RuntimeValue::Thunk(thought) => {
+                if !self.seen.insert(memo::name(thought)) {
+                    metrics::count(Counter::Traps);
+                    return Err(trap::cycle_detected());
+                }
+                self._thought = Some(gc::pin(Handle::Thunk(thought)));
+                self.thunk = thought;
+                self.thoughts.push(thought);
+                Ok(None)
+            }
+            RuntimeValue::Data(x) => Ok(Some(x)),
+        }
+    }
+
+    // Memoize every thought, and adjust the Data's accessibility (if requested).
+    fn finish(self, data: Data) -> Result<Data> {
+        put(memo::put(&self.thoughts, data))?;
+        Ok(match self.accessibility {
+            None => data,
+            Some(true) => Data::Object(data.lift()?),
+            Some(false) => Data::Ref(data.lower()),
+        })
+    }
+}
+
 // Execute an Encode, producing Data.
-// The Thunk is thinked until no more thoughts arrive (i.e. it's Data).
+// The Thunk is thinked until no more thoughts arrive (i.e. it's Data), unless the memo table
+// already knows the result of one of the thoughts (and traps if a thought repeats, since
+// that would never finish). Every thought is then memoized.
 // Then, if requested, the Data accessibility is adjusted.
-fn execute(e: Encode) -> Result<Data> {
-    match e {
-        Encode {
-            mut thunk,
-            accessibility,
-        } => {
-            let data = loop {
-                match think(thunk)? {
-                    RuntimeValue::Thunk(thought) => thunk = thought,
-                    RuntimeValue::Data(x) => break x,
-                }
-            };
-            Ok(match accessibility {
-                None => data,
-                Some(true) => Data::Object(data.lift()),
-                Some(false) => Data::Ref(data.lower()),
-            })
+fn execute(e: Encode, context: &Context) -> Result<Data> {
+    let mut execution = Execution::new(e, context);
+    let data = loop {
+        if let Some(x) = memo::get(execution.thunk) {
+            context.hooks.on_cache_hit(execution.thunk, x);
+            break x;
+        }
+        execution.think()?;
+        let thought = think(execution.thunk, &execution.context);
+        if let Some(x) = execution.thought(thought)? {
+            break x;
         }
+    };
+    execution.finish(data)
+}
+
+// eval keeps its own stack (rather than recursing), so Values of any depth can be evaluated.
+enum Frame {
+    // A Tree whose elements are being evaluated, in order.
+    // Once they all are, a combination is applied; otherwise the Tree is the Value.
+    Tree {
+        tree: TreeName,
+        elements: std::vec::IntoIter<Handle>,
+        values: Vec<Value>,
+        combination: bool,
+    },
+    // An Encode being executed, whose Data is then evaluated in its place.
+    Execute(Box<Execution>),
+}
+
+impl Frame {
+    fn tree(tree: TreeName, combination: bool) -> Result<Self> {
+        let elements = tree.try_load()?;
+        Ok(Frame::Tree {
+            tree,
+            values: Vec::with_capacity(elements.len()),
+            elements: elements.into_iter(),
+            combination,
+        })
     }
 }
 
+// What eval does next (to the frame on top of the stack).
+enum Step {
+    Eval(Handle),
+    Return(Value),
+    NextElement,
+    Think,
+    Thought(Result<RuntimeValue>),
+    Executed(Data),
+}
+
 // Evaluate a Handle to a Value (a data structure with no accessible Encodes).
-// Any Encodes are executed, and accessible Trees are recursed into. Everything else is self-evaluating.
-fn eval(h: Handle) -> Result<Value> {
-    Ok(match h {
-        Handle::Encode(e) => eval(Handle::Data(execute(e)?))?,
-        Handle::Data(d) => Value::Data(match d {
-            Data::Object(Object::Tree(x)) => Data::Object(Object::Tree(x.try_map(eval)?)),
-            Data::Object(Object::Blob(x)) => Data::Object(Object::Blob(x)),
-            Data::Ref(x) => Data::Ref(x),
-        }),
-        Handle::Thunk(thunk) => Value::Thunk(thunk),
-    })
+// Any Encodes are executed (as in execute), and accessible Trees are recursed into.
+// Everything else is self-evaluating.
+// The Encodes found ahead of time are executed first, in parallel (see schedule), and so
+// are those in the combinations of later thoughts.
+fn eval(h: Handle, context: &Context) -> Result<Value> {
+    let mut stack = Vec::new();
+    let result = eval_on(h, &mut stack, context);
+    // A trap while thinking is the trap of every thought being thought about.
+    if let Err(trap) = result {
+        for frame in stack.iter_mut().rev() {
+            if let Frame::Execute(execution) = frame {
+                execution.trapped(trap);
+            }
+        }
+    }
+    result
+}
+
+fn eval_on(h: Handle, stack: &mut Vec<Frame>, context: &Context) -> Result<Value> {
+    let mut traps = schedule::run(h, context);
+    let mut step = Step::Eval(h);
+    loop {
+        step = match step {
+            Step::Eval(Handle::Encode(e)) => {
+                if let Some(&trap) = traps.get(&memo::name(e.thunk)) {
+                    return Err(trap);
+                }
+                stack.push(Frame::Execute(Box::new(Execution::new(e, context))));
+                Step::Think
+            }
+            Step::Eval(Handle::Data(Data::Object(Object::Tree(x)))) => {
+                stack.push(Frame::tree(x, false)?);
+                Step::NextElement
+            }
+            Step::Eval(Handle::Data(Data::Object(Object::Blob(x)))) => {
+                Step::Return(Value::Data(Data::Object(Object::Blob(x))))
+            }
+            Step::Eval(Handle::Data(Data::Ref(x))) => Step::Return(Value::Data(Data::Ref(x))),
+            Step::Eval(Handle::Thunk(x)) => Step::Return(Value::Thunk(x)),
+            Step::Return(value) => match stack.last_mut() {
+                None => return Ok(value),
+                Some(Frame::Tree { values, .. }) => {
+                    values.push(value);
+                    Step::NextElement
+                }
+                Some(Frame::Execute(_)) => unreachable!("Value returned to an Execute"),
+            },
+            Step::NextElement => {
+                let Some(Frame::Tree { elements, .. }) = stack.last_mut() else {
+                    unreachable!("no Tree to evaluate")
+                };
+                match elements.next() {
+                    Some(h) => Step::Eval(h),
+                    None => {
+                        let Some(Frame::Tree {
+                            tree,
+                            values,
+                            combination,
+                            ..
+                        }) = stack.pop()
+                        else {
+                            unreachable!("no Tree to evaluate")
+                        };
+                        let tree = tree.mapped(values)?;
+                        types::record(tree);
+                        if combination {
+                            let Some(Frame::Execute(execution)) = stack.last() else {
+                                unreachable!("combination without an Encode")
+                            };
+                            Step::Thought(apply(tree, &execution.context))
+                        } else {
+                            Step::Return(Value::Data(Data::Object(Object::Tree(tree))))
+                        }
+                    }
+                }
+            }
+            Step::Think => {
+                let Some(Frame::Execute(execution)) = stack.last_mut() else {
+                    unreachable!("no Encode to execute")
+                };
+                let remembered = memo::get(execution.thunk);
+                match remembered {
+                    Some(x) => context.hooks.on_cache_hit(execution.thunk, x),
+                    None => execution.think()?,
+                }
+                match (remembered, execution.thunk) {
+                    (Some(x), _) => Step::Executed(x),
+                    (None, Thunk::Application(combination)) => {
+                        if execution.thoughts.len() > 1 {
+                            let combination = Data::Object(Object::Tree(combination));
+                            traps.extend(schedule::run(Handle::Data(combination), context));
+                        }
+                        stack.push(Frame::tree(combination, true)?);
+                        Step::NextElement
+                    }
+                    (None, thunk) => Step::Thought(think(thunk, &execution.context)),
+                }
+            }
+            Step::Thought(x) => {
+                let Some(Frame::Execute(execution)) = stack.last_mut() else {
+                    unreachable!("no Encode to execute")
+                };
+                match execution.thought(x)? {
+                    Some(x) => Step::Executed(x),
+                    None => Step::Think,
+                }
+            }
+            Step::Executed(x) => {
+                let Some(Frame::Execute(execution)) = stack.pop() else {
+                    unreachable!("no Encode to execute")
+                };
+                Step::Eval(Handle::Data(execution.finish(x)?))
+            }
+        }
+    }
+}
+
+// Evaluate a Handle only `depth` Trees deep: Encodes are executed (as in eval) down to that
+// depth, and accessible Trees below it are left as they are (so may still hold Encodes).
+// A depth of zero only executes a top-level Encode (see eval_shallow).
+// This goes a level at a time rather than recursing (executing each level's Encodes in
+// parallel), then remakes the Trees from the deepest level up. If anything traps, the trap
+// is the first one's, in element order (as with par_try_map).
+fn eval_to_depth(h: Handle, depth: usize, context: &Context) -> Result<Handle> {
+    let top = |h| match h {
+        Handle::Encode(e) => execute(e, context).map(Handle::Data),
+        h => Ok(h),
+    };
+    // Each level below the first is the elements of the accessible Trees in the one above,
+    // with the index of the Tree each came from.
+    let mut levels: Vec<(Vec<Result<Handle>>, Vec<usize>)> = vec![(vec![top(h)], vec![])];
+    while levels.len() <= depth {
+        let (above, _) = levels.last_mut().unwrap();
+        let (mut elements, mut parents) = (Vec::new(), Vec::new());
+        for (i, node) in above.iter_mut().enumerate() {
+            if let Ok(Handle::Data(Data::Object(Object::Tree(x)))) = *node {
+                match x.try_load() {
+                    Ok(x) => {
+                        parents.resize(parents.len() + x.len(), i);
+                        elements.extend(x);
+                    }
+                    Err(trap) => *node = Err(trap),
+                }
+            }
+        }
+        if elements.is_empty() {
+            break;
+        }
+        levels.push((elements.into_par_iter().map(top).collect(), parents));
+    }
+    let (mut below, mut parents) = levels.pop().unwrap();
+    while let Some((mut level, above)) = levels.pop() {
+        let mut elements = vec![Vec::new(); level.len()];
+        for (x, parent) in below.into_iter().zip(parents) {
+            elements[parent].push(x);
+        }
+        for (node, elements) in level.iter_mut().zip(elements) {
+            if let Ok(Handle::Data(Data::Object(Object::Tree(x)))) = *node {
+                *node = elements
+                    .into_iter()
+                    .collect::<Result<Vec<_>>>()
+                    .and_then(|elements| x.mapped(elements))
+                    .map(|x| Handle::Data(Data::Object(Object::Tree(x))));
+            }
+        }
+        (below, parents) = (level, above);
+    }
+    below.pop().unwrap()
+}
+
+// Execute a top-level Encode (without evaluating anything inside its result), so a caller
+// can look at a structure's shape without evaluating all of it.
+fn eval_shallow(h: Handle, context: &Context) -> Result<Handle> {
+    eval_to_depth(h, 0, context)
 }
 
 // impl blocks for Names, Refs, Data, Value, and Handle
 
 // Associated functions of Blob and Tree Names:
-// - load (Name -> object)
-// - name & create (object -> Name)
+// - try_load (Name -> object), which traps on a missing or corrupt object
+// - name & create (object -> Name); create stores the object, and traps if it can't
 // - size & footprint (Name -> usize)
 //
 // TreeNames also support `try_map`, which maps a function over the elements to create a new Tree,
 // as well as `relax`, which converts a TreeName of more-restrictive Handles to a general Treename.
 impl BlobName {
-    fn load(&self) -> &Blob {
-        match self {
-            BlobName::Literal((storage, length)) => &storage[0..*length as usize],
-            BlobName::Name((_, _)) => unimplemented!("load Blob from Pointer"),
-        }
+    fn try_load(&self) -> Result<BlobData<'_>> {
+        self.check();
+        Ok(match self {
+            BlobName::Literal((storage, length)) => {
+                BlobData::Literal(&storage[0..*length as usize])
+            }
+            BlobName::Name((name, size)) => {
+                metrics::add(Counter::BytesLoaded, *size as u64);
+                let started = trace::loading();
+                let blob = stored(chunk::get(*name, *size), "Blob")?;
+                trace::loaded(started, "load Blob", *size);
+                trace::loaded_object(Ref::Blob(*self));
+                BlobData::Stored(blob)
+            }
+        })
     }
 
-    fn name(_blob: &Blob) -> Self {
-        unimplemented!("BlobName::name")
+    // Bytes `start..end` (which must be in bounds), loading only the chunks they overlap.
+    fn try_load_range(&self, start: usize, end: usize) -> Result<Vec<u8>> {
+        self.check();
+        Ok(match self {
+            BlobName::Literal((storage, _)) => storage[start..end].to_vec(),
+            BlobName::Name((name, size)) => {
+                metrics::add(Counter::BytesLoaded, (end - start) as u64);
+                let started = trace::loading();
+                let bytes = stored(chunk::get_range(*name, *size, start, end), "Blob")?;
+                trace::loaded(started, "load Blob", end - start);
+                trace::loaded_object(Ref::Blob(*self));
+                bytes
+            }
+        })
+    }
+
+    // Blobs too long for a Literal are named by their canonical hash: BLAKE3 of the
+    // contents, or for a chunked Blob, the hash of its chunk list (see hash and chunk).
+    fn name(blob: &Blob) -> Self {
+        Self::literal(blob).unwrap_or_else(|| BlobName::Name((chunk::name(blob), blob.len())))
     }
 
-    fn create(_blobdata: Vec<u8>) -> Self {
-        unimplemented!("BlobName::create")
+    fn create(blobdata: Vec<u8>) -> Result<Self> {
+        if let Some(literal) = Self::literal(&blobdata) {
+            return Ok(literal);
+        }
+        let size = blobdata.len();
+        let pointer = put(chunk::put(blobdata.into()))?;
+        Ok(BlobName::Name((pointer, size)))
+    }
+
+    // Name a small Blob by its contents, with no storage interaction or allocation.
+    // The unused storage is zeroed so the Name has a single representation.
+    fn literal(blob: &Blob) -> Option<Self> {
+        let mut storage = [0; 30];
+        storage.get_mut(..blob.len())?.copy_from_slice(blob);
+        Some(BlobName::Literal((storage, blob.len() as u8)))
     }
 
     fn size(&self) -> usize {
+        self.check();
         match self {
             BlobName::Literal((_, length)) => *length as usize,
             BlobName::Name((_, length)) => *length,
@@ -217,26 +797,120 @@ impl BlobName {
     fn footprint(&self) -> u32 {
         self.size().div_ceil(PAGE_SIZE) as u32
     }
+
+    // A Literal can hold at most 30 bytes, and anything that fits must be a Literal.
+    fn check(&self) {
+        if STRICT_INVARIANTS {
+            match self {
+                BlobName::Literal((storage, length)) => assert!(
+                    *length as usize <= storage.len(),
+                    "Literal length {length} out of range"
+                ),
+                BlobName::Name((_, length)) => {
+                    assert!(*length > 30, "Blob of {length} bytes named by Pointer")
+                }
+            }
+        }
+    }
 }
 
 impl<T: HandleType> TreeName<T> {
-    fn load(&self) -> &Tree<T> {
-        unimplemented!("load Tree from Pointer")
+    fn try_load(&self) -> Result<Vec<T>> {
+        let started = trace::loading();
+        let tree = stored(
+            local::storage_of(self.name).get_tree(key(self.name)),
+            "Tree",
+        )?;
+        metrics::add(Counter::BytesLoaded, (tree.len() * HANDLE_SIZE) as u64);
+        trace::loaded(started, "load Tree", tree.len() * HANDLE_SIZE);
+        trace::loaded_object(Ref::Tree(TreeName {
+            tag: false,
+            ..self.cast()
+        }));
+        prefetch::read_ahead(&tree);
+        let tree: Vec<T> = tree.iter().map(|h| T::restrict(h.unpack())).collect();
+        self.check(&tree);
+        Ok(tree)
     }
 
-    fn name(_tree: &Tree<T>) -> Self {
-        unimplemented!("TreeName::name")
+    // A Tree is named by the canonical hash of its packed elements.
+    fn name(tree: &Tree<T>) -> Self {
+        Self::name_packed(tree, &Self::pack(tree))
     }
 
-    fn create(treedata: Vec<T>) -> Self {
-        let _size = treedata.len() as u32;
-        let _footprint = (treedata.len() * HANDLE_SIZE).div_ceil(PAGE_SIZE) as u32
-            + treedata
+    // Local objects within the Tree are stored too (see local).
+    fn create(treedata: Vec<T>) -> Result<Self> {
+        let packed = treedata
+            .iter()
+            .map(|h| local::canonical(PackedHandle::pack(h.relax())))
+            .collect::<io::Result<Vec<_>>>();
+        let packed = put(packed)?;
+        let name = Self::name_packed(&treedata, &packed);
+        put(storage().put_tree(key(name.name), packed.into()))?;
+        Ok(name)
+    }
+
+    // Elements naming local objects are named canonically, without storing them (see local).
+    fn pack(tree: &Tree<T>) -> Vec<PackedHandle> {
+        tree.iter()
+            .map(|h| local::canonical_name(PackedHandle::pack(h.relax())))
+            .collect()
+    }
+
+    fn name_packed(tree: &Tree<T>, packed: &Tree<PackedHandle>) -> Self {
+        let (size, footprint, eq) = Self::metadata(tree);
+        TreeName {
+            name: hash::hash_tree(packed),
+            size,
+            footprint,
+            eq,
+            tag: false,
+        }
+    }
+
+    // Reinterpret the elements as another Handle type (the Name doesn't depend on it).
+    fn cast<U: HandleType>(self) -> TreeName<U> {
+        let (a, b, c, _) = self.name;
+        TreeName {
+            name: (a, b, c, PhantomData),
+            size: self.size,
+            footprint: self.footprint,
+            eq: self.eq,
+            tag: self.tag,
+        }
+    }
+
+    // The size, footprint, and eq-ness of a Tree, as recorded in its Name.
+    fn metadata(tree: &Tree<T>) -> (u32, u32, bool) {
+        let size = tree.len() as u32;
+        let footprint = (tree.len() * HANDLE_SIZE).div_ceil(PAGE_SIZE) as u32
+            + tree
                 .iter()
                 .fold(0, |acc: u32, elem| acc.saturating_add(elem.footprint()));
-        let _eq = treedata.iter().all(|h| h.is_eq());
+        let footprint = footprint.min(packed::MAX_FOOTPRINT);
+        let eq = tree.iter().all(|h| h.is_eq());
+        (size, footprint, eq)
+    }
 
-        unimplemented!("TreeName::create")
+    // Check that this Name's metadata matches the Tree it names.
+    fn check(&self, tree: &Tree<T>) {
+        if STRICT_INVARIANTS {
+            let (size, footprint, eq) = Self::metadata(tree);
+            assert_eq!(self.size, size, "TreeName size does not match its Tree");
+            assert_eq!(
+                self.footprint, footprint,
+                "TreeName footprint does not match its Tree"
+            );
+            assert_eq!(self.eq, eq, "TreeName eq does not match its elements");
+        }
+    }
+
+    // Check that a Name derived from this one (by mapping or relaxing) kept its size and tag.
+    fn check_derived<U: HandleType>(&self, derived: &TreeName<U>) {
+        if STRICT_INVARIANTS {
+            assert_eq!(self.size, derived.size, "derived TreeName changed size");
+            assert_eq!(self.tag, derived.tag, "derived TreeName lost its tag");
+        }
     }
 
     fn size(&self) -> usize {
@@ -247,38 +921,101 @@ impl<T: HandleType> TreeName<T> {
         self.footprint
     }
 
-    fn try_map<FuncType, TgT: HandleType>(&self, f: FuncType) -> Result<TreeName<TgT>>
-    where
-        FuncType: Fn(T) -> Result<TgT>,
-    {
-        self.load()
-            .iter()
-            .map(|h| f(*h))
-            .collect::<Result<Vec<TgT>>>()
-            .map(|vec| TreeName {
-                tag: self.tag,
-                ..TreeName::create(vec)
-            })
+    fn mapped<TgT: HandleType>(&self, vec: Vec<TgT>) -> Result<TreeName<TgT>> {
+        let mapped = TreeName {
+            tag: self.tag,
+            ..TreeName::create(vec)?
+        };
+        self.check_derived(&mapped);
+        Ok(mapped)
     }
 
+    // Relaxing only forgets the element type: the relaxed Tree has the same canonical Name.
     fn relax(self) -> TreeName {
-        TreeName {
-            tag: self.tag,
-            ..TreeName::create(
-                self.load()
-                    .iter()
-                    .map(|h| h.relax())
-                    .collect::<Vec<Handle>>(),
-            )
+        self.cast()
+    }
+
+    // The elements one at a time, loaded a page at a time (from storage that can load a
+    // range of a Tree), so a large Tree can be scanned without loading all of it at once.
+    // Each is a trap if its page is corrupt (and the iterator ends after that).
+    fn try_iter(&self) -> Elements<T> {
+        Elements {
+            tree: *self,
+            next: 0,
+            page: Vec::new().into_iter(),
+            failed: false,
+        }
+    }
+}
+
+// The elements of a Tree, loaded as they're needed (see TreeName::try_iter).
+struct Elements<T: HandleType> {
+    tree: TreeName<T>,
+    // The index of the first element not loaded yet.
+    next: usize,
+    page: std::vec::IntoIter<PackedHandle>,
+    failed: bool,
+}
+
+impl<T: HandleType> Iterator for Elements<T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        if let Some(h) = self.page.next() {
+            return Some(Ok(T::restrict(h.unpack())));
+        }
+        if self.failed || self.next == self.tree.size() {
+            return None;
+        }
+        let end = self.tree.size().min(self.next + PAGE_SIZE / HANDLE_SIZE);
+        let started = trace::loading();
+        let page =
+            local::storage_of(self.tree.name).get_tree_range(key(self.tree.name), self.next, end);
+        match stored(page, "Tree") {
+            Ok(page) => {
+                metrics::add(Counter::BytesLoaded, (page.len() * HANDLE_SIZE) as u64);
+                trace::loaded(started, "load Tree", page.len() * HANDLE_SIZE);
+                self.next = end;
+                self.page = page.into_iter();
+                self.next()
+            }
+            Err(trap) => {
+                self.failed = true;
+                Some(Err(trap))
+            }
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // (Fewer, if a page is corrupt.)
+        let remaining = match self.failed {
+            true => self.page.len(),
+            false => self.page.len() + self.tree.size() - self.next,
+        };
+        (self.page.len(), Some(remaining))
+    }
 }
 
 // Blob Names can always be compared for equality.
 // The Names are equal iff the underlying Blobs are.
+// Literals are compared in place; a Literal never equals a Pointer Name (its Blob is too short).
+// Canonical Pointers are compared by hash; a local one (see local) has no hash, so the Blobs
+// are loaded and compared. (A Blob that can't be loaded equals nothing but its own Name.)
 impl PartialEq for BlobName {
-    fn eq(&self, _other: &Self) -> bool {
-        todo!("equality of BlobNames");
+    fn eq(&self, other: &Self) -> bool {
+        let loaded = || match (self.try_load(), other.try_load()) {
+            (Ok(x), Ok(y)) => *x == *y,
+            _ => false,
+        };
+        match (self, other) {
+            (BlobName::Literal(_), BlobName::Literal(_)) => loaded(),
+            (BlobName::Name((x, m)), BlobName::Name((y, n))) => {
+                m == n
+                    && (key(*x) == key(*y)
+                        || (local::is_local(*x) || local::is_local(*y)) && loaded())
+            }
+            _ => false,
+        }
     }
 }
 
@@ -291,12 +1028,51 @@ impl PartialEq for TreeName {
             return false;
         }
         match (self.eq, other.eq) {
-            (true, true) => todo!("equality of Eq TreeNames"),
+            (true, true) => equal_trees(*self, *other),
             _ => false,
         }
     }
 }
 
+// Two eq Trees are equal iff their elements are, pairwise. The same Pointer is the same Tree,
+// but different ones may still name equal Trees (an element may be an Object in one and a Ref
+// in the other, or local), so those are loaded and compared, a level at a time. Elements
+// packed the same are the same, so runs of those are skipped (see packed::first_difference),
+// and only the others are unpacked. (Trees that can't be loaded are unequal.)
+fn equal_trees(x: TreeName, y: TreeName) -> bool {
+    let load = |x: TreeName| local::storage_of(x.name).get_tree(key(x.name));
+    let mut work = vec![(x, y)];
+    while let Some((x, y)) = work.pop() {
+        if x.tag != y.tag || x.size != y.size {
+            return false;
+        }
+        if key(x.name) == key(y.name) {
+            continue;
+        }
+        let (Ok(Some(x)), Ok(Some(y))) = (load(x), load(y)) else {
+            return false;
+        };
+        let mut start = 0;
+        while let Some(i) = packed::first_difference(&x[start..], &y[start..]) {
+            let i = start + i;
+            let (Some(a), Some(b)) = (x.get(i), y.get(i)) else {
+                return false;
+            };
+            let (Some(Handle::Data(a)), Some(Handle::Data(b))) = (a.try_unpack(), b.try_unpack())
+            else {
+                return false;
+            };
+            match (a.lower(), b.lower()) {
+                (Ref::Blob(a), Ref::Blob(b)) if a == b => {}
+                (Ref::Tree(a), Ref::Tree(b)) => work.push((a, b)),
+                _ => return false,
+            }
+            start = i + 1;
+        }
+    }
+    true
+}
+
 // Associated functions of Refs: is_eq, lift
 impl Ref {
     // Is the Ref eq? (Blobs always are, Trees are iff every element is)
@@ -308,14 +1084,23 @@ impl Ref {
     }
 
     // "lift" a Ref (make it accessible by loading the underlying object)
-    fn lift(&self) -> Object {
-        match self {
-            Ref::Blob(x) => Object::Blob(BlobName::name(x.load())),
-            Ref::Tree(x) => Object::Tree(TreeName {
-                tag: x.tag,
-                ..TreeName::name(x.load())
-            }),
-        }
+    // A local object keeps its local Name.
+    fn lift(&self) -> Result<Object> {
+        Ok(match self {
+            Ref::Blob(x @ BlobName::Name((name, _))) if local::is_local(*name) => Object::Blob(*x),
+            Ref::Tree(x) if local::is_local(x.name) => Object::Tree(*x),
+            Ref::Blob(x) => Object::Blob(BlobName::name(&x.try_load()?)),
+            Ref::Tree(x) => {
+                let tree = x.try_load()?;
+                let lifted = TreeName {
+                    tag: x.tag,
+                    ..TreeName::name(&tree)
+                };
+                lifted.check(&tree);
+                x.check_derived(&lifted);
+                Object::Tree(lifted)
+            }
+        })
     }
 }
 
@@ -345,9 +1130,9 @@ impl<T: HandleType> Object<T> {
 // Associated functions of Data: lift, lower, is_eq, footprint
 // These dispatch to the underlying Object or Ref.
 impl<T: HandleType> Data<T> {
-    fn lift(&self) -> Object {
+    fn lift(&self) -> Result<Object> {
         match self {
-            Data::Object(x) => x.relax(),
+            Data::Object(x) => Ok(x.relax()),
             Data::Ref(x) => x.lift(),
         }
     }
@@ -379,6 +1164,8 @@ trait HandleType: Copy + Clone + PartialEq {
     fn is_eq(&self) -> bool;
     fn footprint(&self) -> u32;
     fn relax(self) -> Handle;
+    // The inverse of relax, for a Handle known a priori to be of this type.
+    fn restrict(h: Handle) -> Self;
 }
 
 // Associated functions of Handle: is_eq, footprint, eq, from(Value)
@@ -400,6 +1187,10 @@ impl HandleType for Handle {
     fn relax(self) -> Handle {
         self
     }
+
+    fn restrict(h: Handle) -> Self {
+        h
+    }
 }
 
 impl PartialEq for Handle {
@@ -448,6 +1239,20 @@ impl HandleType for Value {
             }
         }
     }
+
+    fn restrict(h: Handle) -> Self {
+        match h {
+            Handle::Thunk(x) => Value::Thunk(x),
+            Handle::Data(Data::Ref(x)) => Value::Data(Data::Ref(x)),
+            Handle::Data(Data::Object(Object::Blob(x))) => {
+                Value::Data(Data::Object(Object::Blob(x)))
+            }
+            Handle::Data(Data::Object(Object::Tree(x))) => {
+                Value::Data(Data::Object(Object::Tree(x.cast())))
+            }
+            Handle::Encode(_) => unreachable!("Value Tree contains an Encode"),
+        }
+    }
 }
 
 impl PartialEq for Value {
@@ -456,6 +1261,228 @@ impl PartialEq for Value {
     }
 }
 
+// The command line (see cli).
 fn main() {
-    println!("Hello, world!");
+    if let Err(e) = cli::run(std::env::args().skip(1).collect()) {
+        eprintln!("fixmodel: {e}");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::Path;
+
+    fn blob(contents: &[u8]) -> Data {
+        Data::Object(Object::Blob(
+            BlobName::create(contents.to_vec()).ok().unwrap(),
+        ))
+    }
+
+    fn tree(elements: Vec<Handle>) -> TreeName {
+        TreeName::create(elements).ok().unwrap()
+    }
+
+    fn encode(thunk: Thunk) -> Encode {
+        Encode {
+            thunk,
+            accessibility: None,
+        }
+    }
+
+    // A Thunk that takes `steps` thinks to execute.
+    fn steps(seed: &[u8], steps: usize) -> Thunk {
+        let mut thunk = Thunk::Identification(blob(seed));
+        for _ in 1..steps {
+            let target = Data::Object(Object::Tree(tree(vec![Handle::Thunk(thunk)])));
+            thunk = Path::new().index(0).thunk(target).ok().unwrap();
+        }
+        thunk
+    }
+
+    fn budget(budget: u64) -> Context {
+        Context {
+            step_budget: Some(budget),
+            ..Context::default()
+        }
+    }
+
+    #[test]
+    fn the_step_budget_is_the_number_of_thinks() {
+        let exhausted = |x: Result<Data>| trap::is(x.err().unwrap(), trap::Kind::ResourceExhausted);
+        let thunk = steps(b"a budget of none", 1);
+        assert!(exhausted(execute(encode(thunk), &budget(0))));
+        let thunk = steps(b"a budget of three", 3);
+        assert!(exhausted(execute(encode(thunk), &budget(2))));
+        assert!(execute(encode(thunk), &budget(3)).is_ok());
+    }
+
+    #[test]
+    fn evaluating_to_a_depth_leaves_deeper_encodes() {
+        let inner = Handle::Encode(encode(steps(b"left alone", 1)));
+        let outer = Handle::Encode(encode(steps(b"executed", 2)));
+        let nested = Handle::Data(Data::Object(Object::Tree(tree(vec![inner]))));
+        let h = Handle::Data(Data::Object(Object::Tree(tree(vec![outer, nested]))));
+        let Handle::Data(Data::Object(Object::Tree(x))) =
+            eval_to_depth(h, 1, &Context::default()).ok().unwrap()
+        else {
+            panic!("not a Tree");
+        };
+        let elements = x.try_load().ok().unwrap();
+        assert!(elements[0] == Handle::Data(blob(b"executed")));
+        assert!(PackedHandle::pack(elements[1]) == PackedHandle::pack(nested));
+    }
+
+    #[test]
+    fn deep_trees_evaluate_without_recursing() {
+        let mut h = Handle::Encode(encode(steps(b"at the bottom", 1)));
+        for _ in 0..100_000 {
+            h = Handle::Data(Data::Object(Object::Tree(tree(vec![h]))));
+        }
+        let context = Context::default();
+        let deep = eval_to_depth(h, usize::MAX, &context).ok().unwrap();
+        let evaluated = eval(h, &context).ok().unwrap();
+        assert!(deep == evaluated.relax());
+    }
+}
+
+// The laws lift, lower and relax obey, and executions' determinism, over generated Handles.
+#[cfg(test)]
+mod laws {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::generate::{self, Shape, build};
+
+    // Blobs (Literals and Names) in Trees, Refs and Identifications, a few levels deep.
+    fn shapes() -> impl Strategy<Value = Shape> {
+        let blob = prop::collection::vec(any::<u8>(), 0..100).prop_map(Shape::Blob);
+        blob.prop_recursive(4, 32, 4, |inner| {
+            prop_oneof![
+                (prop::collection::vec(inner.clone(), 0..4), any::<bool>())
+                    .prop_map(|(elements, tag)| Shape::Tree(elements, tag)),
+                inner.clone().prop_map(|x| Shape::Ref(Box::new(x))),
+                inner.prop_map(|x| Shape::Identification(Box::new(x))),
+            ]
+        })
+    }
+
+    // Shapes with Selections (possibly out of range), Applications (which trap without a
+    // procedure) and Encodes of any of them.
+    fn programs() -> impl Strategy<Value = Shape> {
+        let blob = prop::collection::vec(any::<u8>(), 0..40).prop_map(Shape::Blob);
+        blob.prop_recursive(5, 48, 4, |inner| {
+            let accessibility = prop_oneof![Just(None), Just(Some(true)), Just(Some(false))];
+            prop_oneof![
+                2 => (prop::collection::vec(inner.clone(), 0..4), any::<bool>())
+                    .prop_map(|(elements, tag)| Shape::Tree(elements, tag)),
+                1 => inner.clone().prop_map(|x| Shape::Ref(Box::new(x))),
+                1 => inner.clone().prop_map(|x| Shape::Identification(Box::new(x))),
+                3 => (inner.clone(), 0..3u64).prop_map(|(x, i)| Shape::Selection(Box::new(x), i)),
+                1 => prop::collection::vec(inner.clone(), 0..3).prop_map(Shape::Application),
+                3 => (inner, accessibility).prop_map(|(x, a)| Shape::Encode(Box::new(x), a)),
+            ]
+        })
+    }
+
+    // What evaluating a Handle gave (by canonical Name, as traps are local Trees), and
+    // whether it trapped.
+    fn outcome(result: Result<Value>) -> (PackedHandle, Option<Data>) {
+        let (h, trap) = match result {
+            Ok(x) => (x.relax(), None),
+            Err(trap) => (Handle::Data(trap), Some(trap)),
+        };
+        (local::canonical_name(PackedHandle::pack(h)), trap)
+    }
+
+    fn packed(h: impl Into<Handle>) -> PackedHandle {
+        PackedHandle::pack(h.into())
+    }
+
+    proptest! {
+        #[test]
+        fn lowering_undoes_lifting(shape in shapes()) {
+            let Handle::Data(x) = build(&shape) else {
+                return Ok(());
+            };
+            let lowered = x.lower();
+            let lifted = Data::<Handle>::Ref(lowered).lift().ok().unwrap();
+            prop_assert!(packed(Data::Ref(lifted.lower())) == packed(Data::Ref(lowered)));
+            if let Data::Object(object) = x {
+                prop_assert!(packed(Data::Object(x.lift().ok().unwrap())) == packed(Data::Object(object)));
+            }
+            prop_assert_eq!(lowered.is_eq(), x.is_eq());
+        }
+
+        #[test]
+        fn relaxing_is_idempotent_and_keeps_eq(shape in shapes()) {
+            let h = build(&shape);
+            let value = Value::try_from(h).ok().unwrap();
+            let relaxed = value.relax();
+            prop_assert!(packed(relaxed) == packed(h));
+            prop_assert!(packed(relaxed.relax()) == packed(relaxed));
+            prop_assert_eq!(value.is_eq(), relaxed.is_eq());
+            prop_assert_eq!(value.footprint(), relaxed.footprint());
+            if let Value::Data(Data::Object(object)) = value {
+                let once = object.relax();
+                prop_assert!(packed(Data::Object(once.relax())) == packed(Data::Object(once)));
+                prop_assert_eq!(object.is_eq(), once.is_eq());
+            }
+        }
+
+        #[test]
+        fn composition_never_shrinks_footprints(
+            shapes in prop::collection::vec(shapes(), 0..6),
+            extra in shapes(),
+        ) {
+            let mut elements: Vec<Handle> = shapes.iter().map(build).collect();
+            let tree = TreeName::create(elements.clone()).ok().unwrap();
+            for element in &elements {
+                prop_assert!(tree.footprint() >= element.footprint());
+            }
+            prop_assert_eq!(tree.eq, elements.iter().all(|x| x.is_eq()));
+            elements.push(build(&extra));
+            let larger = TreeName::create(elements).ok().unwrap();
+            prop_assert!(larger.footprint() >= tree.footprint());
+        }
+
+        // Evaluating a Handle always gives the same result (the second time, from the memo
+        // table), and a step budget can only make it trap by running out.
+        #[test]
+        fn executions_are_deterministic_within_budgets(program in programs(), budget in 0..6u64) {
+            let h = build(&Shape::Encode(Box::new(program), None));
+            let budgeted = Context {
+                step_budget: Some(budget),
+                ..Context::default()
+            };
+            let (within_budget, trap) = outcome(eval(h, &budgeted));
+            let (first, _) = outcome(eval(h, &Context::default()));
+            let (again, _) = outcome(eval(h, &Context::default()));
+            prop_assert!(again == first);
+            prop_assert!(
+                within_budget == first
+                    || trap.is_some_and(|x| trap::is(x, trap::Kind::ResourceExhausted))
+            );
+        }
+
+        // So do the generator's graphs, of every density of Thunks.
+        #[test]
+        fn generated_graphs_agree_with_the_reference(seed in any::<u64>(), thunks in 0..100u64) {
+            let options = generate::Options {
+                thunks,
+                seed,
+                ..Default::default()
+            };
+            let h = build(&Shape::Encode(Box::new(generate::shape(&options)), None));
+            prop_assert!(reference::cross_check(h, &Context::default()).is_ok());
+        }
+
+        // The optimized evaluator agrees with the reference one, traps included.
+        #[test]
+        fn eval_agrees_with_the_reference(program in programs()) {
+            let h = build(&Shape::Tree(vec![program.clone(), Shape::Encode(Box::new(program), None)], false));
+            prop_assert!(reference::cross_check(h, &Context::default()).is_ok());
+        }
+    }
 }
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, LazyLock, RwLock};
+
+use crate::metrics::{self, Counter};
+use crate::packed::PackedHandle;
+use crate::repository::Repository;
+use crate::sync::Mutex;
+use crate::{Data, Handle, Thunk, equivalence, local};
+
+// Fix computations are deterministic, so the result of forcing a Thunk can be remembered:
+// the memo table maps each Thunk (by its canonical Name, up to equivalence) to the Data it
+// produced.
+// Only results are remembered, never traps.
+//
+// The table is a cache: a garbage collection forgets every result whose Thunk or Data it
+// deleted (see retain).
+//
+// The table can be persisted in a Repository, so results outlive the process: each result
+// is recorded there (canonically, by the Thunk's own canonical Name rather than its
+// representative, so the record holds however Names are equated later), and loaded by the
+// next process to persist the table in the same Repository.
+//
+// Each entry is keyed by the Thunk's representative, and holds the Thunk itself with the
+// result (so it can be re-keyed when Names are equated).
+type Entry = (PackedHandle, PackedHandle);
+
+// The entries, by key, under one lock.
+#[derive(Default)]
+struct Table(Mutex<HashMap<PackedHandle, Entry>>);
+
+impl Table {
+    fn result(&self, name: PackedHandle) -> Option<PackedHandle> {
+        self.0.lock().unwrap().get(&name).map(|&(_, result)| result)
+    }
+
+    fn insert(&self, entries: impl IntoIterator<Item = (PackedHandle, Entry)>) {
+        self.0.lock().unwrap().extend(entries);
+    }
+
+    // Re-key every entry by `key` of its Thunk. (Under one lock, so no lookup finds the
+    // table half re-keyed, and no insert is lost to it.)
+    fn rekey(&self, key: impl Fn(PackedHandle) -> PackedHandle) {
+        let mut table = self.0.lock().unwrap();
+        let entries: Vec<_> = table.drain().map(|(_, entry)| entry).collect();
+        for (thunk, result) in entries {
+            table.insert(key(thunk), (thunk, result));
+        }
+    }
+
+    fn retain(&self, keep: impl Fn(&Entry) -> bool) {
+        self.0.lock().unwrap().retain(|_, entry| keep(entry));
+    }
+
+    fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+static MEMO: LazyLock<Table> = LazyLock::new(Table::default);
+
+static PERSISTENT: RwLock<Option<Arc<Repository>>> = RwLock::new(None);
+
+// Persist the table in a Repository: remember every result recorded there, and record every
+// result remembered from now on (written when the Repository is flushed). The Repository
+// must be the Storage (or one of its tiers), so it holds the objects the results name.
+pub(crate) fn persist(repository: Arc<Repository>) -> io::Result<()> {
+    let remembered: Vec<_> = repository
+        .remembered()?
+        .into_iter()
+        .map(|(thunk, result)| (equivalence::representative(thunk), (thunk, result)))
+        .collect();
+    MEMO.insert(remembered);
+    *PERSISTENT.write().unwrap() = Some(repository);
+    Ok(())
+}
+
+// The key for a Thunk: its canonical Name, or the representative of its equivalence class
+// (see equivalence).
+pub(crate) fn name(thunk: Thunk) -> PackedHandle {
+    equivalence::representative(PackedHandle::pack(Handle::Thunk(thunk)))
+}
+
+// The remembered result of a Thunk, counted as a cache hit or miss (so only executing an
+// Encode should ask this way; anything else looking ahead uses lookup).
+pub(crate) fn get(thunk: Thunk) -> Option<Data> {
+    let result = lookup(thunk);
+    metrics::count(match result {
+        Some(_) => Counter::CacheHits,
+        None => Counter::CacheMisses,
+    });
+    result
+}
+
+// The remembered result of a Thunk, uncounted. (The key is found before locking the table,
+// as finding it may load Trees.)
+pub(crate) fn lookup(thunk: Thunk) -> Option<Data> {
+    let name = name(thunk);
+    let result = MEMO.result(name)?;
+    match result.unpack() {
+        Handle::Data(x) => Some(x),
+        _ => unreachable!("memoized result is not Data"),
+    }
+}
+
+// Remember that each of `thunks` produces `result`. Fails if the table is persisted and the
+// local objects (see local) the record names can't be stored.
+pub(crate) fn put(thunks: &[Thunk], result: Data) -> io::Result<()> {
+    let names: Vec<_> = thunks.iter().map(|&x| name(x)).collect();
+    let result = PackedHandle::pack(Handle::Data(result));
+    if let Some(repository) = &*PERSISTENT.read().unwrap() {
+        let result = local::canonical(result)?;
+        for &thunk in thunks {
+            repository.remember(
+                local::canonical(PackedHandle::pack(Handle::Thunk(thunk)))?,
+                result,
+            );
+        }
+    }
+    MEMO.insert(
+        thunks
+            .iter()
+            .zip(names)
+            .map(|(&thunk, name)| (name, (PackedHandle::pack(Handle::Thunk(thunk)), result))),
+    );
+    Ok(())
+}
+
+// Re-key every remembered result by its Thunk's representative (after Names were equated).
+// (Finding the keys, with the table locked, may load Trees: equating is rare.)
+pub(crate) fn rekey() {
+    MEMO.rekey(equivalence::representative);
+}
+
+// Forget every result for which `keep` rejects the Thunk's Name (or representative) or the
+// result, here and in the persisted table.
+pub(crate) fn retain(keep: impl Fn(PackedHandle) -> bool) -> io::Result<()> {
+    MEMO.retain(|&(thunk, result)| keep(thunk) && keep(result));
+    match &*PERSISTENT.read().unwrap() {
+        Some(repository) => repository.retain_memo(|&(thunk, result)| keep(thunk) && keep(result)),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn clear() -> io::Result<()> {
+    MEMO.clear();
+    if let Some(repository) = &*PERSISTENT.read().unwrap() {
+        repository.forget()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BlobName, Object, TreeName};
+
+    fn data(contents: &[u8]) -> Data {
+        Data::Object(Object::Blob(BlobName::literal(contents).unwrap()))
+    }
+
+    #[test]
+    fn local_and_canonical_thunks_share_a_result() {
+        let blob = Handle::Data(Data::Object(Object::Blob(local::blob(
+            b"memoized under its canonical Name".to_vec(),
+        ))));
+        let local = Thunk::Selection(local::tree(vec![blob]));
+        let canonical = match local::canonicalize(Handle::Thunk(local)).unwrap() {
+            Handle::Thunk(x) => x,
+            _ => unreachable!(),
+        };
+        assert!(lookup(canonical).is_none());
+        put(&[local], data(b"result")).unwrap();
+        let before = metrics::metrics();
+        assert!(get(canonical).is_some_and(|x| x == data(b"result")));
+        assert!(metrics::metrics().since(&before).cache_hits >= 1);
+    }
+
+    #[test]
+    fn retaining_forgets_what_is_rejected() {
+        let thunk = |x: &[u8]| {
+            let tree = TreeName::create(vec![Handle::Data(data(x))]).ok().unwrap();
+            Thunk::Selection(tree)
+        };
+        let (kept, forgotten) = (thunk(b"kept by retain"), thunk(b"forgotten by retain"));
+        put(&[kept], data(b"kept")).unwrap();
+        put(&[forgotten], data(b"forgotten")).unwrap();
+        let rejected = PackedHandle::pack(Handle::Data(data(b"forgotten")));
+        retain(|h| h != rejected).unwrap();
+        assert!(lookup(kept).is_some());
+        assert!(lookup(forgotten).is_none());
+    }
+
+    // A log recording a Thunk as producing an Encode isn't loaded (where looking the Thunk up
+    // would find a result that isn't Data).
+    #[test]
+    fn a_malformed_log_is_not_loaded() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path().join("repository");
+        drop(Repository::create(&root).unwrap());
+        let thunk = Thunk::Identification(data(b"logged as an Encode"));
+        let encode = Handle::Encode(crate::Encode {
+            thunk,
+            accessibility: None,
+        });
+        let record = [
+            PackedHandle::pack(Handle::Thunk(thunk)),
+            PackedHandle::pack(encode),
+        ];
+        std::fs::write(root.join("memo"), record.map(|x| *x.as_bytes()).concat()).unwrap();
+        let repository = Arc::new(Repository::open(&root).unwrap());
+        assert!(persist(repository).is_err());
+        assert!(lookup(thunk).is_none());
+    }
+
+    // Threads remembering and looking up results at once only ever see a Thunk's own result.
+    #[test]
+    fn concurrent_puts_and_lookups_agree() {
+        let thunk = |i: u32| {
+            Thunk::Identification(data(&[b"put concurrently ", &i.to_le_bytes()[..]].concat()))
+        };
+        let result = |i: u32| data(&i.to_le_bytes());
+        std::thread::scope(|scope| {
+            for t in 0..8 {
+                scope.spawn(move || {
+                    for i in 0..400 {
+                        let mine = t * 400 + i;
+                        put(&[thunk(mine)], result(mine)).unwrap();
+                        assert!(lookup(thunk(mine)) == Some(result(mine)));
+                        let theirs = (mine * 7) % 3200;
+                        assert!(lookup(thunk(theirs)).is_none_or(|x| x == result(theirs)));
+                    }
+                });
+            }
+        });
+        assert!((0..3200).all(|i| lookup(thunk(i)) == Some(result(i))));
+    }
+
+    // A put racing a re-keying is never lost, however they interleave, and the re-keyed
+    // result is found under its new key.
+    #[cfg(loom)]
+    #[test]
+    fn loom_a_put_racing_a_rekey_is_kept() {
+        let name = |x: &[u8]| PackedHandle::pack(Handle::Thunk(Thunk::Identification(data(x))));
+        let result = |x: &[u8]| PackedHandle::pack(Handle::Data(data(x)));
+        let (equated, representative, put) =
+            (name(b"equated"), name(b"representative"), name(b"put"));
+        loom::model(move || {
+            let table = Arc::new(Table::default());
+            table.insert([(equated, (equated, result(b"remembered")))]);
+            let putting = {
+                let table = table.clone();
+                loom::thread::spawn(move || table.insert([(put, (put, result(b"put")))]))
+            };
+            let rekeying = {
+                let table = table.clone();
+                loom::thread::spawn(move || {
+                    table.rekey(|x| if x == equated { representative } else { x })
+                })
+            };
+            assert!(table.result(put).is_none_or(|x| x == result(b"put")));
+            putting.join().unwrap();
+            rekeying.join().unwrap();
+            assert!(table.result(representative) == Some(result(b"remembered")));
+            assert!(table.result(put) == Some(result(b"put")));
+            assert!(table.result(equated).is_none());
+        });
+    }
+}
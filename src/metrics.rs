@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Counters of the work evaluations do (in this process, since it started), for reporting
+// throughput: take a snapshot before and after, and subtract.
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct Metrics {
+    // Steps of executions: each time one thinks about a Thunk it has no memoized result for.
+    pub(crate) thinks: u64,
+    pub(crate) applies: u64,
+    pub(crate) selections: u64,
+    // Lookups in the memo table (by executions and by the scheduler).
+    pub(crate) cache_hits: u64,
+    pub(crate) cache_misses: u64,
+    // Blob contents and Tree elements (packed) loaded, other than Literals.
+    pub(crate) bytes_loaded: u64,
+    // Applies and selections that trapped, and executions stopped (by a step budget, a cycle,
+    // cancellation or a timeout).
+    pub(crate) traps: u64,
+}
+
+impl Metrics {
+    // The counts since an earlier snapshot.
+    pub(crate) fn since(&self, earlier: &Metrics) -> Metrics {
+        Metrics {
+            thinks: self.thinks - earlier.thinks,
+            applies: self.applies - earlier.applies,
+            selections: self.selections - earlier.selections,
+            cache_hits: self.cache_hits - earlier.cache_hits,
+            cache_misses: self.cache_misses - earlier.cache_misses,
+            bytes_loaded: self.bytes_loaded - earlier.bytes_loaded,
+            traps: self.traps - earlier.traps,
+        }
+    }
+
+    // The fraction of memo lookups that hit (0 if there were none).
+    pub(crate) fn hit_ratio(&self) -> f64 {
+        let lookups = self.cache_hits + self.cache_misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.cache_hits as f64 / lookups as f64
+    }
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum Counter {
+    Thinks,
+    Applies,
+    Selections,
+    CacheHits,
+    CacheMisses,
+    BytesLoaded,
+    Traps,
+}
+
+static COUNTERS: [AtomicU64; 7] = [const { AtomicU64::new(0) }; 7];
+
+pub(crate) fn add(counter: Counter, n: u64) {
+    COUNTERS[counter as usize].fetch_add(n, Ordering::Relaxed);
+}
+
+pub(crate) fn count(counter: Counter) {
+    add(counter, 1);
+}
+
+// A snapshot of the counters.
+pub(crate) fn metrics() -> Metrics {
+    let get = |counter: Counter| COUNTERS[counter as usize].load(Ordering::Relaxed);
+    Metrics {
+        thinks: get(Counter::Thinks),
+        applies: get(Counter::Applies),
+        selections: get(Counter::Selections),
+        cache_hits: get(Counter::CacheHits),
+        cache_misses: get(Counter::CacheMisses),
+        bytes_loaded: get(Counter::BytesLoaded),
+        traps: get(Counter::Traps),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_since_a_snapshot() {
+        let before = metrics();
+        add(Counter::BytesLoaded, 100);
+        count(Counter::Traps);
+        // (Other tests count concurrently, so these are lower bounds.)
+        let since = metrics().since(&before);
+        assert!(since.bytes_loaded >= 100);
+        assert!(since.traps >= 1);
+    }
+
+    #[test]
+    fn the_hit_ratio_is_of_lookups() {
+        let metrics = |cache_hits, cache_misses| Metrics {
+            cache_hits,
+            cache_misses,
+            ..Default::default()
+        };
+        assert_eq!(metrics(0, 0).hit_ratio(), 0.0);
+        assert_eq!(metrics(3, 1).hit_ratio(), 0.75);
+    }
+}
@@ -0,0 +1,614 @@
+use std::marker::PhantomData;
+
+use crate::storage::{Key, key};
+use crate::{
+    BlobName, Data, Encode, HANDLE_SIZE, Handle, Object, PAGE_SIZE, Pointer, Ref, Thunk, TreeName,
+};
+
+// A PackedHandle is the 256-bit form of a Handle, as stored in the elements of a Tree.
+// (The enum form is far larger than HANDLE_SIZE and is only used to describe the semantics.)
+// It's also how Handles leave the process: archives, labels, the memo log, and serialized
+// Handles all hold this form (canonicalized), and pack and try_unpack convert losslessly.
+// So it's the crate's raw wire and ABI representation too: there's no separate raw type, and
+// from_bytes and as_bytes are the conversions to and from the wire.
+//
+// Layout (byte offsets; multi-byte fields are little-endian):
+//
+//   0..24   Pointer (three u64s), or the first 24 bytes of a Literal
+//   24..31  Blob Name: size (48 bits), then 0
+//           Literal:   bytes 24..30 of the contents, then the length (0..=30)
+//           Tree Name: size (32 bits), then footprint (24 bits)
+//   31      kind: bits 0..5 shape (see Shape::code), bit 5 is_tree,
+//                 bit 6 literal (Blobs) / eq (Trees), bit 7 tag (Trees)
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C, align(8))]
+pub(crate) struct PackedHandle([u8; 32]);
+
+const _: () = assert!(size_of::<PackedHandle>() == HANDLE_SIZE);
+const _: () = assert!(align_of::<PackedHandle>() == 8);
+
+// The largest Blob size a packed Name can express.
+pub(crate) const MAX_BLOB_SIZE: usize = (1 << 48) - 1;
+
+// The largest Tree footprint a packed Name can express (Tree footprints saturate here).
+pub(crate) const MAX_FOOTPRINT: u32 = (1 << 24) - 1;
+
+const KIND: usize = 31;
+const LITERAL_LENGTH: usize = 30;
+const IS_TREE: u8 = 1 << 5;
+const LITERAL_OR_EQ: u8 = 1 << 6;
+const TAG: u8 = 1 << 7;
+
+// The "shape" of a Handle is everything about it except the Name it carries.
+// Encodes take 12 shapes: one per accessibility (None, Some(true), Some(false))
+// for each of the four Thunk shapes.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum ThunkShape {
+    IdentifyRef,
+    IdentifyObject,
+    Selection,
+    Application,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Shape {
+    Ref,
+    Object,
+    Thunk(ThunkShape),
+    Encode(ThunkShape, Option<bool>),
+}
+
+const THUNK_SHAPES: [ThunkShape; 4] = [
+    ThunkShape::IdentifyRef,
+    ThunkShape::IdentifyObject,
+    ThunkShape::Selection,
+    ThunkShape::Application,
+];
+const ACCESSIBILITIES: [Option<bool>; 3] = [None, Some(true), Some(false)];
+
+impl Shape {
+    fn code(self) -> u8 {
+        let thunk = |t| THUNK_SHAPES.iter().position(|&s| s == t).unwrap() as u8;
+        match self {
+            Shape::Ref => 0,
+            Shape::Object => 1,
+            Shape::Thunk(t) => 2 + thunk(t),
+            Shape::Encode(t, a) => {
+                6 + 4 * ACCESSIBILITIES.iter().position(|&s| s == a).unwrap() as u8 + thunk(t)
+            }
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Shape> {
+        Some(match code {
+            0 => Shape::Ref,
+            1 => Shape::Object,
+            2..6 => Shape::Thunk(THUNK_SHAPES[(code - 2) as usize]),
+            6..18 => Shape::Encode(
+                THUNK_SHAPES[((code - 6) % 4) as usize],
+                ACCESSIBILITIES[((code - 6) / 4) as usize],
+            ),
+            _ => return None,
+        })
+    }
+}
+
+// The Name inside a Handle, with the type parameter forgotten.
+enum Named {
+    Blob(BlobName),
+    Tree(TreeName),
+}
+
+fn split(h: Handle) -> (Shape, Named) {
+    fn data(d: Data) -> (bool, Named) {
+        match d {
+            Data::Ref(Ref::Blob(x)) => (false, Named::Blob(x)),
+            Data::Ref(Ref::Tree(x)) => (false, Named::Tree(x)),
+            Data::Object(Object::Blob(x)) => (true, Named::Blob(x)),
+            Data::Object(Object::Tree(x)) => (true, Named::Tree(x)),
+        }
+    }
+    fn thunk(t: Thunk) -> (ThunkShape, Named) {
+        match t {
+            Thunk::Identification(d) => match data(d) {
+                (false, n) => (ThunkShape::IdentifyRef, n),
+                (true, n) => (ThunkShape::IdentifyObject, n),
+            },
+            Thunk::Selection(x) => (ThunkShape::Selection, Named::Tree(x)),
+            Thunk::Application(x) => (ThunkShape::Application, Named::Tree(x)),
+        }
+    }
+    match h {
+        Handle::Data(d) => match data(d) {
+            (false, n) => (Shape::Ref, n),
+            (true, n) => (Shape::Object, n),
+        },
+        Handle::Thunk(t) => {
+            let (t, n) = thunk(t);
+            (Shape::Thunk(t), n)
+        }
+        Handle::Encode(Encode {
+            thunk: t,
+            accessibility,
+        }) => {
+            let (t, n) = thunk(t);
+            (Shape::Encode(t, accessibility), n)
+        }
+    }
+}
+
+fn join(shape: Shape, named: Named) -> Option<Handle> {
+    fn data(object: bool, n: Named) -> Data {
+        match (object, n) {
+            (false, Named::Blob(x)) => Data::Ref(Ref::Blob(x)),
+            (false, Named::Tree(x)) => Data::Ref(Ref::Tree(x)),
+            (true, Named::Blob(x)) => Data::Object(Object::Blob(x)),
+            (true, Named::Tree(x)) => Data::Object(Object::Tree(x)),
+        }
+    }
+    fn thunk(t: ThunkShape, n: Named) -> Option<Thunk> {
+        Some(match (t, n) {
+            (ThunkShape::IdentifyRef, n) => Thunk::Identification(data(false, n)),
+            (ThunkShape::IdentifyObject, n) => Thunk::Identification(data(true, n)),
+            (ThunkShape::Selection, Named::Tree(x)) => Thunk::Selection(x),
+            (ThunkShape::Application, Named::Tree(x)) => Thunk::Application(x),
+            _ => return None,
+        })
+    }
+    Some(match shape {
+        Shape::Ref => Handle::Data(data(false, named)),
+        Shape::Object => Handle::Data(data(true, named)),
+        Shape::Thunk(t) => Handle::Thunk(thunk(t, named)?),
+        Shape::Encode(t, accessibility) => Handle::Encode(Encode {
+            thunk: thunk(t, named)?,
+            accessibility,
+        }),
+    })
+}
+
+fn put_pointer<T: ?Sized>(bytes: &mut [u8; 32], (a, b, c, _): Pointer<T>) {
+    bytes[0..8].copy_from_slice(&a.to_le_bytes());
+    bytes[8..16].copy_from_slice(&b.to_le_bytes());
+    bytes[16..24].copy_from_slice(&c.to_le_bytes());
+}
+
+fn get_pointer<T: ?Sized>(bytes: &[u8; 32]) -> Pointer<T> {
+    (
+        get_u64(&bytes[0..8]),
+        get_u64(&bytes[8..16]),
+        get_u64(&bytes[16..24]),
+        PhantomData,
+    )
+}
+
+fn get_u64(bytes: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    word[..bytes.len()].copy_from_slice(bytes);
+    u64::from_le_bytes(word)
+}
+
+impl PackedHandle {
+    pub(crate) fn pack(h: Handle) -> PackedHandle {
+        let mut bytes = [0; 32];
+        let (shape, named) = split(h);
+        let mut kind = shape.code();
+        match named {
+            Named::Blob(BlobName::Literal((storage, length))) => {
+                bytes[..length as usize].copy_from_slice(&storage[..length as usize]);
+                bytes[LITERAL_LENGTH] = length;
+                kind |= LITERAL_OR_EQ;
+            }
+            Named::Blob(BlobName::Name((pointer, size))) => {
+                assert!(size <= MAX_BLOB_SIZE, "Blob too large to name");
+                put_pointer(&mut bytes, pointer);
+                bytes[24..30].copy_from_slice(&(size as u64).to_le_bytes()[..6]);
+            }
+            Named::Tree(x) => {
+                assert!(x.footprint <= MAX_FOOTPRINT, "Tree footprint out of range");
+                put_pointer(&mut bytes, x.name);
+                bytes[24..28].copy_from_slice(&x.size.to_le_bytes());
+                bytes[28..31].copy_from_slice(&x.footprint.to_le_bytes()[..3]);
+                kind |= IS_TREE;
+                if x.eq {
+                    kind |= LITERAL_OR_EQ;
+                }
+                if x.tag {
+                    kind |= TAG;
+                }
+            }
+        }
+        bytes[KIND] = kind;
+        PackedHandle(bytes)
+    }
+
+    pub(crate) fn unpack(&self) -> Handle {
+        self.try_unpack().expect("malformed PackedHandle")
+    }
+
+    // Unpack bytes from an untrusted source: None unless they're the one packed form of a
+    // Handle (see is_canonical), so the Names it yields uphold the invariants loading relies on.
+    pub(crate) fn try_unpack(&self) -> Option<Handle> {
+        let shape = Shape::from_code(self.kind() & (IS_TREE - 1))?;
+        if !self.is_canonical() {
+            return None;
+        }
+        join(shape, self.named())
+    }
+
+    fn named(&self) -> Named {
+        let bytes = &self.0;
+        if self.is_tree() {
+            Named::Tree(TreeName {
+                name: get_pointer(bytes),
+                size: self.size() as u32,
+                footprint: get_u64(&bytes[28..31]) as u32,
+                eq: self.kind() & LITERAL_OR_EQ != 0,
+                tag: self.tag(),
+            })
+        } else if self.is_literal() {
+            let mut storage = [0; 30];
+            storage.copy_from_slice(&bytes[..30]);
+            Named::Blob(BlobName::Literal((storage, bytes[LITERAL_LENGTH])))
+        } else {
+            Named::Blob(BlobName::Name((get_pointer(bytes), self.size())))
+        }
+    }
+
+    fn kind(&self) -> u8 {
+        self.0[KIND]
+    }
+
+    #[cfg(test)]
+    fn shape(&self) -> Shape {
+        Shape::from_code(self.kind() & (IS_TREE - 1)).expect("malformed PackedHandle")
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub(crate) fn from_bytes(bytes: [u8; 32]) -> Self {
+        PackedHandle(bytes)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_data(&self) -> bool {
+        matches!(self.shape(), Shape::Ref | Shape::Object)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_thunk(&self) -> bool {
+        matches!(self.shape(), Shape::Thunk(_))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_encode(&self) -> bool {
+        matches!(self.shape(), Shape::Encode(..))
+    }
+
+    // Is the Name a Tree's (as opposed to a Blob's)?
+    pub(crate) fn is_tree(&self) -> bool {
+        self.kind() & IS_TREE != 0
+    }
+
+    pub(crate) fn is_literal(&self) -> bool {
+        !self.is_tree() && self.kind() & LITERAL_OR_EQ != 0
+    }
+
+    pub(crate) fn tag(&self) -> bool {
+        self.is_tree() && self.kind() & TAG != 0
+    }
+
+    // The storage key of the named object, unless it is a Literal (which is not stored).
+    pub(crate) fn key(&self) -> Option<Key> {
+        (!self.is_literal()).then(|| key(get_pointer::<()>(&self.0)))
+    }
+
+    // The same Handle, naming the object with another storage key (not for Literals).
+    pub(crate) fn with_key(&self, (a, b, c): Key) -> PackedHandle {
+        assert!(!self.is_literal(), "a Literal has no storage key");
+        let mut bytes = self.0;
+        put_pointer::<()>(&mut bytes, (a, b, c, PhantomData));
+        PackedHandle(bytes)
+    }
+
+    // The size of the named Blob (in bytes) or Tree (in elements).
+    pub(crate) fn size(&self) -> usize {
+        let bytes = &self.0;
+        if self.is_tree() {
+            get_u64(&bytes[24..28]) as usize
+        } else if self.is_literal() {
+            bytes[LITERAL_LENGTH] as usize
+        } else {
+            get_u64(&bytes[24..30]) as usize
+        }
+    }
+
+    // The size, footprint and eq a Tree Name records (None for a Blob Name).
+    pub(crate) fn tree_metadata(&self) -> Option<(u32, u32, bool)> {
+        match self.named() {
+            Named::Tree(x) => Some((x.size, x.footprint, x.eq)),
+            Named::Blob(_) => None,
+        }
+    }
+
+    // Is this the one packed form of its Handle?
+    // - a Literal holds at most 30 bytes, and its unused bytes are zero
+    // - a Blob Name is of more than 30 bytes (shorter Blobs are Literals), and the byte after
+    //   its size is zero
+    // - only a Tree Name is tagged, and its footprint is at least that of the Tree itself
+    pub(crate) fn is_canonical(&self) -> bool {
+        let bytes = &self.0;
+        if self.is_tree() {
+            let own = (self.size() * HANDLE_SIZE).div_ceil(PAGE_SIZE) as u32;
+            get_u64(&bytes[28..31]) as u32 >= own.min(MAX_FOOTPRINT)
+        } else if self.kind() & TAG != 0 {
+            false
+        } else if self.is_literal() {
+            let length = bytes[LITERAL_LENGTH] as usize;
+            length <= LITERAL_LENGTH && bytes[length..LITERAL_LENGTH].iter().all(|&b| b == 0)
+        } else {
+            self.size() > LITERAL_LENGTH && bytes[30] == 0
+        }
+    }
+
+    #[cfg(test)]
+    // Same as Handle::is_eq, without unpacking.
+    pub(crate) fn is_eq(&self) -> bool {
+        self.is_data() && (!self.is_tree() || self.kind() & LITERAL_OR_EQ != 0)
+    }
+
+    #[cfg(test)]
+    // Same as Handle::footprint, without unpacking.
+    pub(crate) fn footprint(&self) -> u32 {
+        match (self.shape(), self.is_tree()) {
+            (Shape::Object, true) => get_u64(&self.0[28..31]) as u32,
+            (Shape::Object, false) => self.size().div_ceil(PAGE_SIZE) as u32,
+            _ => 0,
+        }
+    }
+}
+
+impl From<Handle> for PackedHandle {
+    fn from(h: Handle) -> Self {
+        PackedHandle::pack(h)
+    }
+}
+
+impl From<PackedHandle> for Handle {
+    fn from(h: PackedHandle) -> Self {
+        h.unpack()
+    }
+}
+
+// Bulk comparison of packed Handles (for Tree equality, see equal_trees).
+// A PackedHandle is exactly one 256-bit vector, so on x86-64 with AVX2 each
+// element is compared with a single instruction; elsewhere they are compared as four u64 words.
+
+// The index of the first element at which two runs of Handles differ,
+// or None if they are identical.
+pub(crate) fn first_difference(a: &[PackedHandle], b: &[PackedHandle]) -> Option<usize> {
+    let n = a.len().min(b.len());
+    #[cfg(target_arch = "x86_64")]
+    let common = if is_x86_feature_detected!("avx2") {
+        // SAFETY: AVX2 support was just checked.
+        unsafe { avx2::first_difference(&a[..n], &b[..n]) }
+    } else {
+        first_difference_words(&a[..n], &b[..n])
+    };
+    #[cfg(not(target_arch = "x86_64"))]
+    let common = first_difference_words(&a[..n], &b[..n]);
+    common.or((a.len() != b.len()).then_some(n))
+}
+
+fn first_difference_words(a: &[PackedHandle], b: &[PackedHandle]) -> Option<usize> {
+    a.iter().zip(b).position(|(x, y)| x.words() != y.words())
+}
+
+impl PackedHandle {
+    fn words(&self) -> [u64; 4] {
+        std::array::from_fn(|i| get_u64(&self.0[8 * i..8 * (i + 1)]))
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::{__m256i, _mm256_cmpeq_epi8, _mm256_loadu_si256, _mm256_movemask_epi8};
+
+    use super::PackedHandle;
+
+    #[target_feature(enable = "avx2")]
+    fn load(h: &PackedHandle) -> __m256i {
+        // SAFETY: a PackedHandle is 32 readable bytes, and loadu has no alignment requirement.
+        unsafe { _mm256_loadu_si256(h.0.as_ptr().cast()) }
+    }
+
+    #[target_feature(enable = "avx2")]
+    fn same(x: __m256i, y: __m256i) -> bool {
+        _mm256_movemask_epi8(_mm256_cmpeq_epi8(x, y)) == -1
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) fn first_difference(a: &[PackedHandle], b: &[PackedHandle]) -> Option<usize> {
+        a.iter().zip(b).position(|(x, y)| !same(load(x), load(y)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::HandleType;
+
+    fn blob(len: usize) -> Data {
+        Data::Object(Object::Blob(BlobName::create(vec![7; len]).ok().unwrap()))
+    }
+
+    fn tree(elements: Vec<Handle>, tag: bool) -> TreeName {
+        TreeName {
+            tag,
+            ..TreeName::create(elements).ok().unwrap()
+        }
+    }
+
+    // A Handle of every shape, naming Literals, Blob Names and Trees.
+    fn samples() -> Vec<Handle> {
+        let names = [
+            blob(0),
+            blob(30),
+            blob(31),
+            blob(100_000),
+            Data::Object(Object::Tree(tree(vec![Handle::Data(blob(3))], false))),
+            Data::Object(Object::Tree(tree(vec![Handle::Data(blob(40))], true))),
+            Data::Object(Object::Tree(tree(vec![Handle::Data(blob(1)); 5000], false))),
+        ];
+        let mut handles = Vec::new();
+        for data in names {
+            let thunks: Vec<Thunk> = [
+                Thunk::Identification(data),
+                Thunk::Identification(Data::Ref(data.lower())),
+            ]
+            .into_iter()
+            .chain(match data {
+                Data::Object(Object::Tree(x)) => vec![Thunk::Selection(x), Thunk::Application(x)],
+                _ => vec![],
+            })
+            .collect();
+            handles.push(Handle::Data(data));
+            handles.push(Handle::Data(Data::Ref(data.lower())));
+            for thunk in thunks {
+                handles.push(Handle::Thunk(thunk));
+                for accessibility in ACCESSIBILITIES {
+                    handles.push(Handle::Encode(Encode {
+                        thunk,
+                        accessibility,
+                    }));
+                }
+            }
+        }
+        handles
+    }
+
+    fn bytes(h: Handle) -> [u8; 32] {
+        *PackedHandle::pack(h).as_bytes()
+    }
+
+    #[test]
+    fn round_trip() {
+        for h in samples() {
+            let packed = PackedHandle::pack(h);
+            assert!(packed.is_canonical());
+            assert_eq!(packed.try_unpack().map(bytes), Some(bytes(h)));
+            let wire = PackedHandle::from_bytes(*packed.as_bytes());
+            assert_eq!(wire.try_unpack().map(bytes), Some(bytes(h)));
+        }
+    }
+
+    // Every 32 bytes try_unpack accepts are the one packed form of their Handle, so bytes
+    // from the wire round-trip exactly (here, each sample with each byte flipped).
+    #[test]
+    fn accepted_bytes_round_trip_exactly() {
+        for h in samples() {
+            for i in 0..32 {
+                for flip in [1, 0x80, 0xff] {
+                    let mut wire = bytes(h);
+                    wire[i] ^= flip;
+                    if let Some(x) = PackedHandle::from_bytes(wire).try_unpack() {
+                        assert_eq!(bytes(x), wire);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn first_difference_matches_comparing_words() {
+        let handles: Vec<_> = samples().into_iter().map(PackedHandle::pack).collect();
+        for i in 0..handles.len() {
+            let mut other = handles.clone();
+            other[i] = PackedHandle::pack(Handle::Data(blob(2)));
+            for (a, b) in [(&handles[..], &other[..]), (&handles[..i], &handles[..])] {
+                let n = a.len().min(b.len());
+                let words =
+                    first_difference_words(&a[..n], &b[..n]).or((a.len() != b.len()).then_some(n));
+                assert_eq!(first_difference(a, b), words);
+            }
+        }
+        assert_eq!(first_difference(&handles, &handles), None);
+    }
+
+    #[test]
+    fn trees_compare_past_identical_elements() {
+        let same = vec![Handle::Data(blob(1)); 100];
+        let with = |last: Data| {
+            let mut elements = same.clone();
+            elements.push(Handle::Data(last));
+            TreeName::create(elements).ok().unwrap()
+        };
+        let object = blob(50);
+        assert!(with(object) == with(Data::Ref(object.lower())));
+        assert!(with(object) != with(blob(51)));
+    }
+
+    #[test]
+    fn metadata_without_unpacking() {
+        for h in samples() {
+            let packed = PackedHandle::pack(h);
+            assert_eq!(packed.is_eq(), h.is_eq());
+            assert_eq!(packed.footprint(), h.footprint());
+            assert_eq!(packed.is_data(), matches!(h, Handle::Data(_)));
+            assert_eq!(packed.is_thunk(), matches!(h, Handle::Thunk(_)));
+            assert_eq!(packed.is_encode(), matches!(h, Handle::Encode(_)));
+        }
+    }
+
+    fn rejected(edit: impl Fn(&mut [u8; 32]), h: Handle) {
+        let mut bytes = bytes(h);
+        edit(&mut bytes);
+        assert!(PackedHandle::from_bytes(bytes).try_unpack().is_none());
+    }
+
+    #[test]
+    fn rejects_other_encodings() {
+        let literal = Handle::Data(blob(3));
+        let name = Handle::Data(blob(100));
+        let tree = Handle::Data(Data::Object(Object::Tree(tree(
+            vec![Handle::Data(blob(1)); 5000],
+            false,
+        ))));
+        // A Literal too long, or with bytes past its length.
+        rejected(|b| b[LITERAL_LENGTH] = 31, literal);
+        rejected(|b| b[LITERAL_LENGTH] = 255, literal);
+        rejected(|b| b[10] = 1, literal);
+        // A Blob Name short enough to be a Literal, or with a byte after its size.
+        rejected(|b| b[24..30].copy_from_slice(&[30, 0, 0, 0, 0, 0]), name);
+        rejected(|b| b[30] = 1, name);
+        // A tagged Blob.
+        rejected(|b| b[KIND] |= TAG, name);
+        rejected(|b| b[KIND] |= TAG, literal);
+        // A Tree whose footprint is less than its own.
+        rejected(|b| b[28..31].copy_from_slice(&[0, 0, 0]), tree);
+        // An unknown shape.
+        for code in 18..32 {
+            rejected(|b| b[KIND] = b[KIND] & !(IS_TREE - 1) | code, tree);
+        }
+        // A selection or application of a Blob.
+        rejected(
+            |b| b[KIND] = b[KIND] & !(IS_TREE - 1) | Shape::Thunk(ThunkShape::Selection).code(),
+            name,
+        );
+    }
+
+    proptest! {
+        // Any 32 bytes decode to a Handle or to nothing, and what's accepted round-trips.
+        #[test]
+        fn arbitrary_bytes_unpack_or_are_rejected(mut wire in any::<[u8; 32]>(), kind in 0..64u8) {
+            wire[KIND] = kind;
+            let h = PackedHandle::from_bytes(wire);
+            if let Some(x) = h.try_unpack() {
+                prop_assert_eq!(bytes(x), wire);
+            }
+        }
+    }
+}
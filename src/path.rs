@@ -0,0 +1,233 @@
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::{
+    BlobName, Context, Data, Handle, Object, Ref, Result, RuntimeValue, Thunk, TreeName, trap,
+};
+
+// Selection paths, e.g. "/3/0/bytes:100..200": each step selects from what the one before it
+// selected, starting from a target.
+//   N            element N of a Tree
+//   N..M         elements N to M of a Tree (as a new Tree)
+//   bytes:N      byte N of a Blob (as a Blob)
+//   bytes:N..M   bytes N to M of a Blob
+// ("/" alone is the empty path, which selects the target itself.)
+//
+// Each step is one selection (see selection for the spec). So a path of one step compiles to
+// a spec Tree, and a Selection Thunk; a longer one is selected a step at a time, as each spec's
+// target is what the previous step selected, which isn't known until then. Nothing is
+// evaluated: a step from an element that's a Thunk traps.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub(crate) struct Path(Vec<Step>);
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct Step {
+    bytes: bool,
+    start: u64,
+    // None for a single element or byte.
+    end: Option<u64>,
+}
+
+impl Path {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn step(mut self, bytes: bool, start: u64, end: Option<u64>) -> Self {
+        self.0.push(Step { bytes, start, end });
+        self
+    }
+
+    // Element `index` of a Tree.
+    pub(crate) fn index(self, index: u64) -> Self {
+        self.step(false, index, None)
+    }
+
+    // A range of a Tree's elements.
+    pub(crate) fn elements(self, range: Range<u64>) -> Self {
+        self.step(false, range.start, Some(range.end))
+    }
+
+    // Byte `index` of a Blob.
+    pub(crate) fn byte(self, index: u64) -> Self {
+        self.step(true, index, None)
+    }
+
+    // A range of a Blob's bytes.
+    pub(crate) fn bytes(self, range: Range<u64>) -> Self {
+        self.step(true, range.start, Some(range.end))
+    }
+
+    // The spec of a path of one step, selecting from `target`.
+    // Traps if the path is longer (or empty), or the step's unit doesn't match the target.
+    pub(crate) fn spec(&self, target: Data) -> Result<TreeName> {
+        match self.0[..] {
+            [step] => step.spec(target),
+            _ => Err(trap::bad_selection(&format!(
+                "a path of {} steps is not one selection",
+                self.len()
+            ))),
+        }
+    }
+
+    pub(crate) fn thunk(&self, target: Data) -> Result<Thunk> {
+        self.spec(target).map(Thunk::Selection)
+    }
+
+    // Select along the path from `target`, a step at a time.
+    pub(crate) fn select(&self, target: Data, context: &Context) -> Result<RuntimeValue> {
+        let mut selected = RuntimeValue::Data(target);
+        for step in &self.0 {
+            let RuntimeValue::Data(target) = selected else {
+                return Err(trap::type_error("selection path goes through a Thunk"));
+            };
+            selected = crate::select(step.spec(target)?, context)?;
+        }
+        Ok(selected)
+    }
+}
+
+impl Step {
+    fn spec(self, target: Data) -> Result<TreeName> {
+        let tree = matches!(
+            target,
+            Data::Object(Object::Tree(_)) | Data::Ref(Ref::Tree(_))
+        );
+        if tree == self.bytes {
+            return Err(trap::bad_selection(match self.bytes {
+                true => "selecting bytes of a Tree",
+                false => "selecting elements of a Blob",
+            }));
+        }
+        let mut range = self.start.to_le_bytes().to_vec();
+        if let Some(end) = self.end {
+            range.extend(end.to_le_bytes());
+        }
+        TreeName::create(vec![
+            Handle::Data(target),
+            Handle::Data(Data::Object(Object::Blob(BlobName::create(range)?))),
+        ])
+    }
+}
+
+impl FromStr for Path {
+    type Err = Data;
+
+    fn from_str(s: &str) -> Result<Path> {
+        let malformed = || trap::bad_selection(&format!("malformed selection path {s:?}"));
+        let rest = s.strip_prefix('/').ok_or_else(malformed)?;
+        if rest.is_empty() {
+            return Ok(Path::new());
+        }
+        let mut path = Path::new();
+        for step in rest.split('/') {
+            let (bytes, step) = match step.strip_prefix("bytes:") {
+                Some(step) => (true, step),
+                None => (false, step),
+            };
+            let number = |x: &str| x.parse::<u64>().map_err(|_| malformed());
+            path = match (bytes, step.split_once("..")) {
+                (false, None) => path.index(number(step)?),
+                (false, Some((start, end))) => path.elements(number(start)?..number(end)?),
+                (true, None) => path.byte(number(step)?),
+                (true, Some((start, end))) => path.bytes(number(start)?..number(end)?),
+            };
+        }
+        Ok(path)
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "/");
+        }
+        for step in &self.0 {
+            write!(f, "/")?;
+            if step.bytes {
+                write!(f, "bytes:")?;
+            }
+            write!(f, "{}", step.start)?;
+            if let Some(end) = step.end {
+                write!(f, "..{end}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packed::PackedHandle;
+    use crate::trap::Kind;
+
+    fn blob(contents: &[u8]) -> Handle {
+        Handle::Data(Data::Object(Object::Blob(
+            BlobName::create(contents.to_vec()).ok().unwrap(),
+        )))
+    }
+
+    fn tree(elements: Vec<Handle>) -> Data {
+        Data::Object(Object::Tree(TreeName::create(elements).ok().unwrap()))
+    }
+
+    #[test]
+    fn paths_parse_and_print() {
+        let built = Path::new().index(3).elements(0..2).byte(7).bytes(1..4);
+        let text = "/3/0..2/bytes:7/bytes:1..4";
+        assert_eq!(text.parse::<Path>().ok().unwrap(), built);
+        assert_eq!(built.to_string(), text);
+        assert_eq!("/".parse::<Path>().ok().unwrap(), Path::new());
+        assert_eq!(Path::new().to_string(), "/");
+        for malformed in ["", "3", "/x", "/1..", "//", "/bytes:", "/-1"] {
+            let trap = malformed.parse::<Path>().err().unwrap();
+            assert!(trap::is(trap, Kind::BadSelection), "{malformed:?}");
+        }
+    }
+
+    #[test]
+    fn paths_select_a_step_at_a_time() {
+        let target = tree(vec![
+            blob(b"zero"),
+            Handle::Data(tree(vec![blob(&[9; 100])])),
+        ]);
+        let path = Path::new().index(1).index(0).bytes(10..20);
+        let Ok(RuntimeValue::Data(selected)) = path.select(target, &Context::default()) else {
+            panic!("nothing selected");
+        };
+        assert!(PackedHandle::pack(Handle::Data(selected)) == PackedHandle::pack(blob(&[9; 10])));
+        let Ok(RuntimeValue::Data(itself)) = Path::new().select(target, &Context::default()) else {
+            panic!("nothing selected");
+        };
+        assert!(
+            PackedHandle::pack(Handle::Data(itself)) == PackedHandle::pack(Handle::Data(target))
+        );
+    }
+
+    #[test]
+    fn only_one_step_is_one_selection() {
+        let target = tree(vec![blob(b"zero")]);
+        assert!(Path::new().index(0).thunk(target).is_ok());
+        assert_eq!(Path::new().index(0).len(), 1);
+        for path in [
+            Path::new(),
+            Path::new().index(0).index(0),
+            Path::new().byte(0),
+        ] {
+            assert!(trap::is(
+                path.spec(target).err().unwrap(),
+                Kind::BadSelection
+            ));
+        }
+    }
+}
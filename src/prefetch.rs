@@ -0,0 +1,335 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::packed::PackedHandle;
+use crate::storage::{Key, key, storage};
+use crate::{BlobName, Data, Handle, Object, Ref, Result, TreeName, Value, chunk, local, trace};
+
+// Speculative prefetching: before a procedure is applied, the Refs it's likely to lift are
+// fetched in the background, so if it does, their contents are already local (loading an
+// object from a TieredStorage promotes it into the faster tiers, and a CachedStorage keeps
+// it in memory).
+//
+// Which Refs are fetched is up to the Prefetch policy in the evaluation's Context. Prefetches
+// never affect results: they run on their own threads, and their failures are ignored.
+//
+// Loading a Tree also reads ahead (when it's enabled: see set_read_ahead): the first few
+// objects among its elements are fetched the same way, since whatever walks a Tree (the
+// evaluator, or a visitor) most likely loads its elements next.
+pub(crate) trait Prefetch: Send + Sync {
+    // The Refs to fetch before applying `combination`.
+    fn refs(&self, combination: TreeName<Value>) -> Vec<Ref>;
+}
+
+// No prefetching (the default).
+impl Prefetch for () {
+    fn refs(&self, _combination: TreeName<Value>) -> Vec<Ref> {
+        Vec::new()
+    }
+}
+
+// Prefetch the Refs among a combination's arguments (its elements after the procedure),
+// up to a footprint each.
+pub(crate) struct Arguments {
+    pub(crate) max_footprint: u32,
+}
+
+impl Prefetch for Arguments {
+    fn refs(&self, combination: TreeName<Value>) -> Vec<Ref> {
+        let Ok(elements) = combination.relax().try_load() else {
+            return Vec::new();
+        };
+        elements
+            .into_iter()
+            .skip(2)
+            .filter_map(|h| match h {
+                Handle::Data(Data::Ref(x)) => Some(x),
+                _ => None,
+            })
+            .filter(|x| footprint(x) <= self.max_footprint)
+            .collect()
+    }
+}
+
+// Prefetch what applying the same procedure loaded before, as a trace recorded it (see
+// trace): the stored objects themselves (which a re-run with the same inputs loads again),
+// and the arguments in the positions those objects were in (for a re-run whose inputs
+// have changed).
+#[derive(Default)]
+pub(crate) struct Learned(HashMap<PackedHandle, Recalled>);
+
+// What applying one procedure loaded.
+#[derive(Default)]
+struct Recalled {
+    // The positions (in the combination) of the arguments it loaded.
+    arguments: BTreeSet<usize>,
+    objects: Vec<Ref>,
+    names: HashSet<PackedHandle>,
+}
+
+impl Learned {
+    // Learn from every apply in a trace.
+    pub(crate) fn from_trace(trace: TreeName) -> Result<Self> {
+        let mut learned = Learned::default();
+        // (A page at a time, as a trace can be long.)
+        for entry in trace.try_iter() {
+            let Handle::Data(Data::Object(Object::Tree(entry))) = entry? else {
+                continue;
+            };
+            if let [
+                Handle::Data(Data::Object(Object::Blob(step))),
+                Handle::Data(Data::Object(Object::Tree(combination))),
+                _,
+                _,
+                Handle::Data(Data::Object(Object::Tree(loaded))),
+            ] = entry.try_load()?[..]
+                && *step.try_load()? == *trace::APPLY
+            {
+                learned.learn(&combination.try_load()?, loaded.try_load()?);
+            }
+        }
+        Ok(learned)
+    }
+
+    fn learn(&mut self, combination: &[Handle], loaded: Vec<Handle>) {
+        let Some(procedure) = combination.get(1).and_then(|&h| procedure(h)) else {
+            return;
+        };
+        let recalled = self.0.entry(procedure).or_default();
+        for x in loaded {
+            if let Handle::Data(Data::Ref(x)) = x
+                && recalled.names.insert(name(x))
+            {
+                recalled.objects.push(x);
+            }
+        }
+        for (i, &h) in combination.iter().enumerate().skip(2) {
+            if let Some(x) = lowered(h)
+                && recalled.names.contains(&name(x))
+            {
+                recalled.arguments.insert(i);
+            }
+        }
+    }
+}
+
+impl Prefetch for Learned {
+    fn refs(&self, combination: TreeName<Value>) -> Vec<Ref> {
+        let Ok(elements) = combination.relax().try_load() else {
+            return Vec::new();
+        };
+        let Some(recalled) = elements.get(1).and_then(|&h| self.0.get(&procedure(h)?)) else {
+            return Vec::new();
+        };
+        let arguments = recalled
+            .arguments
+            .iter()
+            .filter_map(|&i| lowered(*elements.get(i)?))
+            .filter(|&x| !recalled.names.contains(&name(x)));
+        recalled.objects.iter().copied().chain(arguments).collect()
+    }
+}
+
+// A procedure, by its Name.
+fn procedure(h: Handle) -> Option<PackedHandle> {
+    match h {
+        Handle::Data(Data::Object(Object::Blob(x)) | Data::Ref(Ref::Blob(x))) => {
+            Some(name(Ref::Blob(x)))
+        }
+        _ => None,
+    }
+}
+
+// Data as a Ref to its object (untagged, as loads are recorded).
+fn lowered(h: Handle) -> Option<Ref> {
+    match h {
+        Handle::Data(Data::Object(Object::Blob(x)) | Data::Ref(Ref::Blob(x))) => Some(Ref::Blob(x)),
+        Handle::Data(Data::Object(Object::Tree(x)) | Data::Ref(Ref::Tree(x))) => {
+            Some(Ref::Tree(TreeName { tag: false, ..x }))
+        }
+        _ => None,
+    }
+}
+
+fn name(x: Ref) -> PackedHandle {
+    local::canonical_name(PackedHandle::pack(Handle::Data(Data::Ref(x))))
+}
+
+// The footprint of a Ref's object, once lifted.
+fn footprint(x: &Ref) -> u32 {
+    match x {
+        Ref::Blob(x) => x.footprint(),
+        Ref::Tree(x) => x.footprint(),
+    }
+}
+
+// Prefetches have threads of their own, so slow fetches don't hold up evaluation.
+const THREADS: usize = 4;
+
+static POOL: LazyLock<ThreadPool> = LazyLock::new(|| {
+    ThreadPoolBuilder::new()
+        .num_threads(THREADS)
+        .thread_name(|i| format!("prefetch-{i}"))
+        .build()
+        .expect("can't start prefetch threads")
+});
+
+// Objects being fetched (so each is only fetched once at a time).
+static FETCHING: LazyLock<Mutex<HashSet<(bool, Key)>>> = LazyLock::new(Mutex::default);
+
+// Start fetching the objects the policy picks for `combination`.
+pub(crate) fn start(policy: &dyn Prefetch, combination: TreeName<Value>) {
+    policy.refs(combination).into_iter().for_each(fetch);
+}
+
+// How many of a loaded Tree's elements to read ahead (none by default: with everything in
+// memory, there's nothing to hide).
+static READ_AHEAD: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn set_read_ahead(elements: usize) {
+    READ_AHEAD.store(elements, Ordering::Relaxed);
+}
+
+// Start fetching the objects among the first elements of a Tree that's been loaded.
+pub(crate) fn read_ahead(tree: &[PackedHandle]) {
+    let window = READ_AHEAD.load(Ordering::Relaxed);
+    if window > 0 {
+        ahead(tree, window).into_iter().for_each(fetch);
+    }
+}
+
+// The stored objects among the first `window` elements (as Refs to them).
+fn ahead(tree: &[PackedHandle], window: usize) -> Vec<Ref> {
+    tree.iter()
+        .take(window)
+        .filter_map(|h| match h.try_unpack()? {
+            Handle::Data(Data::Object(Object::Blob(x @ BlobName::Name(_)))) => Some(Ref::Blob(x)),
+            Handle::Data(Data::Object(Object::Tree(x))) => Some(Ref::Tree(x)),
+            _ => None,
+        })
+        .collect()
+}
+
+// Fetch an object in the background, unless it's local or already being fetched.
+fn fetch(x: Ref) {
+    let id = match x {
+        Ref::Blob(BlobName::Name((pointer, _))) if !local::is_local(pointer) => {
+            (false, key(pointer))
+        }
+        Ref::Tree(tree) if !local::is_local(tree.name) => (true, key(tree.name)),
+        _ => return,
+    };
+    if !FETCHING.lock().unwrap().insert(id) {
+        return;
+    }
+    POOL.spawn(move || {
+        let _ = match x {
+            Ref::Blob(BlobName::Name((pointer, size))) => chunk::get(pointer, size).map(drop),
+            Ref::Tree(tree) => storage().get_tree(key(tree.name)).map(drop),
+            Ref::Blob(BlobName::Literal(_)) => Ok(()),
+        };
+        FETCHING.lock().unwrap().remove(&id);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hooks::{Hooks, Usage};
+    use crate::trace::Trace;
+    use crate::{PAGE_SIZE, RuntimeValue, types};
+
+    fn blob(len: usize) -> BlobName {
+        BlobName::create(vec![9; len]).ok().unwrap()
+    }
+
+    #[test]
+    fn arguments_are_prefetched_up_to_a_footprint() {
+        let (small, large) = (blob(100), blob(3 * PAGE_SIZE));
+        let combination = TreeName::create(vec![
+            Handle::Data(Data::Ref(Ref::Blob(blob(200)))),
+            Handle::Data(Data::Ref(Ref::Blob(blob(300)))),
+            Handle::Data(Data::Ref(Ref::Blob(small))),
+            Handle::Data(Data::Ref(Ref::Blob(large))),
+            Handle::Data(Data::Object(Object::Blob(blob(400)))),
+        ])
+        .ok()
+        .unwrap();
+        let combination = types::value(combination).ok().unwrap();
+        let refs = Arguments { max_footprint: 2 }.refs(combination);
+        assert!(matches!(refs[..], [Ref::Blob(x)] if x == small));
+        assert_eq!(Arguments { max_footprint: 3 }.refs(combination).len(), 2);
+        assert!(().refs(combination).is_empty());
+    }
+
+    #[test]
+    fn stored_objects_are_read_ahead_within_the_window() {
+        let (large, literal) = (blob(100), blob(10));
+        let tree = TreeName::create(vec![]).ok().unwrap();
+        let elements: Vec<_> = [
+            Handle::Data(Data::Object(Object::Blob(literal))),
+            Handle::Data(Data::Ref(Ref::Blob(large))),
+            Handle::Data(Data::Object(Object::Blob(large))),
+            Handle::Data(Data::Object(Object::Tree(tree))),
+        ]
+        .into_iter()
+        .map(PackedHandle::pack)
+        .collect();
+        assert!(matches!(ahead(&elements, 3)[..], [Ref::Blob(x)] if x == large));
+        assert!(matches!(ahead(&elements, 4)[..], [_, Ref::Tree(x)] if x == tree));
+        assert!(ahead(&elements, 0).is_empty());
+    }
+
+    #[test]
+    fn what_an_apply_loaded_is_prefetched_when_its_procedure_is_applied_again() {
+        let (procedure, limits) = (blob(500), blob(0));
+        let combination = |argument: BlobName, other: BlobName| {
+            let elements = vec![limits, procedure, argument, other]
+                .into_iter()
+                .map(|x| Handle::Data(Data::Ref(Ref::Blob(x))))
+                .collect();
+            types::value(TreeName::create(elements).ok().unwrap())
+                .ok()
+                .unwrap()
+        };
+        let (loaded, unloaded) = (blob(600), blob(700));
+        let table = TreeName::create(vec![]).ok().unwrap();
+        // An apply that loads its first argument, and a Tree that isn't an argument.
+        let trace = Trace::default();
+        let applied = combination(loaded, unloaded);
+        trace.on_apply_start(applied);
+        assert!(loaded.try_load().is_ok() && table.try_load().is_ok());
+        let usage = Usage {
+            time: Default::default(),
+            fuel: 0,
+        };
+        trace.on_apply_finish(
+            applied,
+            &Ok(RuntimeValue::Data(Data::Ref(Ref::Blob(limits)))),
+            usage,
+        );
+        let learned = Learned::from_trace(trace.tree().ok().unwrap())
+            .ok()
+            .unwrap();
+        let names = |refs: Vec<Ref>| -> HashSet<_> { refs.into_iter().map(name).collect() };
+        let expected = names(vec![Ref::Blob(loaded), Ref::Tree(table)]);
+        assert!(names(learned.refs(applied)) == expected);
+        // With a new first argument, that's prefetched too.
+        let changed = blob(800);
+        let refs = names(learned.refs(combination(changed, unloaded)));
+        assert!(refs.is_superset(&expected) && refs.contains(&name(Ref::Blob(changed))));
+        assert!(!refs.contains(&name(Ref::Blob(unloaded))));
+        // Another procedure has learned nothing.
+        let other = vec![limits, blob(900)]
+            .into_iter()
+            .map(|x| Handle::Data(Data::Ref(Ref::Blob(x))))
+            .collect();
+        let other = types::value(TreeName::create(other).ok().unwrap())
+            .ok()
+            .unwrap();
+        assert!(learned.refs(other).is_empty());
+    }
+}
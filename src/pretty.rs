@@ -0,0 +1,261 @@
+use std::fmt;
+use std::io;
+
+use crate::packed::PackedHandle;
+use crate::repository::hex;
+use crate::storage::{Key, key};
+use crate::{BlobName, Data, Encode, Handle, Object, Ref, Thunk, TreeName, chunk, local};
+
+// Handles as text, for debugging and for people reading a CLI's output, e.g.
+//
+//   Tree 3f09a1c2e4b7 (3 elements, footprint 1, eq)
+//     Blob "hello" (5 bytes)
+//     Ref Blob 9d41be07a3f2 (70000 bytes)
+//     Encode as Object: Application of Tree 0c5e8b21d9a4 (4 elements, footprint 1)
+//
+// A Handle alone (as Display) is one line, and loads nothing: a Literal's contents are shown,
+// and an object named by a Pointer is shown by its Name (abbreviated). Printed with its
+// closure to some depth (see pretty), the Trees it reaches are expanded, nested, and the
+// Blobs it reaches are previewed, that many levels deep (whether or not they're accessible).
+// Objects that can't be loaded, and Tree elements that aren't valid Handles, are shown as
+// such, rather than failing.
+pub(crate) struct Pretty {
+    handle: Handle,
+    depth: usize,
+}
+
+// A Handle with its closure expanded `depth` levels (0 for just the Handle).
+pub(crate) fn pretty(handle: Handle, depth: usize) -> Pretty {
+    Pretty { handle, depth }
+}
+
+impl fmt::Display for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        pretty(*self, 0).fmt(f)
+    }
+}
+
+impl fmt::Display for Pretty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        handle(f, self.handle, self.depth, 0)
+    }
+}
+
+// The bytes of a Blob previewed, and the elements of a Tree shown, at most.
+const PREVIEW: usize = 32;
+const ELEMENTS: usize = 64;
+
+fn handle(f: &mut fmt::Formatter, h: Handle, depth: usize, indent: usize) -> fmt::Result {
+    write!(f, "{:1$}", "", 2 * indent)?;
+    match h {
+        Handle::Data(x) => data(f, x, depth, indent),
+        Handle::Thunk(x) => thunk(f, x, depth, indent),
+        Handle::Encode(Encode {
+            thunk: x,
+            accessibility,
+        }) => {
+            match accessibility {
+                None => write!(f, "Encode: ")?,
+                Some(true) => write!(f, "Encode as Object: ")?,
+                Some(false) => write!(f, "Encode as Ref: ")?,
+            }
+            thunk(f, x, depth, indent)
+        }
+    }
+}
+
+fn thunk(f: &mut fmt::Formatter, x: Thunk, depth: usize, indent: usize) -> fmt::Result {
+    match x {
+        Thunk::Application(x) => {
+            write!(f, "Application of ")?;
+            tree(f, x, depth, indent)
+        }
+        Thunk::Selection(x) => {
+            write!(f, "Selection of ")?;
+            tree(f, x, depth, indent)
+        }
+        Thunk::Identification(x) => {
+            write!(f, "Identification of ")?;
+            data(f, x, depth, indent)
+        }
+    }
+}
+
+fn data(f: &mut fmt::Formatter, x: Data, depth: usize, indent: usize) -> fmt::Result {
+    match x {
+        Data::Object(Object::Blob(x)) => blob(f, x, depth),
+        Data::Object(Object::Tree(x)) => tree(f, x, depth, indent),
+        Data::Ref(Ref::Blob(x)) => {
+            write!(f, "Ref ")?;
+            blob(f, x, depth)
+        }
+        Data::Ref(Ref::Tree(x)) => {
+            write!(f, "Ref ")?;
+            tree(f, x, depth, indent)
+        }
+    }
+}
+
+fn blob(f: &mut fmt::Formatter, x: BlobName, depth: usize) -> fmt::Result {
+    let size = x.size();
+    let preview = match x {
+        BlobName::Literal((storage, _)) => Ok(Some(storage[..size].to_vec())),
+        BlobName::Name(_) if depth == 0 => Ok(None),
+        BlobName::Name((pointer, _)) => chunk::get_range(pointer, size, 0, size.min(PREVIEW)),
+    };
+    write!(f, "Blob ")?;
+    if let BlobName::Name((pointer, _)) = x {
+        write!(f, "{} ", name(key(pointer)))?;
+    }
+    match preview {
+        Ok(Some(bytes)) => {
+            let more = if size > bytes.len() { "…" } else { "" };
+            write!(
+                f,
+                "\"{}\"{more} ",
+                bytes[..bytes.len().min(PREVIEW)].escape_ascii()
+            )?;
+        }
+        Ok(None) if depth > 0 => write!(f, "(missing) ")?,
+        Ok(None) => {}
+        Err(e) => write!(f, "{} ", unreadable(e))?,
+    }
+    write!(f, "({size} bytes)")
+}
+
+fn tree(f: &mut fmt::Formatter, x: TreeName, depth: usize, indent: usize) -> fmt::Result {
+    write!(
+        f,
+        "Tree {} ({} elements, footprint {}",
+        name(key(x.name)),
+        x.size(),
+        x.footprint()
+    )?;
+    if x.eq {
+        write!(f, ", eq")?;
+    }
+    if x.tag {
+        write!(f, ", tagged")?;
+    }
+    write!(f, ")")?;
+    if depth == 0 || x.size() == 0 {
+        return Ok(());
+    }
+    let shown = x.size().min(ELEMENTS);
+    let elements = local::storage_of(x.name).get_tree_range(key(x.name), 0, shown);
+    match elements {
+        Ok(Some(elements)) => {
+            for h in elements {
+                writeln!(f)?;
+                match h.try_unpack() {
+                    Some(h) => handle(f, h, depth - 1, indent + 1)?,
+                    None => invalid(f, h, indent + 1)?,
+                }
+            }
+            if x.size() > shown {
+                write!(
+                    f,
+                    "\n{:1$}… {2} more",
+                    "",
+                    2 * (indent + 1),
+                    x.size() - shown
+                )?;
+            }
+            Ok(())
+        }
+        Ok(None) => write!(f, " (missing)"),
+        Err(e) => write!(f, " {}", unreadable(e)),
+    }
+}
+
+// An element of a (corrupt) Tree that isn't a Handle at all, shown as its bytes.
+fn invalid(f: &mut fmt::Formatter, h: PackedHandle, indent: usize) -> fmt::Result {
+    write!(f, "{:1$}(invalid Handle ", "", 2 * indent)?;
+    for byte in h.as_bytes() {
+        write!(f, "{byte:02x}")?;
+    }
+    write!(f, ")")
+}
+
+fn unreadable(e: io::Error) -> String {
+    match e.kind() {
+        io::ErrorKind::InvalidData => "(corrupt)".to_string(),
+        _ => format!("(unreadable: {e})"),
+    }
+}
+
+// A Pointer, abbreviated (local ones by their id).
+fn name(name: Key) -> String {
+    match local::local_id(name) {
+        Some(id) => format!("local:{id}"),
+        None => hex(name)[..12].to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::hash_tree;
+    use crate::storage::storage;
+
+    fn blob(contents: &[u8]) -> Handle {
+        Handle::Data(Data::Object(Object::Blob(
+            BlobName::create(contents.to_vec()).ok().unwrap(),
+        )))
+    }
+
+    #[test]
+    fn a_handle_is_one_line() {
+        let tree = TreeName::create(vec![
+            blob(b"hello"),
+            blob(b"a Blob too long to be a Literal, previewed"),
+        ])
+        .ok()
+        .unwrap();
+        let encode = Handle::Encode(Encode {
+            thunk: Thunk::Application(tree),
+            accessibility: Some(true),
+        });
+        assert_eq!(
+            encode.to_string(),
+            "Encode as Object: Application of Tree 7bebe099cdfc (2 elements, footprint 3, eq)"
+        );
+    }
+
+    #[test]
+    fn a_closure_is_expanded() {
+        let tree = TreeName::create(vec![
+            blob(b"hello"),
+            blob(b"a Blob too long to be a Literal, previewed"),
+        ])
+        .ok()
+        .unwrap();
+        let tree = Handle::Data(Data::Object(Object::Tree(tree)));
+        let expected = concat!(
+            "Tree 7bebe099cdfc (2 elements, footprint 3, eq)\n",
+            "  Blob \"hello\" (5 bytes)\n",
+            "  Blob 580face6db60 \"a Blob too long to be a Literal,\"… (42 bytes)",
+        );
+        assert_eq!(pretty(tree, 2).to_string(), expected);
+    }
+
+    #[test]
+    fn an_invalid_element_is_shown_as_bytes() {
+        let elements = vec![PackedHandle::from_bytes([0xff; 32])];
+        let name = hash_tree(&elements);
+        storage().put_tree(key(name), elements.into()).unwrap();
+        let tree = TreeName {
+            name,
+            size: 1,
+            footprint: 1,
+            eq: false,
+            tag: false,
+        };
+        let tree = Handle::Data(Data::Ref(Ref::Tree(tree)));
+        let expected = concat!(
+            "Ref Tree aaf0c0fb60f0 (1 elements, footprint 1)\n",
+            "  (invalid Handle ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff)",
+        );
+        assert_eq!(pretty(tree, 1).to_string(), expected);
+    }
+}
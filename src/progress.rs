@@ -0,0 +1,185 @@
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::hooks::{Hooks, Usage};
+use crate::{Data, Result, RuntimeValue, Thunk, TreeName, Value};
+
+// The progress of an evaluation, as a set of Hooks writing reports as it runs: the Thunks
+// completed (thought about, or found in the memo table) out of the total, the applies
+// running, the memo hit rate, and an estimate of the time left.
+//
+// The total is an estimate: the Encodes known before the evaluation started (see graph),
+// raised to the Thunks completed when it's passed, as executions find more to do.
+pub(crate) struct Progress {
+    format: Format,
+    started: Instant,
+    state: Mutex<State>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Format {
+    // One line, rewritten in place (for a terminal).
+    Text,
+    // A JSON object per line (for scripts).
+    Json,
+}
+
+struct State {
+    out: Box<dyn Write + Send>,
+    total: u64,
+    thoughts: u64,
+    hits: u64,
+    applying: u64,
+    reported: Option<Instant>,
+}
+
+// How often a report is written, at most.
+const INTERVAL: Duration = Duration::from_millis(100);
+
+impl Progress {
+    pub(crate) fn new(format: Format, total: usize, out: Box<dyn Write + Send>) -> Self {
+        Progress {
+            format,
+            started: Instant::now(),
+            state: Mutex::new(State {
+                out,
+                total: total as u64,
+                thoughts: 0,
+                hits: 0,
+                applying: 0,
+                reported: None,
+            }),
+        }
+    }
+
+    // Write a report, unless one was written recently (or `last`, always, ending the line).
+    fn report(&self, state: &mut State, last: bool) {
+        let now = Instant::now();
+        if !last && state.reported.is_some_and(|x| now - x < INTERVAL) {
+            return;
+        }
+        state.reported = Some(now);
+        let completed = state.thoughts + state.hits;
+        state.total = state.total.max(completed);
+        let hit_ratio = match completed {
+            0 => 0.0,
+            _ => state.hits as f64 / completed as f64,
+        };
+        let elapsed = now - self.started;
+        let eta = (completed > 0)
+            .then(|| elapsed.mul_f64((state.total - completed) as f64 / completed as f64));
+        // (Progress is only advisory, so failing to report it is ignored.)
+        let _ = match self.format {
+            Format::Text => write!(
+                state.out,
+                "\r{completed}/{} thunks, {} applying, {:.0}% memo hits, {}{}",
+                state.total,
+                state.applying,
+                100.0 * hit_ratio,
+                eta.map_or("ETA unknown".to_string(), |x| format!(
+                    "ETA {:.1}s",
+                    x.as_secs_f64()
+                )),
+                if last { "\n" } else { "" }
+            ),
+            Format::Json => writeln!(
+                state.out,
+                "{{\"completed\":{completed},\"total\":{},\"applying\":{},\"hit_ratio\":{hit_ratio:.3},\"elapsed_ms\":{},\"eta_ms\":{}}}",
+                state.total,
+                state.applying,
+                elapsed.as_millis(),
+                eta.map_or("null".to_string(), |x| x.as_millis().to_string())
+            ),
+        };
+        let _ = state.out.flush();
+    }
+
+    fn update(&self, change: impl FnOnce(&mut State)) {
+        let mut state = self.state.lock().unwrap();
+        change(&mut state);
+        self.report(&mut state, false);
+    }
+
+    // Write the final report.
+    pub(crate) fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        self.report(&mut state, true);
+    }
+}
+
+impl Hooks for Progress {
+    fn on_thought(&self, _: Thunk, _: &Result<RuntimeValue>, _: Usage) {
+        self.update(|x| x.thoughts += 1);
+    }
+
+    fn on_cache_hit(&self, _: Thunk, _: Data) {
+        self.update(|x| x.hits += 1);
+    }
+
+    fn on_apply_start(&self, _: TreeName<Value>) {
+        self.update(|x| x.applying += 1);
+    }
+
+    fn on_apply_finish(&self, _: TreeName<Value>, _: &Result<RuntimeValue>, _: Usage) {
+        self.update(|x| x.applying -= 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::path::Path;
+    use crate::{BlobName, Context, Encode, Handle, Object, eval};
+
+    // A Write a test can read back.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reports_thunks_completed_as_json_lines() {
+        let blob = BlobName::create(b"reported by progress".to_vec())
+            .ok()
+            .unwrap();
+        let tree = TreeName::create(vec![Handle::Data(Data::Object(Object::Blob(blob)))]);
+        let thunk = Path::new()
+            .index(0)
+            .thunk(Data::Object(Object::Tree(tree.ok().unwrap())))
+            .ok()
+            .unwrap();
+        let out = Shared::default();
+        let progress = Arc::new(Progress::new(Format::Json, 1, Box::new(out.clone())));
+        let context = Context {
+            hooks: progress.clone(),
+            ..Default::default()
+        };
+        let encode = Encode {
+            thunk,
+            accessibility: None,
+        };
+        assert!(eval(Handle::Encode(encode), &context).is_ok());
+        progress.finish();
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let reports: Vec<serde_json::Value> = out
+            .lines()
+            .map(|x| serde_json::from_str(x).unwrap())
+            .collect();
+        let last = reports.last().unwrap();
+        assert!(last["completed"].as_u64().unwrap() >= 1);
+        assert_eq!(last["total"], last["completed"]);
+        assert_eq!(last["applying"], 0);
+        assert_eq!(last["eta_ms"], 0);
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashSet;
+
+use crate::packed::PackedHandle;
+use crate::{
+    Context, Data, Encode, Handle, HandleType, Object, Result, RuntimeValue, Thunk, Value, call,
+    local, memo, selection, trap,
+};
+
+// A reference evaluator: the semantics of eval, written as directly as possible (recursive,
+// sequential, with no memo table, hooks, metrics, scheduling or offloading), so the
+// optimized evaluator can be checked against it (see cross_check).
+//
+// It's slow, and recurses per Tree and Encode, so it's only for small inputs.
+
+// Evaluate a Handle to a Value: an Encode is executed and its Data evaluated, and an
+// accessible Tree's elements are evaluated, in order (so a trap is the first one's).
+pub(crate) fn eval(h: Handle, context: &Context) -> Result<Value> {
+    match h {
+        Handle::Encode(e) => eval(Handle::Data(execute(e, context)?), context),
+        Handle::Data(Data::Object(Object::Tree(x))) => {
+            let values = x
+                .try_load()?
+                .into_iter()
+                .map(|x| eval(x, context))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Value::Data(Data::Object(Object::Tree(x.mapped(values)?))))
+        }
+        Handle::Data(Data::Object(Object::Blob(x))) => {
+            Ok(Value::Data(Data::Object(Object::Blob(x))))
+        }
+        Handle::Data(Data::Ref(x)) => Ok(Value::Data(Data::Ref(x))),
+        Handle::Thunk(x) => Ok(Value::Thunk(x)),
+    }
+}
+
+// Think about an Encode's Thunk until it's Data (trapping if a thought repeats, or the step
+// budget runs out), then adjust the Data's accessibility.
+fn execute(e: Encode, context: &Context) -> Result<Data> {
+    let context = context.execution();
+    let mut thunk = e.thunk;
+    let mut seen = HashSet::from([memo::name(thunk)]);
+    let mut steps = 0;
+    let data = loop {
+        context.check()?;
+        if let Some(budget) = context.step_budget
+            && steps >= budget
+        {
+            return Err(trap::resource_exhausted("steps", budget));
+        }
+        steps += 1;
+        match think(thunk, &context)? {
+            RuntimeValue::Thunk(thought) => {
+                if !seen.insert(memo::name(thought)) {
+                    return Err(trap::cycle_detected());
+                }
+                thunk = thought;
+            }
+            RuntimeValue::Data(x) => break x,
+        }
+    };
+    Ok(match e.accessibility {
+        None => data,
+        Some(true) => Data::Object(data.lift()?),
+        Some(false) => Data::Ref(data.lower()),
+    })
+}
+
+fn think(thunk: Thunk, context: &Context) -> Result<RuntimeValue> {
+    match thunk {
+        Thunk::Application(combination) => {
+            let combination = Handle::Data(Data::Object(Object::Tree(combination)));
+            let Value::Data(Data::Object(Object::Tree(combination))) = eval(combination, context)?
+            else {
+                unreachable!("a Tree evaluated to something else");
+            };
+            call(combination, context, &mut 0)
+        }
+        Thunk::Selection(spec) => selection::select(spec),
+        Thunk::Identification(x) => Ok(RuntimeValue::Data(x)),
+    }
+}
+
+// What an evaluation gave: its Value, or its trap.
+fn outcome(x: Result<Value>) -> Handle {
+    x.map_or_else(Handle::Data, HandleType::relax)
+}
+
+// Evaluate a Handle with eval, and again with the reference evaluator, returning what eval
+// gave, or (if they differ, comparing traps like Values by canonical Name) what each gave.
+pub(crate) fn cross_check(
+    h: Handle,
+    context: &Context,
+) -> std::result::Result<Result<Value>, Box<(Handle, Handle)>> {
+    let engine = crate::eval(h, context);
+    let reference = eval(h, context);
+    let name = |x| local::canonical_name(PackedHandle::pack(outcome(x)));
+    match name(engine) == name(reference) {
+        true => Ok(engine),
+        false => Err(Box::new((outcome(engine), outcome(reference)))),
+    }
+}
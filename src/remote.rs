@@ -0,0 +1,1307 @@
+use std::collections::HashSet;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::archive::Closure;
+use crate::fetch::{self, Mismatch, quarantining};
+use crate::packed::PackedHandle;
+use crate::repository::{Repository, pack_tree, unpack_tree};
+use crate::storage::{Key, SharedBlob, Storage, key_bytes, key_from_bytes, storage};
+use crate::{
+    Context, Data, Encode, Execution, HANDLE_SIZE, Handle, PAGE_SIZE, Result, Thunk, Tree, archive,
+    chunk, execute, local, memo,
+};
+
+// Offloading Encodes to worker processes: local ones, serving on their stdin and stdout,
+// or (for a pool spread over machines) ones listening on TCP, serving each connection.
+//
+// Each request a coordinator sends a worker starts with a byte for its kind. To have an
+// Encode executed (EXECUTE), it sends the limits of the execution, then the canonical Thunk
+// of the Encode, with its closure, as an archive (see archive). The limits are two u64s
+// (little-endian), the step budget and the time left before the Encode times out, in
+// milliseconds (each u64::MAX for no limit). The worker executes the Thunk under those
+// limits and answers with a status byte, OK or TRAP, and an archive of the result (or the
+// trap), leaving out what the request named. Importing the answer checks every object in it
+// against its Pointer, so a coordinator only records a result whose objects are intact (and
+// complete, with what it holds), and then it's memoized just as if it had been executed
+// locally.
+//
+// A request's archive leaves out what's only reachable through Refs: a worker fetches those
+// objects when it finds it needs them (e.g. when a Selection lifts a Ref), from the
+// coordinator whose request named them (see Demand). Before its answer, it may send
+// any number of fetches: the status byte FETCH, then the object's kind (as in an archive)
+// and key. The coordinator replies to each with a byte, 1 if it has the object (followed by
+// its length, as a u64, and its contents) or 0 if it doesn't.
+//
+// A worker may keep labels (in a Repository: see keep_labels), e.g. a daemon's keep each
+// tenant's apart from the others'. A coordinator reads one with GET_LABEL, then the label's
+// name (its length, as a u32, and the name), and the worker answers with a byte, 1 followed
+// by an archive of the Handle it names, or 0 if there's no such label (or the worker keeps
+// none). It sets one with SET_LABEL, the name, and an archive of the Handle (leaving out what
+// the worker holds), and the worker answers 1 once it's set, or 0 if the name isn't a
+// label's, or the worker keeps no labels.
+//
+// A worker keeps the objects it's sent (and those it produces), and the coordinator keeps
+// track of them: a request's archive leaves out what the worker already holds (see
+// archive::send). An Encode goes to the worker it costs least to run on: the bytes of its
+// inputs (by their sizes, as their Names record them) the worker would have to be sent,
+// plus QUEUED for each request the worker has yet to answer. So Thunks move to their data
+// rather than the other way around, unless the worker holding it is busy, and the data is
+// small enough that an idle worker does better to take the Thunk (stealing it from the
+// busy worker's queue) and be sent the data.
+//
+// Workers are only an optimization: results don't depend on where an Encode executes, so if
+// a worker fails (or sends something malformed), it's no longer used, and the Encode is
+// retried on another (or executed locally, once there's none left). A worker that doesn't
+// answer in time (by the Encode's deadline, plus a grace period, or the coordinator's own
+// timeout) has failed too, and a worker process is killed (or a socket to one shut down,
+// ending the request). So has one that goes quiet: while it executes, a worker sends the
+// status byte HEARTBEAT every PULSE, and a coordinator holds each request to a lease (LEASE,
+// by default) that lapses once nothing has moved either way for that long (as the request
+// is sent, executed or answered), which a worker that crashes (or whose machine does) lets
+// happen long before its Encode's deadline. Cancellation isn't sent:
+// the coordinator stops waiting once the evaluation is cancelled, and traps as a local
+// execution would. (The other settings of the Context, such as hooks, only apply where the
+// evaluation runs.)
+//
+// A coordinator's results are memoized (as persistent as the memo table is: see
+// memo::persist), and it flushes its Storage at most every CHECKPOINT after a result comes
+// back. That's all a coordinator keeps: nothing of its scheduling (which workers hold what,
+// or which requests are under way) survives it. So a coordinator that fails is replaced by
+// running the evaluation again on the same repository, which re-executes only the Encodes
+// whose results weren't checkpointed (each at most CHECKPOINT's worth), as the rest are
+// memoized.
+const EXECUTE: u8 = 0;
+const GET_LABEL: u8 = 1;
+const SET_LABEL: u8 = 2;
+
+const OK: u8 = 0;
+const TRAP: u8 = 1;
+const FETCH: u8 = 2;
+const HEARTBEAT: u8 = 3;
+
+const NO_LIMIT: u64 = u64::MAX;
+
+// The longest label name a request may hold.
+const MAX_LABEL: u64 = 255;
+
+// How long past an Encode's deadline a worker may take to answer (with its timed-out trap).
+const GRACE: Duration = Duration::from_secs(1);
+
+// What waiting behind one request is taken to cost, in bytes sent.
+const QUEUED: usize = 1 << 20;
+
+// How often a coordinator waiting for an answer checks for cancellation.
+const POLL: Duration = Duration::from_millis(10);
+
+// How often an executing worker says it still is, and how long a coordinator waits to hear
+// from it before giving up on it.
+pub(crate) const PULSE: Duration = Duration::from_secs(1);
+const LEASE: Duration = Duration::from_secs(10);
+
+// How often a coordinator flushes the results it's been sent.
+const CHECKPOINT: Duration = Duration::from_secs(1);
+
+// The limits a worker executes under.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct Limits {
+    step_budget: Option<u64>,
+    timeout: Option<Duration>,
+}
+
+impl Limits {
+    // The limits left to an execution (of which some time may have passed).
+    fn of(context: &Context) -> Limits {
+        Limits {
+            step_budget: context.step_budget,
+            timeout: context
+                .deadline
+                .map(|x| x.saturating_duration_since(Instant::now())),
+        }
+    }
+
+    fn write(self, output: &mut impl Write) -> io::Result<()> {
+        let timeout = self
+            .timeout
+            .map(|x| x.as_millis().min(NO_LIMIT as u128 - 1) as u64);
+        output.write_all(&self.step_budget.unwrap_or(NO_LIMIT).to_le_bytes())?;
+        output.write_all(&timeout.unwrap_or(NO_LIMIT).to_le_bytes())
+    }
+
+    fn read(input: &mut impl Read) -> io::Result<Limits> {
+        let mut field = || {
+            let mut bytes = [0; 8];
+            input.read_exact(&mut bytes)?;
+            Ok::<_, io::Error>(Some(u64::from_le_bytes(bytes)).filter(|&x| x != NO_LIMIT))
+        };
+        Ok(Limits {
+            step_budget: field()?,
+            timeout: field()?.map(Duration::from_millis),
+        })
+    }
+}
+
+// The most steps any request served here may take, whatever its limits (e.g. a tenant's
+// quota: see daemon).
+static STEP_LIMIT: AtomicU64 = AtomicU64::new(NO_LIMIT);
+
+pub(crate) fn set_step_limit(steps: u64) {
+    STEP_LIMIT.store(steps, Ordering::Relaxed);
+}
+
+// The fuel left to every apply executed here, together (e.g. a tenant's quota: see daemon).
+static FUEL_LEFT: AtomicU64 = AtomicU64::new(NO_LIMIT);
+
+pub(crate) fn set_fuel_limit(fuel: u64) {
+    FUEL_LEFT.store(fuel, Ordering::Relaxed);
+}
+
+// Take up to `wanted` of the fuel left, for an apply (which gives back what it didn't use).
+#[cfg(feature = "wasm")]
+pub(crate) fn draw_fuel(wanted: u64) -> u64 {
+    draw(&FUEL_LEFT, wanted)
+}
+
+#[cfg(feature = "wasm")]
+pub(crate) fn return_fuel(unused: u64) {
+    give_back(&FUEL_LEFT, unused)
+}
+
+#[cfg(feature = "wasm")]
+fn draw(left: &AtomicU64, wanted: u64) -> u64 {
+    let drawn = left.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+        (left != NO_LIMIT).then(|| left - left.min(wanted))
+    });
+    match drawn {
+        Ok(left) => left.min(wanted),
+        Err(_) => wanted,
+    }
+}
+
+#[cfg(feature = "wasm")]
+fn give_back(left: &AtomicU64, unused: u64) {
+    let _ = left.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+        (left != NO_LIMIT).then(|| left.saturating_add(unused).min(NO_LIMIT - 1))
+    });
+}
+
+// The Repository whose labels are kept here (e.g. a tenant's: see daemon), if any.
+static LABELS: RwLock<Option<Arc<Repository>>> = RwLock::new(None);
+
+pub(crate) fn keep_labels(repository: Arc<Repository>) {
+    *LABELS.write().unwrap() = Some(repository);
+}
+
+// A coordinator's connection, as a worker sees it: requests are read from one stream, and
+// answers (and fetches) written to the other.
+struct Connection {
+    streams: Mutex<Streams>,
+    // What its requests have named (their closures, and what they leave out), and what's
+    // reachable from what's been fetched from it: all it may be asked for.
+    named: Mutex<HashSet<(bool, Key)>>,
+}
+
+type Streams = (Box<dyn Read + Send>, BufWriter<Box<dyn Write + Send>>);
+
+impl Connection {
+    fn new(input: impl Read + Send + 'static, output: impl Write + Send + 'static) -> Self {
+        let input: Box<dyn Read + Send> = Box::new(input);
+        let output: Box<dyn Write + Send> = Box::new(output);
+        Connection {
+            streams: Mutex::new((input, BufWriter::new(output))),
+            named: Mutex::default(),
+        }
+    }
+}
+
+// The connections whose requests are executing, which are waiting on an answer (and so can
+// answer fetches).
+static EXECUTING: Mutex<Vec<Arc<Connection>>> = Mutex::new(Vec::new());
+
+// A connection's place in EXECUTING, for as long as it lives, and its heartbeats.
+struct Executing {
+    connection: Arc<Connection>,
+    heartbeats: Option<(mpsc::Sender<()>, thread::JoinHandle<()>)>,
+}
+
+impl Executing {
+    fn new(connection: &Arc<Connection>) -> Self {
+        EXECUTING.lock().unwrap().push(connection.clone());
+        let (stop, stopped) = mpsc::channel();
+        let beating = connection.clone();
+        let heartbeats = thread::spawn(move || {
+            while stopped.recv_timeout(PULSE) == Err(mpsc::RecvTimeoutError::Timeout) {
+                let mut streams = beating.streams.lock().unwrap();
+                // (A connection that's failed also fails the answer.)
+                if streams
+                    .1
+                    .write_all(&[HEARTBEAT])
+                    .and_then(|_| streams.1.flush())
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        });
+        Executing {
+            connection: connection.clone(),
+            heartbeats: Some((stop, heartbeats)),
+        }
+    }
+}
+
+impl Drop for Executing {
+    fn drop(&mut self) {
+        if let Some((stop, heartbeats)) = self.heartbeats.take() {
+            drop(stop);
+            let _ = heartbeats.join();
+        }
+        EXECUTING
+            .lock()
+            .unwrap()
+            .retain(|x| !Arc::ptr_eq(x, &self.connection));
+    }
+}
+
+// Answer requests from `input` on `output`, until `input` ends.
+pub(crate) fn serve(
+    input: impl Read + Send + 'static,
+    output: impl Write + Send + 'static,
+) -> io::Result<()> {
+    let connection = Arc::new(Connection::new(BufReader::new(input), output));
+    loop {
+        let mut streams = connection.streams.lock().unwrap();
+        let mut kind = [0];
+        match streams.0.read_exact(&mut kind) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        match kind[0] {
+            EXECUTE => {}
+            GET_LABEL | SET_LABEL => {
+                let (input, output) = &mut *streams;
+                label(input, output, kind[0] == SET_LABEL)?;
+                continue;
+            }
+            _ => return Err(io::Error::new(ErrorKind::InvalidData, "unknown request")),
+        }
+        let limits = Limits::read(&mut streams.0)?;
+        let (thunk, named) = match archive::receive(&mut streams.0, Closure::Lazy)? {
+            (Handle::Thunk(thunk), named) => (thunk, named),
+            _ => return Err(io::Error::new(ErrorKind::InvalidData, "not a Thunk")),
+        };
+        drop(streams);
+        connection.named.lock().unwrap().extend(&named);
+        let e = Encode {
+            thunk,
+            accessibility: None,
+        };
+        let step_limit = Some(STEP_LIMIT.load(Ordering::Relaxed)).filter(|&x| x != NO_LIMIT);
+        let context = Context {
+            step_budget: limits.step_budget.into_iter().chain(step_limit).min(),
+            timeout: limits.timeout,
+            ..Context::default()
+        };
+        // (Fetches go out until the answer is written, as it may name objects not yet here.)
+        let executing = Executing::new(&connection);
+        let (status, answer) = match execute(e, &context) {
+            Ok(data) => (OK, data),
+            Err(trap) => (TRAP, trap),
+        };
+        let mut archive = Vec::new();
+        let mut named = named.into_iter().collect();
+        archive::send(Handle::Data(answer), &mut archive, &mut named, false)?;
+        drop(executing);
+        let mut streams = connection.streams.lock().unwrap();
+        streams.1.write_all(&[status])?;
+        streams.1.write_all(&archive)?;
+        streams.1.flush()?;
+        // (So what's kept in a Repository, with the result, outlives the worker.)
+        storage().flush()?;
+    }
+}
+
+// Answer a request to get or set a label (see above).
+fn label(input: &mut impl Read, output: &mut impl Write, set: bool) -> io::Result<()> {
+    let name = read_name(input)?;
+    let labels = LABELS.read().unwrap().clone();
+    match set {
+        true => {
+            let (h, _) = archive::receive(&mut *input, Closure::Held)?;
+            let set = match labels.map(|x| x.set_label(&name, h)) {
+                Some(Ok(())) => true,
+                Some(Err(e)) if e.kind() != ErrorKind::InvalidInput => return Err(e),
+                _ => false,
+            };
+            output.write_all(&[u8::from(set)])?;
+        }
+        false => match labels.and_then(|x| x.label(&name)) {
+            Some(h) => {
+                output.write_all(&[1])?;
+                archive::send(h, &mut *output, &mut HashSet::new(), false)?;
+            }
+            None => output.write_all(&[0])?,
+        },
+    }
+    output.flush()
+}
+
+fn write_name(output: &mut impl Write, name: &str) -> io::Result<()> {
+    output.write_all(&(name.len() as u32).to_le_bytes())?;
+    output.write_all(name.as_bytes())
+}
+
+fn read_name(input: &mut impl Read) -> io::Result<String> {
+    let mut length = [0; 4];
+    input.read_exact(&mut length)?;
+    let length = u32::from_le_bytes(length) as u64;
+    if length > MAX_LABEL {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "label name too long",
+        ));
+    }
+    let mut name = Vec::new();
+    input.take(length).read_to_end(&mut name)?;
+    if name.len() as u64 != length {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(name).map_err(|_| io::Error::new(ErrorKind::InvalidData, "not a label name"))
+}
+
+// A worker's Storage: its own, which objects it doesn't hold are fetched into (see above)
+// from the coordinators whose requests are executing. A fetch only goes to a coordinator
+// whose requests named the object (or reach it through what's been fetched from it), so one
+// coordinator's requests can't draw objects from another's. (Any of those will do, as
+// objects are named by their contents, and what's fetched is checked against its name.)
+pub(crate) struct Demand(pub(crate) Arc<dyn Storage>);
+
+impl Demand {
+    // An object's contents, from the first coordinator that named it and has it, with that
+    // coordinator's connection.
+    fn fetch(&self, tree: bool, name: Key) -> io::Result<Option<(Vec<u8>, Arc<Connection>)>> {
+        let executing: Vec<_> = EXECUTING
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|x| x.named.lock().unwrap().contains(&(tree, name)))
+            .cloned()
+            .collect();
+        for connection in executing {
+            let mut streams = connection.streams.lock().unwrap();
+            let (input, output) = &mut *streams;
+            output.write_all(&[FETCH, u8::from(tree)])?;
+            output.write_all(&key_bytes(name))?;
+            output.flush()?;
+            let mut found = [0];
+            input.read_exact(&mut found)?;
+            if found[0] == 0 {
+                continue;
+            }
+            let mut length = [0; 8];
+            input.read_exact(&mut length)?;
+            let length = u64::from_le_bytes(length);
+            let mut contents = Vec::new();
+            input.take(length).read_to_end(&mut contents)?;
+            if contents.len() as u64 != length {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+            drop(streams);
+            return Ok(Some((contents, connection)));
+        }
+        Ok(None)
+    }
+}
+
+impl Storage for Demand {
+    fn get_blob(&self, name: Key) -> io::Result<Option<SharedBlob>> {
+        if let Some(blob) = self.0.get_blob(name)? {
+            return Ok(Some(blob));
+        }
+        let Some((contents, _)) = self.fetch(false, name)? else {
+            return Ok(None);
+        };
+        quarantining(&*self.0, fetch::check_blob(name, &contents))?;
+        let blob = SharedBlob::from(contents);
+        self.0.put_blob(name, blob.clone())?;
+        Ok(Some(blob))
+    }
+
+    fn put_blob(&self, name: Key, blob: SharedBlob) -> io::Result<()> {
+        self.0.put_blob(name, blob)
+    }
+
+    fn contains_blob(&self, name: Key) -> io::Result<bool> {
+        self.0.contains_blob(name)
+    }
+
+    fn delete_blob(&self, name: Key) -> io::Result<()> {
+        self.0.delete_blob(name)
+    }
+
+    fn get_tree(&self, name: Key) -> io::Result<Option<Arc<Tree<PackedHandle>>>> {
+        if let Some(tree) = self.0.get_tree(name)? {
+            return Ok(Some(tree));
+        }
+        let Some((contents, connection)) = self.fetch(true, name)? else {
+            return Ok(None);
+        };
+        let tree = unpack_tree(&contents)?;
+        quarantining(&*self.0, fetch::check_tree(name, &tree))?;
+        let reachable = tree
+            .iter()
+            .filter_map(|h| Some((chunk::stored_as_tree(h), h.key()?)));
+        connection.named.lock().unwrap().extend(reachable);
+        let tree: Arc<Tree<PackedHandle>> = tree.into();
+        self.0.put_tree(name, tree.clone())?;
+        Ok(Some(tree))
+    }
+
+    fn put_tree(&self, name: Key, tree: Arc<Tree<PackedHandle>>) -> io::Result<()> {
+        self.0.put_tree(name, tree)
+    }
+
+    fn contains_tree(&self, name: Key) -> io::Result<bool> {
+        self.0.contains_tree(name)
+    }
+
+    fn delete_tree(&self, name: Key) -> io::Result<()> {
+        self.0.delete_tree(name)
+    }
+
+    fn list_blobs(&self) -> io::Result<Vec<Key>> {
+        self.0.list_blobs()
+    }
+
+    fn list_trees(&self) -> io::Result<Vec<Key>> {
+        self.0.list_trees()
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.0.flush()
+    }
+
+    fn quarantine(&self, mismatch: &Mismatch) -> io::Result<()> {
+        self.0.quarantine(mismatch)
+    }
+}
+
+// Answer requests on each connection to `listener` (each on a thread of its own).
+pub(crate) fn listen(listener: TcpListener) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        stream.set_nodelay(true)?;
+        let input = stream.try_clone()?;
+        // (A connection that fails only ends itself.)
+        thread::spawn(move || serve(input, stream));
+    }
+    Ok(())
+}
+
+// A connection to a worker: requests are written to one stream, and answers read from another.
+pub(crate) struct Worker {
+    streams: Mutex<(Box<dyn Read + Send>, Box<dyn Write + Send>)>,
+    child: Mutex<Option<Child>>,
+    // Set once a request fails (after which the streams may be out of step).
+    failed: AtomicBool,
+    // The objects the worker holds: those sent to it, and those it's sent back.
+    held: Mutex<HashSet<(bool, Key)>>,
+    // The requests sent to it (or waiting to be) that it hasn't answered.
+    pending: AtomicUsize,
+    // While a request is under way, when the worker was last heard from (or took some of
+    // what it was sent); None while none is.
+    heard: Heard,
+    // The socket to a worker listening on TCP, shut down when it fails.
+    socket: Option<TcpStream>,
+}
+
+type Heard = Arc<Mutex<Option<Instant>>>;
+
+// A stream to or from a worker, noting when anything moves along it.
+struct Watched<S> {
+    stream: S,
+    heard: Heard,
+}
+
+impl<S> Watched<S> {
+    fn moved(&self, n: usize) {
+        if n > 0 {
+            *self.heard.lock().unwrap() = Some(Instant::now());
+        }
+    }
+}
+
+impl<S: Read> Read for Watched<S> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let n = self.stream.read(buffer)?;
+        self.moved(n);
+        Ok(n)
+    }
+}
+
+impl<S: Write> Write for Watched<S> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        let n = self.stream.write(buffer)?;
+        self.moved(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Worker {
+    pub(crate) fn new(
+        input: impl Read + Send + 'static,
+        output: impl Write + Send + 'static,
+    ) -> Self {
+        let heard = Heard::default();
+        let input = Watched {
+            stream: BufReader::new(input),
+            heard: heard.clone(),
+        };
+        let output = Watched {
+            stream: output,
+            heard: heard.clone(),
+        };
+        Worker {
+            streams: Mutex::new((Box::new(input), Box::new(output))),
+            child: Mutex::new(None),
+            failed: AtomicBool::new(false),
+            held: Mutex::default(),
+            pending: AtomicUsize::new(0),
+            heard,
+            socket: None,
+        }
+    }
+
+    // Start a worker process, which serves on its stdin and stdout (e.g. `fixmodel worker`).
+    pub(crate) fn spawn(command: &mut Command) -> io::Result<Self> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let (stdout, stdin) = (child.stdout.take().unwrap(), child.stdin.take().unwrap());
+        let worker = Worker::new(stdout, stdin);
+        *worker.child.lock().unwrap() = Some(child);
+        Ok(worker)
+    }
+
+    // Connect to a worker listening on TCP (see listen).
+    pub(crate) fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        Worker::over(TcpStream::connect(address)?)
+    }
+
+    // Connect to a daemon serving several tenants, as the one `token` names (see daemon).
+    pub(crate) fn connect_as(address: impl ToSocketAddrs, token: &str) -> io::Result<Self> {
+        let mut stream = TcpStream::connect(address)?;
+        writeln!(stream, "{token}")?;
+        Worker::over(stream)
+    }
+
+    fn over(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        let mut worker = Worker::new(stream.try_clone()?, stream.try_clone()?);
+        worker.socket = Some(stream);
+        Ok(worker)
+    }
+
+    // Have the worker execute a Thunk, returning the Data it produced or its trap.
+    fn execute(&self, thunk: Thunk, limits: Limits) -> io::Result<Result<Data>> {
+        let mut streams = self.streams.lock().unwrap();
+        *self.heard.lock().unwrap() = Some(Instant::now());
+        let answer = self.exchange(&mut streams, thunk, limits);
+        *self.heard.lock().unwrap() = None;
+        answer
+    }
+
+    fn exchange(
+        &self,
+        (input, output): &mut (Box<dyn Read + Send>, Box<dyn Write + Send>),
+        thunk: Thunk,
+        limits: Limits,
+    ) -> io::Result<Result<Data>> {
+        let mut request = BufWriter::new(&mut *output);
+        request.write_all(&[EXECUTE])?;
+        limits.write(&mut request)?;
+        archive::send(
+            Handle::Thunk(thunk),
+            &mut request,
+            &mut self.held.lock().unwrap(),
+            true,
+        )?;
+        request.flush()?;
+        drop(request);
+        let mut status = [0];
+        loop {
+            input.read_exact(&mut status)?;
+            match status[0] {
+                HEARTBEAT => {}
+                FETCH => {
+                    if let Some(object) = lend(input, output)? {
+                        self.held.lock().unwrap().insert(object);
+                    }
+                }
+                _ => break,
+            }
+        }
+        let answer = match archive::receive(input, Closure::Held)? {
+            (Handle::Data(x), received) => {
+                self.held.lock().unwrap().extend(received);
+                x
+            }
+            _ => return Err(io::Error::new(ErrorKind::InvalidData, "answer is not Data")),
+        };
+        match status[0] {
+            OK => Ok(Ok(answer)),
+            TRAP => Ok(Err(answer)),
+            _ => Err(io::Error::new(ErrorKind::InvalidData, "unknown status")),
+        }
+    }
+
+    // The Handle one of the labels the worker keeps names (its closure stored here), if it
+    // keeps the label (see above).
+    pub(crate) fn label(&self, name: &str) -> io::Result<Option<Handle>> {
+        let mut streams = self.streams.lock().unwrap();
+        let (input, output) = &mut *streams;
+        let mut request = BufWriter::new(&mut *output);
+        request.write_all(&[GET_LABEL])?;
+        write_name(&mut request, name)?;
+        request.flush()?;
+        drop(request);
+        let mut found = [0];
+        input.read_exact(&mut found)?;
+        if found[0] == 0 {
+            return Ok(None);
+        }
+        let (h, received) = archive::receive(input, Closure::Complete)?;
+        self.held.lock().unwrap().extend(received);
+        Ok(Some(h))
+    }
+
+    // Have the worker keep a label naming `h`, returning whether it does.
+    pub(crate) fn set_label(&self, name: &str, h: Handle) -> io::Result<bool> {
+        let mut streams = self.streams.lock().unwrap();
+        let (input, output) = &mut *streams;
+        let mut request = BufWriter::new(&mut *output);
+        request.write_all(&[SET_LABEL])?;
+        write_name(&mut request, name)?;
+        archive::send(h, &mut request, &mut self.held.lock().unwrap(), false)?;
+        request.flush()?;
+        drop(request);
+        let mut set = [0];
+        input.read_exact(&mut set)?;
+        Ok(set[0] == 1)
+    }
+
+    // Has a request to the worker gone longer than `lease` with nothing moving either way?
+    // (While it's sent, executed or answered: a worker executing sends heartbeats.)
+    fn lapsed(&self, lease: Duration) -> bool {
+        let heard = *self.heard.lock().unwrap();
+        heard.is_some_and(|x| x.elapsed() > lease)
+    }
+
+    // Stop using the worker, ending a request it hasn't answered (by killing its process, or
+    // shutting its socket down).
+    fn fail(&self) {
+        self.failed.store(true, Ordering::Relaxed);
+        if let Some(child) = &mut *self.child.lock().unwrap() {
+            let _ = child.kill();
+        }
+        if let Some(socket) = &self.socket {
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+// Reply to a worker's fetch (see above), returning the object if it was sent.
+fn lend(input: &mut impl Read, output: &mut impl Write) -> io::Result<Option<(bool, Key)>> {
+    let mut request = [0; 25];
+    input.read_exact(&mut request)?;
+    let tree = match request[0] {
+        0 => false,
+        1 => true,
+        _ => return Err(io::Error::new(ErrorKind::InvalidData, "unknown kind")),
+    };
+    let name = key_from_bytes(&request[1..]).unwrap();
+    let contents = match tree {
+        true => storage().get_tree(name)?.map(|x| pack_tree(&x)),
+        false => storage().get_blob(name)?.map(|x| x.to_vec()),
+    };
+    let mut reply = BufWriter::new(output);
+    match &contents {
+        Some(contents) => {
+            reply.write_all(&[1])?;
+            reply.write_all(&(contents.len() as u64).to_le_bytes())?;
+            reply.write_all(contents)?;
+        }
+        None => reply.write_all(&[0])?,
+    }
+    reply.flush()?;
+    Ok(contents.map(|_| (tree, name)))
+}
+
+impl Worker {
+    // What it costs to run a Thunk with `inputs` on the worker (see above). (While a request
+    // is being sent, the worker holds nothing as far as this is concerned, so placement
+    // never waits on a send.)
+    fn cost(&self, inputs: &[((bool, Key), usize)]) -> usize {
+        let missing: usize = match self.held.try_lock() {
+            Ok(held) => inputs
+                .iter()
+                .filter(|(x, _)| !held.contains(x))
+                .map(|x| x.1)
+                .sum(),
+            Err(_) => inputs.iter().map(|x| x.1).sum(),
+        };
+        missing.saturating_add(self.pending.load(Ordering::Relaxed).saturating_mul(QUEUED))
+    }
+}
+
+// The objects a Thunk names directly (its Data, or the elements of its combination or
+// specification), by their canonical keys, each with the size of its object: a Blob's
+// length, or a Tree's footprint.
+fn inputs(thunk: Thunk) -> Vec<((bool, Key), usize)> {
+    let elements = match thunk {
+        Thunk::Application(x) | Thunk::Selection(x) => x.try_load().unwrap_or_default(),
+        Thunk::Identification(x) => vec![Handle::Data(x)],
+    };
+    elements
+        .into_iter()
+        .filter_map(|h| {
+            let h = local::canonical_name(PackedHandle::pack(h));
+            let size = match h.tree_metadata() {
+                Some((_, footprint, _)) => {
+                    (footprint as usize * PAGE_SIZE).max(h.size() * HANDLE_SIZE)
+                }
+                None => h.size(),
+            };
+            Some(((chunk::stored_as_tree(&h), h.key()?), size))
+        })
+        .collect()
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        if let Some(child) = &mut *self.child.lock().unwrap() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+// Workers to offload Encodes to, taken in turn.
+pub(crate) struct Coordinator {
+    workers: Vec<Arc<Worker>>,
+    next: AtomicUsize,
+    // How long to wait for any answer (None to wait as long as the Encode may take).
+    timeout: Option<Duration>,
+    // How long to wait to hear from a worker.
+    lease: Duration,
+    // When the Storage was last flushed.
+    checkpointed: Mutex<Instant>,
+}
+
+impl Coordinator {
+    pub(crate) fn new(workers: Vec<Worker>) -> Self {
+        assert!(!workers.is_empty(), "Coordinator needs a worker");
+        Coordinator {
+            workers: workers.into_iter().map(Arc::new).collect(),
+            next: AtomicUsize::new(0),
+            timeout: None,
+            lease: LEASE,
+            checkpointed: Mutex::new(Instant::now()),
+        }
+    }
+
+    // Give up on a worker that hasn't been heard from for `lease` (which has to be longer
+    // than PULSE).
+    pub(crate) fn with_lease(mut self, lease: Duration) -> Self {
+        self.lease = lease;
+        self
+    }
+
+    // Give up on a worker that hasn't answered within `timeout` (and execute locally).
+    pub(crate) fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    // Execute an Encode (as in execute) on the worker (of those that haven't failed) it costs
+    // least to run on, or of those that cost the same, the next in turn.
+    pub(crate) fn execute(&self, e: Encode, context: &Context) -> Result<Data> {
+        if memo::lookup(e.thunk).is_some() {
+            return execute(e, context);
+        }
+        context.check()?;
+        let execution = Execution::new(e, context);
+        let inputs = inputs(e.thunk);
+        loop {
+            let first = self.next.fetch_add(1, Ordering::Relaxed);
+            let worker = (0..self.workers.len())
+                .map(|i| &self.workers[(first + i) % self.workers.len()])
+                .filter(|x| !x.failed.load(Ordering::Relaxed))
+                .map(|x| (x, x.cost(&inputs)))
+                .reduce(|best, x| if x.1 < best.1 { x } else { best })
+                .map(|(x, _)| x);
+            let Some(worker) = worker else {
+                return execute(e, context);
+            };
+            match self.request(worker, e.thunk, &execution.context) {
+                Some(Ok(data)) => {
+                    let result = execution.finish(data);
+                    self.checkpoint();
+                    return result;
+                }
+                Some(Err(trap)) => return Err(trap),
+                None => {
+                    worker.fail();
+                    execution.context.check()?;
+                }
+            }
+        }
+    }
+
+    // Flush the Storage (with the memo table, if it's persisted there), if it's been
+    // CHECKPOINT since it last was. (A failure is reported, but the result it would have
+    // kept is still returned: only the next checkpoint, or the evaluation's own flush, can
+    // keep it now.)
+    fn checkpoint(&self) {
+        let mut checkpointed = self.checkpointed.lock().unwrap();
+        if checkpointed.elapsed() >= CHECKPOINT {
+            *checkpointed = Instant::now();
+            drop(checkpointed);
+            if let Err(e) = storage().flush() {
+                eprintln!("fixmodel: checkpoint failed: {e}");
+            }
+        }
+    }
+
+    // The worker's answer, or None if it failed, or didn't answer in time.
+    fn request(
+        &self,
+        worker: &Arc<Worker>,
+        thunk: Thunk,
+        context: &Context,
+    ) -> Option<Result<Data>> {
+        let limits = Limits::of(context);
+        let start = Instant::now();
+        let patience = [
+            self.timeout,
+            limits.timeout.map(|x| x.saturating_add(GRACE)),
+        ];
+        let deadline = patience.into_iter().flatten().min().map(|x| start + x);
+        let (send, receive) = mpsc::channel();
+        let requested = worker.clone();
+        requested.pending.fetch_add(1, Ordering::Relaxed);
+        thread::spawn(move || {
+            let answer = requested.execute(thunk, limits);
+            requested.pending.fetch_sub(1, Ordering::Relaxed);
+            send.send(answer)
+        });
+        loop {
+            match receive.recv_timeout(POLL) {
+                Ok(answer) => return answer.ok(),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+            }
+            if context.cancellation.is_cancelled()
+                || deadline.is_some_and(|x| Instant::now() >= x)
+                || worker.lapsed(self.lease)
+            {
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::path::Path;
+    use crate::{BlobName, Object, TreeName, trap};
+
+    // A worker served by a thread of this process.
+    fn worker() -> Worker {
+        let (requests, request_writer) = io::pipe().unwrap();
+        let (answer_reader, answers) = io::pipe().unwrap();
+        thread::spawn(move || serve(requests, answers));
+        Worker::new(answer_reader, request_writer)
+    }
+
+    fn encode(thunk: Thunk) -> Encode {
+        Encode {
+            thunk,
+            accessibility: None,
+        }
+    }
+
+    // A Thunk that takes `steps` thoughts to reach its Data.
+    fn chain(seed: &[u8], steps: usize) -> Thunk {
+        let data = Data::Object(Object::Blob(BlobName::create(seed.to_vec()).ok().unwrap()));
+        let mut thunk = Thunk::Identification(data);
+        for _ in 0..steps {
+            let tree = TreeName::create(vec![Handle::Thunk(thunk)]).ok().unwrap();
+            let target = Data::Object(Object::Tree(tree));
+            thunk = Path::new().index(0).thunk(target).ok().unwrap();
+        }
+        thunk
+    }
+
+    #[test]
+    fn executes_on_a_worker() {
+        let coordinator = Coordinator::new(vec![worker()]);
+        let thunk = chain(b"executes on a worker, in a few steps", 3);
+        let result = coordinator.execute(encode(thunk), &Context::default());
+        assert!(result.is_ok());
+        assert!(!coordinator.workers[0].failed.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn executes_on_workers_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || listen(listener));
+        let workers = (0..2).map(|_| Worker::connect(address).unwrap()).collect();
+        let coordinator = Coordinator::new(workers);
+        for seed in [&b"executes on a worker over TCP"[..], b"and on another"] {
+            let result = coordinator.execute(encode(chain(seed, 2)), &Context::default());
+            assert!(result.is_ok());
+        }
+        assert!(
+            coordinator
+                .workers
+                .iter()
+                .all(|x| !x.failed.load(Ordering::Relaxed))
+        );
+    }
+
+    // An Application of a combination with a large Blob (which traps, applying nothing).
+    fn application(large: Handle, seed: &[u8]) -> Thunk {
+        let seed = Handle::Data(Data::Object(Object::Blob(
+            BlobName::create(seed.to_vec()).ok().unwrap(),
+        )));
+        Thunk::Application(TreeName::create(vec![seed, large]).ok().unwrap())
+    }
+
+    #[test]
+    fn encodes_go_to_the_worker_holding_their_inputs() {
+        let coordinator = Coordinator::new(vec![worker(), worker()]);
+        let large = BlobName::create(vec![0x10; 3 * PAGE_SIZE]).ok().unwrap();
+        let large = Handle::Data(Data::Object(Object::Blob(large)));
+        let first = application(large, b"the first of two Encodes with a large input");
+        assert!(
+            coordinator
+                .execute(encode(first), &Context::default())
+                .is_err()
+        );
+        let holder = holder(&coordinator, large).unwrap();
+        // The next in turn is the other worker, but this one holds the input.
+        let second = application(large, b"the second, which goes to the same worker");
+        assert!(
+            coordinator
+                .execute(encode(second), &Context::default())
+                .is_err()
+        );
+        assert_eq!(
+            coordinator.workers[1 - holder].held.lock().unwrap().len(),
+            0
+        );
+        assert!(
+            coordinator
+                .workers
+                .iter()
+                .all(|x| !x.failed.load(Ordering::Relaxed))
+        );
+    }
+
+    // Which of `coordinator`'s workers holds a Blob.
+    fn holder(coordinator: &Coordinator, blob: Handle) -> Option<usize> {
+        let h = PackedHandle::pack(blob);
+        let object = (chunk::stored_as_tree(&h), h.key().unwrap());
+        let mut workers = coordinator.workers.iter();
+        workers.position(|x| x.held.lock().unwrap().contains(&object))
+    }
+
+    #[test]
+    fn idle_workers_take_encodes_whose_data_is_cheap_to_send() {
+        let coordinator = Coordinator::new(vec![worker(), worker()]);
+        let blob = |len| {
+            let blob = BlobName::create(vec![0x20; len]).ok().unwrap();
+            Handle::Data(Data::Object(Object::Blob(blob)))
+        };
+        let (large, small) = (blob(2 * QUEUED), blob(PAGE_SIZE));
+        let run = |thunk| coordinator.execute(encode(thunk), &Context::default());
+        assert!(run(application(large, b"the first, placed on the first worker")).is_err());
+        assert!(run(application(small, b"the second, placed on the second")).is_err());
+        assert_eq!(
+            (holder(&coordinator, large), holder(&coordinator, small)),
+            (Some(0), Some(1))
+        );
+        // While the second worker is busy, the Encode with the small input is the first's (its
+        // data is cheaper to send than the wait), but the Encode with the large input isn't.
+        coordinator.workers[1].pending.store(1, Ordering::Relaxed);
+        let small_again = application(small, b"stolen from the busy worker");
+        assert!(run(small_again).is_err());
+        assert_eq!(holder(&coordinator, small), Some(0));
+        coordinator.workers[0].pending.store(1, Ordering::Relaxed);
+        coordinator.workers[1].pending.store(0, Ordering::Relaxed);
+        let before = coordinator.workers[1].held.lock().unwrap().len();
+        assert!(run(application(large, b"worth waiting for the busy worker")).is_err());
+        assert_eq!(coordinator.workers[1].held.lock().unwrap().len(), before);
+    }
+
+    #[test]
+    fn a_worker_keeps_the_step_budget() {
+        let coordinator = Coordinator::new(vec![worker()]);
+        let thunk = chain(b"a worker keeps the step budget of its caller", 5);
+        let context = Context {
+            step_budget: Some(2),
+            ..Context::default()
+        };
+        let trap = coordinator.execute(encode(thunk), &context).err().unwrap();
+        assert!(trap::is(trap, trap::Kind::ResourceExhausted));
+        assert!(!coordinator.workers[0].failed.load(Ordering::Relaxed));
+    }
+
+    // A coordinator (sharing this process's storage) that only answers fetches, and its
+    // connection as its worker sees it.
+    fn lender() -> Arc<Connection> {
+        let (mut fetches, fetch_writer) = io::pipe().unwrap();
+        let (replies, mut reply_writer) = io::pipe().unwrap();
+        thread::spawn(move || {
+            let mut status = [0];
+            while fetches.read_exact(&mut status).is_ok() && status[0] != OK {
+                if status[0] == FETCH {
+                    lend(&mut fetches, &mut reply_writer).unwrap();
+                }
+            }
+        });
+        Arc::new(Connection::new(replies, fetch_writer))
+    }
+
+    #[test]
+    fn a_worker_fetches_what_it_is_missing() {
+        let connection = lender();
+        let executing = Executing::new(&connection);
+
+        let worker = Demand(Arc::new(crate::storage::memory::MemoryStorage::default()));
+        let blob = BlobName::create(b"held by the coordinator, fetched by its worker".to_vec());
+        let blob = PackedHandle::pack(Handle::Data(Data::Object(Object::Blob(blob.ok().unwrap()))));
+        let tree = TreeName::create(vec![blob.unpack()]).ok().unwrap();
+        let tree = PackedHandle::pack(Handle::Data(Data::Object(Object::Tree(tree))));
+        // The request named the Tree (and so, once it's fetched, its element).
+        connection
+            .named
+            .lock()
+            .unwrap()
+            .insert((true, tree.key().unwrap()));
+        assert!(worker.get_blob(blob.key().unwrap()).unwrap().is_none());
+        for (kind, h) in [(true, tree), (false, blob)] {
+            let name = h.key().unwrap();
+            assert!(
+                !worker.0.contains_blob(name).unwrap() && !worker.0.contains_tree(name).unwrap()
+            );
+            let fetched = match kind {
+                true => worker.get_tree(name).unwrap().is_some(),
+                false => worker.get_blob(name).unwrap().is_some(),
+            };
+            assert!(fetched);
+            // (Once fetched, it's held.)
+            assert!(worker.0.contains_blob(name).unwrap() || worker.0.contains_tree(name).unwrap());
+        }
+        assert!(worker.get_blob((1, 2, 3)).unwrap().is_none());
+        // Once the request is answered, its coordinator isn't asked.
+        drop(executing);
+        assert!(
+            EXECUTING
+                .lock()
+                .unwrap()
+                .iter()
+                .all(|x| !Arc::ptr_eq(x, &connection))
+        );
+    }
+
+    #[test]
+    fn a_fetch_only_goes_to_the_coordinator_that_named_it() {
+        // A coordinator whose requests didn't name the object (and which fails if it's asked),
+        // and one whose requests did.
+        let other = Arc::new(Connection::new(io::empty(), io::sink()));
+        let naming = lender();
+        let executing = [Executing::new(&other), Executing::new(&naming)];
+        let worker = Demand(Arc::new(crate::storage::memory::MemoryStorage::default()));
+        let contents = b"named by one of two coordinators".to_vec();
+        let blob = BlobName::create(contents.clone()).ok().unwrap();
+        let name = PackedHandle::pack(Handle::Data(Data::Object(Object::Blob(blob))))
+            .key()
+            .unwrap();
+        storage()
+            .put_blob(name, SharedBlob::from(contents))
+            .unwrap();
+        assert!(worker.get_blob(name).unwrap().is_none());
+        naming.named.lock().unwrap().insert((false, name));
+        assert!(worker.get_blob(name).unwrap().is_some());
+        drop(executing);
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn applies_draw_on_the_fuel_left() {
+        let left = AtomicU64::new(100);
+        assert_eq!(draw(&left, 60), 60);
+        assert_eq!(draw(&left, 60), 40);
+        assert_eq!(draw(&left, 60), 0);
+        give_back(&left, 30);
+        assert_eq!(draw(&left, 60), 30);
+        // (With no limit, every apply has all it asks for.)
+        let unlimited = AtomicU64::new(NO_LIMIT);
+        assert_eq!(draw(&unlimited, 60), 60);
+        give_back(&unlimited, 60);
+        assert_eq!(unlimited.load(Ordering::Relaxed), NO_LIMIT);
+    }
+
+    #[test]
+    fn keeps_labels_for_its_coordinators() {
+        let dir = tempfile::TempDir::new().unwrap();
+        keep_labels(Arc::new(
+            Repository::create(dir.path().join("labels")).unwrap(),
+        ));
+        let worker = worker();
+        let blob = BlobName::create(b"labelled on a worker, for its coordinator".to_vec());
+        let h = Handle::Data(Data::Object(Object::Blob(blob.ok().unwrap())));
+        assert!(worker.label("kept").unwrap().is_none());
+        assert!(worker.set_label("kept", h).unwrap());
+        assert!(
+            PackedHandle::pack(worker.label("kept").unwrap().unwrap()) == PackedHandle::pack(h)
+        );
+        // (Not a label's name.)
+        assert!(!worker.set_label("../kept", h).unwrap());
+        assert!(!worker.failed.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn a_hung_worker_is_abandoned() {
+        // A worker that never answers (while its other end stays open).
+        let (answers, _never_written) = io::pipe().unwrap();
+        let hung = Worker::new(answers, io::sink());
+        let coordinator = Coordinator::new(vec![hung]).with_timeout(Duration::from_millis(50));
+        let thunk = chain(b"a hung worker is abandoned for a local execution", 1);
+        assert!(
+            coordinator
+                .execute(encode(thunk), &Context::default())
+                .is_ok()
+        );
+        assert!(coordinator.workers[0].failed.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn a_silent_worker_loses_its_lease_to_another() {
+        // A worker that never answers, nor sends a heartbeat, and one that does.
+        let (answers, _never_written) = io::pipe().unwrap();
+        let silent = Worker::new(answers, io::sink());
+        let coordinator =
+            Coordinator::new(vec![silent, worker()]).with_lease(Duration::from_millis(100));
+        let thunk = chain(b"a silent worker's Encode is retried on another", 1);
+        let result = coordinator.execute(encode(thunk), &Context::default());
+        assert!(result.is_ok());
+        assert!(coordinator.workers[0].failed.load(Ordering::Relaxed));
+        assert!(!coordinator.workers[1].failed.load(Ordering::Relaxed));
+        assert!(!coordinator.workers[1].held.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_worker_stalling_in_its_answer_loses_its_lease() {
+        // A worker that takes its requests, then sends a status and nothing after.
+        let (mut requests, request_writer) = io::pipe().unwrap();
+        let (answers, mut answer_writer) = io::pipe().unwrap();
+        thread::spawn(move || {
+            let mut request = [0; 16];
+            requests.read_exact(&mut request).unwrap();
+            answer_writer.write_all(&[OK]).unwrap();
+            io::copy(&mut requests, &mut io::sink())
+        });
+        let stalling = Worker::new(answers, request_writer);
+        let coordinator = Coordinator::new(vec![stalling]).with_lease(Duration::from_millis(100));
+        let thunk = chain(b"a worker stalling in its answer is abandoned", 1);
+        let result = coordinator.execute(encode(thunk), &Context::default());
+        assert!(result.is_ok());
+        assert!(coordinator.workers[0].failed.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn failing_a_worker_over_tcp_ends_its_request() {
+        // A worker that accepts the connection, and never answers.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            io::copy(&mut &stream, &mut io::sink())
+        });
+        let coordinator = Coordinator::new(vec![Worker::connect(address).unwrap()])
+            .with_lease(Duration::from_millis(100));
+        let thunk = chain(b"failing a worker over TCP ends its request", 1);
+        assert!(
+            coordinator
+                .execute(encode(thunk), &Context::default())
+                .is_ok()
+        );
+        let worker = &coordinator.workers[0];
+        let start = Instant::now();
+        while worker.pending.load(Ordering::Relaxed) > 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(POLL);
+        }
+        // (The streams are free again.)
+        drop(worker.streams.lock().unwrap());
+    }
+
+    #[test]
+    fn a_busy_worker_keeps_its_lease() {
+        // (What a coordinator sees of a worker executing longer than PULSE.)
+        let (mut heartbeats, output) = io::pipe().unwrap();
+        let connection = Arc::new(Connection::new(io::empty(), output));
+        let executing = Executing::new(&connection);
+        let mut status = [0];
+        heartbeats.read_exact(&mut status).unwrap();
+        assert_eq!(status[0], HEARTBEAT);
+        drop(executing);
+    }
+
+    #[test]
+    fn waiting_for_a_worker_can_be_cancelled() {
+        let (answers, _never_written) = io::pipe().unwrap();
+        let coordinator = Coordinator::new(vec![Worker::new(answers, io::sink())]);
+        let context = Context::default();
+        let cancellation = context.cancellation.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            cancellation.cancel();
+        });
+        let thunk = chain(b"waiting for a worker can be cancelled", 1);
+        let trap = coordinator.execute(encode(thunk), &context).err().unwrap();
+        assert!(trap::is(trap, trap::Kind::Cancelled));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        // A worker given a malformed request stops serving with an error, rather than panicking.
+        #[test]
+        fn arbitrary_requests_are_rejected(request in prop::collection::vec(any::<u8>(), 0..100)) {
+            let mut answer = Vec::new();
+            let (mut answers, output) = io::pipe().unwrap();
+            let _ = serve(io::Cursor::new(request), output);
+            answers.read_to_end(&mut answer).unwrap();
+            prop_assert!(answer.is_empty() || answer[0] <= TRAP);
+        }
+    }
+}
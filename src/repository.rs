@@ -0,0 +1,647 @@
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use memmap2::MmapOptions;
+
+mod config;
+mod equivalences;
+mod labels;
+mod memo;
+mod pack;
+
+use labels::Labels;
+use pack::{BLOB, Pack, PackWriter, TREE};
+
+use crate::fetch::Mismatch;
+use crate::packed::PackedHandle;
+use crate::storage::memory::MemoryStorage;
+use crate::storage::{Key, SharedBlob, Storage, slice};
+use crate::{HANDLE_SIZE, PAGE_SIZE, Tree};
+
+// A Repository keeps objects on disk (conventionally in a `.fix` directory), one file per object
+// until they are repacked:
+//
+//   FORMAT               the repository format (FORMAT_VERSION)
+//   objects/blob/<hex>   the contents of a Blob
+//   objects/tree/<hex>   the elements of a Tree, packed
+//   objects/pack/        pack files, each holding many objects (see pack::Pack)
+//   objects/tmp/         objects (and labels) being written
+//   labels/<name>        a label (see labels)
+//   memo                 remembered results (see memo)
+//   equivalences         equated Names (see equivalences)
+//   config               settings, such as the remotes fetched from (see config)
+//   quarantine/<name>    what was received that didn't match what was asked for (see
+//                        fetch::Mismatch::file_name), kept for inspection
+//
+// Objects are keyed by their canonical Pointer, in hex (48 digits).
+// Each object file starts with a header (HEADER_SIZE bytes) whose first byte is
+// the object's encoding, RAW or ZSTD; the rest is reserved and zero. Compression is
+// per object, so a repository may hold both.
+// New objects are held in memory until the Repository is flushed.
+//
+// Writes are atomic: an object is written and synced under objects/tmp, then renamed into
+// place (a pack becomes visible when its index is renamed into place). Opening a repository
+// discards whatever a crash left behind, so it assumes no other process is writing to it.
+pub(crate) struct Repository {
+    root: PathBuf,
+    pending: MemoryStorage,
+    packs: RwLock<Vec<Pack>>,
+    compression: Option<i32>,
+    labels: Mutex<Labels>,
+    // Remembered results not flushed yet.
+    remembering: Mutex<Vec<(PackedHandle, PackedHandle)>>,
+}
+
+const FORMAT_VERSION: &str = "fix repository 3\n";
+
+const HEADER_SIZE: usize = 8;
+const RAW: u8 = 0;
+const ZSTD: u8 = 1;
+
+// Objects smaller than this are never worth compressing.
+const COMPRESS_THRESHOLD: usize = 512;
+
+// Blobs at least this large are memory-mapped rather than read.
+const MAP_THRESHOLD: u64 = PAGE_SIZE as u64;
+
+impl Repository {
+    // Create a new, empty repository at `root` (which must not exist yet).
+    pub(crate) fn create(root: impl AsRef<Path>) -> io::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir(&root)?;
+        fs::create_dir_all(root.join("objects/blob"))?;
+        fs::create_dir_all(root.join("objects/tree"))?;
+        fs::create_dir_all(root.join("objects/pack"))?;
+        fs::create_dir_all(root.join("objects/tmp"))?;
+        fs::write(root.join("FORMAT"), FORMAT_VERSION)?;
+        Ok(Repository {
+            root,
+            pending: MemoryStorage::default(),
+            packs: RwLock::default(),
+            compression: None,
+            labels: Mutex::default(),
+            remembering: Mutex::default(),
+        })
+    }
+
+    pub(crate) fn open(root: impl AsRef<Path>) -> io::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        if fs::read_to_string(root.join("FORMAT"))? != FORMAT_VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "unsupported repository format",
+            ));
+        }
+        recover(&root)?;
+        let packs = Pack::open_all(&root.join("objects/pack"))?;
+        let labels = Labels::load(&root.join("labels"))?;
+        Ok(Repository {
+            root,
+            pending: MemoryStorage::default(),
+            packs: RwLock::new(packs),
+            compression: None,
+            labels: Mutex::new(labels),
+            remembering: Mutex::default(),
+        })
+    }
+
+    // Compress objects written from now on with zstd at `level` (where it makes them smaller).
+    // Existing objects are read either way.
+    pub(crate) fn with_compression(mut self, level: i32) -> Self {
+        self.compression = Some(level);
+        self
+    }
+
+    fn blob_path(&self, name: Key) -> PathBuf {
+        self.root.join("objects/blob").join(hex(name))
+    }
+
+    fn tree_path(&self, name: Key) -> PathBuf {
+        self.root.join("objects/tree").join(hex(name))
+    }
+
+    fn packed(&self, kind: u8, name: Key) -> bool {
+        self.packs
+            .read()
+            .unwrap()
+            .iter()
+            .any(|p| p.contains(kind, name))
+    }
+
+    // The contents of an object from a pack, if any pack holds it.
+    fn get_packed(&self, kind: u8, name: Key) -> io::Result<Option<Vec<u8>>> {
+        let packs = self.packs.read().unwrap();
+        packs
+            .iter()
+            .find_map(|p| p.get(kind, name))
+            .map(decode_record)
+            .transpose()
+    }
+
+    // The loose objects in `dir` (ignoring stray files).
+    fn loose(dir: &Path) -> io::Result<Vec<Key>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            if let Some(name) = entry?.file_name().to_str().and_then(from_hex) {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    // The objects in `dir`, in packs, and still pending.
+    fn list(&self, kind: u8, dir: &Path, pending: Vec<Key>) -> io::Result<Vec<Key>> {
+        let mut names = BTreeSet::from_iter(pending);
+        names.extend(Self::loose(dir)?);
+        for pack in self.packs.read().unwrap().iter() {
+            names.extend(pack.keys(kind));
+        }
+        Ok(names.into_iter().collect())
+    }
+
+    // Move the loose Trees and small Blobs (and everything in existing packs) into a
+    // single new pack. Large Blobs stay loose so they can still be memory-mapped.
+    pub(crate) fn repack(&self) -> io::Result<()> {
+        self.flush()?;
+        let dir = self.root.join("objects/pack");
+        let mut packs = self.packs.write().unwrap();
+        let mut writer = PackWriter::create(&dir)?;
+        let mut loose = Vec::new();
+        for (kind, name, path) in Self::loose(&self.root.join("objects/tree"))?
+            .into_iter()
+            .map(|name| (TREE, name, self.tree_path(name)))
+            .chain(
+                Self::loose(&self.root.join("objects/blob"))?
+                    .into_iter()
+                    .map(|name| (BLOB, name, self.blob_path(name))),
+            )
+        {
+            if kind == BLOB && fs::metadata(&path)?.len() >= HEADER_SIZE as u64 + MAP_THRESHOLD {
+                continue;
+            }
+            writer.add(kind, name, &fs::read(&path)?)?;
+            loose.push(path);
+        }
+        for pack in packs.iter() {
+            for (kind, name, record) in pack.records() {
+                writer.add(kind, name, record)?;
+            }
+        }
+        // Only once the new pack is complete are the objects removed from anywhere else.
+        let pack = writer.finish()?;
+        for path in loose {
+            fs::remove_file(path)?;
+        }
+        for old in packs.drain(..) {
+            old.delete()?;
+        }
+        if pack.is_empty() {
+            pack.delete()?;
+        } else {
+            packs.push(pack);
+        }
+        Ok(())
+    }
+
+    // Write an object with its header, compressed if that is enabled and helps.
+    // (The caller syncs the directory.)
+    fn write(&self, path: &Path, object: &[u8]) -> io::Result<()> {
+        let compressed = match self.compression {
+            Some(level) if object.len() >= COMPRESS_THRESHOLD => {
+                Some(zstd::bulk::compress(object, level)?).filter(|c| c.len() < object.len())
+            }
+            _ => None,
+        };
+        let (encoding, body) = match &compressed {
+            Some(c) => (ZSTD, &c[..]),
+            None => (RAW, object),
+        };
+        let mut header = [0u8; HEADER_SIZE];
+        header[0] = encoding;
+        self.write_file(path, &[&header, body])
+    }
+
+    // Write a file atomically: in objects/tmp, then renamed into place.
+    fn write_file(&self, path: &Path, parts: &[&[u8]]) -> io::Result<()> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let temporary = self.root.join("objects/tmp").join(format!(
+            "{}.{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut file = File::create(&temporary)?;
+        for part in parts {
+            file.write_all(part)?;
+        }
+        file.sync_all()?;
+        fs::rename(temporary, path)
+    }
+}
+
+impl Storage for Repository {
+    fn get_blob(&self, name: Key) -> io::Result<Option<SharedBlob>> {
+        if let Some(blob) = self.pending.get_blob(name)? {
+            return Ok(Some(blob));
+        }
+        let Some(file) = missing_as_none(File::open(self.blob_path(name)))? else {
+            return Ok(self.get_packed(BLOB, name)?.map(SharedBlob::from));
+        };
+        let mut header = [0u8; HEADER_SIZE];
+        (&file).read_exact(&mut header)?;
+        if header[0] == RAW && file.metadata()?.len() >= HEADER_SIZE as u64 + MAP_THRESHOLD {
+            // SAFETY: objects are never modified once written (their file name is their hash).
+            let map = unsafe { MmapOptions::new().offset(HEADER_SIZE as u64).map(&file)? };
+            return Ok(Some(SharedBlob::Mapped(Arc::new(map))));
+        }
+        let mut body = Vec::new();
+        (&file).read_to_end(&mut body)?;
+        Ok(Some(decode(header[0], body)?.into()))
+    }
+
+    fn put_blob(&self, name: Key, blob: SharedBlob) -> io::Result<()> {
+        if self.contains_blob(name)? {
+            return Ok(());
+        }
+        self.pending.put_blob(name, blob)
+    }
+
+    fn contains_blob(&self, name: Key) -> io::Result<bool> {
+        Ok(self.pending.contains_blob(name)?
+            || self.blob_path(name).try_exists()?
+            || self.packed(BLOB, name))
+    }
+
+    fn delete_blob(&self, name: Key) -> io::Result<()> {
+        self.pending.delete_blob(name)?;
+        for pack in self.packs.write().unwrap().iter_mut() {
+            pack.remove(BLOB, name);
+        }
+        missing_as_none(fs::remove_file(self.blob_path(name))).map(|_| ())
+    }
+
+    fn get_tree(&self, name: Key) -> io::Result<Option<Arc<Tree<PackedHandle>>>> {
+        if let Some(tree) = self.pending.get_tree(name)? {
+            return Ok(Some(tree));
+        }
+        let bytes = match missing_as_none(fs::read(self.tree_path(name)))? {
+            Some(record) => decode_record(&record)?,
+            None => match self.get_packed(TREE, name)? {
+                Some(bytes) => bytes,
+                None => return Ok(None),
+            },
+        };
+        Ok(Some(unpack_tree(&bytes)?.into()))
+    }
+
+    // A loose, uncompressed Tree is read in place (just the range's elements).
+    fn get_tree_range(
+        &self,
+        name: Key,
+        start: usize,
+        end: usize,
+    ) -> io::Result<Option<Vec<PackedHandle>>> {
+        if !self.pending.contains_tree(name)?
+            && let Some(mut file) = missing_as_none(File::open(self.tree_path(name)))?
+        {
+            let mut header = [0u8; HEADER_SIZE];
+            file.read_exact(&mut header)?;
+            if header[0] == RAW {
+                let length = file.metadata()?.len() - HEADER_SIZE as u64;
+                if start > end
+                    || end
+                        .checked_mul(HANDLE_SIZE)
+                        .is_none_or(|x| x as u64 > length)
+                {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        "Tree range out of bounds",
+                    ));
+                }
+                file.seek(SeekFrom::Start((HEADER_SIZE + start * HANDLE_SIZE) as u64))?;
+                let mut bytes = vec![0; (end - start) * HANDLE_SIZE];
+                file.read_exact(&mut bytes)?;
+                return Ok(Some(unpack_tree(&bytes)?));
+            }
+        }
+        self.get_tree(name)?
+            .map(|tree| slice(&tree, start, end))
+            .transpose()
+    }
+
+    fn put_tree(&self, name: Key, tree: Arc<Tree<PackedHandle>>) -> io::Result<()> {
+        if self.contains_tree(name)? {
+            return Ok(());
+        }
+        self.pending.put_tree(name, tree)
+    }
+
+    fn contains_tree(&self, name: Key) -> io::Result<bool> {
+        Ok(self.pending.contains_tree(name)?
+            || self.tree_path(name).try_exists()?
+            || self.packed(TREE, name))
+    }
+
+    fn delete_tree(&self, name: Key) -> io::Result<()> {
+        self.pending.delete_tree(name)?;
+        for pack in self.packs.write().unwrap().iter_mut() {
+            pack.remove(TREE, name);
+        }
+        missing_as_none(fs::remove_file(self.tree_path(name))).map(|_| ())
+    }
+
+    fn list_blobs(&self) -> io::Result<Vec<Key>> {
+        let dir = self.root.join("objects/blob");
+        self.list(BLOB, &dir, self.pending.list_blobs()?)
+    }
+
+    fn list_trees(&self) -> io::Result<Vec<Key>> {
+        let dir = self.root.join("objects/tree");
+        self.list(TREE, &dir, self.pending.list_trees()?)
+    }
+
+    // Keep what was received in place of an object, when it failed verification, under
+    // `quarantine` (named for the object it was meant to be) for inspection.
+    fn quarantine(&self, mismatch: &Mismatch) -> io::Result<()> {
+        let dir = self.root.join("quarantine");
+        fs::create_dir_all(&dir)?;
+        self.write_file(&dir.join(mismatch.file_name()), &[&mismatch.contents])?;
+        sync_dir(&dir)
+    }
+
+    // Write every pending object to disk (and the indexes of packs objects were deleted from),
+    // then the remembered results.
+    fn flush(&self) -> io::Result<()> {
+        // Taken first, so every object they name is pending or already written.
+        let remembered = std::mem::take(&mut *self.remembering.lock().unwrap());
+        let mut packs = self.packs.write().unwrap();
+        for pack in packs.iter_mut() {
+            pack.sync()?;
+        }
+        packs.retain(|p| !p.is_empty());
+        drop(packs);
+        let (blobs, trees) = self.pending.snapshot();
+        for (name, blob) in blobs {
+            self.write(&self.blob_path(name), &blob)?;
+            self.pending.delete_blob(name)?;
+        }
+        for (name, tree) in trees {
+            self.write(&self.tree_path(name), &pack_tree(&tree))?;
+            self.pending.delete_tree(name)?;
+        }
+        sync_dir(&self.root.join("objects/blob"))?;
+        sync_dir(&self.root.join("objects/tree"))?;
+        self.append_memo(&remembered)
+    }
+}
+
+pub(crate) fn pack_tree(tree: &Tree<PackedHandle>) -> Vec<u8> {
+    tree.iter().flat_map(|h| h.as_bytes()).copied().collect()
+}
+
+pub(crate) fn unpack_tree(bytes: &[u8]) -> io::Result<Vec<PackedHandle>> {
+    if !bytes.len().is_multiple_of(HANDLE_SIZE) {
+        return Err(io::Error::new(ErrorKind::InvalidData, "truncated Tree"));
+    }
+    Ok(bytes
+        .chunks_exact(HANDLE_SIZE)
+        .map(|h| PackedHandle::from_bytes(h.try_into().unwrap()))
+        .collect())
+}
+
+// An object's contents, from its record (header and body).
+fn decode_record(record: &[u8]) -> io::Result<Vec<u8>> {
+    if record.len() < HEADER_SIZE {
+        return Err(io::Error::new(ErrorKind::InvalidData, "truncated object"));
+    }
+    decode(record[0], record[HEADER_SIZE..].to_vec())
+}
+
+// An object's contents, from its encoding and its body.
+fn decode(encoding: u8, body: Vec<u8>) -> io::Result<Vec<u8>> {
+    match encoding {
+        RAW => Ok(body),
+        ZSTD => zstd::stream::decode_all(&body[..]),
+        _ => Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "unknown object encoding",
+        )),
+    }
+}
+
+// Discard incomplete writes: temporary objects, packs (or indexes) that were never
+// renamed into place, and partial memo and equivalence records.
+fn recover(root: &Path) -> io::Result<()> {
+    memo::recover(root)?;
+    equivalences::recover(root)?;
+    let tmp = root.join("objects/tmp");
+    fs::create_dir_all(&tmp)?;
+    for entry in fs::read_dir(&tmp)? {
+        fs::remove_file(entry?.path())?;
+    }
+    let dir = root.join("objects/pack");
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let incomplete = match path.extension().and_then(|x| x.to_str()) {
+            Some("pack") => !path.with_extension("idx").exists(),
+            Some("idx") => false,
+            _ => true,
+        };
+        if incomplete {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+// Make renames into `dir` durable.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    if cfg!(unix) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+fn missing_as_none<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+    match result {
+        Ok(x) => Ok(Some(x)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) fn hex((a, b, c): Key) -> String {
+    format!("{a:016x}{b:016x}{c:016x}")
+}
+
+pub(crate) fn from_hex(hex: &str) -> Option<Key> {
+    if hex.len() != 48 || !hex.is_ascii() {
+        return None;
+    }
+    let word = |i: usize| u64::from_str_radix(&hex[16 * i..16 * (i + 1)], 16).ok();
+    Some((word(0)?, word(1)?, word(2)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn repository() -> (TempDir, PathBuf, Repository) {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("repository");
+        let repository = Repository::create(&root).unwrap();
+        (dir, root, repository)
+    }
+
+    fn tree(length: u8) -> Arc<Tree<PackedHandle>> {
+        (0..length)
+            .map(|i| PackedHandle::from_bytes([i; HANDLE_SIZE]))
+            .collect()
+    }
+
+    #[test]
+    fn conforms() {
+        let (_dir, _, repository) = repository();
+        crate::storage::tests::conformance(&repository);
+    }
+
+    #[test]
+    fn flushed_objects_survive_reopening() {
+        let (_dir, root, repository) = repository();
+        repository.put_blob((1, 1, 1), vec![1; 100].into()).unwrap();
+        repository.put_tree((2, 2, 2), tree(3)).unwrap();
+        repository.flush().unwrap();
+        repository.put_blob((3, 3, 3), vec![3; 100].into()).unwrap();
+        drop(repository);
+
+        let repository = Repository::open(&root).unwrap();
+        assert_eq!(
+            &*repository.get_blob((1, 1, 1)).unwrap().unwrap(),
+            &[1; 100]
+        );
+        assert!(*repository.get_tree((2, 2, 2)).unwrap().unwrap() == *tree(3));
+        assert!(!repository.contains_blob((3, 3, 3)).unwrap());
+        assert!(repository.get_tree_range((2, 2, 2), 1, 3).unwrap().unwrap() == tree(3)[1..]);
+        assert!(repository.get_tree_range((2, 2, 2), 2, 4).is_err());
+    }
+
+    #[test]
+    fn opening_checks_the_format() {
+        let (_dir, root, _) = repository();
+        fs::write(root.join("FORMAT"), "fix repository 0\n").unwrap();
+        assert_eq!(
+            Repository::open(&root).err().unwrap().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn large_blobs_are_mapped() {
+        let (_dir, _, repository) = repository();
+        let (small, large) = (vec![1; 100], vec![2; MAP_THRESHOLD as usize]);
+        repository
+            .put_blob((1, 1, 1), small.clone().into())
+            .unwrap();
+        repository
+            .put_blob((2, 2, 2), large.clone().into())
+            .unwrap();
+        repository.flush().unwrap();
+        let small_read = repository.get_blob((1, 1, 1)).unwrap().unwrap();
+        let large_read = repository.get_blob((2, 2, 2)).unwrap().unwrap();
+        assert!(matches!(small_read, SharedBlob::Heap(_)));
+        assert!(matches!(large_read, SharedBlob::Mapped(_)));
+        assert_eq!((&*small_read, &*large_read), (&small[..], &large[..]));
+    }
+
+    #[test]
+    fn objects_are_compressed_where_that_helps() {
+        let (_dir, root, repository) = repository();
+        let repository = repository.with_compression(3);
+        let compressible = vec![0; 2 * MAP_THRESHOLD as usize];
+        let mut incompressible = vec![0; 1000];
+        blake3::Hasher::new()
+            .finalize_xof()
+            .fill(&mut incompressible);
+        let tiny = vec![0; COMPRESS_THRESHOLD - 1];
+        for (i, blob) in [&compressible, &incompressible, &tiny]
+            .into_iter()
+            .enumerate()
+        {
+            repository
+                .put_blob((i as u64, 0, 0), blob.clone().into())
+                .unwrap();
+        }
+        repository.flush().unwrap();
+        let encoding = |i| fs::read(repository.blob_path((i, 0, 0))).unwrap()[0];
+        assert_eq!([encoding(0), encoding(1), encoding(2)], [ZSTD, RAW, RAW]);
+        drop(repository);
+
+        // Reading doesn't depend on the setting, and a compressed Blob isn't mapped.
+        let repository = Repository::open(&root).unwrap();
+        let read = repository.get_blob((0, 0, 0)).unwrap().unwrap();
+        assert!(matches!(read, SharedBlob::Heap(_)) && *read == compressible[..]);
+        assert_eq!(
+            &*repository.get_blob((1, 0, 0)).unwrap().unwrap(),
+            &incompressible[..]
+        );
+        fs::write(repository.blob_path((1, 0, 0)), [9; HEADER_SIZE]).unwrap();
+        let error = repository.get_blob((1, 0, 0)).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn opening_discards_incomplete_writes() {
+        let (_dir, root, repository) = repository();
+        repository.put_blob((1, 1, 1), vec![1; 100].into()).unwrap();
+        repository.flush().unwrap();
+        drop(repository);
+        // What a crash could leave: a temporary object, and a pack without its index.
+        fs::write(root.join("objects/tmp/1.0"), [0; 100]).unwrap();
+        fs::write(root.join("objects/pack/orphan.pack"), [0; 100]).unwrap();
+        fs::write(root.join("objects/pack/stray"), [0; 100]).unwrap();
+
+        let repository = Repository::open(&root).unwrap();
+        assert_eq!(fs::read_dir(root.join("objects/tmp")).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(root.join("objects/pack")).unwrap().count(), 0);
+        assert_eq!(repository.list_blobs().unwrap(), [(1, 1, 1)]);
+    }
+
+    #[test]
+    fn keys_round_trip_through_hex() {
+        let key = (1, u64::MAX, 0xabc);
+        assert_eq!(from_hex(&hex(key)), Some(key));
+        assert_eq!(from_hex("0"), None);
+        assert_eq!(from_hex(&"g".repeat(48)), None);
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_records_decode_or_are_rejected(record in prop::collection::vec(any::<u8>(), 0..100), encoding in 0..3u8) {
+            let _ = unpack_tree(&record);
+            let mut record = record;
+            if let Some(first) = record.first_mut() {
+                *first = encoding;
+            }
+            let _ = decode_record(&record);
+        }
+    }
+
+    #[test]
+    fn a_range_past_any_tree_is_rejected() {
+        let (_dir, _, repository) = repository();
+        repository.put_tree((1, 0, 0), tree(3)).unwrap();
+        repository.flush().unwrap();
+        let error = repository
+            .get_tree_range((1, 0, 0), 0, usize::MAX / 2)
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,124 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+
+use super::{Repository, labels, missing_as_none, sync_dir};
+
+// A repository's settings are kept in `config`, one per line, as a key and then its values:
+//
+//   remote NAME LOCATION   a Storage to fetch from (see fetch), by the name it's fetched
+//                          by (a LOCATION as the command line's)
+//
+// Names are as label names are (see labels). The file is changed by atomically replacing
+// it, so a crash leaves either the old settings or the new.
+impl Repository {
+    fn config_path(&self) -> PathBuf {
+        self.root.join("config")
+    }
+
+    fn config(&self) -> io::Result<Vec<(String, String)>> {
+        let text = missing_as_none(fs::read_to_string(self.config_path()))?.unwrap_or_default();
+        let mut remotes = Vec::new();
+        for line in text.lines() {
+            let malformed = || io::Error::new(ErrorKind::InvalidData, "malformed config");
+            let ("remote", rest) = line.split_once(' ').ok_or_else(malformed)? else {
+                return Err(malformed());
+            };
+            let (name, location) = rest.split_once(' ').ok_or_else(malformed)?;
+            if !labels::valid(name) || location.is_empty() {
+                return Err(malformed());
+            }
+            remotes.push((name.to_string(), location.to_string()));
+        }
+        Ok(remotes)
+    }
+
+    fn set_config(&self, remotes: &[(String, String)]) -> io::Result<()> {
+        let text: String = remotes
+            .iter()
+            .map(|(name, location)| format!("remote {name} {location}\n"))
+            .collect();
+        self.write_file(&self.config_path(), &[text.as_bytes()])?;
+        sync_dir(&self.root)
+    }
+
+    // Every remote, with its location, in the order they were added.
+    pub(crate) fn remotes(&self) -> io::Result<Vec<(String, String)>> {
+        self.config()
+    }
+
+    // Where the remote `name` is, if there's one.
+    pub(crate) fn remote(&self, name: &str) -> io::Result<Option<String>> {
+        Ok(self
+            .config()?
+            .into_iter()
+            .find(|(x, _)| x == name)
+            .map(|(_, location)| location))
+    }
+
+    // Add the remote `name`, or move it to `location`.
+    pub(crate) fn set_remote(&self, name: &str, location: &str) -> io::Result<()> {
+        if !labels::valid(name) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "invalid remote name",
+            ));
+        }
+        if location.is_empty() || location.contains('\n') {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "invalid location"));
+        }
+        let mut remotes = self.config()?;
+        match remotes.iter_mut().find(|(x, _)| x == name) {
+            Some((_, x)) => *x = location.to_string(),
+            None => remotes.push((name.to_string(), location.to_string())),
+        }
+        self.set_config(&remotes)
+    }
+
+    pub(crate) fn delete_remote(&self, name: &str) -> io::Result<()> {
+        let mut remotes = self.config()?;
+        let count = remotes.len();
+        remotes.retain(|(x, _)| x != name);
+        if remotes.len() == count {
+            return Err(io::Error::new(ErrorKind::NotFound, "no such remote"));
+        }
+        self.set_config(&remotes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn remotes_persist() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("repository");
+        let repository = Repository::create(&root).unwrap();
+        assert!(repository.remotes().unwrap().is_empty());
+        repository.set_remote("origin", "/srv/a b/.fix").unwrap();
+        repository.set_remote("mirror", "/srv/mirror").unwrap();
+        repository.set_remote("origin", "/srv/origin").unwrap();
+        assert!(repository.set_remote("../up", "/srv").is_err());
+        assert!(repository.set_remote("empty", "").is_err());
+        drop(repository);
+        let repository = Repository::open(&root).unwrap();
+        assert_eq!(
+            repository.remotes().unwrap(),
+            [
+                ("origin".to_string(), "/srv/origin".to_string()),
+                ("mirror".to_string(), "/srv/mirror".to_string())
+            ]
+        );
+        assert_eq!(repository.remote("mirror").unwrap().unwrap(), "/srv/mirror");
+        repository.delete_remote("mirror").unwrap();
+        assert!(repository.remote("mirror").unwrap().is_none());
+        let error = repository.delete_remote("mirror").err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        // (A location keeps its spaces.)
+        repository.set_remote("spaced", "/srv/a b").unwrap();
+        assert_eq!(repository.remote("spaced").unwrap().unwrap(), "/srv/a b");
+    }
+}
@@ -0,0 +1,98 @@
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use super::memo::{RECORD_SIZE, Record, discard_partial, log_bytes};
+use super::{Repository, missing_as_none};
+use crate::HANDLE_SIZE;
+use crate::packed::PackedHandle;
+
+// The persistent equivalence table (see equivalence::persist) is a log, `equivalences`, of
+// records as in the memo log: two canonical Names that were equated, each packed. A record
+// is appended (and synced) as the Names are equated; they needn't be stored, as equating
+// doesn't need their objects. A crash may leave a partial record at the end, which opening
+// discards.
+impl Repository {
+    fn equivalences_path(&self) -> PathBuf {
+        self.root.join("equivalences")
+    }
+
+    // Every pair of Names equated, in the order they were.
+    pub(crate) fn equated(&self) -> io::Result<Vec<Record>> {
+        let log = missing_as_none(fs::read(self.equivalences_path()))?.unwrap_or_default();
+        log.chunks_exact(RECORD_SIZE)
+            .map(|record| {
+                let (a, b) = record.split_at(HANDLE_SIZE);
+                let (a, b) = (
+                    PackedHandle::from_bytes(a.try_into().unwrap()),
+                    PackedHandle::from_bytes(b.try_into().unwrap()),
+                );
+                if a.try_unpack().is_none() || b.try_unpack().is_none() {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        "malformed equivalence record",
+                    ));
+                }
+                Ok((a, b))
+            })
+            .collect()
+    }
+
+    // Record that two canonical Names were equated.
+    pub(crate) fn equate(&self, a: PackedHandle, b: PackedHandle) -> io::Result<()> {
+        let mut log = File::options()
+            .create(true)
+            .append(true)
+            .open(self.equivalences_path())?;
+        log.write_all(&log_bytes(&[(a, b)]))?;
+        log.sync_all()
+    }
+
+    // Forget every equivalence.
+    pub(crate) fn forget_equivalences(&self) -> io::Result<()> {
+        match missing_as_none(fs::remove_file(self.equivalences_path()))? {
+            Some(()) => super::sync_dir(&self.root),
+            None => Ok(()),
+        }
+    }
+}
+
+// Discard a partial record left at the end of the log.
+pub(super) fn recover(root: &Path) -> io::Result<()> {
+    discard_partial(&root.join("equivalences"))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{BlobName, Data, Handle, Object};
+
+    fn name(byte: u8) -> PackedHandle {
+        let blob = BlobName::create(vec![byte; 64]).ok().unwrap();
+        PackedHandle::pack(Handle::Data(Data::Object(Object::Blob(blob))))
+    }
+
+    #[test]
+    fn equivalences_persist_until_forgotten() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("repository");
+        let repository = Repository::create(&root).unwrap();
+        assert!(repository.equated().unwrap().is_empty());
+        repository.equate(name(1), name(2)).unwrap();
+        repository.equate(name(3), name(1)).unwrap();
+        drop(repository);
+        // (With a partial record, as a crash while equating leaves.)
+        let mut log = File::options()
+            .append(true)
+            .open(root.join("equivalences"))
+            .unwrap();
+        log.write_all(&[5; HANDLE_SIZE]).unwrap();
+        let repository = Repository::open(&root).unwrap();
+        let equated = repository.equated().unwrap();
+        assert!(equated == [(name(1), name(2)), (name(3), name(1))]);
+        repository.forget_equivalences().unwrap();
+        assert!(repository.equated().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use super::{Repository, missing_as_none, sync_dir};
+use crate::gc::{self, Pin};
+use crate::packed::PackedHandle;
+use crate::storage::Storage;
+use crate::{Handle, local};
+
+// Labels give Handles stable names: labels/<name> holds a canonical Handle, packed.
+// Every label is a GC root for as long as the Repository is open. A label is changed
+// by atomically replacing its file, after the objects it names are flushed, so it never
+// names an object missing from the repository.
+//
+// Names are made of ASCII letters, digits, '.', '-' and '_', and don't start with '.'.
+#[derive(Default)]
+pub(super) struct Labels(HashMap<String, (PackedHandle, Pin)>);
+
+impl Labels {
+    pub(super) fn load(dir: &Path) -> io::Result<Labels> {
+        let mut labels = Labels::default();
+        let Some(entries) = missing_as_none(fs::read_dir(dir))? else {
+            return Ok(labels);
+        };
+        for entry in entries {
+            let entry = entry?;
+            let Some(name) = entry
+                .file_name()
+                .to_str()
+                .filter(|x| valid(x))
+                .map(String::from)
+            else {
+                continue;
+            };
+            let h = fs::read(entry.path())?
+                .try_into()
+                .ok()
+                .map(PackedHandle::from_bytes)
+                .filter(|h| h.try_unpack().is_some())
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "malformed label"))?;
+            labels.0.insert(name, (h, gc::pin(h.unpack())));
+        }
+        Ok(labels)
+    }
+}
+
+pub(super) fn valid(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"._-".contains(&b))
+}
+
+impl Repository {
+    pub(crate) fn label(&self, name: &str) -> Option<Handle> {
+        let labels = self.labels.lock().unwrap();
+        labels.0.get(name).map(|(h, _)| h.unpack())
+    }
+
+    // Every label, by name.
+    pub(crate) fn labels(&self) -> Vec<(String, Handle)> {
+        let labels = self.labels.lock().unwrap();
+        let mut labels: Vec<_> = labels
+            .0
+            .iter()
+            .map(|(name, (h, _))| (name.clone(), h.unpack()))
+            .collect();
+        labels.sort_by(|a, b| a.0.cmp(&b.0));
+        labels
+    }
+
+    pub(crate) fn set_label(&self, name: &str, h: Handle) -> io::Result<()> {
+        self.update_label(name, |_| Some(h)).map(|_| ())
+    }
+
+    pub(crate) fn delete_label(&self, name: &str) -> io::Result<()> {
+        self.update_label(name, |_| None).map(|_| ())
+    }
+
+    // Set (or delete) a label only if it still names `expected`; returns whether it did.
+    pub(crate) fn compare_and_set_label(
+        &self,
+        name: &str,
+        expected: Option<Handle>,
+        new: Option<Handle>,
+    ) -> io::Result<bool> {
+        let expected = expected
+            .map(|h| local::canonicalize(h).map(PackedHandle::pack))
+            .transpose()?;
+        let mut swapped = false;
+        self.update_label(name, |current| {
+            swapped = current.map(|h| *h.as_bytes()) == expected.map(|h| *h.as_bytes());
+            if swapped {
+                new
+            } else {
+                current.map(|h| h.unpack())
+            }
+        })?;
+        Ok(swapped)
+    }
+
+    // Replace a label with a function of its current value (atomically, with respect to
+    // other updates through this Repository). Returns the new value.
+    pub(crate) fn update_label(
+        &self,
+        name: &str,
+        update: impl FnOnce(Option<PackedHandle>) -> Option<Handle>,
+    ) -> io::Result<Option<Handle>> {
+        if !valid(name) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "invalid label name",
+            ));
+        }
+        let mut labels = self.labels.lock().unwrap();
+        let current = labels.0.get(name).map(|&(h, _)| h);
+        let new = update(current)
+            .map(|h| local::canonicalize(h).map(PackedHandle::pack))
+            .transpose()?;
+        if current.map(|h| *h.as_bytes()) == new.map(|h| *h.as_bytes()) {
+            return Ok(new.map(|h| h.unpack()));
+        }
+
+        let dir = self.root.join("labels");
+        let path = dir.join(name);
+        match new {
+            Some(h) => {
+                // Pinned first, so it can't be collected before the label holds it.
+                let pin = gc::pin(h.unpack());
+                self.flush()?;
+                fs::create_dir_all(&dir)?;
+                self.write_file(&path, &[h.as_bytes()])?;
+                labels.0.insert(name.to_string(), (h, pin));
+            }
+            None => {
+                missing_as_none(fs::remove_file(&path))?;
+                labels.0.remove(name);
+            }
+        }
+        sync_dir(&dir)?;
+        Ok(new.map(|h| h.unpack()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{BlobName, Data, Object};
+
+    fn blob(contents: &[u8]) -> Handle {
+        Handle::Data(Data::Object(Object::Blob(
+            BlobName::create(contents.to_vec()).ok().unwrap(),
+        )))
+    }
+
+    fn names(repository: &Repository) -> Vec<(String, [u8; crate::HANDLE_SIZE])> {
+        let labels = repository.labels().into_iter();
+        labels
+            .map(|(name, h)| (name, *PackedHandle::pack(h).as_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn labels_persist_canonically() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("repository");
+        let repository = Repository::create(&root).unwrap();
+        let long = Handle::Data(Data::Object(Object::Blob(local::blob(vec![7; 100]))));
+        let short = blob(b"short");
+        repository.set_label("short", short).unwrap();
+        repository.set_label("long", long).unwrap();
+        repository.set_label("deleted", short).unwrap();
+        repository.delete_label("deleted").unwrap();
+        // A local object is labelled by its canonical Name.
+        let canonical = local::canonical(PackedHandle::pack(long)).unwrap();
+        assert!(PackedHandle::pack(repository.label("long").unwrap()) == canonical);
+        let labelled = names(&repository);
+        drop(repository);
+
+        let repository = Repository::open(&root).unwrap();
+        assert_eq!(names(&repository), labelled);
+        assert_eq!(
+            labelled.iter().map(|(x, _)| x.as_str()).collect::<Vec<_>>(),
+            ["long", "short"]
+        );
+        assert!(repository.label("deleted").is_none());
+    }
+
+    #[test]
+    fn names_are_checked() {
+        let dir = TempDir::new().unwrap();
+        let repository = Repository::create(dir.path().join("repository")).unwrap();
+        for name in ["", ".hidden", "a/b", "..", "tab\t"] {
+            let error = repository.set_label(name, blob(b"x")).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+        repository.set_label("a-b_c.1", blob(b"x")).unwrap();
+    }
+
+    #[test]
+    fn compare_and_set_only_replaces_what_was_expected() {
+        let dir = TempDir::new().unwrap();
+        let repository = Repository::create(dir.path().join("repository")).unwrap();
+        let (a, b) = (blob(b"a"), blob(&[8; 100]));
+        assert!(
+            !repository
+                .compare_and_set_label("x", Some(a), Some(b))
+                .unwrap()
+        );
+        assert!(
+            repository
+                .compare_and_set_label("x", None, Some(b))
+                .unwrap()
+        );
+        assert!(
+            !repository
+                .compare_and_set_label("x", Some(a), None)
+                .unwrap()
+        );
+        assert!(
+            repository
+                .compare_and_set_label("x", Some(b), Some(a))
+                .unwrap()
+        );
+        assert!(PackedHandle::pack(repository.label("x").unwrap()) == PackedHandle::pack(a));
+        assert!(
+            repository
+                .compare_and_set_label("x", Some(a), None)
+                .unwrap()
+        );
+        assert!(repository.label("x").is_none());
+    }
+
+    #[test]
+    fn a_malformed_label_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("repository");
+        drop(Repository::create(&root).unwrap());
+        fs::create_dir(root.join("labels")).unwrap();
+        fs::write(root.join("labels/short"), [1; 5]).unwrap();
+        let error = Repository::open(&root).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
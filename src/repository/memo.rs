@@ -0,0 +1,244 @@
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use super::{Repository, missing_as_none, sync_dir};
+use crate::packed::PackedHandle;
+use crate::{HANDLE_SIZE, Handle};
+
+// The persistent memo table (see memo::persist) is a log, `memo`, of records: a canonical
+// Thunk and the Data it produced, each packed. Records are appended when the Repository is
+// flushed, after the objects they name, so a record never names an object missing from the
+// repository. A crash may leave a partial record at the end, which opening discards.
+pub(super) const RECORD_SIZE: usize = 2 * HANDLE_SIZE;
+
+pub(super) type Record = (PackedHandle, PackedHandle);
+
+impl Repository {
+    fn memo_path(&self) -> PathBuf {
+        self.root.join("memo")
+    }
+
+    // Every remembered result (including those not flushed yet).
+    pub(crate) fn remembered(&self) -> io::Result<Vec<Record>> {
+        let mut records = self.logged()?;
+        records.extend(self.remembering.lock().unwrap().iter().copied());
+        Ok(records)
+    }
+
+    fn logged(&self) -> io::Result<Vec<Record>> {
+        let log = missing_as_none(fs::read(self.memo_path()))?.unwrap_or_default();
+        log.chunks_exact(RECORD_SIZE)
+            .map(|record| {
+                let (thunk, result) = record.split_at(HANDLE_SIZE);
+                let (thunk, result) = (
+                    PackedHandle::from_bytes(thunk.try_into().unwrap()),
+                    PackedHandle::from_bytes(result.try_into().unwrap()),
+                );
+                // (A record must name a Thunk and the Data it produced: anything else would be
+                // remembered as a result no Thunk can have.)
+                let well_formed = matches!(thunk.try_unpack(), Some(Handle::Thunk(_)))
+                    && matches!(result.try_unpack(), Some(Handle::Data(_)));
+                if !well_formed {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        "malformed memo record",
+                    ));
+                }
+                Ok((thunk, result))
+            })
+            .collect()
+    }
+
+    // Remember that a canonical Thunk produced a canonical result (from the next flush on).
+    pub(crate) fn remember(&self, thunk: PackedHandle, result: PackedHandle) {
+        self.remembering.lock().unwrap().push((thunk, result));
+    }
+
+    // Forget every remembered result.
+    pub(crate) fn forget(&self) -> io::Result<()> {
+        let mut remembering = self.remembering.lock().unwrap();
+        remembering.clear();
+        match missing_as_none(File::options().write(true).open(self.memo_path()))? {
+            Some(log) => {
+                log.set_len(0)?;
+                log.sync_all()
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Forget the remembered results `keep` rejects (rewriting the log atomically).
+    pub(crate) fn retain_memo(&self, keep: impl Fn(&Record) -> bool) -> io::Result<()> {
+        let mut remembering = self.remembering.lock().unwrap();
+        remembering.retain(&keep);
+        let records = self.logged()?;
+        let kept: Vec<_> = records.iter().copied().filter(&keep).collect();
+        if kept.len() == records.len() {
+            return Ok(());
+        }
+        self.write_file(&self.memo_path(), &[&log_bytes(&kept)])?;
+        sync_dir(&self.root)
+    }
+
+    // Append records (whose objects are already flushed) to the log.
+    pub(super) fn append_memo(&self, records: &[Record]) -> io::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let bytes = log_bytes(records);
+        let mut log = File::options()
+            .create(true)
+            .append(true)
+            .open(self.memo_path())?;
+        log.write_all(&bytes)?;
+        log.sync_all()
+    }
+}
+
+pub(super) fn log_bytes(records: &[Record]) -> Vec<u8> {
+    records
+        .iter()
+        .flat_map(|(thunk, result)| [thunk.as_bytes(), result.as_bytes()])
+        .flatten()
+        .copied()
+        .collect()
+}
+
+// Discard a partial record left at the end of the log.
+pub(super) fn recover(root: &Path) -> io::Result<()> {
+    discard_partial(&root.join("memo"))
+}
+
+// Discard a partial record left at the end of a log of records.
+pub(super) fn discard_partial(path: &Path) -> io::Result<()> {
+    let Some(log) = missing_as_none(File::options().write(true).open(path))? else {
+        return Ok(());
+    };
+    let length = log.metadata()?.len();
+    if !length.is_multiple_of(RECORD_SIZE as u64) {
+        log.set_len(length - length % RECORD_SIZE as u64)?;
+        log.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::storage::Storage;
+    use crate::{BlobName, Data, Encode, Object, Thunk};
+
+    fn record(byte: u8) -> Record {
+        let data = Data::Object(Object::Blob(BlobName::literal(&[byte]).unwrap()));
+        (
+            PackedHandle::pack(Handle::Thunk(Thunk::Identification(data))),
+            PackedHandle::pack(Handle::Data(data)),
+        )
+    }
+
+    #[test]
+    fn forgets_what_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("repository");
+        let repository = Repository::create(&root).unwrap();
+        let (a, b, c) = (record(1), record(2), record(3));
+        repository.remember(a.0, a.1);
+        repository.remember(b.0, b.1);
+        repository.flush().unwrap();
+        repository.remember(c.0, c.1);
+        repository.retain_memo(|&x| x != b).unwrap();
+        assert!(repository.remembered().unwrap() == [a, c]);
+        repository.flush().unwrap();
+        drop(repository);
+        let repository = Repository::open(&root).unwrap();
+        assert!(repository.remembered().unwrap() == [a, c]);
+        repository.forget().unwrap();
+        assert!(repository.remembered().unwrap().is_empty());
+    }
+
+    #[test]
+    fn remembers_across_opening() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("repository");
+        let repository = Repository::create(&root).unwrap();
+        let (a, b) = (record(1), record(2));
+        repository.remember(a.0, a.1);
+        repository.flush().unwrap();
+        repository.remember(b.0, b.1);
+        assert!(repository.remembered().unwrap() == [a, b]);
+        drop(repository);
+        let repository = Repository::open(&root).unwrap();
+        assert!(repository.remembered().unwrap() == [a]);
+    }
+
+    #[test]
+    fn recovers_from_a_partial_record() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("repository");
+        let repository = Repository::create(&root).unwrap();
+        let a = record(1);
+        repository.remember(a.0, a.1);
+        repository.flush().unwrap();
+        drop(repository);
+        let mut log = File::options()
+            .append(true)
+            .open(root.join("memo"))
+            .unwrap();
+        log.write_all(&[7; HANDLE_SIZE + 3]).unwrap();
+        let repository = Repository::open(&root).unwrap();
+        assert!(repository.remembered().unwrap() == [a]);
+    }
+
+    #[test]
+    fn rejects_a_malformed_record() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("repository");
+        let repository = Repository::create(&root).unwrap();
+        fs::write(root.join("memo"), [0xff; RECORD_SIZE]).unwrap();
+        assert!(repository.remembered().is_err());
+    }
+
+    #[test]
+    fn rejects_a_record_of_the_wrong_kinds() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("repository");
+        drop(Repository::create(&root).unwrap());
+        let (thunk, data) = record(4);
+        let Handle::Thunk(inner) = thunk.unpack() else {
+            unreachable!()
+        };
+        let encode = PackedHandle::pack(Handle::Encode(Encode {
+            thunk: inner,
+            accessibility: None,
+        }));
+        // A Thunk producing an Encode, and a Blob (not a Thunk) producing Data.
+        for wrong in [(thunk, encode), (data, data)] {
+            fs::write(root.join("memo"), log_bytes(&[(thunk, data), wrong])).unwrap();
+            let repository = Repository::open(&root).unwrap();
+            let error = repository.remembered().err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        // Any log opens (with a partial record discarded), and reads back or is rejected.
+        #[test]
+        fn arbitrary_logs_are_read_or_rejected(log in prop::collection::vec(any::<u8>(), 0..3 * RECORD_SIZE)) {
+            let dir = TempDir::new().unwrap();
+            let root = dir.path().join("repository");
+            drop(Repository::create(&root).unwrap());
+            fs::write(root.join("memo"), &log).unwrap();
+            let repository = Repository::open(&root).unwrap();
+            if let Ok(records) = repository.remembered() {
+                prop_assert_eq!(records.len(), log.len() / RECORD_SIZE);
+            }
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use memmap2::Mmap;
+
+use crate::storage::{Key, key_bytes, key_from_bytes};
+
+// A pack bundles many objects into one file, so the filesystem isn't asked to hold
+// millions of tiny ones (see Repository::repack):
+//
+//   objects/pack/<id>.pack   object records (header and body, as in a loose object file), back to back
+//   objects/pack/<id>.idx    one INDEX_ENTRY-byte entry per object: kind (BLOB or TREE),
+//                            key (24 bytes), then the offset and length of its record (u64 each)
+//
+// A pack file is never modified once written. Deleting an object from a pack only
+// drops it from the index; its bytes stay until the next repack.
+pub(super) struct Pack {
+    dir: PathBuf,
+    id: String,
+    map: Option<Mmap>,
+    index: HashMap<(u8, Key), (usize, usize)>,
+    dirty: bool,
+}
+
+pub(super) const BLOB: u8 = 0;
+pub(super) const TREE: u8 = 1;
+
+const INDEX_ENTRY: usize = 1 + 24 + 8 + 8;
+
+impl Pack {
+    // Every pack in `dir`.
+    pub(super) fn open_all(dir: &Path) -> io::Result<Vec<Pack>> {
+        let mut packs = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?.file_name();
+            if let Some(id) = entry.to_str().and_then(|x| x.strip_suffix(".idx")) {
+                packs.push(Pack::open(dir, id)?);
+            }
+        }
+        Ok(packs)
+    }
+
+    fn open(dir: &Path, id: &str) -> io::Result<Pack> {
+        let corrupt = || io::Error::new(ErrorKind::InvalidData, "corrupt pack index");
+        let bytes = fs::read(dir.join(format!("{id}.idx")))?;
+        if !bytes.len().is_multiple_of(INDEX_ENTRY) {
+            return Err(corrupt());
+        }
+        let file = File::open(dir.join(format!("{id}.pack")))?;
+        // SAFETY: packs are never modified once written.
+        let map = (file.metadata()?.len() > 0)
+            .then(|| unsafe { Mmap::map(&file) })
+            .transpose()?;
+        let len = map.as_ref().map_or(0, |m| m.len());
+        let mut index = HashMap::new();
+        for entry in bytes.chunks_exact(INDEX_ENTRY) {
+            let word = |i: usize| u64::from_le_bytes(entry[i..i + 8].try_into().unwrap()) as usize;
+            let (offset, length) = (word(25), word(33));
+            let name = key_from_bytes(&entry[1..25]).ok_or_else(corrupt)?;
+            if entry[0] > TREE || offset.checked_add(length).is_none_or(|end| end > len) {
+                return Err(corrupt());
+            }
+            index.insert((entry[0], name), (offset, length));
+        }
+        Ok(Pack {
+            dir: dir.to_path_buf(),
+            id: id.to_string(),
+            map,
+            index,
+            dirty: false,
+        })
+    }
+
+    // The record of an object in this pack.
+    pub(super) fn get(&self, kind: u8, name: Key) -> Option<&[u8]> {
+        let &(offset, length) = self.index.get(&(kind, name))?;
+        Some(&self.map.as_ref()?[offset..offset + length])
+    }
+
+    pub(super) fn contains(&self, kind: u8, name: Key) -> bool {
+        self.index.contains_key(&(kind, name))
+    }
+
+    pub(super) fn remove(&mut self, kind: u8, name: Key) {
+        if self.index.remove(&(kind, name)).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    pub(super) fn keys(&self, kind: u8) -> impl Iterator<Item = Key> + '_ {
+        self.index
+            .keys()
+            .filter(move |(k, _)| *k == kind)
+            .map(|&(_, name)| name)
+    }
+
+    // Every object in this pack, with its record.
+    pub(super) fn records(&self) -> impl Iterator<Item = (u8, Key, &[u8])> + '_ {
+        self.index
+            .keys()
+            .filter_map(|&(kind, name)| Some((kind, name, self.get(kind, name)?)))
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    // Write out the index if objects were removed from it (or remove the pack if it is now empty).
+    pub(super) fn sync(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if self.is_empty() {
+            self.delete()?;
+        } else {
+            let entries = self
+                .index
+                .iter()
+                .map(|(&(kind, name), &location)| (kind, name, location));
+            write_index(&self.dir, &self.id, entries)?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    // Remove the pack's files (the index first, so a half-removed pack is never opened).
+    pub(super) fn delete(&self) -> io::Result<()> {
+        fs::remove_file(self.dir.join(format!("{}.idx", self.id)))?;
+        fs::remove_file(self.dir.join(format!("{}.pack", self.id)))
+    }
+}
+
+fn write_index(
+    dir: &Path,
+    id: &str,
+    entries: impl Iterator<Item = (u8, Key, (usize, usize))>,
+) -> io::Result<()> {
+    let temporary = dir.join(format!("{id}.idx.tmp"));
+    let mut file = BufWriter::new(File::create(&temporary)?);
+    for (kind, name, (offset, length)) in entries {
+        file.write_all(&[kind])?;
+        file.write_all(&key_bytes(name))?;
+        file.write_all(&(offset as u64).to_le_bytes())?;
+        file.write_all(&(length as u64).to_le_bytes())?;
+    }
+    file.into_inner()?.sync_all()?;
+    fs::rename(temporary, dir.join(format!("{id}.idx")))?;
+    super::sync_dir(dir)
+}
+
+// Writes a new pack, one record at a time. The pack only exists once finished.
+pub(super) struct PackWriter {
+    dir: PathBuf,
+    id: String,
+    file: BufWriter<File>,
+    entries: Vec<(u8, Key, (usize, usize))>,
+    offset: usize,
+}
+
+impl PackWriter {
+    pub(super) fn create(dir: &Path) -> io::Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let id = format!("{nanos:x}-{}", std::process::id());
+        Ok(PackWriter {
+            file: BufWriter::new(File::create(dir.join(format!("{id}.pack")))?),
+            dir: dir.to_path_buf(),
+            id,
+            entries: Vec::new(),
+            offset: 0,
+        })
+    }
+
+    pub(super) fn add(&mut self, kind: u8, name: Key, record: &[u8]) -> io::Result<()> {
+        self.file.write_all(record)?;
+        self.entries.push((kind, name, (self.offset, record.len())));
+        self.offset += record.len();
+        Ok(())
+    }
+
+    pub(super) fn finish(self) -> io::Result<Pack> {
+        self.file.into_inner()?.sync_all()?;
+        write_index(&self.dir, &self.id, self.entries.into_iter())?;
+        Pack::open(&self.dir, &self.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::repository::{MAP_THRESHOLD, Repository};
+    use crate::storage::Storage;
+
+    fn pack(dir: &Path) -> Pack {
+        let mut writer = PackWriter::create(dir).unwrap();
+        writer.add(BLOB, (1, 1, 1), b"one").unwrap();
+        writer.add(TREE, (1, 1, 1), b"a tree").unwrap();
+        writer.add(BLOB, (2, 2, 2), b"two").unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn removals_persist_in_the_index() {
+        let dir = TempDir::new().unwrap();
+        let mut written = pack(dir.path());
+        assert_eq!(written.get(TREE, (1, 1, 1)), Some(&b"a tree"[..]));
+        written.remove(BLOB, (1, 1, 1));
+        written.sync().unwrap();
+
+        let mut packs = Pack::open_all(dir.path()).unwrap();
+        assert_eq!(packs.len(), 1);
+        let mut reopened = packs.pop().unwrap();
+        assert!(!reopened.contains(BLOB, (1, 1, 1)));
+        assert_eq!(reopened.get(BLOB, (2, 2, 2)), Some(&b"two"[..]));
+        assert_eq!(reopened.records().count(), 2);
+        reopened.remove(BLOB, (2, 2, 2));
+        reopened.remove(TREE, (1, 1, 1));
+        reopened.sync().unwrap();
+        // An emptied pack is removed.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn a_corrupt_index_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        let id = pack(dir.path()).id;
+        let index = dir.path().join(format!("{id}.idx"));
+        let entries = fs::read(&index).unwrap();
+        let mut corruptions = vec![entries[..INDEX_ENTRY - 1].to_vec()];
+        // An unknown kind, and a record past the end of the pack.
+        let (mut kind, mut offset) = (entries.clone(), entries.clone());
+        kind[0] = 7;
+        offset[25..33].copy_from_slice(&u64::MAX.to_le_bytes());
+        corruptions.extend([kind, offset]);
+        for corruption in corruptions {
+            fs::write(&index, corruption).unwrap();
+            let error = Pack::open_all(dir.path()).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn repacking_keeps_every_object() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("repository");
+        let repository = Repository::create(&root).unwrap();
+        let large = vec![3; MAP_THRESHOLD as usize];
+        repository.put_blob((1, 1, 1), vec![1; 100].into()).unwrap();
+        repository.put_blob((2, 2, 2), vec![2; 100].into()).unwrap();
+        repository
+            .put_blob((3, 3, 3), large.clone().into())
+            .unwrap();
+        repository.flush().unwrap();
+        repository.repack().unwrap();
+        repository.put_blob((4, 4, 4), vec![4; 100].into()).unwrap();
+        repository.repack().unwrap();
+        repository.delete_blob((2, 2, 2)).unwrap();
+        repository.flush().unwrap();
+        drop(repository);
+
+        // Large Blobs stay loose; the rest are in one pack.
+        let loose = fs::read_dir(root.join("objects/blob")).unwrap().count();
+        let packs = fs::read_dir(root.join("objects/pack")).unwrap().count();
+        assert_eq!((loose, packs), (1, 2));
+        let repository = Repository::open(&root).unwrap();
+        assert_eq!(
+            repository.list_blobs().unwrap(),
+            [(1, 1, 1), (3, 3, 3), (4, 4, 4)]
+        );
+        assert_eq!(
+            &*repository.get_blob((1, 1, 1)).unwrap().unwrap(),
+            &[1; 100]
+        );
+        assert_eq!(
+            &*repository.get_blob((3, 3, 3)).unwrap().unwrap(),
+            &large[..]
+        );
+        assert!(repository.get_blob((2, 2, 2)).unwrap().is_none());
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        // An index is accepted only if every entry is within the pack.
+        #[test]
+        fn arbitrary_indexes_open_or_are_rejected(index in prop::collection::vec(any::<u8>(), 0..3 * INDEX_ENTRY)) {
+            let dir = TempDir::new().unwrap();
+            let pack = pack(dir.path());
+            fs::write(dir.path().join(format!("{}.idx", pack.id)), &index).unwrap();
+            if let Ok(mut packs) = Pack::open_all(dir.path()) {
+                let pack = packs.pop().unwrap();
+                for (kind, name, _) in pack.records() {
+                    prop_assert!(pack.get(kind, name).is_some());
+                }
+            }
+        }
+    }
+}
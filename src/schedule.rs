@@ -0,0 +1,511 @@
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
+
+use rayon::Yield;
+
+use crate::graph::Graph;
+use crate::packed::PackedHandle;
+use crate::sync::{AtomicUsize, Condvar, Mutex};
+use crate::{Context, Data, Handle, Result, execute, memo};
+
+// Before evaluating a Handle, eval executes the Encodes it depends on, in parallel.
+//
+// An Encode whose dependencies (see graph) are all done is ready, and runs as a task on
+// rayon's pool, so independent Encodes keep every core busy (in order of priority: see
+// Priority). The Data an
+// Encode produces can hold more Encodes, which join the Graph when it's done. The
+// dependencies of an Encode the memo table remembers are never needed, so they're left out.
+//
+// Every result goes to the memo table, so eval then finds each Encode already executed.
+// Scheduling never changes a result: once anything traps, nothing more is started, and
+// eval reaches the first trap (in order) itself. (Traps are returned to it, so an Encode
+// that trapped isn't executed again.)
+#[derive(Default)]
+struct Dag {
+    graph: Graph,
+    blocked: Blocked,
+}
+
+// The nodes blocked on others: the number of unfinished dependencies of each node, and the
+// nodes waiting on it.
+#[derive(Default)]
+struct Blocked {
+    pending: Vec<usize>,
+    dependents: Vec<Vec<usize>>,
+    done: Vec<bool>,
+}
+
+impl Blocked {
+    // Make room for `len` nodes.
+    fn grow(&mut self, len: usize) {
+        self.pending.resize(len, 0);
+        self.dependents.resize(len, Vec::new());
+        self.done.resize(len, false);
+    }
+
+    // Add a node (which there's room for), returning whether it's ready.
+    fn add(&mut self, node: usize, dependencies: &[usize]) -> bool {
+        for &dependency in dependencies {
+            if !self.done[dependency] {
+                self.pending[node] += 1;
+                self.dependents[dependency].push(node);
+            }
+        }
+        self.pending[node] == 0
+    }
+
+    // Mark a node done, returning the nodes it made ready.
+    fn finish(&mut self, node: usize) -> Vec<usize> {
+        self.done[node] = true;
+        let mut ready = Vec::new();
+        for dependent in std::mem::take(&mut self.dependents[node]) {
+            self.pending[dependent] -= 1;
+            if self.pending[dependent] == 0 {
+                ready.push(dependent);
+            }
+        }
+        ready
+    }
+}
+
+impl Dag {
+    // Add the Encodes that evaluating `h` executes, returning those ready to run.
+    fn discover(&mut self, h: Handle) -> Result<Vec<usize>> {
+        let new = self.graph.extend(h, |x| memo::lookup(x).is_none())?;
+        let (graph, blocked) = (&self.graph, &mut self.blocked);
+        blocked.grow(graph.len());
+        Ok(new
+            .into_iter()
+            .filter(|&x| blocked.add(x, graph.dependencies(x)))
+            .collect())
+    }
+
+    // Mark a node done (having produced `data`), returning the nodes that are now ready.
+    fn finish(&mut self, node: usize, data: Data) -> Result<Vec<usize>> {
+        let dependents = self.blocked.finish(node);
+        let mut ready = self.discover(Handle::Data(data))?;
+        ready.extend(dependents);
+        Ok(ready)
+    }
+}
+
+// How urgent an evaluation's Encodes are, relative to other evaluations'. Ready Encodes
+// run earliest deadline first, where an Encode's deadline is its place in the order Encodes
+// became ready, plus its priority's delay: so an interactive Encode overtakes batch work,
+// but an Encode only waits for a bounded number of those that became ready after it (its
+// delay), and nothing starves.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub(crate) enum Priority {
+    Interactive,
+    #[default]
+    Normal,
+    Batch,
+}
+
+impl Priority {
+    fn delay(self) -> u64 {
+        match self {
+            Priority::Interactive => 0,
+            Priority::Normal => 1 << 10,
+            Priority::Batch => 1 << 16,
+        }
+    }
+}
+
+// A ready Encode, waiting for a thread.
+struct Task {
+    deadline: u64,
+    sequence: u64,
+    job: Box<dyn FnOnce() + Send>,
+}
+
+impl Task {
+    fn order(&self) -> Reverse<(u64, u64)> {
+        Reverse((self.deadline, self.sequence))
+    }
+}
+
+impl PartialEq for Task {
+    fn eq(&self, other: &Self) -> bool {
+        self.order() == other.order()
+    }
+}
+
+impl Eq for Task {}
+
+impl PartialOrd for Task {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Task {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.order().cmp(&other.order())
+    }
+}
+
+// The ready Encodes of every evaluation, and the number of runners taking them. The
+// condition is notified when a task is queued, and when a Scheduler has nothing left to run.
+#[derive(Default)]
+struct Queue {
+    tasks: BinaryHeap<Task>,
+    sequence: u64,
+    runners: usize,
+}
+
+#[derive(Default)]
+struct Pool {
+    queue: Mutex<Queue>,
+    condition: Condvar,
+}
+
+static QUEUE: LazyLock<Pool> = LazyLock::new(Pool::default);
+
+impl Pool {
+    // Queue a task, returning whether to start another runner (there being fewer than
+    // `threads`).
+    fn push(&self, priority: Priority, job: Box<dyn FnOnce() + Send>, threads: usize) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        let sequence = queue.sequence;
+        queue.sequence += 1;
+        queue.tasks.push(Task {
+            deadline: sequence + priority.delay(),
+            sequence,
+            job,
+        });
+        let start = queue.runners < threads;
+        if start {
+            queue.runners += 1;
+        }
+        drop(queue);
+        self.condition.notify_all();
+        start
+    }
+
+    // The first task, for a runner (which, if there's none, stops).
+    fn next(&self) -> Option<Task> {
+        let mut queue = self.queue.lock().unwrap();
+        let task = queue.tasks.pop();
+        if task.is_none() {
+            queue.runners -= 1;
+        }
+        task
+    }
+
+    // Count a task of `outstanding` finished: the last wakes whoever waits for them (with
+    // the queue locked, so they can't miss it).
+    fn finished(&self, outstanding: &AtomicUsize) {
+        if outstanding.fetch_sub(1, Ordering::SeqCst) == 1 {
+            let _queue = self.queue.lock().unwrap();
+            self.condition.notify_all();
+        }
+    }
+
+    // Run queued tasks (anyone's) until every task of `outstanding` has finished.
+    fn wait(&self, outstanding: &AtomicUsize) {
+        let mut tasks = self.queue.lock().unwrap();
+        while outstanding.load(Ordering::SeqCst) > 0 {
+            match tasks.tasks.pop() {
+                Some(task) => {
+                    drop(tasks);
+                    (task.job)();
+                    tasks = self.queue.lock().unwrap();
+                }
+                None => tasks = self.condition.wait(tasks).unwrap(),
+            }
+        }
+    }
+}
+
+// Queue a task (starting another runner on rayon's pool, unless every thread has one).
+fn submit(priority: Priority, job: Box<dyn FnOnce() + Send>) {
+    if QUEUE.push(priority, job, rayon::current_num_threads()) {
+        rayon::spawn(runner);
+    }
+}
+
+// Run tasks, first to last, until there are none. Between tasks, the rest of the pool's
+// work goes first (e.g. a parallel map that a running Encode is waiting for).
+fn runner() {
+    loop {
+        while rayon::yield_now() == Some(Yield::Executed) {}
+        let Some(task) = QUEUE.next() else {
+            return;
+        };
+        (task.job)();
+    }
+}
+
+struct Scheduler {
+    dag: Mutex<Dag>,
+    context: Context,
+    trapped: AtomicBool,
+    traps: Mutex<Traps>,
+    // The tasks queued or running, and the first panic of any of them.
+    outstanding: AtomicUsize,
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+// The traps of Encodes, by the canonical Name of their Thunks.
+pub(crate) type Traps = HashMap<PackedHandle, Data>;
+
+impl Scheduler {
+    fn spawn(self: &Arc<Self>, nodes: Vec<usize>) {
+        for node in nodes {
+            self.outstanding.fetch_add(1, Ordering::SeqCst);
+            let scheduler = self.clone();
+            submit(
+                self.context.priority,
+                Box::new(move || scheduler.task(node)),
+            );
+        }
+    }
+
+    fn task(self: &Arc<Self>, node: usize) {
+        if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| self.run(node))) {
+            self.trapped.store(true, Ordering::Relaxed);
+            self.panic.lock().unwrap().get_or_insert(panic);
+        }
+        QUEUE.finished(&self.outstanding);
+    }
+
+    fn run(self: &Arc<Self>, node: usize) {
+        if self.trapped.load(Ordering::Relaxed) {
+            return;
+        }
+        let e = self.dag.lock().unwrap().graph.encode(node);
+        let result = match &self.context.offload {
+            Some(coordinator) => coordinator.execute(e, &self.context),
+            None => execute(e, &self.context),
+        };
+        let ready = match result {
+            Ok(data) => self.dag.lock().unwrap().finish(node, data),
+            Err(trap) => {
+                self.traps.lock().unwrap().insert(memo::name(e.thunk), trap);
+                Err(trap)
+            }
+        };
+        match ready {
+            Ok(ready) => self.spawn(ready),
+            Err(_) => self.trapped.store(true, Ordering::Relaxed),
+        }
+    }
+}
+
+// Execute every Encode that evaluating `h` would (as far as they can be found), in parallel,
+// returning the traps of those that trapped. While waiting, this thread runs queued tasks
+// too (anyone's), so evaluations nested in a task still make progress.
+pub(crate) fn run(h: Handle, context: &Context) -> Traps {
+    let mut dag = Dag::default();
+    let ready = match dag.discover(h) {
+        Ok(ready) if !dag.graph.is_empty() => ready,
+        _ => return Traps::new(),
+    };
+    let scheduler = Arc::new(Scheduler {
+        dag: Mutex::new(dag),
+        context: context.clone(),
+        trapped: AtomicBool::new(false),
+        traps: Mutex::default(),
+        outstanding: AtomicUsize::new(0),
+        panic: Mutex::default(),
+    });
+    scheduler.spawn(ready);
+    QUEUE.wait(&scheduler.outstanding);
+    if let Some(panic) = scheduler.panic.lock().unwrap().take() {
+        panic::resume_unwind(panic);
+    }
+    std::mem::take(&mut *scheduler.traps.lock().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::Path;
+    use crate::{BlobName, Encode, Object, Thunk, TreeName};
+
+    fn encode(thunk: Thunk) -> Handle {
+        Handle::Encode(Encode {
+            thunk,
+            accessibility: None,
+        })
+    }
+
+    fn selection(seed: &[u8], index: u64) -> Thunk {
+        let blob = Data::Object(Object::Blob(BlobName::create(seed.to_vec()).ok().unwrap()));
+        let tree = TreeName::create(vec![Handle::Data(blob)]).ok().unwrap();
+        Path::new()
+            .index(index)
+            .thunk(Data::Object(Object::Tree(tree)))
+            .ok()
+            .unwrap()
+    }
+
+    fn tree(elements: Vec<Handle>) -> Handle {
+        Handle::Data(Data::Object(Object::Tree(
+            TreeName::create(elements).ok().unwrap(),
+        )))
+    }
+
+    #[test]
+    fn executes_every_encode_and_returns_the_traps() {
+        let (a, b) = (
+            selection(b"scheduled first", 0),
+            selection(b"scheduled second", 0),
+        );
+        let bad = selection(b"scheduled out of range", 3);
+        let traps = run(tree(vec![encode(a), encode(b)]), &Context::default());
+        assert!(traps.is_empty());
+        assert!(memo::lookup(a).is_some() && memo::lookup(b).is_some());
+
+        let traps = run(tree(vec![encode(bad)]), &Context::default());
+        assert_eq!(traps.len(), 1);
+        assert!(traps.contains_key(&memo::name(bad)));
+        assert!(memo::lookup(bad).is_none());
+    }
+
+    #[test]
+    fn only_encodes_without_pending_dependencies_are_ready() {
+        let leaf = encode(selection(b"a leaf of the dag", 0));
+        let combination = TreeName::create(vec![leaf]).ok().unwrap();
+        let outer = encode(Thunk::Application(combination));
+        let mut dag = Dag::default();
+        let ready = dag.discover(outer).ok().unwrap();
+        assert_eq!(dag.graph.len(), 2);
+        let Handle::Encode(leaf) = leaf else {
+            unreachable!()
+        };
+        let leaf = dag.graph.node(leaf).unwrap();
+        assert_eq!(ready, [leaf]);
+        let blob = Data::Object(Object::Blob(BlobName::literal(b"done").unwrap()));
+        assert_eq!(dag.finish(leaf, blob).ok().unwrap(), [1 - leaf]);
+    }
+
+    // Many evaluations of overlapping DAGs at once (on the shared queue) all finish, and
+    // agree with the reference evaluator, however their tasks interleave.
+    #[test]
+    fn concurrent_evaluations_agree_and_finish() {
+        let name = |x: Result<crate::Value>| {
+            let h = x.map_or_else(Handle::Data, crate::HandleType::relax);
+            crate::local::canonical_name(PackedHandle::pack(h))
+        };
+        for round in 0..10u8 {
+            // Leaves shared by every evaluation, some behind an Identification (so found
+            // only once it's done), and one evaluation with a trap.
+            let leaves: Vec<_> = (0..12u8)
+                .map(|i| {
+                    encode(selection(
+                        &[b"shared by every evaluation", &[round, i][..]].concat(),
+                        0,
+                    ))
+                })
+                .collect();
+            let hidden = |i: usize| {
+                let Handle::Data(x) = tree(leaves[i..i + 3].to_vec()) else {
+                    unreachable!()
+                };
+                encode(Thunk::Identification(x))
+            };
+            let bad = encode(selection(
+                &[b"out of range in round ", &[round][..]].concat(),
+                1,
+            ));
+            let (sender, receiver) = std::sync::mpsc::channel();
+            for evaluation in 0..8 {
+                let mut elements = leaves[evaluation..].to_vec();
+                elements.push(hidden(evaluation % 9));
+                if evaluation == 7 {
+                    elements.insert(1, bad);
+                }
+                let h = tree(elements);
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    let engine = crate::eval(h, &Context::default());
+                    let reference = crate::reference::eval(h, &Context::default());
+                    sender.send(name(engine) == name(reference)).unwrap();
+                });
+            }
+            for _ in 0..8 {
+                // (A lost wakeup would leave an evaluation waiting forever.)
+                let agreed = receiver
+                    .recv_timeout(std::time::Duration::from_secs(60))
+                    .expect("an evaluation didn't finish");
+                assert!(agreed);
+            }
+        }
+    }
+
+    #[test]
+    fn urgent_tasks_overtake_but_nothing_starves() {
+        let task = |sequence, priority: Priority| Task {
+            deadline: sequence + priority.delay(),
+            sequence,
+            job: Box::new(|| {}),
+        };
+        let mut tasks =
+            BinaryHeap::from([task(0, Priority::Batch), task(1, Priority::Interactive)]);
+        assert_eq!(tasks.pop().unwrap().sequence, 1);
+        let mut tasks = BinaryHeap::from([
+            task(0, Priority::Normal),
+            task(Priority::Normal.delay() + 1, Priority::Interactive),
+        ]);
+        assert_eq!(tasks.pop().unwrap().sequence, 0);
+    }
+
+    // Two dependencies finishing at once ready their dependent exactly once (never twice,
+    // so it isn't executed twice, and never not at all), however they interleave.
+    #[cfg(loom)]
+    #[test]
+    fn loom_a_dependent_is_readied_once() {
+        loom::model(|| {
+            let mut blocked = Blocked::default();
+            blocked.grow(3);
+            assert!(blocked.add(0, &[]) && blocked.add(1, &[]));
+            assert!(!blocked.add(2, &[0, 1]));
+            let blocked = Arc::new(Mutex::new(blocked));
+            let finishing: Vec<_> = [0, 1]
+                .map(|node| {
+                    let blocked = blocked.clone();
+                    loom::thread::spawn(move || blocked.lock().unwrap().finish(node))
+                })
+                .into();
+            let ready: Vec<_> = finishing
+                .into_iter()
+                .flat_map(|x| x.join().unwrap())
+                .collect();
+            assert_eq!(ready, [2]);
+        });
+    }
+
+    // Whoever waits for an evaluation's tasks wakes once the last finishes, however the
+    // runner taking them interleaves with it (a lost wakeup would leave it waiting forever,
+    // which loom reports as a deadlock).
+    #[cfg(loom)]
+    #[test]
+    fn loom_the_last_task_wakes_the_waiter() {
+        loom::model(|| {
+            let pool = Arc::new(Pool::default());
+            let outstanding = Arc::new(AtomicUsize::new(2));
+            for _ in 0..2 {
+                let (finishing, outstanding) = (pool.clone(), outstanding.clone());
+                let job = Box::new(move || finishing.finished(&outstanding));
+                pool.push(Priority::Normal, job, 1);
+            }
+            let runner = {
+                let pool = pool.clone();
+                loom::thread::spawn(move || {
+                    while let Some(task) = pool.next() {
+                        (task.job)();
+                    }
+                })
+            };
+            pool.wait(&outstanding);
+            assert_eq!(outstanding.load(Ordering::SeqCst), 0);
+            runner.join().unwrap();
+        });
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+
+use crate::path::Path;
+use crate::{BlobName, Context, Data, Encode, Handle, Object, Thunk, eval, local};
+
+// A small expression language for building Handles, e.g.
+//
+//   # A Tree of a string and a number, and an Encode selecting from it.
+//   pair = tree("first", 2)
+//   select(pair, "/1")
+//   eval(select(pair, "/0"))
+//
+// Each line is a statement: `NAME = EXPRESSION` binds a variable, and an EXPRESSION alone
+// produces its Handle (which the caller prints). `#` starts a comment. The expressions are
+//   "text"                 a Blob of the string (with \n, \t, \\, \" and \xNN escapes)
+//   123, -5                a Blob of the number, as an i64 (or a u64, if it doesn't fit)
+//   true, false            a Blob of the bool
+//   NAME                   a variable
+//   @LABEL, HANDLE         a label, or a Handle in hex (see lookup)
+//   tree(E, ...)           a Tree of the Handles
+//   apply(E, ...)          an Encode applying the Tree of the Handles: the limits, the
+//                          procedure, then the arguments
+//   select(E, "PATH")      an Encode selecting along a one-step path from Data (see path)
+//   identify(E)            an Encode identifying Data
+//   object(E), ref(E)      Data made accessible (loading it) or not; for an Encode, one that
+//                          makes its result so
+//   eval(E)                the result of evaluating the Handle, now
+//   file("PATH")           a Blob of a file's contents
+//
+// What's created is local (see local) until it's printed, so nothing is stored for the
+// intermediate steps.
+pub(crate) struct Script<'a> {
+    variables: HashMap<String, Handle>,
+    lookup: &'a dyn Fn(&str) -> io::Result<Handle>,
+    context: Context,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Word(String),
+    Label(String),
+    Text(Vec<u8>),
+    Punctuation(char),
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, message.into())
+}
+
+fn tokens(line: &str) -> io::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => break,
+            c if c.is_whitespace() => {}
+            '(' | ')' | ',' | '=' => tokens.push(Token::Punctuation(c)),
+            '"' => {
+                let mut text = Vec::new();
+                loop {
+                    let c = chars.next().ok_or_else(|| invalid("unterminated string"))?;
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some('n') => text.push(b'\n'),
+                            Some('t') => text.push(b'\t'),
+                            Some('\\') => text.push(b'\\'),
+                            Some('"') => text.push(b'"'),
+                            Some('x') => {
+                                let hex: String = chars.by_ref().take(2).collect();
+                                let byte = u8::from_str_radix(&hex, 16)
+                                    .map_err(|_| invalid("bad \\x escape"))?;
+                                text.push(byte);
+                            }
+                            _ => return Err(invalid("bad escape")),
+                        },
+                        c => text.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                    }
+                }
+                tokens.push(Token::Text(text));
+            }
+            c if c == '@' || c == '-' || c == '_' || c.is_alphanumeric() => {
+                let mut word = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if c == '-' || c == '_' || c == '.' || c.is_alphanumeric() {
+                        word.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(match word.strip_prefix('@') {
+                    Some(label) => Token::Label(label.to_string()),
+                    None => Token::Word(word),
+                });
+            }
+            c => return Err(invalid(format!("unexpected {c:?}"))),
+        }
+    }
+    Ok(tokens)
+}
+
+fn blob(x: BlobName) -> Handle {
+    Handle::Data(Data::Object(Object::Blob(x)))
+}
+
+fn encode(thunk: Thunk) -> Handle {
+    Handle::Encode(Encode {
+        thunk,
+        accessibility: None,
+    })
+}
+
+fn data(h: Handle) -> io::Result<Data> {
+    match h {
+        Handle::Data(x) => Ok(x),
+        _ => Err(invalid("not Data")),
+    }
+}
+
+impl<'a> Script<'a> {
+    // A script whose labels and hex Handles are found by `lookup` (which is given a label's
+    // name, or the hex).
+    pub(crate) fn new(lookup: &'a dyn Fn(&str) -> io::Result<Handle>) -> Self {
+        Script {
+            variables: HashMap::new(),
+            lookup,
+            context: Context::default(),
+        }
+    }
+
+    // The Handle a variable is bound to.
+    pub(crate) fn variable(&self, name: &str) -> Option<Handle> {
+        self.variables.get(name).copied()
+    }
+
+    // Run one statement, returning the Handle it produced (if it's an expression).
+    pub(crate) fn run(&mut self, line: &str) -> io::Result<Option<Handle>> {
+        let tokens = tokens(line)?;
+        let (name, expression) = match &tokens[..] {
+            [] => return Ok(None),
+            [Token::Word(name), Token::Punctuation('='), expression @ ..] => {
+                (Some(name.clone()), expression)
+            }
+            expression => (None, expression),
+        };
+        let mut rest = expression;
+        let h = self.expression(&mut rest)?;
+        if !rest.is_empty() {
+            return Err(invalid("unexpected text after the expression"));
+        }
+        match name {
+            Some(name) => {
+                self.variables.insert(name, h);
+                Ok(None)
+            }
+            None => Ok(Some(h)),
+        }
+    }
+
+    // Run every statement of a script, returning the Handles produced (failing with the
+    // number of the line that failed).
+    pub(crate) fn run_all(&mut self, script: &str) -> io::Result<Vec<Handle>> {
+        let mut produced = Vec::new();
+        for (i, line) in script.lines().enumerate() {
+            let h = self
+                .run(line)
+                .map_err(|e| io::Error::new(e.kind(), format!("line {}: {e}", i + 1)))?;
+            produced.extend(h);
+        }
+        Ok(produced)
+    }
+
+    fn expression(&self, tokens: &mut &[Token]) -> io::Result<Handle> {
+        let (first, rest) = tokens
+            .split_first()
+            .ok_or_else(|| invalid("expected an expression"))?;
+        *tokens = rest;
+        match first {
+            Token::Text(x) => Ok(blob(local::blob(x.clone()))),
+            Token::Label(x) => (self.lookup)(x),
+            Token::Word(x) if x == "true" || x == "false" => Ok(blob((x == "true").into())),
+            Token::Word(x) if x.starts_with(|c: char| c == '-' || c.is_ascii_digit()) => {
+                if let Ok(n) = x.parse::<i64>() {
+                    Ok(blob(n.into()))
+                } else if let Ok(n) = x.parse::<u64>() {
+                    Ok(blob(n.into()))
+                } else if x.len() == 64 {
+                    (self.lookup)(x)
+                } else {
+                    Err(invalid(format!("not a number: {x}")))
+                }
+            }
+            Token::Word(x) if tokens.first() == Some(&Token::Punctuation('(')) => {
+                *tokens = &tokens[1..];
+                let arguments = self.arguments(tokens)?;
+                self.call(x, arguments)
+            }
+            Token::Word(x) => match self.variables.get(x) {
+                Some(&h) => Ok(h),
+                None if x.len() == 64 => (self.lookup)(x),
+                None => Err(invalid(format!("no variable {x}"))),
+            },
+            Token::Punctuation(c) => Err(invalid(format!("unexpected {c:?}"))),
+        }
+    }
+
+    // The arguments of a call, after its `(`, through its `)`.
+    fn arguments(&self, tokens: &mut &[Token]) -> io::Result<Vec<Handle>> {
+        let mut arguments = Vec::new();
+        if tokens.first() == Some(&Token::Punctuation(')')) {
+            *tokens = &tokens[1..];
+            return Ok(arguments);
+        }
+        loop {
+            arguments.push(self.expression(tokens)?);
+            match tokens.split_first() {
+                Some((Token::Punctuation(','), rest)) => *tokens = rest,
+                Some((Token::Punctuation(')'), rest)) => {
+                    *tokens = rest;
+                    return Ok(arguments);
+                }
+                _ => return Err(invalid("expected , or )")),
+            }
+        }
+    }
+
+    fn call(&self, function: &str, arguments: Vec<Handle>) -> io::Result<Handle> {
+        let trap = io::Error::from;
+        match (function, &arguments[..]) {
+            ("tree", _) => Ok(Handle::Data(Data::Object(Object::Tree(local::tree(
+                arguments,
+            ))))),
+            ("apply", _) => Ok(encode(Thunk::Application(local::tree(arguments)))),
+            ("select", &[target, path]) => {
+                let path = match data(path)? {
+                    Data::Object(Object::Blob(x)) => x.as_string().map_err(trap)?,
+                    _ => return Err(invalid("a path is a string")),
+                };
+                let path: Path = path.parse()?;
+                Ok(encode(path.thunk(data(target)?).map_err(trap)?))
+            }
+            ("identify", &[x]) => Ok(encode(Thunk::Identification(data(x)?))),
+            ("object" | "ref", &[x]) => {
+                let object = function == "object";
+                Ok(match x {
+                    Handle::Encode(e) => Handle::Encode(Encode {
+                        accessibility: Some(object),
+                        ..e
+                    }),
+                    Handle::Data(x) if object => {
+                        Handle::Data(Data::Object(x.lift().map_err(trap)?))
+                    }
+                    Handle::Data(x) => Handle::Data(Data::Ref(x.lower())),
+                    Handle::Thunk(_) => return Err(invalid("not Data or an Encode")),
+                })
+            }
+            ("eval", &[x]) => eval(x, &self.context)
+                .map(crate::HandleType::relax)
+                .map_err(trap),
+            ("file", &[path]) => {
+                let path = match data(path)? {
+                    Data::Object(Object::Blob(x)) => x.as_string().map_err(trap)?,
+                    _ => return Err(invalid("a file name is a string")),
+                };
+                Ok(blob(local::blob(std::fs::read(path)?)))
+            }
+            ("select" | "identify" | "object" | "ref" | "eval" | "file", _) => {
+                Err(invalid(format!("wrong number of arguments to {function}")))
+            }
+            _ => Err(invalid(format!("no function {function}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packed::PackedHandle;
+
+    fn no_labels(_: &str) -> io::Result<Handle> {
+        Err(invalid("no labels"))
+    }
+
+    fn name(h: Handle) -> PackedHandle {
+        local::canonical_name(PackedHandle::pack(h))
+    }
+
+    #[test]
+    fn statements_bind_and_produce_handles() {
+        let mut script = Script::new(&no_labels);
+        let produced = script
+            .run_all(
+                "# a pair, and its second element\n\
+                 pair = tree(\"first \\x41\", 2)\n\
+                 \n\
+                 pair\n\
+                 eval(select(pair, \"/1\"))  # selected\n",
+            )
+            .unwrap();
+        let pair = Handle::Data(Data::Object(Object::Tree(local::tree(vec![
+            blob(local::blob(b"first A".to_vec())),
+            blob(2i64.into()),
+        ]))));
+        assert_eq!(produced.len(), 2);
+        assert!(name(produced[0]) == name(pair));
+        assert!(name(produced[1]) == name(blob(2i64.into())));
+    }
+
+    #[test]
+    fn labels_and_accessibility() {
+        let labelled = blob(true.into());
+        let lookup = |label: &str| match label {
+            "yes" => Ok(labelled),
+            _ => Err(invalid("no such label")),
+        };
+        let mut script = Script::new(&lookup);
+        let h = script.run("ref(identify(@yes))").unwrap().unwrap();
+        let Handle::Encode(e) = h else {
+            panic!("not an Encode");
+        };
+        assert_eq!(e.accessibility, Some(false));
+        assert!(script.run("@no").is_err());
+    }
+
+    #[test]
+    fn errors_name_their_line() {
+        let mut script = Script::new(&no_labels);
+        for (text, message) in [
+            ("x = 1\ny = missing", "line 2: no variable missing"),
+            ("tree(1", "line 1: expected , or )"),
+            ("\"open", "line 1: unterminated string"),
+            ("select(1)", "line 1: wrong number of arguments to select"),
+            ("frobnicate()", "line 1: no function frobnicate"),
+            ("1 2", "line 1: unexpected text after the expression"),
+        ] {
+            let error = script.run_all(text).err().unwrap();
+            assert_eq!(error.to_string(), message);
+        }
+    }
+}
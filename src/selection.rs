@@ -0,0 +1,222 @@
+use crate::metrics::{self, Counter};
+use crate::packed::PackedHandle;
+use crate::storage::key;
+use crate::{
+    BlobName, Data, HANDLE_SIZE, Handle, Object, Ref, Result, RuntimeValue, TreeName, local,
+    stored, trace, trap,
+};
+
+// A selection's specification is a Tree of
+//   0  the target: a Blob or Tree (an Object or a Ref)
+//   1  what to select: a Blob of one or two u64s (little-endian)
+//      - an index: that element of a Tree, or that byte of a Blob (as a Blob)
+//      - a start and end: that range of the Tree or Blob, as a new Tree or Blob
+//   2  (optional) a Blob whose first byte is nonzero, to truncate the selected Tree elements:
+//      each Blob or Tree is replaced by an empty one with the same accessibility, so the
+//      element types can be discovered without loading any of their contents
+//
+// What's selected is accessible, even if the target isn't. Only the selected part is
+// loaded: a range of a Tree reads just its elements (from storage that can), and a range
+// of a chunked Blob just the chunks it overlaps.
+//
+// Specs can be built from paths, e.g. "/3/bytes:0..16" (see path).
+//
+// A selection traps if the spec is malformed, the index or range is out of bounds, or it
+// selects a single Encode (which isn't a RuntimeValue).
+pub(crate) fn select(spec: TreeName) -> Result<RuntimeValue> {
+    let (target, range, truncate) = match spec.try_load()?[..] {
+        [target, range] => (target, range, false),
+        [target, range, truncate] => (target, range, blob(truncate)?.first() > Some(&0)),
+        _ => return Err(malformed()),
+    };
+    let range = blob(range)?
+        .chunks(8)
+        .map(|x| x.try_into().map(u64::from_le_bytes).map(|x| x as usize))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| malformed())?;
+    let (start, end, single) = match range[..] {
+        [index] => (index, index.checked_add(1).ok_or_else(out_of_range)?, true),
+        [start, end] => (start, end, false),
+        _ => return Err(malformed()),
+    };
+    let (blob, tree) = match target {
+        Handle::Data(Data::Object(Object::Blob(x)) | Data::Ref(Ref::Blob(x))) => (Some(x), None),
+        Handle::Data(Data::Object(Object::Tree(x)) | Data::Ref(Ref::Tree(x))) => (None, Some(x)),
+        _ => return Err(malformed()),
+    };
+    let size = blob.map_or_else(|| tree.unwrap().size(), |x| x.size());
+    if start > end || end > size {
+        return Err(out_of_range());
+    }
+
+    if let Some(name) = blob {
+        let bytes = name.try_load_range(start, end)?;
+        return Ok(RuntimeValue::Data(Data::Object(Object::Blob(
+            BlobName::create(bytes)?,
+        ))));
+    }
+    let name = tree.unwrap();
+    let started = trace::loading();
+    let elements = stored(
+        local::storage_of(name.name).get_tree_range(key(name.name), start, end),
+        "Tree",
+    )?;
+    metrics::add(Counter::BytesLoaded, (elements.len() * HANDLE_SIZE) as u64);
+    trace::loaded(started, "load Tree", elements.len() * HANDLE_SIZE);
+    let empty = TreeName::create(Vec::new())?;
+    let mut elements = elements
+        .iter()
+        .map(PackedHandle::unpack)
+        .map(|h| if truncate { truncated(h, empty) } else { h });
+    if single {
+        return match elements.next().unwrap() {
+            Handle::Data(x) => Ok(RuntimeValue::Data(x)),
+            Handle::Thunk(x) => Ok(RuntimeValue::Thunk(x)),
+            Handle::Encode(_) => Err(selected_encode()),
+        };
+    }
+    Ok(RuntimeValue::Data(Data::Object(Object::Tree(
+        TreeName::create(elements.collect())?,
+    ))))
+}
+
+// An empty Blob or Tree in place of a Data element (Thunks and Encodes have no contents).
+fn truncated(h: Handle, empty: TreeName) -> Handle {
+    let empty_blob = BlobName::literal(b"").unwrap();
+    let empty_tree = |x: TreeName| TreeName {
+        tag: x.tag,
+        ..empty
+    };
+    match h {
+        Handle::Data(Data::Object(Object::Blob(_))) => {
+            Handle::Data(Data::Object(Object::Blob(empty_blob)))
+        }
+        Handle::Data(Data::Object(Object::Tree(x))) => {
+            Handle::Data(Data::Object(Object::Tree(empty_tree(x))))
+        }
+        Handle::Data(Data::Ref(Ref::Blob(_))) => Handle::Data(Data::Ref(Ref::Blob(empty_blob))),
+        Handle::Data(Data::Ref(Ref::Tree(x))) => Handle::Data(Data::Ref(Ref::Tree(empty_tree(x)))),
+        Handle::Thunk(_) | Handle::Encode(_) => h,
+    }
+}
+
+// The contents of a Blob in the spec.
+fn blob(h: Handle) -> Result<Vec<u8>> {
+    match h {
+        Handle::Data(Data::Object(Object::Blob(x)) | Data::Ref(Ref::Blob(x))) => {
+            Ok(x.try_load()?.to_vec())
+        }
+        _ => Err(malformed()),
+    }
+}
+
+fn malformed() -> Data {
+    trap::bad_selection("malformed selection")
+}
+
+fn out_of_range() -> Data {
+    trap::bad_selection("selection out of range")
+}
+
+fn selected_encode() -> Data {
+    trap::type_error("selected an Encode")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Thunk;
+    use crate::trap::Kind;
+
+    fn blob(contents: &[u8]) -> Handle {
+        Handle::Data(Data::Object(Object::Blob(
+            BlobName::create(contents.to_vec()).ok().unwrap(),
+        )))
+    }
+
+    fn tree(elements: Vec<Handle>) -> Handle {
+        Handle::Data(Data::Object(Object::Tree(
+            TreeName::create(elements).ok().unwrap(),
+        )))
+    }
+
+    fn spec(target: Handle, range: &[u64], truncate: bool) -> TreeName {
+        let range = range
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut spec = vec![target, blob(&range)];
+        if truncate {
+            spec.push(blob(&[1]));
+        }
+        TreeName::create(spec).ok().unwrap()
+    }
+
+    fn selected(spec: TreeName) -> PackedHandle {
+        match select(spec).ok().unwrap() {
+            RuntimeValue::Data(x) => PackedHandle::pack(Handle::Data(x)),
+            RuntimeValue::Thunk(x) => PackedHandle::pack(Handle::Thunk(x)),
+        }
+    }
+
+    fn traps(spec: TreeName, kind: Kind) -> bool {
+        trap::is(select(spec).err().unwrap(), kind)
+    }
+
+    #[test]
+    fn selects_elements_and_bytes() {
+        let long = blob(&[5; 100]);
+        let thunk = Handle::Thunk(Thunk::Identification(Data::Object(Object::Blob(
+            BlobName::literal(b"x").unwrap(),
+        ))));
+        let target = tree(vec![blob(b"zero"), long, thunk]);
+        assert!(selected(spec(target, &[1], false)) == PackedHandle::pack(long));
+        assert!(selected(spec(target, &[2], false)) == PackedHandle::pack(thunk));
+        let range = PackedHandle::pack(tree(vec![long, thunk]));
+        assert!(selected(spec(target, &[1, 3], false)) == range);
+        assert!(selected(spec(long, &[7], false)) == PackedHandle::pack(blob(&[5])));
+        assert!(selected(spec(long, &[10, 60], false)) == PackedHandle::pack(blob(&[5; 50])));
+        // A selection of nothing is empty.
+        assert!(selected(spec(long, &[100, 100], false)) == PackedHandle::pack(blob(b"")));
+    }
+
+    #[test]
+    fn truncation_empties_the_selected_objects() {
+        let inner = TreeName::create(vec![blob(b"a")]).ok().unwrap();
+        let tagged = Handle::Data(Data::Ref(Ref::Tree(TreeName { tag: true, ..inner })));
+        let target = tree(vec![blob(&[5; 100]), tagged]);
+        let Ok(RuntimeValue::Data(Data::Object(Object::Tree(x)))) =
+            select(spec(target, &[0, 2], true))
+        else {
+            panic!("not a Tree");
+        };
+        let elements = x.try_load().ok().unwrap();
+        assert!(PackedHandle::pack(elements[0]) == PackedHandle::pack(blob(b"")));
+        let Handle::Data(Data::Ref(Ref::Tree(empty))) = elements[1] else {
+            panic!("not a Tree Ref");
+        };
+        assert!(empty.tag && empty.size() == 0);
+    }
+
+    #[test]
+    fn bad_specs_trap() {
+        let target = tree(vec![blob(b"zero")]);
+        assert!(traps(spec(target, &[1], false), Kind::BadSelection));
+        assert!(traps(spec(target, &[1, 0], false), Kind::BadSelection));
+        assert!(traps(spec(target, &[0, 2], false), Kind::BadSelection));
+        assert!(traps(spec(target, &[u64::MAX], false), Kind::BadSelection));
+        assert!(traps(spec(target, &[0, 1, 2], false), Kind::BadSelection));
+        let range = TreeName::create(vec![target, blob(&[0; 3])]).ok().unwrap();
+        assert!(traps(range, Kind::BadSelection));
+        let encode = Handle::Encode(crate::Encode {
+            thunk: Thunk::Identification(Data::Object(Object::Blob(
+                BlobName::literal(b"x").unwrap(),
+            ))),
+            accessibility: None,
+        });
+        assert!(traps(
+            spec(tree(vec![encode]), &[0], false),
+            Kind::TypeError
+        ));
+    }
+}
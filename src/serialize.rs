@@ -0,0 +1,226 @@
+use std::fmt;
+
+use serde::de::{self, SeqAccess, Visitor};
+use serde::ser;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::packed::PackedHandle;
+use crate::{BlobName, Data, Encode, Handle, Object, Ref, Thunk, TreeName, local};
+
+// Handles (and their parts) serialize as their canonical packed form: 32 bytes, or 64 hex
+// digits in human-readable formats. Local objects are canonicalized (and stored) first, so
+// a serialized Handle never names an object only this process has.
+//
+// Only the Handle is serialized, not the objects it names: a Handle deserialized in another
+// process can only be loaded there if its objects are transferred too (e.g. in an archive).
+fn serialize<S: Serializer>(h: Handle, serializer: S) -> Result<S::Ok, S::Error> {
+    let packed = local::canonical(PackedHandle::pack(h)).map_err(ser::Error::custom)?;
+    if serializer.is_human_readable() {
+        let hex: String = packed
+            .as_bytes()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        serializer.serialize_str(&hex)
+    } else {
+        serializer.serialize_bytes(packed.as_bytes())
+    }
+}
+
+fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Handle, D::Error> {
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_str(Packed)
+    } else {
+        deserializer.deserialize_bytes(Packed)
+    }
+}
+
+struct Packed;
+
+impl Packed {
+    fn unpack<E: de::Error>(bytes: &[u8]) -> Result<Handle, E> {
+        let bytes: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| E::invalid_length(bytes.len(), &Packed))?;
+        PackedHandle::from_bytes(bytes)
+            .try_unpack()
+            .ok_or_else(|| E::custom("malformed Handle"))
+    }
+}
+
+impl<'de> Visitor<'de> for Packed {
+    type Value = Handle;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a packed Handle (32 bytes, or 64 hex digits)")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Handle, E> {
+        Self::unpack(bytes)
+    }
+
+    fn visit_str<E: de::Error>(self, hex: &str) -> Result<Handle, E> {
+        let digits = |i: usize| {
+            hex.get(2 * i..2 * i + 2)
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+        };
+        let bytes: Option<Vec<u8>> = (0..hex.len() / 2).map(digits).collect();
+        match bytes {
+            Some(bytes) if hex.len().is_multiple_of(2) => Self::unpack(&bytes),
+            _ => Err(E::invalid_value(de::Unexpected::Str(hex), &self)),
+        }
+    }
+
+    // (Formats without bytes send them as a sequence.)
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Handle, A::Error> {
+        let mut bytes = Vec::with_capacity(32);
+        while let Some(b) = seq.next_element()? {
+            bytes.push(b);
+        }
+        Self::unpack(&bytes)
+    }
+}
+
+// Each part serializes as the Handle it's the (only) content of, and deserializes from one
+// of the right kind.
+macro_rules! via_handle {
+    ($type:ty, $what:literal, |$x:ident| $into:expr, $from:pat => $part:expr) => {
+        impl Serialize for $type {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let $x = *self;
+                serialize($into, serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $type {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                match deserialize(deserializer)? {
+                    $from => Ok($part),
+                    _ => Err(de::Error::custom(concat!("Handle is not ", $what))),
+                }
+            }
+        }
+    };
+}
+
+impl Serialize for Handle {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize(*self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Handle {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize(deserializer)
+    }
+}
+
+via_handle!(Data, "Data", |x| Handle::Data(x), Handle::Data(x) => x);
+via_handle!(Object, "an Object", |x| Handle::Data(Data::Object(x)), Handle::Data(Data::Object(x)) => x);
+via_handle!(Ref, "a Ref", |x| Handle::Data(Data::Ref(x)), Handle::Data(Data::Ref(x)) => x);
+via_handle!(
+    BlobName,
+    "a Blob Object",
+    |x| Handle::Data(Data::Object(Object::Blob(x))),
+    Handle::Data(Data::Object(Object::Blob(x))) => x
+);
+via_handle!(
+    TreeName,
+    "a Tree Object",
+    |x| Handle::Data(Data::Object(Object::Tree(x))),
+    Handle::Data(Data::Object(Object::Tree(x))) => x
+);
+via_handle!(Thunk, "a Thunk", |x| Handle::Thunk(x), Handle::Thunk(x) => x);
+via_handle!(Encode, "an Encode", |x| Handle::Encode(x), Handle::Encode(x) => x);
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn samples() -> (Data, Thunk, Encode) {
+        let blob = BlobName::create(vec![3; 100]).ok().unwrap();
+        let tree = TreeName::create(vec![Handle::Data(Data::Object(Object::Blob(blob)))]);
+        let data = Data::Object(Object::Tree(tree.ok().unwrap()));
+        let thunk = Thunk::Selection(tree.ok().unwrap());
+        let encode = Encode {
+            thunk,
+            accessibility: Some(false),
+        };
+        (data, thunk, encode)
+    }
+
+    fn packed(h: impl Into<Handle>) -> PackedHandle {
+        PackedHandle::pack(h.into())
+    }
+
+    #[test]
+    fn parts_round_trip_in_both_forms() {
+        let (data, thunk, encode) = samples();
+        let json = serde_json::to_string(&data).unwrap();
+        assert_eq!(json.len(), 2 + 64);
+        assert!(packed(serde_json::from_str::<Data>(&json).unwrap()) == packed(data));
+        let bytes = postcard::to_allocvec(&thunk).unwrap();
+        assert!(packed(postcard::from_bytes::<Thunk>(&bytes).unwrap()) == packed(thunk));
+        let json = serde_json::to_string(&Handle::Encode(encode)).unwrap();
+        assert!(packed(serde_json::from_str::<Encode>(&json).unwrap()) == packed(encode));
+        let Data::Object(Object::Tree(tree)) = data else {
+            unreachable!()
+        };
+        let bytes = postcard::to_allocvec(&tree).unwrap();
+        assert!(
+            packed(Data::Object(Object::Tree(
+                postcard::from_bytes(&bytes).unwrap()
+            ))) == packed(data)
+        );
+    }
+
+    #[test]
+    fn local_objects_serialize_canonically() {
+        let blob = local::blob(b"serialized while still local".to_vec());
+        let local = Handle::Data(Data::Object(Object::Blob(blob)));
+        let json = serde_json::to_string(&local).unwrap();
+        let canonical = local::canonical(packed(local)).unwrap();
+        assert!(packed(serde_json::from_str::<Handle>(&json).unwrap()) == canonical);
+    }
+
+    #[test]
+    fn malformed_or_mismatched_handles_are_rejected() {
+        let (data, thunk, _) = samples();
+        let json = serde_json::to_string(&thunk).unwrap();
+        assert!(serde_json::from_str::<Data>(&json).is_err());
+        assert!(serde_json::from_str::<Ref>(&serde_json::to_string(&data).unwrap()).is_err());
+        assert!(serde_json::from_str::<Handle>("\"abc\"").is_err());
+        assert!(serde_json::from_str::<Handle>(&format!("\"{}\"", "zz".repeat(32))).is_err());
+        assert!(serde_json::from_str::<Handle>(&format!("\"{}\"", "ff".repeat(32))).is_err());
+        assert!(postcard::from_bytes::<Handle>(&[4, 1, 2, 3, 4]).is_err());
+    }
+
+    #[test]
+    fn formats_without_bytes_send_a_sequence() {
+        let (data, ..) = samples();
+        let bytes = packed(data).as_bytes().to_vec();
+        let h: Handle = Packed
+            .visit_seq(de::value::SeqDeserializer::<_, de::value::Error>::new(
+                bytes.into_iter(),
+            ))
+            .unwrap();
+        assert!(packed(h) == packed(data));
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_input_deserializes_or_is_rejected(
+            bytes in prop::collection::vec(any::<u8>(), 0..40),
+            text in "[0-9a-fx\\[\\], \"]{0,70}",
+        ) {
+            if let Ok(h) = postcard::from_bytes::<Handle>(&bytes) {
+                prop_assert!(bytes.len() > 32);
+                prop_assert!(PackedHandle::pack(h).as_bytes()[..] == bytes[1..33]);
+            }
+            let _ = serde_json::from_str::<Handle>(&text);
+            let _ = serde_json::from_str::<Data>(&text);
+        }
+    }
+}
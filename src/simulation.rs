@@ -0,0 +1,324 @@
+use std::collections::BTreeSet;
+use std::io::{self, ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::packed::PackedHandle;
+use crate::path::Path;
+use crate::remote::{Coordinator, Worker, serve};
+use crate::storage::memory::MemoryStorage;
+use crate::storage::{Key, SharedBlob, Storage, storage};
+use crate::{
+    BlobName, Context, Data, Encode, Handle, HandleType, Object, Ref, Thunk, Tree, TreeName, fetch,
+    local, reference,
+};
+
+// Simulation testing: the same work, under faults chosen by a seeded generator (so a failing
+// seed can be replayed), must give the same results as with no faults at all.
+//
+//   workers   the streams to worker processes break (an error or an early end, on either
+//             side, partway through) or hang; the coordinator falls back to executing
+//             locally, so evaluating gives what the reference evaluator does
+//   storage   a remote's reads fail or return corrupt objects; fetching traps (storing
+//             nothing that isn't intact), and retrying fetches exactly the closure
+//
+// A worker's streams (like a pipe or TCP connection) are taken to break but never to corrupt
+// what they carry, which nothing checks. Time is the real clock (waits are short).
+
+// xorshift64*, seeded.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+// What goes wrong with a stream, after how many bytes.
+#[derive(Copy, Clone)]
+enum Fault {
+    Error,
+    End,
+    Hang,
+}
+
+// A stream that works until its fault.
+struct Faulty<T> {
+    inner: T,
+    fault: Option<(Fault, usize)>,
+}
+
+impl<T> Faulty<T> {
+    fn new(inner: T, rng: &mut Rng) -> Self {
+        let fault = match rng.next() % 4 {
+            0 => None,
+            1 => Some(Fault::Error),
+            2 => Some(Fault::End),
+            _ => Some(Fault::Hang),
+        };
+        let after = rng.next() as usize % 2000;
+        Faulty {
+            inner,
+            fault: fault.map(|x| (x, after)),
+        }
+    }
+
+    // How much of `len` bytes can pass before the fault (failing if it's now).
+    fn allow(&mut self, len: usize) -> io::Result<usize> {
+        let Some((fault, after)) = &mut self.fault else {
+            return Ok(len);
+        };
+        if *after > 0 {
+            let allowed = len.min(*after);
+            *after -= allowed;
+            return Ok(allowed);
+        }
+        match fault {
+            Fault::Error => Err(io::Error::new(ErrorKind::ConnectionReset, "simulated")),
+            Fault::End => Ok(0),
+            Fault::Hang => loop {
+                thread::park();
+            },
+        }
+    }
+}
+
+impl<T: Read> Read for Faulty<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let allowed = self.allow(buf.len())?;
+        self.inner.read(&mut buf[..allowed])
+    }
+}
+
+impl<T: Write> Write for Faulty<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.allow(buf.len())? {
+            0 if !buf.is_empty() => Err(io::Error::new(ErrorKind::WriteZero, "simulated")),
+            allowed => self.inner.write(&buf[..allowed]),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// A worker served by a thread, each of whose four stream ends may be faulty.
+fn worker(rng: &mut Rng) -> Worker {
+    let (requests, request_writer) = io::pipe().unwrap();
+    let (answer_reader, answers) = io::pipe().unwrap();
+    let (requests, answers) = (Faulty::new(requests, rng), Faulty::new(answers, rng));
+    thread::spawn(move || serve(requests, answers));
+    Worker::new(
+        Faulty::new(answer_reader, rng),
+        Faulty::new(request_writer, rng),
+    )
+}
+
+// A Storage whose reads sometimes fail, or give a corrupt copy of the object.
+struct Flaky<'a> {
+    inner: &'a dyn Storage,
+    rng: Mutex<Rng>,
+}
+
+impl Flaky<'_> {
+    fn fault(&self) -> Option<io::Result<()>> {
+        let mut rng = self.rng.lock().unwrap();
+        match rng.next() % 6 {
+            0 => Some(Err(io::Error::new(ErrorKind::TimedOut, "simulated"))),
+            1 => Some(Ok(())),
+            _ => None,
+        }
+    }
+}
+
+impl Storage for Flaky<'_> {
+    fn get_blob(&self, name: Key) -> io::Result<Option<SharedBlob>> {
+        let blob = self.inner.get_blob(name)?;
+        match self.fault() {
+            Some(Err(e)) => Err(e),
+            Some(Ok(())) => Ok(blob.map(|x| {
+                let mut corrupt = x.to_vec();
+                corrupt.push(0);
+                corrupt.into()
+            })),
+            None => Ok(blob),
+        }
+    }
+
+    fn put_blob(&self, name: Key, blob: SharedBlob) -> io::Result<()> {
+        self.inner.put_blob(name, blob)
+    }
+
+    fn contains_blob(&self, name: Key) -> io::Result<bool> {
+        self.inner.contains_blob(name)
+    }
+
+    fn delete_blob(&self, name: Key) -> io::Result<()> {
+        self.inner.delete_blob(name)
+    }
+
+    fn get_tree(&self, name: Key) -> io::Result<Option<Arc<Tree<PackedHandle>>>> {
+        let tree = self.inner.get_tree(name)?;
+        match self.fault() {
+            Some(Err(e)) => Err(e),
+            Some(Ok(())) => Ok(tree.map(|x| {
+                let mut corrupt = x.to_vec();
+                corrupt.reverse();
+                corrupt.push(PackedHandle::pack(Handle::Data(Data::Object(
+                    Object::Blob(BlobName::literal(b"corrupt").unwrap()),
+                ))));
+                Arc::from(corrupt)
+            })),
+            None => Ok(tree),
+        }
+    }
+
+    fn put_tree(&self, name: Key, tree: Arc<Tree<PackedHandle>>) -> io::Result<()> {
+        self.inner.put_tree(name, tree)
+    }
+
+    fn contains_tree(&self, name: Key) -> io::Result<bool> {
+        self.inner.contains_tree(name)
+    }
+
+    fn delete_tree(&self, name: Key) -> io::Result<()> {
+        self.inner.delete_tree(name)
+    }
+
+    fn list_blobs(&self) -> io::Result<Vec<Key>> {
+        self.inner.list_blobs()
+    }
+
+    fn list_trees(&self) -> io::Result<Vec<Key>> {
+        self.inner.list_trees()
+    }
+}
+
+fn blob(contents: Vec<u8>) -> Handle {
+    Handle::Data(Data::Object(Object::Blob(
+        BlobName::create(contents).ok().unwrap(),
+    )))
+}
+
+// A Tree of Encodes for a seed, each a few Selections deep, one out of range (so trapping).
+fn program(seed: u64) -> Handle {
+    let encodes = (0..6u64)
+        .map(|i| {
+            let contents = [
+                b"simulated ".as_slice(),
+                &seed.to_le_bytes(),
+                &[i as u8; 24],
+            ];
+            let mut thunk = Thunk::Identification(match blob(contents.concat()) {
+                Handle::Data(x) => x,
+                _ => unreachable!(),
+            });
+            for _ in 0..i % 3 {
+                let tree = TreeName::create(vec![Handle::Thunk(thunk)]).ok().unwrap();
+                let index = if i == 5 { 1 } else { 0 };
+                thunk = Path::new()
+                    .index(index)
+                    .thunk(Data::Object(Object::Tree(tree)))
+                    .ok()
+                    .unwrap();
+            }
+            Handle::Encode(Encode {
+                thunk,
+                accessibility: None,
+            })
+        })
+        .collect();
+    Handle::Data(Data::Object(Object::Tree(
+        TreeName::create(encodes).ok().unwrap(),
+    )))
+}
+
+fn name(result: crate::Result<crate::Value>) -> PackedHandle {
+    let h = result.map_or_else(Handle::Data, HandleType::relax);
+    local::canonical_name(PackedHandle::pack(h))
+}
+
+#[test]
+fn offloading_gives_the_same_results_whatever_fails() {
+    for seed in 0..24 {
+        let mut rng = Rng::new(seed);
+        let workers = (0..3).map(|_| worker(&mut rng)).collect();
+        let coordinator = Coordinator::new(workers).with_timeout(Duration::from_millis(200));
+        let context = Context {
+            offload: Some(Arc::new(coordinator)),
+            ..Context::default()
+        };
+        // (Each seed's program is its own, so nothing comes from the memo table.)
+        let h = program(seed);
+        let simulated = name(crate::eval(h, &context));
+        let expected = name(reference::eval(h, &Context::default()));
+        assert!(simulated == expected, "seed {seed}");
+    }
+}
+
+// The objects stored, by kind and key.
+fn contents(storage: &MemoryStorage) -> BTreeSet<(bool, Key)> {
+    let (blobs, trees) = storage.snapshot();
+    let blobs = blobs.into_keys().map(|x| (false, x));
+    blobs.chain(trees.into_keys().map(|x| (true, x))).collect()
+}
+
+// Whether everything `local` holds is as `remote` holds it.
+fn intact(local: &MemoryStorage, remote: &MemoryStorage) -> bool {
+    let (blobs, trees) = local.snapshot();
+    let blobs = blobs.iter().all(|(&name, x)| {
+        remote
+            .get_blob(name)
+            .unwrap()
+            .is_some_and(|y| x[..] == y[..])
+    });
+    let trees = trees.iter().all(|(&name, x)| {
+        remote
+            .get_tree(name)
+            .unwrap()
+            .is_some_and(|y| x[..] == y[..])
+    });
+    blobs && trees
+}
+
+#[test]
+fn fetching_from_a_flaky_remote_stores_only_the_closure() {
+    let leaves: Vec<_> = (0..8u8)
+        .map(|i| blob([b"fetched from a flaky remote ".as_slice(), &[i; 40]].concat()))
+        .collect();
+    let inner = TreeName::create(leaves[4..].to_vec()).ok().unwrap();
+    let mut elements = leaves[..4].to_vec();
+    elements.push(Handle::Data(Data::Ref(Ref::Tree(inner))));
+    let root = Handle::Data(Data::Ref(Ref::Tree(
+        TreeName::create(elements).ok().unwrap(),
+    )));
+    let remote = MemoryStorage::default();
+    fetch::fetch_into(&remote, &*storage(), root, false, |_| ()).unwrap();
+    let mut retries = 0;
+    for seed in 0..32 {
+        let flaky = Flaky {
+            inner: &remote,
+            rng: Mutex::new(Rng::new(seed)),
+        };
+        let local = MemoryStorage::default();
+        let mut attempts = 0;
+        while fetch::fetch_into(&local, &flaky, root, false, |_| ()).is_err() {
+            attempts += 1;
+            assert!(attempts < 100, "seed {seed} never fetched");
+            assert!(intact(&local, &remote), "seed {seed}");
+        }
+        assert!(contents(&local) == contents(&remote), "seed {seed}");
+        retries += attempts;
+    }
+    // (The faults did happen.)
+    assert!(retries > 0);
+}
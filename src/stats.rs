@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::io;
+
+use crate::packed::MAX_FOOTPRINT;
+use crate::storage::{Key, Storage, storage};
+use crate::{HANDLE_SIZE, PAGE_SIZE, TreeName, chunk};
+
+// Statistics about the process-wide Storage, for monitoring its growth.
+// Chunks count as Blobs and chunk lists as Trees, since that's how they're stored.
+#[derive(Clone, Default, Debug)]
+pub(crate) struct Stats {
+    pub(crate) blobs: usize,
+    pub(crate) trees: usize,
+    // Stored bytes: Blob contents, and Tree elements (packed).
+    pub(crate) blob_bytes: u64,
+    pub(crate) tree_bytes: u64,
+    // Tree elements naming Literals, and naming stored objects.
+    pub(crate) literal_elements: usize,
+    pub(crate) pointer_elements: usize,
+    // The bytes that would be stored if every Tree element naming an object had its own
+    // copy of that object (though not of the objects below it), and each unnamed object one.
+    pub(crate) undeduplicated_bytes: u64,
+    // Objects by footprint: bucket 0 counts footprints of 0 pages, and bucket i > 0
+    // footprints of 2^(i-1) up to 2^i - 1 pages.
+    pub(crate) footprints: [usize; FOOTPRINT_BUCKETS],
+}
+
+pub(crate) const FOOTPRINT_BUCKETS: usize = MAX_FOOTPRINT.ilog2() as usize + 2;
+
+impl Stats {
+    pub(crate) fn objects(&self) -> usize {
+        self.blobs + self.trees
+    }
+
+    pub(crate) fn bytes(&self) -> u64 {
+        self.blob_bytes + self.tree_bytes
+    }
+
+    // The fraction of Tree elements that are Literals (0 if there are no elements).
+    pub(crate) fn literal_ratio(&self) -> f64 {
+        let elements = self.literal_elements + self.pointer_elements;
+        if elements == 0 {
+            return 0.0;
+        }
+        self.literal_elements as f64 / elements as f64
+    }
+
+    // The bytes saved by storing each object once.
+    pub(crate) fn deduplication_savings(&self) -> u64 {
+        self.undeduplicated_bytes.saturating_sub(self.bytes())
+    }
+}
+
+fn bucket(footprint: u32) -> usize {
+    match footprint {
+        0 => 0,
+        x => x.ilog2() as usize + 1,
+    }
+}
+
+pub(crate) fn stats() -> io::Result<Stats> {
+    stats_in(&*storage())
+}
+
+fn stats_in(storage: &dyn Storage) -> io::Result<Stats> {
+    let mut stats = Stats::default();
+    // The size of each stored object (keyed as in Storage, by whether it's stored as a Tree),
+    // and how many Tree elements name it.
+    let mut objects: HashMap<(bool, Key), u64> = HashMap::new();
+    let mut references: HashMap<(bool, Key), u64> = HashMap::new();
+
+    for name in storage.list_blobs()? {
+        let Some(blob) = storage.get_blob(name)? else {
+            continue;
+        };
+        stats.blobs += 1;
+        stats.blob_bytes += blob.len() as u64;
+        stats.footprints[bucket(blob.len().div_ceil(PAGE_SIZE) as u32)] += 1;
+        objects.insert((false, name), blob.len() as u64);
+    }
+    for name in storage.list_trees()? {
+        let Some(tree) = storage.get_tree(name)? else {
+            continue;
+        };
+        let bytes = (tree.len() * HANDLE_SIZE) as u64;
+        stats.trees += 1;
+        stats.tree_bytes += bytes;
+        objects.insert((true, name), bytes);
+
+        let mut elements = Vec::with_capacity(tree.len());
+        for h in tree.iter() {
+            if h.is_literal() {
+                stats.literal_elements += 1;
+            } else {
+                stats.pointer_elements += 1;
+            }
+            elements.extend(h.try_unpack());
+        }
+        let (_, footprint, _) = TreeName::metadata(&elements);
+        stats.footprints[bucket(footprint)] += 1;
+        for h in tree.iter() {
+            if let Some(name) = h.key() {
+                *references
+                    .entry((chunk::stored_as_tree(h), name))
+                    .or_default() += 1;
+            }
+        }
+    }
+    stats.undeduplicated_bytes = objects
+        .iter()
+        .map(|(object, bytes)| bytes * references.get(object).copied().unwrap_or(1))
+        .sum();
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::{hash_blob, hash_tree};
+    use crate::packed::PackedHandle;
+    use crate::storage::key;
+    use crate::storage::memory::MemoryStorage;
+    use crate::{BlobName, Data, Handle, Object};
+
+    #[test]
+    fn counts_shared_objects_once() {
+        let storage = MemoryStorage::default();
+        let contents = vec![1; 100];
+        let pointer = hash_blob(&contents);
+        storage.put_blob(key(pointer), contents.into()).unwrap();
+        let blob = PackedHandle::pack(Handle::Data(Data::Object(Object::Blob(BlobName::Name((
+            pointer, 100,
+        ))))));
+        let literal = PackedHandle::pack(Handle::Data(Data::Object(Object::Blob(
+            BlobName::literal(b"literal").unwrap(),
+        ))));
+        for tree in [vec![blob, blob, literal], vec![blob]] {
+            storage
+                .put_tree(key(hash_tree::<()>(&tree)), tree.into())
+                .unwrap();
+        }
+
+        let stats = stats_in(&storage).unwrap();
+        assert_eq!((stats.blobs, stats.trees, stats.objects()), (1, 2, 3));
+        assert_eq!((stats.blob_bytes, stats.tree_bytes), (100, 4 * 32));
+        assert_eq!((stats.literal_elements, stats.pointer_elements), (1, 3));
+        assert_eq!(stats.literal_ratio(), 0.25);
+        // The Blob is named three times, and each Tree is unnamed.
+        assert_eq!(stats.undeduplicated_bytes, 3 * 100 + 4 * 32);
+        assert_eq!(stats.deduplication_savings(), 2 * 100);
+        // Every Blob (even a Literal) spans a page, and each Tree a page of its own beside
+        // its elements': 1, 2 and 4 pages.
+        assert_eq!(&stats.footprints[..5], [0, 1, 1, 1, 0]);
+    }
+
+    #[test]
+    fn footprints_are_bucketed_by_powers_of_two() {
+        let buckets: Vec<_> = [0, 1, 2, 3, 4, 7, 8].into_iter().map(bucket).collect();
+        assert_eq!(buckets, [0, 1, 2, 2, 3, 3, 4]);
+        assert_eq!(bucket(MAX_FOOTPRINT), FOOTPRINT_BUCKETS - 1);
+    }
+}
@@ -0,0 +1,302 @@
+use std::io;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::{Arc, LazyLock, RwLock};
+
+use memmap2::Mmap;
+
+use crate::fetch::Mismatch;
+use crate::packed::PackedHandle;
+use crate::{Blob, PAGE_SIZE, Pointer, Tree};
+
+pub(crate) mod cache;
+#[cfg(feature = "sled")]
+pub(crate) mod kv;
+pub(crate) mod memory;
+pub(crate) mod quota;
+#[cfg(feature = "s3")]
+pub(crate) mod s3;
+pub(crate) mod tiered;
+pub(crate) mod verify;
+
+// Storage holds the contents of the Blobs and Trees named by Pointer.
+// Objects are keyed by the Pointer's 192 bits, with Blobs and Trees in separate namespaces
+// (a Blob and a Tree can have the same hash). Tree elements are stored packed.
+//
+// Puts of an object already present are no-ops, and deleting a missing object is not an error.
+pub(crate) trait Storage: Send + Sync {
+    fn get_blob(&self, name: Key) -> io::Result<Option<SharedBlob>>;
+    fn put_blob(&self, name: Key, blob: SharedBlob) -> io::Result<()>;
+    fn contains_blob(&self, name: Key) -> io::Result<bool>;
+    fn delete_blob(&self, name: Key) -> io::Result<()>;
+
+    fn get_tree(&self, name: Key) -> io::Result<Option<Arc<Tree<PackedHandle>>>>;
+    fn put_tree(&self, name: Key, tree: Arc<Tree<PackedHandle>>) -> io::Result<()>;
+    fn contains_tree(&self, name: Key) -> io::Result<bool>;
+    fn delete_tree(&self, name: Key) -> io::Result<()>;
+
+    // Elements `start..end` of a Tree, for backends that can read them without the rest.
+    // (By default the whole Tree is loaded.)
+    fn get_tree_range(
+        &self,
+        name: Key,
+        start: usize,
+        end: usize,
+    ) -> io::Result<Option<Vec<PackedHandle>>> {
+        self.get_tree(name)?
+            .map(|tree| slice(&tree, start, end))
+            .transpose()
+    }
+
+    // Every object currently stored (e.g. for garbage collection).
+    fn list_blobs(&self) -> io::Result<Vec<Key>>;
+    fn list_trees(&self) -> io::Result<Vec<Key>>;
+
+    // Make every put so far durable (for backends that buffer writes).
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Keep something received that didn't match what was asked for (see fetch), apart from
+    // the objects, for inspection. (By default it's dropped.)
+    fn quarantine(&self, _mismatch: &Mismatch) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// A shared Storage (e.g. the Repository a command opened) can be layered on too.
+impl<S: Storage + ?Sized> Storage for Arc<S> {
+    fn get_blob(&self, name: Key) -> io::Result<Option<SharedBlob>> {
+        (**self).get_blob(name)
+    }
+
+    fn put_blob(&self, name: Key, blob: SharedBlob) -> io::Result<()> {
+        (**self).put_blob(name, blob)
+    }
+
+    fn contains_blob(&self, name: Key) -> io::Result<bool> {
+        (**self).contains_blob(name)
+    }
+
+    fn delete_blob(&self, name: Key) -> io::Result<()> {
+        (**self).delete_blob(name)
+    }
+
+    fn get_tree(&self, name: Key) -> io::Result<Option<Arc<Tree<PackedHandle>>>> {
+        (**self).get_tree(name)
+    }
+
+    fn put_tree(&self, name: Key, tree: Arc<Tree<PackedHandle>>) -> io::Result<()> {
+        (**self).put_tree(name, tree)
+    }
+
+    fn contains_tree(&self, name: Key) -> io::Result<bool> {
+        (**self).contains_tree(name)
+    }
+
+    fn delete_tree(&self, name: Key) -> io::Result<()> {
+        (**self).delete_tree(name)
+    }
+
+    fn get_tree_range(
+        &self,
+        name: Key,
+        start: usize,
+        end: usize,
+    ) -> io::Result<Option<Vec<PackedHandle>>> {
+        (**self).get_tree_range(name, start, end)
+    }
+
+    fn list_blobs(&self) -> io::Result<Vec<Key>> {
+        (**self).list_blobs()
+    }
+
+    fn list_trees(&self) -> io::Result<Vec<Key>> {
+        (**self).list_trees()
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn quarantine(&self, mismatch: &Mismatch) -> io::Result<()> {
+        (**self).quarantine(mismatch)
+    }
+}
+
+pub(crate) type Key = (u64, u64, u64);
+
+pub(crate) fn slice(
+    tree: &Tree<PackedHandle>,
+    start: usize,
+    end: usize,
+) -> io::Result<Vec<PackedHandle>> {
+    tree.get(start..end)
+        .map(<[_]>::to_vec)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Tree range out of bounds"))
+}
+
+pub(crate) fn key<T: ?Sized>((a, b, c, _): Pointer<T>) -> Key {
+    (a, b, c)
+}
+
+pub(crate) fn pointer<T: ?Sized>((a, b, c): Key) -> Pointer<T> {
+    (a, b, c, PhantomData)
+}
+
+pub(crate) fn key_bytes((a, b, c): Key) -> [u8; 24] {
+    let mut bytes = [0; 24];
+    bytes[0..8].copy_from_slice(&a.to_le_bytes());
+    bytes[8..16].copy_from_slice(&b.to_le_bytes());
+    bytes[16..24].copy_from_slice(&c.to_le_bytes());
+    bytes
+}
+
+pub(crate) fn key_from_bytes(bytes: &[u8]) -> Option<Key> {
+    let bytes: &[u8; 24] = bytes.try_into().ok()?;
+    let word = |i: usize| u64::from_le_bytes(bytes[8 * i..8 * (i + 1)].try_into().unwrap());
+    Some((word(0), word(1), word(2)))
+}
+
+static STORAGE: LazyLock<RwLock<Arc<dyn Storage>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(memory::MemoryStorage::default())));
+
+// The process-wide Storage behind every Pointer (in memory, unless replaced).
+pub(crate) fn storage() -> Arc<dyn Storage> {
+    STORAGE.read().unwrap().clone()
+}
+
+pub(crate) fn set_storage(storage: Arc<dyn Storage>) {
+    *STORAGE.write().unwrap() = storage;
+}
+
+// The contents of a stored Blob: on the heap, or a read-only mapping of a file.
+#[derive(Clone)]
+pub(crate) enum SharedBlob {
+    Heap(Arc<Blob>),
+    Mapped(Arc<Mmap>),
+}
+
+impl Deref for SharedBlob {
+    type Target = Blob;
+
+    fn deref(&self) -> &Blob {
+        match self {
+            SharedBlob::Heap(x) => x,
+            SharedBlob::Mapped(x) => x,
+        }
+    }
+}
+
+impl From<Vec<u8>> for SharedBlob {
+    fn from(blob: Vec<u8>) -> Self {
+        SharedBlob::Heap(blob.into())
+    }
+}
+
+impl SharedBlob {
+    // The memory actually in use, in units of PAGE_SIZE: a mapping only counts its resident pages.
+    pub(crate) fn resident_footprint(&self) -> u32 {
+        match self {
+            SharedBlob::Heap(x) => x.len().div_ceil(PAGE_SIZE) as u32,
+            SharedBlob::Mapped(x) => resident_bytes(x).div_ceil(PAGE_SIZE) as u32,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn resident_bytes(map: &Mmap) -> usize {
+    // SAFETY: sysconf has no preconditions.
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    // A mapping at an offset into its file need not start on a page boundary.
+    let skew = map.as_ptr() as usize % page;
+    let mut resident = vec![0u8; (skew + map.len()).div_ceil(page)];
+    // SAFETY: the range is the mapping's pages, and `resident` has one byte per page of it.
+    let status = unsafe {
+        libc::mincore(
+            map.as_ptr().sub(skew) as *mut libc::c_void,
+            skew + map.len(),
+            resident.as_mut_ptr().cast(),
+        )
+    };
+    if status != 0 {
+        return map.len();
+    }
+    let pages = resident.iter().filter(|&&p| p & 1 != 0).count();
+    (pages * page).min(map.len())
+}
+
+#[cfg(not(unix))]
+fn resident_bytes(map: &Mmap) -> usize {
+    map.len()
+}
+
+// The contents of a loaded Blob: borrowed from a Literal Name, or shared with the Storage.
+pub(crate) enum BlobData<'a> {
+    Literal(&'a Blob),
+    Stored(SharedBlob),
+}
+
+impl Deref for BlobData<'_> {
+    type Target = Blob;
+
+    fn deref(&self) -> &Blob {
+        match self {
+            BlobData::Literal(x) => x,
+            BlobData::Stored(x) => x,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // What every Storage must do, checked on an empty one.
+    pub(crate) fn conformance(storage: &dyn Storage) {
+        let (a, b) = ((1, 2, 3), (4, 5, 6));
+        let tree: Arc<Tree<PackedHandle>> = (0..4u8)
+            .map(|i| PackedHandle::from_bytes([i; crate::HANDLE_SIZE]))
+            .collect();
+        storage.put_blob(a, vec![1; 100].into()).unwrap();
+        // A put of an object already present is a no-op.
+        storage.put_blob(a, vec![1; 100].into()).unwrap();
+        storage.put_tree(a, tree.clone()).unwrap();
+        storage.flush().unwrap();
+
+        // Blobs and Trees are separate namespaces.
+        assert_eq!(&*storage.get_blob(a).unwrap().unwrap(), &[1; 100][..]);
+        assert!(*storage.get_tree(a).unwrap().unwrap() == *tree);
+        assert!(storage.contains_blob(a).unwrap() && storage.contains_tree(a).unwrap());
+        assert!(!storage.contains_blob(b).unwrap() && !storage.contains_tree(b).unwrap());
+        assert!(storage.get_blob(b).unwrap().is_none());
+        assert!(storage.get_tree(b).unwrap().is_none());
+        assert!(storage.get_tree_range(a, 1, 3).unwrap().unwrap() == tree[1..3]);
+        assert!(storage.get_tree_range(a, 3, 5).is_err());
+        assert_eq!(storage.list_blobs().unwrap(), [a]);
+        assert_eq!(storage.list_trees().unwrap(), [a]);
+
+        // Deleting a missing object is not an error.
+        storage.delete_blob(a).unwrap();
+        storage.delete_blob(a).unwrap();
+        storage.delete_tree(b).unwrap();
+        storage.flush().unwrap();
+        assert!(!storage.contains_blob(a).unwrap() && storage.contains_tree(a).unwrap());
+        assert!(storage.list_blobs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn keys_round_trip_through_bytes() {
+        let key = (1, u64::MAX, 0x0123_4567_89ab_cdef);
+        assert_eq!(key_from_bytes(&key_bytes(key)), Some(key));
+        assert_eq!(key_from_bytes(&[0; 23]), None);
+        let pointer: Pointer<Blob> = super::pointer(key);
+        assert_eq!(super::key(pointer), key);
+    }
+
+    #[test]
+    fn a_heap_blob_is_resident() {
+        let blob = SharedBlob::from(vec![0; PAGE_SIZE + 1]);
+        assert_eq!(blob.resident_footprint(), 2);
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use super::{Key, SharedBlob, Storage, slice};
+use crate::fetch::Mismatch;
+use crate::packed::PackedHandle;
+use crate::{HANDLE_SIZE, PAGE_SIZE, Tree};
+
+// Keeps recently loaded objects of another Storage in memory, within a budget of pages
+// (the units of footprint). When the budget is exceeded, the least recently used objects
+// are evicted: they are demoted to being only named (as by a Ref), and are loaded
+// from the backing Storage again when next needed. Puts are written through.
+pub(crate) struct CachedStorage<S> {
+    inner: S,
+    budget: u64,
+    cache: Mutex<Lru>,
+}
+
+#[derive(Clone)]
+enum Cached {
+    Blob(SharedBlob),
+    Tree(Arc<Tree<PackedHandle>>),
+}
+
+impl Cached {
+    fn footprint(&self) -> u64 {
+        match self {
+            Cached::Blob(x) => x.resident_footprint().max(1) as u64,
+            Cached::Tree(x) => (x.len() * HANDLE_SIZE).div_ceil(PAGE_SIZE).max(1) as u64,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Lru {
+    // Each object, with when it was last used and its footprint when it was cached.
+    entries: HashMap<(bool, Key), (Cached, u64, u64)>,
+    // Entries by when they were last used.
+    order: BTreeMap<u64, (bool, Key)>,
+    clock: u64,
+    pages: u64,
+}
+
+impl Lru {
+    fn get(&mut self, id: (bool, Key)) -> Option<Cached> {
+        let (object, used, _) = self.entries.get_mut(&id)?;
+        self.order.remove(used);
+        self.clock += 1;
+        *used = self.clock;
+        self.order.insert(self.clock, id);
+        Some(object.clone())
+    }
+
+    fn insert(&mut self, id: (bool, Key), object: Cached, budget: u64) {
+        if self.get(id).is_some() {
+            return;
+        }
+        self.clock += 1;
+        let pages = object.footprint();
+        self.pages += pages;
+        self.entries.insert(id, (object, self.clock, pages));
+        self.order.insert(self.clock, id);
+        while self.pages > budget {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.evict(oldest);
+        }
+    }
+
+    fn evict(&mut self, id: (bool, Key)) {
+        if let Some((_, used, pages)) = self.entries.remove(&id) {
+            self.order.remove(&used);
+            self.pages -= pages;
+        }
+    }
+}
+
+impl<S: Storage> CachedStorage<S> {
+    pub(crate) fn new(inner: S, budget: u64) -> Self {
+        CachedStorage {
+            inner,
+            budget,
+            cache: Mutex::default(),
+        }
+    }
+
+    // The pages currently held in memory.
+    #[cfg(test)]
+    fn pages(&self) -> u64 {
+        self.cache.lock().unwrap().pages
+    }
+
+    fn cache(&self, id: (bool, Key), object: Cached) {
+        self.cache.lock().unwrap().insert(id, object, self.budget);
+    }
+}
+
+impl<S: Storage> Storage for CachedStorage<S> {
+    fn get_blob(&self, name: Key) -> io::Result<Option<SharedBlob>> {
+        if let Some(Cached::Blob(blob)) = self.cache.lock().unwrap().get((false, name)) {
+            return Ok(Some(blob));
+        }
+        let blob = self.inner.get_blob(name)?;
+        if let Some(blob) = &blob {
+            self.cache((false, name), Cached::Blob(blob.clone()));
+        }
+        Ok(blob)
+    }
+
+    fn put_blob(&self, name: Key, blob: SharedBlob) -> io::Result<()> {
+        self.inner.put_blob(name, blob.clone())?;
+        self.cache((false, name), Cached::Blob(blob));
+        Ok(())
+    }
+
+    fn contains_blob(&self, name: Key) -> io::Result<bool> {
+        if self
+            .cache
+            .lock()
+            .unwrap()
+            .entries
+            .contains_key(&(false, name))
+        {
+            return Ok(true);
+        }
+        self.inner.contains_blob(name)
+    }
+
+    fn delete_blob(&self, name: Key) -> io::Result<()> {
+        self.cache.lock().unwrap().evict((false, name));
+        self.inner.delete_blob(name)
+    }
+
+    fn get_tree(&self, name: Key) -> io::Result<Option<Arc<Tree<PackedHandle>>>> {
+        if let Some(Cached::Tree(tree)) = self.cache.lock().unwrap().get((true, name)) {
+            return Ok(Some(tree));
+        }
+        let tree = self.inner.get_tree(name)?;
+        if let Some(tree) = &tree {
+            self.cache((true, name), Cached::Tree(tree.clone()));
+        }
+        Ok(tree)
+    }
+
+    fn put_tree(&self, name: Key, tree: Arc<Tree<PackedHandle>>) -> io::Result<()> {
+        self.inner.put_tree(name, tree.clone())?;
+        self.cache((true, name), Cached::Tree(tree));
+        Ok(())
+    }
+
+    fn contains_tree(&self, name: Key) -> io::Result<bool> {
+        if self
+            .cache
+            .lock()
+            .unwrap()
+            .entries
+            .contains_key(&(true, name))
+        {
+            return Ok(true);
+        }
+        self.inner.contains_tree(name)
+    }
+
+    fn delete_tree(&self, name: Key) -> io::Result<()> {
+        self.cache.lock().unwrap().evict((true, name));
+        self.inner.delete_tree(name)
+    }
+
+    // Part of a Tree is only cached as the whole Tree, when that's already cached.
+    fn get_tree_range(
+        &self,
+        name: Key,
+        start: usize,
+        end: usize,
+    ) -> io::Result<Option<Vec<PackedHandle>>> {
+        if let Some(Cached::Tree(tree)) = self.cache.lock().unwrap().get((true, name)) {
+            return slice(&tree, start, end).map(Some);
+        }
+        self.inner.get_tree_range(name, start, end)
+    }
+
+    fn list_blobs(&self) -> io::Result<Vec<Key>> {
+        self.inner.list_blobs()
+    }
+
+    fn list_trees(&self) -> io::Result<Vec<Key>> {
+        self.inner.list_trees()
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn quarantine(&self, mismatch: &Mismatch) -> io::Result<()> {
+        self.inner.quarantine(mismatch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+
+    fn page(byte: u8) -> SharedBlob {
+        vec![byte; PAGE_SIZE].into()
+    }
+
+    #[test]
+    fn conforms() {
+        let storage = CachedStorage::new(MemoryStorage::default(), 100);
+        crate::storage::tests::conformance(&storage);
+    }
+
+    #[test]
+    fn the_least_recently_used_are_evicted() {
+        let storage = CachedStorage::new(MemoryStorage::default(), 2);
+        let (a, b, c) = ((1, 1, 1), (2, 2, 2), (3, 3, 3));
+        storage.put_blob(a, page(1)).unwrap();
+        storage.put_blob(b, page(2)).unwrap();
+        storage.get_blob(a).unwrap();
+        storage.put_blob(c, page(3)).unwrap();
+        assert_eq!(storage.pages(), 2);
+
+        // Evicted objects are loaded from the backing Storage, and cached again.
+        for name in [a, b, c] {
+            storage.inner.delete_blob(name).unwrap();
+        }
+        assert_eq!(&storage.get_blob(a).unwrap().unwrap()[..1], &[1]);
+        assert!(storage.get_blob(b).unwrap().is_none());
+        assert!(storage.contains_blob(c).unwrap());
+        storage.inner.put_blob(b, page(2)).unwrap();
+        storage.get_blob(b).unwrap();
+        assert!(storage.get_blob(c).unwrap().is_none());
+        assert_eq!(storage.pages(), 2);
+    }
+
+    #[test]
+    fn ranges_come_from_a_cached_tree() {
+        let storage = CachedStorage::new(MemoryStorage::default(), 10);
+        let tree: Arc<Tree<PackedHandle>> = (0..4u8)
+            .map(|i| PackedHandle::from_bytes([i; HANDLE_SIZE]))
+            .collect();
+        storage.put_tree((1, 1, 1), tree.clone()).unwrap();
+        storage.inner.delete_tree((1, 1, 1)).unwrap();
+        assert!(storage.get_tree_range((1, 1, 1), 1, 3).unwrap().unwrap() == tree[1..3]);
+        storage.delete_tree((1, 1, 1)).unwrap();
+        assert!(storage.get_tree_range((1, 1, 1), 1, 3).unwrap().is_none());
+        assert_eq!(storage.pages(), 0);
+    }
+}
@@ -0,0 +1,189 @@
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use super::memory::MemoryStorage;
+use super::{Key, SharedBlob, Storage, key_bytes, key_from_bytes};
+use crate::Tree;
+use crate::packed::PackedHandle;
+use crate::repository::{pack_tree, unpack_tree};
+
+// Storage in an embedded sled database, with one sled Tree per object kind.
+// Suited to many small objects, which are slow as individual files.
+//
+// Puts are buffered and written as one batch per kind when BATCH_SIZE objects are pending
+// or on flush.
+pub(crate) struct KvStorage {
+    db: sled::Db,
+    blobs: sled::Tree,
+    trees: sled::Tree,
+    pending: MemoryStorage,
+    writing: Mutex<()>,
+}
+
+const BATCH_SIZE: usize = 1024;
+
+impl KvStorage {
+    pub(crate) fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let db = sled::open(path)?;
+        Ok(KvStorage {
+            blobs: db.open_tree("blobs")?,
+            trees: db.open_tree("trees")?,
+            db,
+            pending: MemoryStorage::default(),
+            writing: Mutex::new(()),
+        })
+    }
+
+    // Write out the pending objects once there are enough of them.
+    fn maybe_write(&self) -> io::Result<()> {
+        if self.pending.len() >= BATCH_SIZE {
+            self.write_pending()?;
+        }
+        Ok(())
+    }
+
+    fn write_pending(&self) -> io::Result<()> {
+        let _writing = self.writing.lock().unwrap();
+        let (blobs, trees) = self.pending.snapshot();
+        let mut batch = sled::Batch::default();
+        for (name, blob) in &blobs {
+            batch.insert(&key_bytes(*name), &**blob);
+        }
+        self.blobs.apply_batch(batch)?;
+        let mut batch = sled::Batch::default();
+        for (name, tree) in &trees {
+            batch.insert(&key_bytes(*name), pack_tree(tree));
+        }
+        self.trees.apply_batch(batch)?;
+        for name in blobs.keys() {
+            self.pending.delete_blob(*name)?;
+        }
+        for name in trees.keys() {
+            self.pending.delete_tree(*name)?;
+        }
+        Ok(())
+    }
+}
+
+fn list(tree: &sled::Tree, pending: Vec<Key>) -> io::Result<Vec<Key>> {
+    let mut names = pending;
+    for entry in tree.iter().keys() {
+        names.extend(key_from_bytes(&entry?));
+    }
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+impl Storage for KvStorage {
+    fn get_blob(&self, name: Key) -> io::Result<Option<SharedBlob>> {
+        if let Some(blob) = self.pending.get_blob(name)? {
+            return Ok(Some(blob));
+        }
+        let blob = self.blobs.get(key_bytes(name))?;
+        Ok(blob.map(|x| SharedBlob::Heap(Arc::from(&*x))))
+    }
+
+    fn put_blob(&self, name: Key, blob: SharedBlob) -> io::Result<()> {
+        if !self.contains_blob(name)? {
+            self.pending.put_blob(name, blob)?;
+            self.maybe_write()?;
+        }
+        Ok(())
+    }
+
+    fn contains_blob(&self, name: Key) -> io::Result<bool> {
+        Ok(self.pending.contains_blob(name)? || self.blobs.contains_key(key_bytes(name))?)
+    }
+
+    fn delete_blob(&self, name: Key) -> io::Result<()> {
+        self.pending.delete_blob(name)?;
+        self.blobs.remove(key_bytes(name))?;
+        Ok(())
+    }
+
+    fn get_tree(&self, name: Key) -> io::Result<Option<Arc<Tree<PackedHandle>>>> {
+        if let Some(tree) = self.pending.get_tree(name)? {
+            return Ok(Some(tree));
+        }
+        match self.trees.get(key_bytes(name))? {
+            Some(bytes) => Ok(Some(unpack_tree(&bytes)?.into())),
+            None => Ok(None),
+        }
+    }
+
+    fn put_tree(&self, name: Key, tree: Arc<Tree<PackedHandle>>) -> io::Result<()> {
+        if !self.contains_tree(name)? {
+            self.pending.put_tree(name, tree)?;
+            self.maybe_write()?;
+        }
+        Ok(())
+    }
+
+    fn contains_tree(&self, name: Key) -> io::Result<bool> {
+        Ok(self.pending.contains_tree(name)? || self.trees.contains_key(key_bytes(name))?)
+    }
+
+    fn delete_tree(&self, name: Key) -> io::Result<()> {
+        self.pending.delete_tree(name)?;
+        self.trees.remove(key_bytes(name))?;
+        Ok(())
+    }
+
+    fn list_blobs(&self) -> io::Result<Vec<Key>> {
+        list(&self.blobs, self.pending.list_blobs()?)
+    }
+
+    fn list_trees(&self) -> io::Result<Vec<Key>> {
+        list(&self.trees, self.pending.list_trees()?)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.write_pending()?;
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn conforms() {
+        let dir = TempDir::new().unwrap();
+        crate::storage::tests::conformance(&KvStorage::open(dir.path().join("db")).unwrap());
+    }
+
+    #[test]
+    fn flushed_objects_survive_reopening() {
+        let dir = TempDir::new().unwrap();
+        let storage = KvStorage::open(dir.path().join("db")).unwrap();
+        // More than a batch is written before the flush.
+        for i in 0..=BATCH_SIZE as u64 {
+            storage.put_blob((i, 0, 0), vec![1; 40].into()).unwrap();
+        }
+        assert!(storage.pending.len() < BATCH_SIZE);
+        storage
+            .put_tree((1, 1, 1), Arc::new([PackedHandle::from_bytes([2; 32])]))
+            .unwrap();
+        storage.flush().unwrap();
+        drop(storage);
+
+        // sled releases its lock on the database from a background thread, shortly after the
+        // last handle is dropped.
+        let storage = (0..100)
+            .find_map(|_| {
+                KvStorage::open(dir.path().join("db"))
+                    .inspect_err(|_| std::thread::sleep(std::time::Duration::from_millis(10)))
+                    .ok()
+            })
+            .unwrap();
+        assert_eq!(storage.list_blobs().unwrap().len(), BATCH_SIZE + 1);
+        assert_eq!(&*storage.get_blob((7, 0, 0)).unwrap().unwrap(), &[1; 40]);
+        assert_eq!(storage.get_tree((1, 1, 1)).unwrap().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+
+use super::{Key, SharedBlob, Storage};
+use crate::Tree;
+use crate::packed::PackedHandle;
+use crate::sync::RwLock;
+
+// Storage in process memory.
+//
+// Every worker thread looks objects up here, so each index is split into shards, each
+// behind its own lock (by the Key's first word, which is a hash's, so they're spread
+// evenly): readers only contend with a writer to the same shard, and never with each other.
+#[derive(Default)]
+pub(crate) struct MemoryStorage {
+    blobs: Sharded<SharedBlob>,
+    trees: Sharded<Arc<Tree<PackedHandle>>>,
+}
+
+pub(crate) type Blobs = HashMap<Key, SharedBlob>;
+pub(crate) type Trees = HashMap<Key, Arc<Tree<PackedHandle>>>;
+
+const SHARDS: usize = 64;
+
+struct Sharded<V>([RwLock<HashMap<Key, V>>; SHARDS]);
+
+impl<V> Default for Sharded<V> {
+    fn default() -> Self {
+        Sharded(std::array::from_fn(|_| RwLock::default()))
+    }
+}
+
+impl<V: Clone> Sharded<V> {
+    fn shard(&self, name: &Key) -> &RwLock<HashMap<Key, V>> {
+        &self.0[name.0 as usize % SHARDS]
+    }
+
+    fn get(&self, name: &Key) -> Option<V> {
+        self.shard(name).read().unwrap().get(name).cloned()
+    }
+
+    fn contains(&self, name: &Key) -> bool {
+        self.shard(name).read().unwrap().contains_key(name)
+    }
+
+    fn insert(&self, name: Key, value: V) {
+        self.shard(&name)
+            .write()
+            .unwrap()
+            .entry(name)
+            .or_insert(value);
+    }
+
+    fn remove(&self, name: &Key) {
+        self.shard(name).write().unwrap().remove(name);
+    }
+
+    #[cfg(any(test, feature = "sled"))]
+    fn len(&self) -> usize {
+        self.0.iter().map(|x| x.read().unwrap().len()).sum()
+    }
+
+    fn keys(&self) -> Vec<Key> {
+        let shards = self.0.iter().map(|x| x.read().unwrap());
+        shards
+            .flat_map(|x| x.keys().copied().collect::<Vec<_>>())
+            .collect()
+    }
+
+    // (Shard by shard, so it's only a snapshot of objects that aren't being put or deleted.)
+    fn snapshot(&self) -> HashMap<Key, V> {
+        let shards = self.0.iter().map(|x| x.read().unwrap().clone());
+        shards.flatten().collect()
+    }
+}
+
+impl MemoryStorage {
+    // The number of objects stored.
+    #[cfg(any(test, feature = "sled"))]
+    pub(crate) fn len(&self) -> usize {
+        self.blobs.len() + self.trees.len()
+    }
+
+    // A copy of the index of every object.
+    pub(crate) fn snapshot(&self) -> (Blobs, Trees) {
+        (self.blobs.snapshot(), self.trees.snapshot())
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get_blob(&self, name: Key) -> io::Result<Option<SharedBlob>> {
+        Ok(self.blobs.get(&name))
+    }
+
+    fn put_blob(&self, name: Key, blob: SharedBlob) -> io::Result<()> {
+        self.blobs.insert(name, blob);
+        Ok(())
+    }
+
+    fn contains_blob(&self, name: Key) -> io::Result<bool> {
+        Ok(self.blobs.contains(&name))
+    }
+
+    fn delete_blob(&self, name: Key) -> io::Result<()> {
+        self.blobs.remove(&name);
+        Ok(())
+    }
+
+    fn get_tree(&self, name: Key) -> io::Result<Option<Arc<Tree<PackedHandle>>>> {
+        Ok(self.trees.get(&name))
+    }
+
+    fn put_tree(&self, name: Key, tree: Arc<Tree<PackedHandle>>) -> io::Result<()> {
+        self.trees.insert(name, tree);
+        Ok(())
+    }
+
+    fn contains_tree(&self, name: Key) -> io::Result<bool> {
+        Ok(self.trees.contains(&name))
+    }
+
+    fn delete_tree(&self, name: Key) -> io::Result<()> {
+        self.trees.remove(&name);
+        Ok(())
+    }
+
+    fn list_blobs(&self) -> io::Result<Vec<Key>> {
+        Ok(self.blobs.keys())
+    }
+
+    fn list_trees(&self) -> io::Result<Vec<Key>> {
+        Ok(self.trees.keys())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conforms() {
+        let storage = MemoryStorage::default();
+        super::super::tests::conformance(&storage);
+        assert_eq!(storage.len(), 1);
+        let (blobs, trees) = storage.snapshot();
+        assert_eq!((blobs.len(), trees.len()), (0, 1));
+    }
+
+    // Threads storing the same objects at once all find them, and each is stored once.
+    #[test]
+    fn concurrent_puts_store_each_object_once() {
+        let storage = MemoryStorage::default();
+        let key = |i: u64| (i, i ^ 0x5eed, 0);
+        std::thread::scope(|scope| {
+            for t in 0..8 {
+                let storage = &storage;
+                scope.spawn(move || {
+                    for i in 0..500u64 {
+                        let i = (i + t * 61) % 500;
+                        let blob = SharedBlob::from(i.to_le_bytes().to_vec());
+                        storage.put_blob(key(i), blob).unwrap();
+                        let stored = storage.get_blob(key(i)).unwrap().unwrap();
+                        assert_eq!(&stored[..], &i.to_le_bytes()[..]);
+                        if i % 5 == 0 {
+                            storage.delete_blob(key(i + 1000)).unwrap();
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(storage.len(), 500);
+        assert_eq!(storage.list_blobs().unwrap().len(), 500);
+        assert_eq!(storage.snapshot().0.len(), 500);
+    }
+
+    // Two threads storing the same object at once, however they interleave (with a delete
+    // of another object in the same shard), both find the one stored first.
+    #[cfg(loom)]
+    #[test]
+    fn loom_racing_puts_find_the_same_object() {
+        loom::model(|| {
+            let storage = Arc::new(MemoryStorage::default());
+            let (name, other) = ((1, 0, 0), (1 + SHARDS as u64, 0, 0));
+            storage.put_blob(other, SharedBlob::from(vec![0])).unwrap();
+            let threads: Vec<_> = [1, 2]
+                .map(|byte| {
+                    let storage = storage.clone();
+                    loom::thread::spawn(move || {
+                        storage
+                            .put_blob(name, SharedBlob::from(vec![byte]))
+                            .unwrap();
+                        storage.get_blob(name).unwrap().unwrap()
+                    })
+                })
+                .into();
+            storage.delete_blob(other).unwrap();
+            let found: Vec<_> = threads.into_iter().map(|x| x.join().unwrap()).collect();
+            assert_eq!(&found[0][..], &found[1][..]);
+            assert_eq!(&storage.get_blob(name).unwrap().unwrap()[..], &found[0][..]);
+            assert_eq!(storage.list_blobs().unwrap(), [name]);
+        });
+    }
+}
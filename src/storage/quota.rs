@@ -0,0 +1,166 @@
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::{Key, SharedBlob, Storage};
+use crate::fetch::Mismatch;
+use crate::packed::PackedHandle;
+use crate::{HANDLE_SIZE, Tree};
+
+// Holds another Storage to a number of bytes (a Blob's length, or a Tree's elements, packed):
+// a put of a new object that would go over fails with QuotaExceeded, and deletes give their
+// objects' bytes back. What the other already holds counts (so a Repository kept between
+// runs stays within it).
+pub(crate) struct Quota<S> {
+    inner: S,
+    limit: u64,
+    used: AtomicU64,
+}
+
+impl<S: Storage> Quota<S> {
+    pub(crate) fn new(inner: S, limit: u64) -> io::Result<Self> {
+        let mut used = 0;
+        for name in inner.list_blobs()? {
+            used += inner.get_blob(name)?.map_or(0, |x| x.len() as u64);
+        }
+        for name in inner.list_trees()? {
+            used += inner.get_tree(name)?.map_or(0, |x| tree_bytes(&x));
+        }
+        Ok(Quota {
+            inner,
+            limit,
+            used: AtomicU64::new(used),
+        })
+    }
+
+    fn charge(&self, bytes: u64) -> io::Result<()> {
+        let charged = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes).filter(|&x| x <= self.limit)
+            });
+        match charged {
+            Ok(_) => Ok(()),
+            Err(_) => Err(io::Error::new(
+                ErrorKind::QuotaExceeded,
+                "storage quota exceeded",
+            )),
+        }
+    }
+
+    fn refund(&self, bytes: u64) {
+        let _ = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used.saturating_sub(bytes))
+            });
+    }
+}
+
+fn tree_bytes(tree: &Tree<PackedHandle>) -> u64 {
+    (tree.len() * HANDLE_SIZE) as u64
+}
+
+impl<S: Storage> Storage for Quota<S> {
+    fn get_blob(&self, name: Key) -> io::Result<Option<SharedBlob>> {
+        self.inner.get_blob(name)
+    }
+
+    fn put_blob(&self, name: Key, blob: SharedBlob) -> io::Result<()> {
+        if self.inner.contains_blob(name)? {
+            return Ok(());
+        }
+        let bytes = blob.len() as u64;
+        self.charge(bytes)?;
+        self.inner
+            .put_blob(name, blob)
+            .inspect_err(|_| self.refund(bytes))
+    }
+
+    fn contains_blob(&self, name: Key) -> io::Result<bool> {
+        self.inner.contains_blob(name)
+    }
+
+    fn delete_blob(&self, name: Key) -> io::Result<()> {
+        if let Some(blob) = self.inner.get_blob(name)? {
+            self.inner.delete_blob(name)?;
+            self.refund(blob.len() as u64);
+        }
+        Ok(())
+    }
+
+    fn get_tree(&self, name: Key) -> io::Result<Option<Arc<Tree<PackedHandle>>>> {
+        self.inner.get_tree(name)
+    }
+
+    fn put_tree(&self, name: Key, tree: Arc<Tree<PackedHandle>>) -> io::Result<()> {
+        if self.inner.contains_tree(name)? {
+            return Ok(());
+        }
+        let bytes = tree_bytes(&tree);
+        self.charge(bytes)?;
+        self.inner
+            .put_tree(name, tree)
+            .inspect_err(|_| self.refund(bytes))
+    }
+
+    fn contains_tree(&self, name: Key) -> io::Result<bool> {
+        self.inner.contains_tree(name)
+    }
+
+    fn delete_tree(&self, name: Key) -> io::Result<()> {
+        if let Some(tree) = self.inner.get_tree(name)? {
+            self.inner.delete_tree(name)?;
+            self.refund(tree_bytes(&tree));
+        }
+        Ok(())
+    }
+
+    fn get_tree_range(
+        &self,
+        name: Key,
+        start: usize,
+        end: usize,
+    ) -> io::Result<Option<Vec<PackedHandle>>> {
+        self.inner.get_tree_range(name, start, end)
+    }
+
+    fn list_blobs(&self) -> io::Result<Vec<Key>> {
+        self.inner.list_blobs()
+    }
+
+    fn list_trees(&self) -> io::Result<Vec<Key>> {
+        self.inner.list_trees()
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn quarantine(&self, mismatch: &Mismatch) -> io::Result<()> {
+        self.inner.quarantine(mismatch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+
+    #[test]
+    fn puts_stop_at_the_quota() {
+        let quota = Quota::new(MemoryStorage::default(), 100).unwrap();
+        let blob = |byte| SharedBlob::from(vec![byte; 60]);
+        quota.put_blob((1, 0, 0), blob(1)).unwrap();
+        // (Putting it again costs nothing.)
+        quota.put_blob((1, 0, 0), blob(1)).unwrap();
+        let error = quota.put_blob((2, 0, 0), blob(2)).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::QuotaExceeded);
+        assert!(!quota.contains_blob((2, 0, 0)).unwrap());
+        quota.delete_blob((1, 0, 0)).unwrap();
+        quota.put_blob((2, 0, 0), blob(2)).unwrap();
+        // What's held already counts.
+        let quota = Quota::new(quota, 100).unwrap();
+        assert!(quota.put_blob((3, 0, 0), blob(3)).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::sync::Arc;
+
+use super::{Key, SharedBlob, Storage};
+use crate::Tree;
+use crate::fetch::Mismatch;
+use crate::packed::PackedHandle;
+
+// Storage composed of tiers, fastest first (e.g. memory, then a Repository, then a remote).
+// Loads try each tier in turn, and an object found in a slower tier is promoted into
+// (put in) every faster one. Puts are written through to every tier.
+pub(crate) struct TieredStorage {
+    tiers: Vec<Arc<dyn Storage>>,
+}
+
+impl TieredStorage {
+    pub(crate) fn new(tiers: Vec<Arc<dyn Storage>>) -> Self {
+        assert!(!tiers.is_empty(), "TieredStorage needs a tier");
+        TieredStorage { tiers }
+    }
+
+    // Find an object in the first tier that has it, promoting it into the tiers above.
+    fn get<T: Clone>(
+        &self,
+        get: impl Fn(&dyn Storage) -> io::Result<Option<T>>,
+        put: impl Fn(&dyn Storage, T) -> io::Result<()>,
+    ) -> io::Result<Option<T>> {
+        for (i, tier) in self.tiers.iter().enumerate() {
+            if let Some(object) = get(&**tier)? {
+                for faster in &self.tiers[..i] {
+                    put(&**faster, object.clone())?;
+                }
+                return Ok(Some(object));
+            }
+        }
+        Ok(None)
+    }
+
+    fn any(&self, contains: impl Fn(&dyn Storage) -> io::Result<bool>) -> io::Result<bool> {
+        for tier in &self.tiers {
+            if contains(&**tier)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn all(&self, f: impl Fn(&dyn Storage) -> io::Result<()>) -> io::Result<()> {
+        self.tiers.iter().try_for_each(|tier| f(&**tier))
+    }
+
+    fn union(&self, list: impl Fn(&dyn Storage) -> io::Result<Vec<Key>>) -> io::Result<Vec<Key>> {
+        let mut names = BTreeSet::new();
+        for tier in &self.tiers {
+            names.extend(list(&**tier)?);
+        }
+        Ok(names.into_iter().collect())
+    }
+}
+
+impl Storage for TieredStorage {
+    fn get_blob(&self, name: Key) -> io::Result<Option<SharedBlob>> {
+        self.get(|s| s.get_blob(name), |s, blob| s.put_blob(name, blob))
+    }
+
+    fn put_blob(&self, name: Key, blob: SharedBlob) -> io::Result<()> {
+        self.all(|s| s.put_blob(name, blob.clone()))
+    }
+
+    fn contains_blob(&self, name: Key) -> io::Result<bool> {
+        self.any(|s| s.contains_blob(name))
+    }
+
+    fn delete_blob(&self, name: Key) -> io::Result<()> {
+        self.all(|s| s.delete_blob(name))
+    }
+
+    fn get_tree(&self, name: Key) -> io::Result<Option<Arc<Tree<PackedHandle>>>> {
+        self.get(|s| s.get_tree(name), |s, tree| s.put_tree(name, tree))
+    }
+
+    fn put_tree(&self, name: Key, tree: Arc<Tree<PackedHandle>>) -> io::Result<()> {
+        self.all(|s| s.put_tree(name, tree.clone()))
+    }
+
+    fn contains_tree(&self, name: Key) -> io::Result<bool> {
+        self.any(|s| s.contains_tree(name))
+    }
+
+    fn delete_tree(&self, name: Key) -> io::Result<()> {
+        self.all(|s| s.delete_tree(name))
+    }
+
+    // Part of a Tree isn't promoted, since the tiers above can only hold whole objects.
+    fn get_tree_range(
+        &self,
+        name: Key,
+        start: usize,
+        end: usize,
+    ) -> io::Result<Option<Vec<PackedHandle>>> {
+        for tier in &self.tiers {
+            if let Some(elements) = tier.get_tree_range(name, start, end)? {
+                return Ok(Some(elements));
+            }
+        }
+        Ok(None)
+    }
+
+    fn list_blobs(&self) -> io::Result<Vec<Key>> {
+        self.union(|s| s.list_blobs())
+    }
+
+    fn list_trees(&self) -> io::Result<Vec<Key>> {
+        self.union(|s| s.list_trees())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.all(|s| s.flush())
+    }
+
+    fn quarantine(&self, mismatch: &Mismatch) -> io::Result<()> {
+        self.all(|s| s.quarantine(mismatch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+
+    fn tiers() -> (Arc<MemoryStorage>, Arc<MemoryStorage>, TieredStorage) {
+        let (fast, slow) = (Arc::new(MemoryStorage::default()), Arc::default());
+        let tiered = TieredStorage::new(vec![fast.clone(), Arc::clone(&slow) as _]);
+        (fast, slow, tiered)
+    }
+
+    #[test]
+    fn conforms() {
+        crate::storage::tests::conformance(&tiers().2);
+    }
+
+    #[test]
+    fn loads_promote_and_puts_write_through() {
+        let (fast, slow, tiered) = tiers();
+        slow.put_blob((1, 1, 1), vec![1; 100].into()).unwrap();
+        assert!(tiered.contains_blob((1, 1, 1)).unwrap());
+        assert!(!fast.contains_blob((1, 1, 1)).unwrap());
+        tiered.get_blob((1, 1, 1)).unwrap().unwrap();
+        assert!(fast.contains_blob((1, 1, 1)).unwrap());
+
+        tiered.put_blob((2, 2, 2), vec![2; 100].into()).unwrap();
+        assert!(fast.contains_blob((2, 2, 2)).unwrap() && slow.contains_blob((2, 2, 2)).unwrap());
+        fast.put_blob((3, 3, 3), vec![3; 100].into()).unwrap();
+        assert_eq!(
+            tiered.list_blobs().unwrap(),
+            [(1, 1, 1), (2, 2, 2), (3, 3, 3)]
+        );
+        tiered.delete_blob((1, 1, 1)).unwrap();
+        assert!(!fast.contains_blob((1, 1, 1)).unwrap() && !slow.contains_blob((1, 1, 1)).unwrap());
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::HashSet;
+use std::io::{self, ErrorKind};
+use std::sync::{Arc, Mutex};
+
+use super::{Key, SharedBlob, Storage, key, slice};
+use crate::fetch::Mismatch;
+use crate::hash::{hash_blob, hash_tree};
+use crate::packed::PackedHandle;
+use crate::{Handle, Tree, TreeName, chunk};
+
+// When to re-hash a loaded object against the Pointer it was loaded by.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Verification {
+    // Every load, also re-deriving the metadata (size, footprint and eq) each element of a
+    // loaded Tree records from the object it names, if that's stored. (This loads every
+    // element's object too, so it's for untrusted storage and hunting corruption.)
+    Paranoid,
+    Always,
+    // Once per object (per process); objects put through the store count as verified.
+    FirstLoad,
+    Never,
+}
+
+// A Storage that verifies the objects loaded from another, failing with InvalidData
+// (which loads turn into a corruption trap) when the contents don't match.
+pub(crate) struct Verified<S> {
+    inner: S,
+    verification: Verification,
+    // Blobs and Trees known to be intact (for FirstLoad).
+    verified: Mutex<HashSet<(bool, Key)>>,
+}
+
+impl<S: Storage> Verified<S> {
+    pub(crate) fn new(inner: S, verification: Verification) -> Self {
+        Verified {
+            inner,
+            verification,
+            verified: Mutex::default(),
+        }
+    }
+
+    // Does this object still need checking? (Marks it as checked if so.)
+    fn check(&self, tree: bool, name: Key) -> bool {
+        match self.verification {
+            Verification::Paranoid | Verification::Always => true,
+            Verification::FirstLoad => self.verified.lock().unwrap().insert((tree, name)),
+            Verification::Never => false,
+        }
+    }
+
+    fn trust(&self, tree: bool, name: Key) {
+        if self.verification == Verification::FirstLoad {
+            self.verified.lock().unwrap().insert((tree, name));
+        }
+    }
+
+    // Can this object be used without checking it (and without marking it as checked)?
+    fn trusted(&self, tree: bool, name: Key) -> bool {
+        match self.verification {
+            Verification::Paranoid | Verification::Always => false,
+            Verification::FirstLoad => self.verified.lock().unwrap().contains(&(tree, name)),
+            Verification::Never => true,
+        }
+    }
+
+    fn forget(&self, tree: bool, name: Key) {
+        self.verified.lock().unwrap().remove(&(tree, name));
+    }
+
+    // Does every element of a Tree record what its object's metadata is (as far as it's
+    // stored)?
+    fn consistent(&self, tree: &Tree<PackedHandle>) -> io::Result<bool> {
+        for h in tree {
+            if h.try_unpack().is_none() || !h.is_canonical() {
+                return Ok(false);
+            }
+            let Some(name) = h.key() else {
+                continue;
+            };
+            let consistent = match h.tree_metadata() {
+                Some(metadata) => match self.inner.get_tree(name)? {
+                    Some(x) => match x.iter().map(|x| x.try_unpack()).collect::<Option<Vec<_>>>() {
+                        Some(x) => TreeName::<Handle>::metadata(&x) == metadata,
+                        None => false,
+                    },
+                    None => true,
+                },
+                // (A chunked Blob's size is checked when its chunk list is parsed.)
+                None if chunk::is_chunked(h.size()) => true,
+                None => match self.inner.get_blob(name)? {
+                    Some(x) => x.len() == h.size(),
+                    None => true,
+                },
+            };
+            if !consistent {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        "loaded object does not match its Pointer",
+    )
+}
+
+impl<S: Storage> Storage for Verified<S> {
+    fn get_blob(&self, name: Key) -> io::Result<Option<SharedBlob>> {
+        let Some(blob) = self.inner.get_blob(name)? else {
+            return Ok(None);
+        };
+        if self.check(false, name) && key(hash_blob(&blob)) != name {
+            self.forget(false, name);
+            return Err(corrupt());
+        }
+        Ok(Some(blob))
+    }
+
+    fn put_blob(&self, name: Key, blob: SharedBlob) -> io::Result<()> {
+        self.inner.put_blob(name, blob)?;
+        self.trust(false, name);
+        Ok(())
+    }
+
+    fn contains_blob(&self, name: Key) -> io::Result<bool> {
+        self.inner.contains_blob(name)
+    }
+
+    fn delete_blob(&self, name: Key) -> io::Result<()> {
+        self.forget(false, name);
+        self.inner.delete_blob(name)
+    }
+
+    fn get_tree(&self, name: Key) -> io::Result<Option<Arc<Tree<PackedHandle>>>> {
+        let Some(tree) = self.inner.get_tree(name)? else {
+            return Ok(None);
+        };
+        let mismatched = self.check(true, name) && key(hash_tree::<()>(&tree)) != name;
+        if mismatched || self.verification == Verification::Paranoid && !self.consistent(&tree)? {
+            self.forget(true, name);
+            return Err(corrupt());
+        }
+        Ok(Some(tree))
+    }
+
+    fn put_tree(&self, name: Key, tree: Arc<Tree<PackedHandle>>) -> io::Result<()> {
+        self.inner.put_tree(name, tree)?;
+        self.trust(true, name);
+        Ok(())
+    }
+
+    fn contains_tree(&self, name: Key) -> io::Result<bool> {
+        self.inner.contains_tree(name)
+    }
+
+    fn delete_tree(&self, name: Key) -> io::Result<()> {
+        self.forget(true, name);
+        self.inner.delete_tree(name)
+    }
+
+    // Part of a Tree can't be checked on its own, so an untrusted Tree is loaded whole.
+    fn get_tree_range(
+        &self,
+        name: Key,
+        start: usize,
+        end: usize,
+    ) -> io::Result<Option<Vec<PackedHandle>>> {
+        if self.trusted(true, name) {
+            return self.inner.get_tree_range(name, start, end);
+        }
+        self.get_tree(name)?
+            .map(|tree| slice(&tree, start, end))
+            .transpose()
+    }
+
+    fn list_blobs(&self) -> io::Result<Vec<Key>> {
+        self.inner.list_blobs()
+    }
+
+    fn list_trees(&self) -> io::Result<Vec<Key>> {
+        self.inner.list_trees()
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn quarantine(&self, mismatch: &Mismatch) -> io::Result<()> {
+        self.inner.quarantine(mismatch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+
+    // A Storage holding an intact Blob at `intact` and a corrupt one at `corrupt`.
+    fn inner() -> (MemoryStorage, Key, Key) {
+        let storage = MemoryStorage::default();
+        let intact = key(hash_blob(&[1; 100]));
+        storage.put_blob(intact, vec![1; 100].into()).unwrap();
+        storage.put_blob((1, 2, 3), vec![2; 100].into()).unwrap();
+        (storage, intact, (1, 2, 3))
+    }
+
+    #[test]
+    fn conforms() {
+        let storage = Verified::new(MemoryStorage::default(), Verification::FirstLoad);
+        crate::storage::tests::conformance(&storage);
+    }
+
+    #[test]
+    fn corrupt_objects_fail_to_load() {
+        for verification in [Verification::Always, Verification::FirstLoad] {
+            let (inner, intact, corrupt) = inner();
+            let storage = Verified::new(inner, verification);
+            assert!(storage.get_blob(intact).unwrap().is_some());
+            for _ in 0..2 {
+                let error = storage.get_blob(corrupt).err().unwrap();
+                assert_eq!(error.kind(), ErrorKind::InvalidData);
+            }
+        }
+        let (inner, _, corrupt) = inner();
+        let storage = Verified::new(inner, Verification::Never);
+        assert!(storage.get_blob(corrupt).unwrap().is_some());
+    }
+
+    #[test]
+    fn only_untrusted_trees_load_whole() {
+        let tree: Arc<Tree<PackedHandle>> = (0..4u8)
+            .map(|i| PackedHandle::from_bytes([i; crate::HANDLE_SIZE]))
+            .collect();
+        let inner = MemoryStorage::default();
+        inner.put_tree((4, 5, 6), tree.clone()).unwrap();
+        let storage = Verified::new(inner, Verification::FirstLoad);
+        // A range of a corrupt Tree fails, as it's checked whole.
+        assert!(storage.get_tree_range((4, 5, 6), 0, 1).is_err());
+        let name = key(hash_tree::<()>(&tree));
+        storage.put_tree(name, tree.clone()).unwrap();
+        assert!(storage.get_tree_range(name, 1, 2).unwrap().unwrap() == tree[1..2]);
+    }
+
+    #[test]
+    fn paranoid_loads_check_what_elements_record() {
+        let inner = Arc::new(MemoryStorage::default());
+        let contents = vec![7; 100];
+        let blob = crate::BlobName::create(contents.clone()).ok().unwrap();
+        let element =
+            PackedHandle::pack(Handle::Data(crate::Data::Object(crate::Object::Blob(blob))));
+        inner
+            .put_blob(element.key().unwrap(), contents.into())
+            .unwrap();
+        // The same Blob, recorded as 50 bytes long.
+        let mut bytes = *element.as_bytes();
+        bytes[24] = 50;
+        let forged = PackedHandle::from_bytes(bytes);
+        assert_eq!(forged.size(), 50);
+        let tree = |element| {
+            let tree: Arc<Tree<PackedHandle>> = Arc::from(vec![element]);
+            let name = key(hash_tree::<()>(&tree));
+            inner.put_tree(name, tree).unwrap();
+            name
+        };
+        let (intact, forged) = (tree(element), tree(forged));
+        let always = Verified::new(inner.clone(), Verification::Always);
+        assert!(always.get_tree(forged).unwrap().is_some());
+        let paranoid = Verified::new(inner, Verification::Paranoid);
+        assert!(paranoid.get_tree(intact).unwrap().is_some());
+        let error = paranoid.get_tree(forged).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
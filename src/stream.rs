@@ -0,0 +1,243 @@
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+
+use crate::chunk::{self, MAX_CHUNK};
+use crate::packed::PackedHandle;
+use crate::storage::{BlobData, SharedBlob};
+use crate::{BlobName, local};
+
+// Creates a Blob from a stream of writes, without holding all of it in memory: once the
+// Blob is too large to be anything but chunked, chunks are stored as their boundaries are
+// known, a batch at a time (so they're hashed in parallel, and at most BATCH + MAX_CHUNK
+// bytes, plus a write, are buffered).
+// The Name is the same as BlobName::create would give the whole Blob.
+#[derive(Default)]
+pub(crate) struct BlobWriter {
+    buffer: Vec<u8>,
+    // The chunks stored so far, once the Blob is known to be chunked.
+    chunks: Option<Vec<PackedHandle>>,
+    size: usize,
+}
+
+// How many bytes of chunks are stored at once.
+const BATCH: usize = 16 * MAX_CHUNK;
+
+impl BlobWriter {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    // Store the buffered chunks whose boundaries are known (or all of them, at the end).
+    fn spill(&mut self, all: bool) -> io::Result<()> {
+        let mut batch = Vec::new();
+        let mut start = 0;
+        while self.buffer.len() - start >= MAX_CHUNK || (all && start < self.buffer.len()) {
+            let length = chunk::cut(&self.buffer[start..]);
+            batch.push(&self.buffer[start..start + length]);
+            start += length;
+        }
+        let stored = chunk::put_chunks(&batch)?;
+        self.chunks.get_or_insert_with(Vec::new).extend(stored);
+        self.buffer.drain(..start);
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<BlobName> {
+        if self.chunks.is_none() {
+            if let Some(literal) = BlobName::literal(&self.buffer) {
+                return Ok(literal);
+            }
+            let pointer = chunk::put(std::mem::take(&mut self.buffer).into())?;
+            return Ok(BlobName::Name((pointer, self.size)));
+        }
+        self.spill(true)?;
+        let pointer = chunk::put_list(self.chunks.take().unwrap())?;
+        Ok(BlobName::Name((pointer, self.size)))
+    }
+}
+
+impl Write for BlobWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        self.size += buf.len();
+        if chunk::is_chunked(self.size) && (self.chunks.is_none() || self.buffer.len() >= BATCH) {
+            self.spill(false)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Reads a Blob's contents incrementally. Only the chunks of a chunked Blob that are
+// actually read are loaded (one at a time); other Blobs are loaded (or mapped) whole.
+pub(crate) struct BlobReader<'a> {
+    source: Source<'a>,
+    size: usize,
+    position: u64,
+}
+
+enum Source<'a> {
+    Whole(BlobData<'a>),
+    Chunked {
+        // Each chunk with its offset in the Blob.
+        chunks: Vec<(usize, BlobName)>,
+        current: Option<(usize, SharedBlob)>,
+    },
+}
+
+fn missing() -> io::Error {
+    io::Error::new(ErrorKind::NotFound, "Blob missing from storage")
+}
+
+impl<'a> BlobReader<'a> {
+    pub(crate) fn new(name: &'a BlobName) -> io::Result<Self> {
+        let size = name.size();
+        let source = match name {
+            BlobName::Name((pointer, _))
+                if chunk::is_chunked(size) && !local::is_local(*pointer) =>
+            {
+                let mut offset = 0;
+                let chunks = chunk::list(*pointer, size)?
+                    .ok_or_else(missing)?
+                    .into_iter()
+                    .map(|chunk| {
+                        offset += chunk.size();
+                        (offset - chunk.size(), chunk)
+                    })
+                    .collect();
+                Source::Chunked {
+                    chunks,
+                    current: None,
+                }
+            }
+            BlobName::Name((pointer, _)) => Source::Whole(BlobData::Stored(
+                chunk::get(*pointer, size)?.ok_or_else(missing)?,
+            )),
+            BlobName::Literal(_) => Source::Whole(name.try_load()?),
+        };
+        Ok(BlobReader {
+            source,
+            size,
+            position: 0,
+        })
+    }
+}
+
+impl Read for BlobReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.size as u64 {
+            return Ok(0);
+        }
+        let position = self.position as usize;
+        let available = match &mut self.source {
+            Source::Whole(blob) => &blob[position..],
+            Source::Chunked { chunks, current } => {
+                let index = chunks.partition_point(|&(offset, _)| offset <= position) - 1;
+                if current.as_ref().is_none_or(|&(i, _)| i != index) {
+                    let chunk = chunk::get_chunk(&chunks[index].1)?.ok_or_else(missing)?;
+                    *current = Some((index, chunk));
+                }
+                &current.as_ref().unwrap().1[position - chunks[index].0..]
+            }
+        };
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for BlobReader<'_> {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        let position = match to {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::End(x) => (self.size as u64).checked_add_signed(x),
+            SeekFrom::Current(x) => self.position.checked_add_signed(x),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::CHUNK_THRESHOLD;
+    use crate::{Data, Handle, Object};
+
+    // Pseudo-random contents (so a chunked Blob has chunk boundaries).
+    fn contents(len: usize) -> Vec<u8> {
+        let mut state = len as u64;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn packed(name: BlobName) -> PackedHandle {
+        PackedHandle::pack(Handle::Data(Data::Object(Object::Blob(name))))
+    }
+
+    #[test]
+    fn written_blobs_are_named_as_created() {
+        for len in [
+            0,
+            20,
+            5000,
+            CHUNK_THRESHOLD + 3 * MAX_CHUNK,
+            2 * BATCH + 5000,
+        ] {
+            let blob = contents(len);
+            let mut writer = BlobWriter::new();
+            for piece in blob.chunks(1000) {
+                writer.write_all(piece).unwrap();
+            }
+            let written = writer.finish().unwrap();
+            let created = BlobName::create(blob).ok().unwrap();
+            assert!(packed(written) == packed(created), "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn reads_and_seeks_across_chunks() {
+        let blob = contents(CHUNK_THRESHOLD + 3 * MAX_CHUNK);
+        let name = BlobName::create(blob.clone()).ok().unwrap();
+        let mut reader = BlobReader::new(&name).unwrap();
+        assert!(matches!(reader.source, Source::Chunked { .. }));
+        let mut all = Vec::new();
+        reader.read_to_end(&mut all).unwrap();
+        assert!(all == blob);
+
+        let start = blob.len() / 2 - 10;
+        reader.seek(SeekFrom::Start(start as u64)).unwrap();
+        let mut middle = vec![0; 3 * MAX_CHUNK / 2];
+        reader.read_exact(&mut middle).unwrap();
+        assert!(middle == blob[start..start + middle.len()]);
+        reader.seek(SeekFrom::End(-10)).unwrap();
+        let mut end = Vec::new();
+        reader.read_to_end(&mut end).unwrap();
+        assert!(end == blob[blob.len() - 10..]);
+        let error = reader.seek(SeekFrom::Current(-(blob.len() as i64) - 1));
+        assert_eq!(error.unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn reads_whole_blobs() {
+        for blob in [contents(20), contents(5000)] {
+            let name = BlobName::create(blob.clone()).ok().unwrap();
+            let mut reader = BlobReader::new(&name).unwrap();
+            let mut all = Vec::new();
+            reader.read_to_end(&mut all).unwrap();
+            assert!(all == blob);
+            assert_eq!(reader.read(&mut [0; 8]).unwrap(), 0);
+        }
+    }
+}
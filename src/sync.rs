@@ -0,0 +1,14 @@
+// The locks and atomics the scheduler, the memo table and MemoryStorage synchronize with:
+// std's, or, built with `--cfg loom`, loom's, so the tests named `loom_` can explore every
+// interleaving of the protocols they model, e.g.
+//   RUSTFLAGS="--cfg loom" cargo test --release --bin fixmodel loom_
+// (Only those tests run under loom: the process-wide tables, which every other test uses,
+// outlive any one of loom's executions.)
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::AtomicUsize;
+#[cfg(loom)]
+pub(crate) use loom::sync::{Condvar, Mutex, RwLock};
+#[cfg(not(loom))]
+pub(crate) use std::sync::atomic::AtomicUsize;
+#[cfg(not(loom))]
+pub(crate) use std::sync::{Condvar, Mutex, RwLock};
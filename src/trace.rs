@@ -0,0 +1,448 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, Instant};
+
+use crate::hooks::{Hooks, Usage};
+use crate::packed::PackedHandle;
+use crate::{
+    BlobName, Context, Data, HANDLE_SIZE, Handle, HandleType, Object, Ref, Result, RuntimeValue,
+    Thunk, TreeName, Value, apply, local, select, think, trap, types,
+};
+
+// A Trace records every step of an evaluation as Fix data, for auditing and replay (see
+// replay, below).
+//
+// It's a set of Hooks: in an evaluation's Context, it appends an entry for each think,
+// apply and select step, a Tree of
+//   0  the step: "think", "apply" or "select"
+//   1  its input: the Thunk thought about, the evaluated combination, or the selection spec
+//   2  its output: a Tree of "ok" and the RuntimeValue produced, or of "trap" and the trap
+//   3  the resources used: a Blob of two u64s (little-endian), the wall-clock time in
+//      nanoseconds and the fuel consumed (see Usage)
+//   4  (only for an apply) the stored objects it loaded: a Tree of Refs to them, in the
+//      order they were first loaded (see prefetch::Learned, which learns from them)
+//
+// A think that traps is recorded with its trap, as is every think it was a part of (the
+// think whose combination was being evaluated, and so on).
+//
+// The entries are sorted by their step, input and output (their canonical Names), so the
+// same evaluation gives the same trace however its Encodes were scheduled, apart from the
+// resources used.
+//
+// How it was scheduled is kept aside (in memory, not in the entries): a span for each step,
+// on the worker thread that did it, and (while the Trace records loads, see record_loads)
+// for each object loaded. They can be exported in Chrome's trace-event format (see
+// chrome), to be seen in chrome://tracing or Perfetto.
+pub(crate) struct Trace {
+    // (Each entry is kept with its elements, to sort by.)
+    entries: Mutex<Vec<(Vec<Handle>, Handle)>>,
+    spans: Mutex<Vec<Span>>,
+    started: Instant,
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Trace {
+            entries: Mutex::default(),
+            spans: Mutex::default(),
+            started: Instant::now(),
+        }
+    }
+}
+
+// When a step (or a load) ran, and where.
+struct Span {
+    name: &'static str,
+    worker: usize,
+    // Since the Trace was created.
+    start: Duration,
+    duration: Duration,
+    // The fuel an apply consumed, or the bytes a load loaded.
+    amount: Option<(&'static str, u64)>,
+}
+
+// The worker running the current thread: threads are numbered as they first do something
+// traced.
+fn worker() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static WORKER: usize = NEXT.fetch_add(1, Ordering::Relaxed);
+    }
+    WORKER.with(|x| *x)
+}
+
+// The Traces recording loads (and how many there are, to check without locking).
+static RECORDING: RwLock<Vec<Weak<Trace>>> = RwLock::new(Vec::new());
+static RECORDERS: AtomicUsize = AtomicUsize::new(0);
+
+// Records loads in a Trace until dropped.
+pub(crate) struct Recording(Weak<Trace>);
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        RECORDING
+            .write()
+            .unwrap()
+            .retain(|x| !Weak::ptr_eq(x, &self.0));
+        RECORDERS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// When a load started, if any Trace is recording loads.
+pub(crate) fn loading() -> Option<Instant> {
+    (RECORDERS.load(Ordering::Relaxed) > 0).then(Instant::now)
+}
+
+// A load that started (see loading) has loaded `bytes` of a Blob or a Tree.
+pub(crate) fn loaded(started: Option<Instant>, what: &'static str, bytes: usize) {
+    let Some(started) = started else {
+        return;
+    };
+    let duration = started.elapsed();
+    for trace in RECORDING.read().unwrap().iter().filter_map(Weak::upgrade) {
+        trace.span(what, started, duration, Some(("bytes", bytes as u64)));
+    }
+}
+
+// The stored objects loaded by the apply running on this thread, while a Trace records it.
+thread_local! {
+    static APPLYING: RefCell<Option<Vec<Ref>>> = const { RefCell::new(None) };
+}
+
+// An object has been loaded (local ones aren't recorded: there's nothing to fetch).
+pub(crate) fn loaded_object(x: Ref) {
+    let stored = match x {
+        Ref::Blob(BlobName::Name((pointer, _))) => !local::is_local(pointer),
+        Ref::Blob(BlobName::Literal(_)) => false,
+        Ref::Tree(x) => !local::is_local(x.name),
+    };
+    if stored {
+        APPLYING.with_borrow_mut(|loads| loads.as_mut().map(|loads| loads.push(x)));
+    }
+}
+
+pub(crate) const THINK: &[u8] = b"think";
+pub(crate) const APPLY: &[u8] = b"apply";
+pub(crate) const SELECT: &[u8] = b"select";
+pub(crate) const OK: &[u8] = b"ok";
+pub(crate) const TRAP: &[u8] = b"trap";
+
+fn blob(x: &[u8]) -> Handle {
+    Handle::Data(Data::Object(Object::Blob(local::blob(x.to_vec()))))
+}
+
+fn tree(elements: Vec<Handle>) -> Handle {
+    Handle::Data(Data::Object(Object::Tree(local::tree(elements))))
+}
+
+pub(crate) fn output(result: &Result<RuntimeValue>) -> Handle {
+    tree(match *result {
+        Ok(RuntimeValue::Data(x)) => vec![blob(OK), Handle::Data(x)],
+        Ok(RuntimeValue::Thunk(x)) => vec![blob(OK), Handle::Thunk(x)],
+        Err(trap) => vec![blob(TRAP), Handle::Data(trap)],
+    })
+}
+
+fn usage(usage: Usage) -> Handle {
+    let mut bytes = (usage.time.as_nanos() as u64).to_le_bytes().to_vec();
+    bytes.extend_from_slice(&usage.fuel.to_le_bytes());
+    blob(&bytes)
+}
+
+// A Handle's canonical Name (entries are made of local objects, see local).
+fn name(h: Handle) -> [u8; HANDLE_SIZE] {
+    *local::canonical_name(PackedHandle::pack(h)).as_bytes()
+}
+
+// What entries are sorted by: the canonical Names of an entry's step, input and output.
+fn step(entry: &(Vec<Handle>, Handle)) -> Vec<[u8; HANDLE_SIZE]> {
+    entry.0[..3].iter().map(|&h| name(h)).collect()
+}
+
+// The distinct Refs loaded, in the order first loaded.
+fn loads(loaded: Vec<Ref>) -> Handle {
+    let mut seen = std::collections::HashSet::new();
+    let refs = loaded
+        .into_iter()
+        .map(|x| Handle::Data(Data::Ref(x)))
+        .filter(|&h| seen.insert(name(h)));
+    tree(refs.collect())
+}
+
+impl Trace {
+    fn record(&self, step: &'static [u8], input: Handle, output: Handle, used: Usage) {
+        self.record_entry(step, vec![blob(step), input, output, usage(used)], used);
+    }
+
+    fn record_entry(&self, step: &'static [u8], entry: Vec<Handle>, used: Usage) {
+        let h = tree(entry.clone());
+        self.entries.lock().unwrap().push((entry, h));
+        let name = std::str::from_utf8(step).unwrap();
+        let fuel = (step == APPLY).then_some(("fuel", used.fuel));
+        self.span(name, Instant::now() - used.time, used.time, fuel);
+    }
+
+    fn span(
+        &self,
+        name: &'static str,
+        started: Instant,
+        duration: Duration,
+        amount: Option<(&'static str, u64)>,
+    ) {
+        self.spans.lock().unwrap().push(Span {
+            name,
+            worker: worker(),
+            start: started.saturating_duration_since(self.started),
+            duration,
+            amount,
+        });
+    }
+
+    // Record the loads any thread makes (not only the evaluation's) until the Recording
+    // is dropped.
+    pub(crate) fn record_loads(self: &Arc<Self>) -> Recording {
+        RECORDING.write().unwrap().push(Arc::downgrade(self));
+        RECORDERS.fetch_add(1, Ordering::Relaxed);
+        Recording(Arc::downgrade(self))
+    }
+
+    // Write the spans in Chrome's trace-event format: a complete event for each, on a
+    // track (thread) per worker.
+    pub(crate) fn chrome(&self, mut out: impl Write) -> io::Result<()> {
+        let spans = self.spans.lock().unwrap();
+        let mut workers: Vec<_> = spans.iter().map(|x| x.worker).collect();
+        workers.sort_unstable();
+        workers.dedup();
+        let micros = |x: Duration| x.as_nanos() as f64 / 1000.0;
+        write!(out, "{{\"traceEvents\":[")?;
+        let mut first = true;
+        let mut separator = |out: &mut dyn Write| {
+            let comma = if first { "" } else { "," };
+            first = false;
+            writeln!(out, "{comma}")
+        };
+        for worker in workers {
+            separator(&mut out)?;
+            write!(
+                out,
+                "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{worker},\"args\":{{\"name\":\"worker {worker}\"}}}}"
+            )?;
+        }
+        for span in spans.iter() {
+            separator(&mut out)?;
+            write!(
+                out,
+                "{{\"name\":\"{}\",\"cat\":\"fix\",\"ph\":\"X\",\"pid\":1,\"tid\":{},\"ts\":{:.3},\"dur\":{:.3}",
+                span.name,
+                span.worker,
+                micros(span.start),
+                micros(span.duration)
+            )?;
+            match span.amount {
+                Some((what, n)) => write!(out, ",\"args\":{{\"{what}\":{n}}}}}")?,
+                None => write!(out, "}}")?,
+            }
+        }
+        writeln!(out, "\n]}}")
+    }
+
+    // The entries so far, as a Tree (stored, with the entries).
+    pub(crate) fn tree(&self) -> Result<TreeName> {
+        let mut entries: Vec<_> = self.entries.lock().unwrap().clone();
+        entries.sort_by_cached_key(step);
+        TreeName::create(entries.into_iter().map(|(_, entry)| entry).collect())
+    }
+}
+
+impl Hooks for Trace {
+    fn on_thought(&self, thunk: Thunk, thought: &Result<RuntimeValue>, used: Usage) {
+        self.record(THINK, Handle::Thunk(thunk), output(thought), used);
+    }
+
+    // (A procedure runs on the thread that applies it, so that's where its loads are.)
+    fn on_apply_start(&self, _combination: TreeName<Value>) {
+        APPLYING.set(Some(Vec::new()));
+    }
+
+    fn on_apply_finish(
+        &self,
+        combination: TreeName<Value>,
+        result: &Result<RuntimeValue>,
+        used: Usage,
+    ) {
+        let combination = Value::Data(Data::Object(Object::Tree(combination))).relax();
+        let loaded = loads(APPLYING.take().unwrap_or_default());
+        let entry = vec![
+            blob(APPLY),
+            combination,
+            output(result),
+            usage(used),
+            loaded,
+        ];
+        self.record_entry(APPLY, entry, used);
+    }
+
+    fn on_select_finish(&self, spec: TreeName, result: &Result<RuntimeValue>, used: Usage) {
+        let spec = Handle::Data(Data::Object(Object::Tree(spec)));
+        self.record(SELECT, spec, output(result), used);
+    }
+}
+
+// Where a replay first produced something other than what its trace recorded.
+pub(crate) struct Divergence {
+    // The entry's index in the trace, and its step ("think", "apply" or "select").
+    pub(crate) index: usize,
+    pub(crate) step: &'static str,
+    // The recorded output and the replayed one (see output).
+    pub(crate) expected: Handle,
+    pub(crate) actual: Handle,
+}
+
+fn malformed() -> Data {
+    trap::type_error("malformed trace entry")
+}
+
+// Replay a trace: perform each step again, in order, and check that it produces the same
+// output (or the same trap). Returns the first step where it doesn't, if any. A Trace
+// records a replay like anything else, so one in `context` gets a trace of the replay.
+pub(crate) fn replay(trace: TreeName, context: &Context) -> Result<Option<Divergence>> {
+    // (A page at a time, as a trace can be long.)
+    for (index, entry) in trace.try_iter().enumerate() {
+        let Handle::Data(Data::Object(Object::Tree(entry))) = entry? else {
+            return Err(malformed());
+        };
+        let entry = entry.try_load()?;
+        let (Some(Handle::Data(Data::Object(Object::Blob(kind)))), Some(&input), Some(&expected)) =
+            (entry.first(), entry.get(1), entry.get(2))
+        else {
+            return Err(malformed());
+        };
+        let kind = kind.try_load()?;
+        let (step, result) = match (&*kind, input) {
+            (THINK, Handle::Thunk(thunk)) => ("think", think(thunk, context)),
+            (APPLY, Handle::Data(Data::Object(Object::Tree(combination)))) => {
+                ("apply", apply(types::value(combination)?, context))
+            }
+            (SELECT, Handle::Data(Data::Object(Object::Tree(spec)))) => {
+                ("select", select(spec, context))
+            }
+            _ => return Err(malformed()),
+        };
+        let actual = output(&result);
+        if name(actual) != name(expected) {
+            return Ok(Some(Divergence {
+                index,
+                step,
+                expected,
+                actual,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::path::Path;
+    use crate::{BlobName, Encode, eval};
+
+    fn target(seed: &[u8]) -> Data {
+        let data = Data::Object(Object::Blob(BlobName::create(seed.to_vec()).ok().unwrap()));
+        let tree = TreeName::create(vec![Handle::Thunk(Thunk::Identification(data))]);
+        Data::Object(Object::Tree(tree.ok().unwrap()))
+    }
+
+    fn traced(thunk: Thunk) -> (Arc<Trace>, Result<Value>) {
+        let trace = Arc::new(Trace::default());
+        let context = Context {
+            hooks: trace.clone(),
+            ..Default::default()
+        };
+        let encode = Encode {
+            thunk,
+            accessibility: None,
+        };
+        let result = eval(Handle::Encode(encode), &context);
+        (trace, result)
+    }
+
+    fn entries(trace: &Trace) -> Vec<Vec<Handle>> {
+        let entries = trace.tree().ok().unwrap().try_load().ok().unwrap();
+        entries
+            .into_iter()
+            .map(|entry| match entry {
+                Handle::Data(Data::Object(Object::Tree(x))) => x.try_load().ok().unwrap(),
+                _ => panic!("entry is not a Tree"),
+            })
+            .collect()
+    }
+
+    fn is(h: Handle, contents: &[u8]) -> bool {
+        matches!(h, Handle::Data(Data::Object(Object::Blob(x))) if &*x.try_load().ok().unwrap() == contents)
+    }
+
+    #[test]
+    fn every_step_is_recorded_with_its_usage_and_replays() {
+        let thunk = Path::new().index(0).thunk(target(b"traced")).ok().unwrap();
+        let (trace, result) = traced(thunk);
+        assert!(result.is_ok());
+        let entries = entries(&trace);
+        assert_eq!(entries.iter().filter(|x| is(x[0], THINK)).count(), 2);
+        assert_eq!(entries.iter().filter(|x| is(x[0], SELECT)).count(), 1);
+        assert!(entries.iter().all(|x| x.len() == 4));
+        let trace = trace.tree().ok().unwrap();
+        assert!(replay(trace, &Context::default()).ok().unwrap().is_none());
+    }
+
+    #[test]
+    fn a_think_that_traps_is_recorded() {
+        let thunk = Path::new().index(5).thunk(target(b"trapped")).ok().unwrap();
+        let (trace, result) = traced(thunk);
+        let trap = result.err().unwrap();
+        let entries = entries(&trace);
+        let think = entries.iter().find(|x| is(x[0], THINK)).unwrap();
+        assert_eq!(name(think[2]), name(output(&Err(trap))));
+    }
+
+    #[test]
+    fn exports_steps_and_loads_per_worker() {
+        let thunk = Path::new()
+            .index(0)
+            .thunk(target(b"exported"))
+            .ok()
+            .unwrap();
+        let trace = Arc::new(Trace::default());
+        let recording = trace.record_loads();
+        let context = Context {
+            hooks: trace.clone(),
+            ..Default::default()
+        };
+        let encode = Encode {
+            thunk,
+            accessibility: None,
+        };
+        assert!(eval(Handle::Encode(encode), &context).is_ok());
+        drop(recording);
+        let mut out = Vec::new();
+        trace.chrome(&mut out).unwrap();
+        let chrome: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let events = chrome["traceEvents"].as_array().unwrap();
+        let named = |name: &str| events.iter().filter(|x| x["name"] == name).count();
+        assert_eq!(named("think"), 2);
+        assert_eq!(named("select"), 1);
+        assert!(named("load Tree") >= 1);
+        // Every span is on a named worker's track.
+        for span in events.iter().filter(|x| x["ph"] == "X") {
+            assert!(
+                events
+                    .iter()
+                    .any(|x| x["name"] == "thread_name" && x["tid"] == span["tid"])
+            );
+        }
+    }
+}
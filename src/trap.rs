@@ -0,0 +1,272 @@
+use std::io;
+use std::time::Duration;
+
+use crate::{Data, Handle, Object, local};
+
+// Traps, as Data: a Tree of
+//   0   the kind, a Blob naming one of the Kinds (e.g. "type-error")
+//   1   a message for people, a Blob (of any length)
+//   2.. details, which depend on the kind (e.g. the limit a computation exceeded)
+// so a trap can be told apart by its kind without parsing its message.
+//
+// A trap is made of local objects (see local), so making one never touches the Storage: it
+// can report the Storage failing.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Kind {
+    // A procedure tried to use more memory than its limit (details: the limit, in pages).
+    OutOfMemory,
+    // A computation exceeded another limit (details: the resource, e.g. "fuel" or "steps",
+    // and the limit, a u64).
+    ResourceExhausted,
+    // An Encode took longer than its timeout (details: the timeout, in milliseconds).
+    TimedOut,
+    // The evaluation was cancelled.
+    Cancelled,
+    // An Encode's thoughts came back to one it already had (so it would never finish).
+    Cycle,
+    // A combination that can't be applied (e.g. malformed resource limits, or no procedure).
+    BadCombination,
+    // A selection whose spec is malformed, or out of range.
+    BadSelection,
+    // A Handle of the wrong type (e.g. an Encode where a Value is needed).
+    TypeError,
+    // An object whose contents don't match its canonical Pointer.
+    Corruption,
+    // An object named by a Pointer that isn't in the Storage (details: "Blob" or "Tree").
+    Missing,
+    // The Storage failed (e.g. an I/O error), so an object couldn't be read or written.
+    StorageFailed,
+    // A procedure failed while running (e.g. a Wasm trap).
+    ProcedureFailed,
+    // An operation this build can't perform (e.g. applying a procedure, without a runtime).
+    Unsupported,
+    // Anything else, with details of its own.
+    UserDefined,
+}
+
+const KINDS: [Kind; 14] = [
+    Kind::OutOfMemory,
+    Kind::ResourceExhausted,
+    Kind::TimedOut,
+    Kind::Cancelled,
+    Kind::Cycle,
+    Kind::BadCombination,
+    Kind::BadSelection,
+    Kind::TypeError,
+    Kind::Corruption,
+    Kind::Missing,
+    Kind::StorageFailed,
+    Kind::ProcedureFailed,
+    Kind::Unsupported,
+    Kind::UserDefined,
+];
+
+impl Kind {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Kind::OutOfMemory => "out-of-memory",
+            Kind::ResourceExhausted => "resource-exhausted",
+            Kind::TimedOut => "timed-out",
+            Kind::Cancelled => "cancelled",
+            Kind::Cycle => "cycle",
+            Kind::BadCombination => "bad-combination",
+            Kind::BadSelection => "bad-selection",
+            Kind::TypeError => "type-error",
+            Kind::Corruption => "corruption",
+            Kind::Missing => "missing",
+            Kind::StorageFailed => "storage-failed",
+            Kind::ProcedureFailed => "procedure-failed",
+            Kind::Unsupported => "unsupported",
+            Kind::UserDefined => "user-defined",
+        }
+    }
+
+    fn from_name(name: &[u8]) -> Option<Kind> {
+        KINDS.into_iter().find(|x| x.name().as_bytes() == name)
+    }
+}
+
+fn blob(x: &[u8]) -> Handle {
+    Handle::Data(Data::Object(Object::Blob(local::blob(x.to_vec()))))
+}
+
+// A trap of a kind, with a message and details.
+pub(crate) fn new(kind: Kind, message: &str, details: &[Handle]) -> Data {
+    let mut elements = vec![blob(kind.name().as_bytes()), blob(message.as_bytes())];
+    elements.extend_from_slice(details);
+    Data::Object(Object::Tree(local::tree(elements)))
+}
+
+#[cfg(feature = "wasm")]
+pub(crate) fn out_of_memory(limit: u64) -> Data {
+    new(
+        Kind::OutOfMemory,
+        "memory limit exceeded",
+        &[blob(&limit.to_le_bytes())],
+    )
+}
+
+pub(crate) fn resource_exhausted(resource: &str, limit: u64) -> Data {
+    new(
+        Kind::ResourceExhausted,
+        &format!("{resource} limit exceeded"),
+        &[blob(resource.as_bytes()), blob(&limit.to_le_bytes())],
+    )
+}
+
+pub(crate) fn timed_out(timeout: Duration) -> Data {
+    let millis = timeout.as_millis() as u64;
+    new(Kind::TimedOut, "timed out", &[blob(&millis.to_le_bytes())])
+}
+
+pub(crate) fn cancelled() -> Data {
+    new(Kind::Cancelled, "cancelled", &[])
+}
+
+pub(crate) fn cycle_detected() -> Data {
+    new(Kind::Cycle, "cycle detected", &[])
+}
+
+#[cfg(feature = "wasm")]
+pub(crate) fn bad_combination(message: &str) -> Data {
+    new(Kind::BadCombination, message, &[])
+}
+
+pub(crate) fn bad_selection(message: &str) -> Data {
+    new(Kind::BadSelection, message, &[])
+}
+
+pub(crate) fn type_error(message: &str) -> Data {
+    new(Kind::TypeError, message, &[])
+}
+
+pub(crate) fn corruption() -> Data {
+    new(Kind::Corruption, "corrupt object", &[])
+}
+
+pub(crate) fn missing(what: &str) -> Data {
+    new(
+        Kind::Missing,
+        &format!("{what} missing from storage"),
+        &[blob(what.as_bytes())],
+    )
+}
+
+pub(crate) fn storage_failed(error: &io::Error) -> Data {
+    new(Kind::StorageFailed, &format!("storage error: {error}"), &[])
+}
+
+#[cfg(feature = "wasm")]
+pub(crate) fn procedure_failed(message: &str) -> Data {
+    new(Kind::ProcedureFailed, message, &[])
+}
+
+// (Only raised without the wasm feature, which applies procedures.)
+#[cfg(not(feature = "wasm"))]
+pub(crate) fn unsupported(message: &str) -> Data {
+    new(Kind::Unsupported, message, &[])
+}
+
+// A trap where an io::Error is needed (e.g. importing objects): its message.
+impl From<Data> for io::Error {
+    fn from(trap: Data) -> io::Error {
+        io::Error::other(message(trap).unwrap_or_else(|| "trap".to_string()))
+    }
+}
+
+// The elements of a trap, if it is one (with a kind and message).
+fn elements(trap: Data) -> Option<(Kind, Vec<u8>, Vec<Handle>)> {
+    let Data::Object(Object::Tree(tree)) = trap else {
+        return None;
+    };
+    let elements = tree.try_load().ok()?;
+    let [
+        Handle::Data(Data::Object(Object::Blob(kind))),
+        Handle::Data(Data::Object(Object::Blob(message))),
+        ..,
+    ] = elements[..]
+    else {
+        return None;
+    };
+    let kind = Kind::from_name(&kind.try_load().ok()?)?;
+    let message = message.try_load().ok()?.to_vec();
+    Some((kind, message, elements[2..].to_vec()))
+}
+
+// The kind of a trap (None if the Data isn't one, e.g. a procedure's own Data).
+pub(crate) fn kind(trap: Data) -> Option<Kind> {
+    elements(trap).map(|(kind, _, _)| kind)
+}
+
+#[cfg(test)]
+pub(crate) fn is(trap: Data, kind: Kind) -> bool {
+    self::kind(trap) == Some(kind)
+}
+
+pub(crate) fn message(trap: Data) -> Option<String> {
+    elements(trap).map(|(_, message, _)| String::from_utf8_lossy(&message).into_owned())
+}
+
+#[cfg(test)]
+pub(crate) fn details(trap: Data) -> Option<Vec<Handle>> {
+    elements(trap).map(|(_, _, details)| details)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::hash_blob;
+    use crate::packed::PackedHandle;
+    use crate::{BlobName, Ref, TreeName};
+
+    #[test]
+    fn kinds_round_trip() {
+        for kind in KINDS {
+            let trap = new(kind, "message", &[blob(b"detail")]);
+            assert_eq!(self::kind(trap), Some(kind));
+            assert_eq!(message(trap).as_deref(), Some("message"));
+            assert_eq!(details(trap).map(|x| x.len()), Some(1));
+        }
+        let other = Data::Object(Object::Blob(BlobName::literal(b"not a trap").unwrap()));
+        assert_eq!(kind(other), None);
+    }
+
+    #[test]
+    fn loading_a_missing_object_traps() {
+        let contents = [0xee; 100];
+        let missing = BlobName::Name((hash_blob(&contents), contents.len()));
+        let trap = missing.try_load().err().unwrap();
+        assert!(is(trap, Kind::Missing));
+
+        let tree = TreeName {
+            name: crate::hash::hash_tree(&[PackedHandle::pack(blob(b"never stored"))]),
+            size: 3,
+            footprint: 1,
+            eq: true,
+            tag: false,
+        };
+        let trap = Ref::Tree(tree).lift().err().unwrap();
+        assert!(is(trap, Kind::Missing));
+        assert_eq!(message(trap).as_deref(), Some("Tree missing from storage"));
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    #[test]
+    fn applying_without_a_runtime_is_unsupported() {
+        let procedure =
+            crate::Value::Data(Data::Object(Object::Blob(BlobName::literal(b"").unwrap())));
+        let combination = TreeName::create(vec![procedure]).ok().unwrap();
+        let trap = crate::apply(combination, &Default::default())
+            .err()
+            .unwrap();
+        assert!(is(trap, Kind::Unsupported));
+    }
+
+    #[test]
+    fn a_trap_as_an_io_error() {
+        let error = io::Error::from(type_error("not a Blob"));
+        assert_eq!(error.to_string(), "not a Blob");
+        let error = io::Error::new(io::ErrorKind::PermissionDenied, "denied");
+        assert!(is(storage_failed(&error), Kind::StorageFailed));
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashSet;
+use std::sync::{LazyLock, RwLock};
+
+use crate::storage::{Key, key};
+use crate::{Data, Handle, Object, Result, TreeName, Value, trap};
+
+// Runtime Tree types: the Trees known to be Values (with no accessible Encodes, at any
+// depth), so a TreeName can be checked to be a TreeName<Value> without traversing it.
+//
+// A Tree is recorded when eval produces it, and when a check traverses it. The record is
+// by Pointer, which names the same elements forever (local Pointers are never reused), so
+// it's never wrong, but it's a cache all the same: it's forgotten when it grows past
+// MAX_VALUES, and a garbage collection forgets the Trees it deleted (see forget).
+static VALUES: LazyLock<RwLock<HashSet<Key>>> = LazyLock::new(RwLock::default);
+
+// Trees recorded before the record is forgotten (32 MiB of Keys, or so).
+const MAX_VALUES: usize = 1 << 20;
+
+// Record that a Tree is a Value.
+pub(crate) fn record(tree: TreeName<Value>) {
+    if !known(key(tree.name)) {
+        let mut values = VALUES.write().unwrap();
+        if values.len() >= MAX_VALUES {
+            values.clear();
+        }
+        values.insert(key(tree.name));
+    }
+}
+
+// Forget the Trees `deleted` names.
+pub(crate) fn forget(deleted: impl Fn(&Key) -> bool) {
+    VALUES.write().unwrap().retain(|x| !deleted(x));
+}
+
+fn known(name: Key) -> bool {
+    VALUES.read().unwrap().contains(&name)
+}
+
+// Is a Tree known to be a Value (without looking at it)?
+pub(crate) fn is_value(tree: TreeName) -> bool {
+    known(key(tree.name))
+}
+
+// The Tree as a Value: immediately if it's known to be one, and otherwise by checking every
+// accessible Tree within it (except those known to be Values). Traps if it holds an
+// accessible Encode.
+pub(crate) fn value(tree: TreeName) -> Result<TreeName<Value>> {
+    let mut work = vec![tree];
+    while let Some(x) = work.pop() {
+        if known(key(x.name)) {
+            continue;
+        }
+        for h in x.try_load()? {
+            match h {
+                Handle::Encode(_) => return Err(not_a_value()),
+                Handle::Data(Data::Object(Object::Tree(x))) => work.push(x),
+                _ => {}
+            }
+        }
+    }
+    let tree = tree.cast();
+    record(tree);
+    Ok(tree)
+}
+
+fn not_a_value() -> Data {
+    trap::type_error("Tree is not a Value")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlobName;
+
+    #[test]
+    fn checked_trees_are_known_until_forgotten() {
+        let blob = BlobName::literal(b"element").unwrap();
+        let tree = TreeName::create(vec![Handle::Data(Data::Object(Object::Blob(blob)))])
+            .ok()
+            .unwrap();
+        assert!(!is_value(tree));
+        assert!(value(tree).is_ok());
+        assert!(is_value(tree));
+        forget(|&x| x == key(tree.name));
+        assert!(!is_value(tree));
+    }
+}
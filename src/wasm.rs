@@ -0,0 +1,762 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use wasmtime::{
+    Caller, Config, Engine, Linker, Memory, Module, ResourceLimiter, Store, Trap, UpdateDeadline,
+    format_err,
+};
+
+use crate::packed::PackedHandle;
+use crate::{
+    BlobName, Context, Data, Encode, Handle, Object, PAGE_SIZE, Ref, Result, RuntimeValue, Thunk,
+    TreeName, local, remote, trap,
+};
+
+// Procedures are WebAssembly modules, run under wasmtime.
+//
+// An evaluated combination is a Tree of
+//   0      the resource limits
+//   1      the procedure: a Blob holding a Wasm module
+//   2..    the arguments (and environment)
+//
+// The resource limits are a Blob of up to three u64s (little-endian): the fuel (roughly,
+// Wasm instructions executed), the memory (in pages, which are the same size for Wasm and
+// for footprints), and the footprint of the output. Limits left out are unlimited, so an
+// empty Blob sets none. Exceeding the memory limit traps (out-of-memory: see trap), and
+// exceeding another limit traps (resource-exhausted).
+// A procedure whose evaluation is cancelled, or whose Encode times out, is interrupted
+// (with wasmtime's epochs) and traps (cancelled, or timed-out).
+//
+// The module exports its `memory` and `apply(combination: i32) -> i32`. Handles never
+// enter Wasm memory: the module sees indices into a table of the Handles it can name,
+// starting with the combination (index 0, as an accessible Tree). The table lasts as long
+// as the instance, and holds each Handle once (so asking for the same one again gives the
+// same index, and takes no more memory). Everything it does
+// with a Handle goes through the imports in module "fix", which trap on an invalid index
+// or a Handle of the wrong kind:
+//
+//   kind(h) -> i32                 0 Blob Object, 1 Tree Object, 2 Blob Ref, 3 Tree Ref,
+//                                  4 Thunk, 5 Encode
+//   size(h) -> i64                 a Blob's length, or a Tree's number of elements
+//   read_blob(h, offset: i64, ptr, len) -> i32
+//                                  copy up to `len` bytes of an accessible Blob, from
+//                                  `offset`, into memory at `ptr`; returns the bytes copied
+//   load_blob_range(h, offset: i64, ptr, len)
+//                                  copy exactly `len` bytes of an accessible Blob, from
+//                                  `offset`, into memory at `ptr` (trapping if they're not
+//                                  all in the Blob)
+//   get(h, index: i64) -> i32      an element of an accessible Tree
+//   get_arg(index: i64) -> i32     an argument: element `2 + index` of the combination
+//   create_blob(ptr, len) -> i32   a Blob of `len` bytes of memory
+//   create_tree(ptr, len) -> i32   a Tree of `len` Handles (i32 indices) in memory
+//   create_tag(ptr, len) -> i32    the same, tagged: the procedure is prepended as its author
+//   lower(h) -> i32                an Object as a Ref (Refs are unchanged)
+//   application(h) -> i32          a Thunk applying a Tree (a combination)
+//   selection(h) -> i32            a Thunk selecting from a Tree (a specification)
+//   identification(h) -> i32       a Thunk identifying Data
+//   encode(h, accessibility) -> i32
+//                                  an Encode of a Thunk: 0 keeps the result's accessibility,
+//                                  1 makes it an Object, 2 a Ref
+//
+// Blobs are only read as far as needed (so reading part of a large one only loads the chunks
+// that part is in).
+//
+// `apply` returns the index of its result, which must be Data or a Thunk (not an Encode).
+// A Wasm trap, or any misuse of the ABI, is a Fix trap whose Data is a Blob of the message.
+//
+// The memory limit covers what the host allocates for the procedure too: the Handles in its
+// table, and the contents of the Blobs and Trees it creates.
+struct Host {
+    handles: Vec<Handle>,
+    // The index of each Handle in the table.
+    indices: HashMap<PackedHandle, i32>,
+    procedure: BlobName,
+    limits: Limits,
+    // Bytes of Wasm memory, and bytes allocated by the host.
+    memory: usize,
+    allocated: usize,
+    // A Fix trap raised by an import (which then aborts the Wasm call).
+    trap: Option<Data>,
+    // What the procedure creates (freed when it returns, unless its result reaches it).
+    arena: local::Arena,
+}
+
+#[derive(Copy, Clone)]
+struct Limits {
+    fuel: u64,
+    memory: u64,
+    footprint: u64,
+}
+
+impl Limits {
+    fn parse(limits: &[u8]) -> Option<Limits> {
+        if limits.len() > 24 || !limits.len().is_multiple_of(8) {
+            return None;
+        }
+        let field = |i: usize| {
+            limits
+                .get(8 * i..8 * (i + 1))
+                .map_or(u64::MAX, |x| u64::from_le_bytes(x.try_into().unwrap()))
+        };
+        Some(Limits {
+            fuel: field(0),
+            memory: field(1),
+            footprint: field(2),
+        })
+    }
+}
+
+impl Host {
+    // Check that `memory` bytes of Wasm memory and `allocated` bytes of host allocations fit
+    // in the memory limit, and if so, account for them.
+    fn reserve(&mut self, memory: usize, allocated: usize) -> wasmtime::Result<()> {
+        let total = memory.saturating_add(allocated);
+        if total.div_ceil(PAGE_SIZE) as u64 > self.limits.memory {
+            self.trap = Some(trap::out_of_memory(self.limits.memory));
+            return Err(format_err!("memory limit exceeded"));
+        }
+        (self.memory, self.allocated) = (memory, allocated);
+        Ok(())
+    }
+
+    // Account for `bytes` more of host allocations.
+    fn charge(&mut self, bytes: usize) -> wasmtime::Result<()> {
+        self.reserve(self.memory, self.allocated.saturating_add(bytes))
+    }
+}
+
+impl ResourceLimiter for Host {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> wasmtime::Result<bool> {
+        self.reserve(desired, self.allocated)?;
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        _desired: usize,
+        _maximum: Option<usize>,
+    ) -> wasmtime::Result<bool> {
+        Ok(true)
+    }
+}
+
+static ENGINE: LazyLock<Engine> = LazyLock::new(|| {
+    let mut config = Config::new();
+    config.consume_fuel(true).epoch_interruption(true);
+    Engine::new(&config).expect("invalid wasmtime configuration")
+});
+
+static LINKER: LazyLock<Linker<Host>> = LazyLock::new(linker);
+
+// Compiled modules, by the procedure's Name.
+static MODULES: LazyLock<Mutex<HashMap<PackedHandle, Module>>> = LazyLock::new(Mutex::default);
+
+// Make every running procedure check whether it's been cancelled or timed out.
+pub(crate) fn interrupt() {
+    ENGINE.increment_epoch();
+}
+
+// While a procedure with a deadline is running, procedures are interrupted every TICK (by a
+// thread that stops once none is left).
+const TICK: Duration = Duration::from_millis(10);
+
+struct Ticker {
+    // Running procedures with deadlines.
+    live: usize,
+    running: bool,
+}
+
+static TICKER: Mutex<Ticker> = Mutex::new(Ticker {
+    live: 0,
+    running: false,
+});
+
+// Keeps procedures being interrupted until dropped.
+struct Ticking;
+
+fn tick() -> Ticking {
+    let mut ticker = TICKER.lock().unwrap();
+    ticker.live += 1;
+    if !ticker.running {
+        ticker.running = true;
+        std::thread::spawn(|| {
+            loop {
+                std::thread::sleep(TICK);
+                interrupt();
+                let mut ticker = TICKER.lock().unwrap();
+                if ticker.live == 0 {
+                    ticker.running = false;
+                    return;
+                }
+            }
+        });
+    }
+    Ticking
+}
+
+impl Drop for Ticking {
+    fn drop(&mut self) {
+        TICKER.lock().unwrap().live -= 1;
+    }
+}
+
+// Apply a procedure, recording the fuel it consumed in `fuel`.
+pub(crate) fn apply(
+    combination: TreeName,
+    context: &Context,
+    fuel: &mut u64,
+) -> Result<RuntimeValue> {
+    let elements = combination.try_load()?;
+    let limits = match elements.first() {
+        Some(Handle::Data(Data::Object(Object::Blob(x)) | Data::Ref(Ref::Blob(x)))) => {
+            Limits::parse(&x.try_load()?)
+        }
+        _ => None,
+    };
+    let limits =
+        limits.ok_or_else(|| trap::bad_combination("combination has malformed resource limits"))?;
+    let procedure = match elements.get(1) {
+        Some(Handle::Data(Data::Object(Object::Blob(x)) | Data::Ref(Ref::Blob(x)))) => *x,
+        _ => return Err(trap::bad_combination("combination has no procedure Blob")),
+    };
+    let module = module(procedure)?;
+
+    let mut store = Store::new(
+        &ENGINE,
+        Host {
+            handles: vec![Handle::Data(Data::Object(Object::Tree(combination)))],
+            indices: HashMap::from([(
+                PackedHandle::pack(Handle::Data(Data::Object(Object::Tree(combination)))),
+                0,
+            )]),
+            procedure,
+            limits,
+            memory: 0,
+            allocated: size_of::<Handle>() + size_of::<(PackedHandle, i32)>(),
+            trap: None,
+            arena: local::Arena::default(),
+        },
+    );
+    store.limiter(|host| host);
+    // Each interruption stops the procedure if it's been cancelled or timed out (and
+    // otherwise, it goes on). Checking after the epoch deadline is set means a cancellation
+    // can't be missed.
+    let _ticking = context.deadline.is_some().then(tick);
+    store.set_epoch_deadline(1);
+    let interrupted = context.clone();
+    store.epoch_deadline_callback(move |mut store| match interrupted.check() {
+        Ok(()) => Ok(UpdateDeadline::Continue(1)),
+        Err(trap) => {
+            store.data_mut().trap = Some(trap);
+            Err(format_err!("interrupted"))
+        }
+    });
+    context.check()?;
+    // (The procedure may have less than its limit, if the fuel left to every apply here is
+    // running out: see remote::set_fuel_limit.)
+    let granted = remote::draw_fuel(limits.fuel);
+    store.set_fuel(granted).unwrap();
+    let result = LINKER
+        .instantiate(&mut store, &module)
+        .and_then(|instance| instance.get_typed_func::<i32, i32>(&mut store, "apply"))
+        .and_then(|apply| apply.call(&mut store, 0));
+    let unused = store.get_fuel().unwrap();
+    remote::return_fuel(unused);
+    *fuel = granted - unused;
+    let host = store.into_data();
+    let result = match (result, host.trap) {
+        (_, Some(trap)) => Err(trap),
+        (Err(e), None) if e.downcast_ref() == Some(&Trap::OutOfFuel) => {
+            Err(trap::resource_exhausted("fuel", granted))
+        }
+        (Err(e), None) => Err(trap::procedure_failed(&e.root_cause().to_string())),
+        (Ok(index), None) => match host.handles.get(index as usize) {
+            Some(Handle::Data(x)) if x.footprint() as u64 > limits.footprint => {
+                Err(trap::resource_exhausted("footprint", limits.footprint))
+            }
+            Some(Handle::Data(x)) => Ok(RuntimeValue::Data(*x)),
+            Some(Handle::Thunk(x)) => Ok(RuntimeValue::Thunk(*x)),
+            Some(Handle::Encode(_)) => Err(trap::type_error("procedure returned an Encode")),
+            None => Err(trap::procedure_failed(
+                "procedure returned an invalid handle",
+            )),
+        },
+    };
+    let kept = match result {
+        Ok(RuntimeValue::Data(x)) | Err(x) => Handle::Data(x),
+        Ok(RuntimeValue::Thunk(x)) => Handle::Thunk(x),
+    };
+    host.arena.release(&[kept]);
+    result
+}
+
+fn module(procedure: BlobName) -> Result<Module> {
+    let name = PackedHandle::pack(Handle::Data(Data::Ref(Ref::Blob(procedure))));
+    if let Some(module) = MODULES.lock().unwrap().get(&name) {
+        return Ok(module.clone());
+    }
+    let module = Module::new(&ENGINE, &*procedure.try_load()?)
+        .map_err(|e| trap::bad_combination(&format!("invalid procedure: {e:#}")))?;
+    MODULES.lock().unwrap().insert(name, module.clone());
+    Ok(module)
+}
+
+type HostCaller<'a> = Caller<'a, Host>;
+
+fn handle(caller: &HostCaller, index: i32) -> wasmtime::Result<Handle> {
+    caller
+        .data()
+        .handles
+        .get(index as usize)
+        .copied()
+        .ok_or_else(|| format_err!("invalid handle {index}"))
+}
+
+// The index of a Handle, added to the table if it isn't there.
+fn push(caller: &mut HostCaller, h: Handle) -> wasmtime::Result<i32> {
+    let packed = PackedHandle::pack(h);
+    if let Some(&index) = caller.data().indices.get(&packed) {
+        return Ok(index);
+    }
+    caller
+        .data_mut()
+        .charge(size_of::<Handle>() + size_of::<(PackedHandle, i32)>())?;
+    let host = caller.data_mut();
+    let index = host.handles.len() as i32;
+    host.handles.push(h);
+    host.indices.insert(packed, index);
+    Ok(index)
+}
+
+fn memory(caller: &mut HostCaller) -> wasmtime::Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(|x| x.into_memory())
+        .ok_or_else(|| format_err!("procedure exports no memory"))
+}
+
+// Raise a Fix trap from an import.
+fn raise<T>(caller: &mut HostCaller, data: Data) -> wasmtime::Result<T> {
+    caller.data_mut().trap = Some(data);
+    Err(format_err!("trap"))
+}
+
+fn tree(h: Handle) -> wasmtime::Result<TreeName> {
+    match h {
+        Handle::Data(Data::Object(Object::Tree(x)) | Data::Ref(Ref::Tree(x))) => Ok(x),
+        _ => Err(format_err!("not a Tree")),
+    }
+}
+
+fn data(h: Handle) -> wasmtime::Result<Data> {
+    match h {
+        Handle::Data(x) => Ok(x),
+        _ => Err(format_err!("not Data")),
+    }
+}
+
+fn accessible_blob(h: Handle) -> wasmtime::Result<BlobName> {
+    match h {
+        Handle::Data(Data::Object(Object::Blob(x))) => Ok(x),
+        _ => Err(format_err!("not an accessible Blob")),
+    }
+}
+
+// Copy bytes `start..end` of a Blob (which must be in bounds) into memory at `ptr`.
+fn copy_blob(
+    caller: &mut HostCaller,
+    blob: BlobName,
+    start: usize,
+    end: usize,
+    ptr: i32,
+) -> wasmtime::Result<()> {
+    let bytes = match blob.try_load_range(start, end) {
+        Ok(x) => x,
+        Err(e) => return raise(caller, e),
+    };
+    let memory = memory(caller)?;
+    memory
+        .data_mut(caller)
+        .get_mut(ptr as u32 as usize..)
+        .and_then(|x| x.get_mut(..bytes.len()))
+        .ok_or_else(|| format_err!("out of bounds"))?
+        .copy_from_slice(&bytes);
+    Ok(())
+}
+
+// The Handles (given as indices) in `len` i32s of memory at `ptr` (charged as host memory).
+fn handles(caller: &mut HostCaller, ptr: i32, len: i32) -> wasmtime::Result<Vec<Handle>> {
+    caller
+        .data_mut()
+        .charge((len as u32 as usize).saturating_mul(size_of::<Handle>()))?;
+    let memory = memory(caller)?;
+    let bytes = memory
+        .data(&caller)
+        .get(ptr as u32 as usize..)
+        .and_then(|x| x.get(..4 * len as u32 as usize))
+        .ok_or_else(|| format_err!("out of bounds"))?
+        .to_vec();
+    bytes
+        .chunks_exact(4)
+        .map(|x| handle(caller, i32::from_le_bytes(x.try_into().unwrap())))
+        .collect()
+}
+
+fn linker() -> Linker<Host> {
+    let mut linker = Linker::new(&ENGINE);
+    linker
+        .func_wrap("fix", "kind", |caller: HostCaller, h: i32| {
+            Ok(match handle(&caller, h)? {
+                Handle::Data(Data::Object(Object::Blob(_))) => 0,
+                Handle::Data(Data::Object(Object::Tree(_))) => 1,
+                Handle::Data(Data::Ref(Ref::Blob(_))) => 2,
+                Handle::Data(Data::Ref(Ref::Tree(_))) => 3,
+                Handle::Thunk(_) => 4,
+                Handle::Encode(_) => 5,
+            })
+        })
+        .unwrap()
+        .func_wrap("fix", "size", |caller: HostCaller, h: i32| {
+            Ok(match data(handle(&caller, h)?)?.lower() {
+                Ref::Blob(x) => x.size() as i64,
+                Ref::Tree(x) => x.size as i64,
+            })
+        })
+        .unwrap()
+        .func_wrap(
+            "fix",
+            "read_blob",
+            |mut caller: HostCaller, h: i32, offset: i64, ptr: i32, len: i32| {
+                let blob = accessible_blob(handle(&caller, h)?)?;
+                let start = (offset as u64 as usize).min(blob.size());
+                let end = start + (blob.size() - start).min(len as u32 as usize);
+                copy_blob(&mut caller, blob, start, end, ptr)?;
+                Ok((end - start) as i32)
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            "fix",
+            "load_blob_range",
+            |mut caller: HostCaller, h: i32, offset: i64, ptr: i32, len: i32| {
+                let blob = accessible_blob(handle(&caller, h)?)?;
+                let start = offset as u64 as usize;
+                let end = start
+                    .checked_add(len as u32 as usize)
+                    .filter(|&end| end <= blob.size())
+                    .ok_or_else(|| format_err!("range out of bounds"))?;
+                copy_blob(&mut caller, blob, start, end, ptr)
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            "fix",
+            "get",
+            |mut caller: HostCaller, h: i32, index: i64| {
+                let Handle::Data(Data::Object(Object::Tree(tree))) = handle(&caller, h)? else {
+                    return Err(format_err!("not an accessible Tree"));
+                };
+                let elements = match tree.try_load() {
+                    Ok(x) => x,
+                    Err(e) => return raise(&mut caller, e),
+                };
+                let element = *usize::try_from(index)
+                    .ok()
+                    .and_then(|i| elements.get(i))
+                    .ok_or_else(|| format_err!("index out of bounds"))?;
+                push(&mut caller, element)
+            },
+        )
+        .unwrap()
+        .func_wrap("fix", "get_arg", |mut caller: HostCaller, index: i64| {
+            let combination = tree(caller.data().handles[0])?;
+            let elements = match combination.try_load() {
+                Ok(x) => x,
+                Err(e) => return raise(&mut caller, e),
+            };
+            let argument = *usize::try_from(index)
+                .ok()
+                .and_then(|i| elements.get(i.checked_add(2)?))
+                .ok_or_else(|| format_err!("no argument {index}"))?;
+            push(&mut caller, argument)
+        })
+        .unwrap()
+        .func_wrap(
+            "fix",
+            "create_blob",
+            |mut caller: HostCaller, ptr: i32, len: i32| {
+                caller.data_mut().charge(len as u32 as usize)?;
+                let memory = memory(&mut caller)?;
+                let bytes = memory
+                    .data(&caller)
+                    .get(ptr as u32 as usize..)
+                    .and_then(|x| x.get(..len as u32 as usize))
+                    .ok_or_else(|| format_err!("out of bounds"))?
+                    .to_vec();
+                let blob = caller.data_mut().arena.blob(bytes);
+                push(&mut caller, Handle::Data(Data::Object(Object::Blob(blob))))
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            "fix",
+            "create_tree",
+            |mut caller: HostCaller, ptr: i32, len: i32| {
+                let elements = handles(&mut caller, ptr, len)?;
+                let tree = caller.data_mut().arena.tree(elements);
+                push(&mut caller, Handle::Data(Data::Object(Object::Tree(tree))))
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            "fix",
+            "create_tag",
+            |mut caller: HostCaller, ptr: i32, len: i32| {
+                caller.data_mut().charge(size_of::<Handle>())?;
+                let author = Handle::Data(Data::Object(Object::Blob(caller.data().procedure)));
+                let mut elements = vec![author];
+                elements.extend(handles(&mut caller, ptr, len)?);
+                let tree = TreeName {
+                    tag: true,
+                    ..caller.data_mut().arena.tree(elements)
+                };
+                push(&mut caller, Handle::Data(Data::Object(Object::Tree(tree))))
+            },
+        )
+        .unwrap()
+        .func_wrap("fix", "lower", |mut caller: HostCaller, h: i32| {
+            let lowered = data(handle(&caller, h)?)?.lower();
+            push(&mut caller, Handle::Data(Data::Ref(lowered)))
+        })
+        .unwrap()
+        .func_wrap("fix", "application", |mut caller: HostCaller, h: i32| {
+            let thunk = Thunk::Application(tree(handle(&caller, h)?)?);
+            push(&mut caller, Handle::Thunk(thunk))
+        })
+        .unwrap()
+        .func_wrap("fix", "selection", |mut caller: HostCaller, h: i32| {
+            let thunk = Thunk::Selection(tree(handle(&caller, h)?)?);
+            push(&mut caller, Handle::Thunk(thunk))
+        })
+        .unwrap()
+        .func_wrap("fix", "identification", |mut caller: HostCaller, h: i32| {
+            let thunk = Thunk::Identification(data(handle(&caller, h)?)?);
+            push(&mut caller, Handle::Thunk(thunk))
+        })
+        .unwrap()
+        .func_wrap(
+            "fix",
+            "encode",
+            |mut caller: HostCaller, h: i32, accessibility: i32| {
+                let Handle::Thunk(thunk) = handle(&caller, h)? else {
+                    return Err(format_err!("not a Thunk"));
+                };
+                let accessibility = match accessibility {
+                    0 => None,
+                    1 => Some(true),
+                    2 => Some(false),
+                    _ => return Err(format_err!("invalid accessibility")),
+                };
+                let encode = Encode {
+                    thunk,
+                    accessibility,
+                };
+                push(&mut caller, Handle::Encode(encode))
+            },
+        )
+        .unwrap();
+    linker
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::trap::Kind;
+
+    // A combination applying the module `wat` with the given limits.
+    fn combination(wat: &str, limits: [u64; 3]) -> TreeName {
+        let limits: Vec<u8> = limits.iter().flat_map(|x| x.to_le_bytes()).collect();
+        let module = wat::parse_str(wat).unwrap();
+        let blob = |x: Vec<u8>| Handle::Data(Data::Object(Object::Blob(local::blob(x))));
+        local::tree(vec![blob(limits), blob(module)])
+    }
+
+    // Creates Blobs of a page of memory, forever.
+    const CREATE_BLOBS: &str = r#"
+        (module
+          (import "fix" "create_blob" (func $create_blob (param i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (func (export "apply") (param i32) (result i32)
+            (loop $again
+              (drop (call $create_blob (i32.const 0) (i32.const 65536)))
+              (br $again))
+            (i32.const 0)))
+    "#;
+
+    #[test]
+    fn created_blobs_count_against_the_memory_limit() {
+        let combination = combination(CREATE_BLOBS, [u64::MAX, 4, u64::MAX]);
+        let trap = apply(combination, &Context::default(), &mut 0)
+            .err()
+            .unwrap();
+        assert!(trap::is(trap, Kind::OutOfMemory));
+    }
+
+    #[test]
+    fn ticking_stops_with_the_last_deadline() {
+        let ticking = (tick(), tick());
+        assert!(TICKER.lock().unwrap().running);
+        drop(ticking);
+        std::thread::sleep(5 * TICK);
+        let ticker = TICKER.lock().unwrap();
+        assert!(!ticker.running || ticker.live > 0);
+    }
+
+    // Asks for its first argument a million times, then returns it.
+    const GET_ARGUMENT: &str = r#"
+        (module
+          (import "fix" "get_arg" (func $get_arg (param i64) (result i32)))
+          (memory (export "memory") 1)
+          (func (export "apply") (param i32) (result i32)
+            (local $a i32) (local $i i32)
+            (local.set $a (call $get_arg (i64.const 0)))
+            (loop $again
+              (if (i32.ne (call $get_arg (i64.const 0)) (local.get $a))
+                (then (unreachable)))
+              (local.set $i (i32.add (local.get $i) (i32.const 1)))
+              (br_if $again (i32.lt_u (local.get $i) (i32.const 1000000))))
+            (local.get $a)))
+    "#;
+
+    #[test]
+    fn each_handle_has_one_index() {
+        let combination = combination(GET_ARGUMENT, [u64::MAX, 2, u64::MAX]);
+        let mut elements = combination.try_load().ok().unwrap();
+        elements.push(blob(b"an argument".to_vec()));
+        let result = apply(local::tree(elements), &Context::default(), &mut 0);
+        let Ok(RuntimeValue::Data(x)) = result else {
+            panic!("not Data");
+        };
+        assert!(
+            PackedHandle::pack(Handle::Data(x))
+                == PackedHandle::pack(blob(b"an argument".to_vec()))
+        );
+    }
+
+    // Copies up to 64 bytes of its first argument (if that's a Blob) into a new Blob, and
+    // returns a Tree of it and the combination; otherwise returns the argument.
+    const COPY_ARGUMENT: &str = r#"
+        (module
+          (import "fix" "get_arg" (func $get_arg (param i64) (result i32)))
+          (import "fix" "kind" (func $kind (param i32) (result i32)))
+          (import "fix" "read_blob" (func $read_blob (param i32 i64 i32 i32) (result i32)))
+          (import "fix" "create_blob" (func $create_blob (param i32 i32) (result i32)))
+          (import "fix" "create_tree" (func $create_tree (param i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (func (export "apply") (param $c i32) (result i32)
+            (local $a i32) (local $n i32)
+            (local.set $a (call $get_arg (i64.const 0)))
+            (if (i32.eqz (call $kind (local.get $a)))
+              (then
+                (local.set $n
+                  (call $read_blob (local.get $a) (i64.const 0) (i32.const 0) (i32.const 64)))
+                (i32.store (i32.const 128) (call $create_blob (i32.const 0) (local.get $n)))
+                (i32.store (i32.const 132) (local.get $c))
+                (return (call $create_tree (i32.const 128) (i32.const 2)))))
+            (local.get $a)))
+    "#;
+
+    #[test]
+    fn the_argument_is_copied() {
+        let combination = combination(COPY_ARGUMENT, [u64::MAX; 3]);
+        let mut elements = combination.try_load().ok().unwrap();
+        elements.push(blob(b"copied".to_vec()));
+        let result = apply(local::tree(elements), &Context::default(), &mut 0);
+        let Ok(RuntimeValue::Data(Data::Object(Object::Tree(tree)))) = result else {
+            panic!("not a Tree");
+        };
+        let copy = tree.try_load().ok().unwrap()[0];
+        assert!(
+            local::canonical_name(PackedHandle::pack(copy))
+                == local::canonical_name(PackedHandle::pack(blob(b"copied".to_vec())))
+        );
+    }
+
+    // Arbitrary modules, and valid ones with a byte changed.
+    fn modules() -> impl Strategy<Value = Vec<u8>> {
+        let valid = wat::parse_str(COPY_ARGUMENT).unwrap();
+        let mutants = (0..valid.len(), any::<u8>()).prop_map(move |(i, byte)| {
+            let mut module = valid.clone();
+            module[i] = byte;
+            module
+        });
+        prop_oneof![prop::collection::vec(any::<u8>(), 0..256), mutants]
+    }
+
+    #[derive(Clone, Debug)]
+    enum Argument {
+        Blob(Vec<u8>),
+        Tree(Vec<Vec<u8>>),
+    }
+
+    fn blob(x: Vec<u8>) -> Handle {
+        Handle::Data(Data::Object(Object::Blob(local::blob(x))))
+    }
+
+    impl Argument {
+        fn handle(self) -> Handle {
+            match self {
+                Argument::Blob(x) => blob(x),
+                Argument::Tree(x) => Handle::Data(Data::Object(Object::Tree(local::tree(
+                    x.into_iter().map(blob).collect(),
+                )))),
+            }
+        }
+    }
+
+    fn arguments() -> impl Strategy<Value = Vec<Argument>> {
+        let bytes = || prop::collection::vec(any::<u8>(), 0..100);
+        let argument = prop_oneof![
+            bytes().prop_map(Argument::Blob),
+            prop::collection::vec(bytes(), 0..3).prop_map(Argument::Tree),
+        ];
+        prop::collection::vec(argument, 1..4)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        // Whatever the module does with whatever arguments, apply returns a valid
+        // RuntimeValue within the limits, or traps.
+        #[test]
+        fn arbitrary_modules_apply_or_trap(module in modules(), arguments in arguments()) {
+            let limits: Vec<u8> = [100_000u64, 4, 64].iter().flat_map(|x| x.to_le_bytes()).collect();
+            let mut elements = vec![blob(limits), blob(module)];
+            elements.extend(arguments.into_iter().map(Argument::handle));
+            let mut fuel = 0;
+            match apply(local::tree(elements), &Context::default(), &mut fuel) {
+                Ok(value) => {
+                    let h = match value {
+                        RuntimeValue::Data(x) => {
+                            prop_assert!(x.footprint() <= 64);
+                            Handle::Data(x)
+                        }
+                        RuntimeValue::Thunk(x) => Handle::Thunk(x),
+                    };
+                    prop_assert!(PackedHandle::pack(h).try_unpack().is_some());
+                }
+                Err(trap) => prop_assert!(trap::message(trap).is_some()),
+            }
+            prop_assert!(fuel <= 100_000);
+        }
+    }
+}
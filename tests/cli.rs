@@ -0,0 +1,497 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tempfile::TempDir;
+
+// Snapshot tests of the command line's output: what scripts read from it (Handles and
+// their pretty-printed closures, listings, reports and JSON progress) is checked against
+// the snapshots under `tests/snapshots`, so a change to a format shows up here.
+//
+// After a deliberate change of format, regenerate the snapshots with
+//   FIXMODEL_BLESS=1 cargo test --test cli
+
+fn snapshots() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots")
+}
+
+// A repository to run commands on, and the transcript of what they printed.
+struct Session {
+    dir: TempDir,
+    transcript: String,
+}
+
+impl Session {
+    fn new() -> Self {
+        let session = Session {
+            dir: TempDir::new().unwrap(),
+            transcript: String::new(),
+        };
+        session.run_quietly(&["init"]);
+        session
+    }
+
+    fn repository(&self) -> PathBuf {
+        self.dir.path().join("repository")
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fixmodel"));
+        command
+            .arg("--repository")
+            .arg(self.repository())
+            .args(args)
+            .current_dir(self.dir.path());
+        command
+    }
+
+    // Run a command, returning its stdout (it must succeed).
+    fn run_quietly(&self, args: &[&str]) -> String {
+        let output = self.command(args).output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "fixmodel {args:?} failed: {stderr}"
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    // Run a command, recording it and what it printed (on stdout, then stderr, with the
+    // fields that vary from run to run redacted) in the transcript.
+    fn run(&mut self, args: &[&str]) -> String {
+        let output = self.command(args).output().unwrap();
+        let (stdout, stderr) = (
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        );
+        self.transcript += &format!("$ fixmodel {}\n{stdout}", args.join(" "));
+        if !stderr.is_empty() {
+            self.transcript += &format!("[stderr]\n{}", redact(&stderr));
+        }
+        if !output.status.success() {
+            self.transcript += &format!("[{}]\n", output.status);
+        }
+        self.transcript += "\n";
+        stdout
+    }
+
+    // Check the transcript against its snapshot (or, when blessing, write it).
+    fn check(&self, name: &str) {
+        let path = snapshots().join(format!("{name}.snap"));
+        if std::env::var_os("FIXMODEL_BLESS").is_some() {
+            fs::write(&path, &self.transcript).unwrap();
+            return;
+        }
+        let snapshot = fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            snapshot == self.transcript,
+            "{} changed; it's now:\n{}",
+            path.display(),
+            self.transcript
+        );
+    }
+}
+
+// Timings, replaced by "_".
+fn redact(text: &str) -> String {
+    let mut redacted = text.to_string();
+    for field in ["\"elapsed_ms\":", "\"eta_ms\":"] {
+        let mut rest = redacted.as_str();
+        let mut out = String::new();
+        while let Some(at) = rest.find(field) {
+            let (before, after) = rest.split_at(at + field.len());
+            out += before;
+            out += "_";
+            rest = after.trim_start_matches(|c: char| c.is_ascii_alphanumeric());
+        }
+        redacted = out + rest;
+    }
+    redacted
+}
+
+const SCRIPT: &str = r#"
+named = "a Blob too large to be a Literal, so it's named by its hash"
+pair = tree("first", 2, true)
+tree(pair, named, ref(tree("behind a Ref")), object(select(pair, "/0")))
+select(pair, "/1")
+"#;
+
+// Handles (one per line) as arguments.
+fn handles(stdout: &str) -> Vec<String> {
+    stdout.lines().map(str::to_string).collect()
+}
+
+#[test]
+fn shows_handles_and_their_closures() {
+    let mut session = Session::new();
+    fs::write(session.dir.path().join("script.fix"), SCRIPT).unwrap();
+    let built = handles(&session.run(&["repl", "--script", "script.fix"]));
+    let (root, selection) = (&built[0], &built[1]);
+    for depth in ["0", "1", "3"] {
+        session.run(&["show", root, depth]);
+    }
+    session.run(&["show", selection]);
+    session.run(&["select", root, "/0/1"]);
+    session.run(&["select", root, "/1/bytes:0..6"]);
+    session.check("show");
+}
+
+#[test]
+fn reports_on_the_repository() {
+    let mut session = Session::new();
+    fs::write(session.dir.path().join("script.fix"), SCRIPT).unwrap();
+    let built = handles(&session.run_quietly(&["repl", "--script", "script.fix"]));
+    session.run(&["label", "root", &built[0]]);
+    session.run(&["label", "selection", &built[1]]);
+    session.run(&["label"]);
+    session.run(&["label", "root"]);
+    session.run(&["graph", "root"]);
+    session.run(&["graph", "--order", "root"]);
+    session.run(&["eval", "--quiet", "--metrics", "root"]);
+    // (Before it's evaluated, so it traps rather than being remembered.)
+    session.run(&["eval", "--quiet", "--steps", "0", "selection"]);
+    session.run(&["eval", "--quiet", "--depth", "0", "selection"]);
+    session.run(&["stats"]);
+    session.run(&["fsck"]);
+    session.check("repository");
+}
+
+#[test]
+fn reports_progress_as_json() {
+    let mut session = Session::new();
+    fs::write(session.dir.path().join("script.fix"), SCRIPT).unwrap();
+    let built = handles(&session.run_quietly(&["repl", "--script", "script.fix"]));
+    session.run(&["eval", "--json-progress", &built[0]]);
+    session.check("json-progress");
+}
+
+// A daemon (see daemon) serving two tenants, and where it listens.
+struct Daemon {
+    process: std::process::Child,
+    address: String,
+}
+
+impl Daemon {
+    fn start(dir: &Path, tenants: &str) -> Self {
+        fs::write(dir.join("tenants"), tenants).unwrap();
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let process = Command::new(env!("CARGO_BIN_EXE_fixmodel"))
+            .arg("--repository")
+            .arg(dir.join("daemon"))
+            .args(["daemon", "--listen", &address, "--tenants"])
+            .arg(dir.join("tenants"))
+            .spawn()
+            .unwrap();
+        while std::net::TcpStream::connect(&address).is_err() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        Daemon { process, address }
+    }
+
+    fn as_tenant(&self, token: &str) -> String {
+        format!("{token}@{}", self.address)
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+#[test]
+fn a_daemon_keeps_each_tenants_labels_apart() {
+    let session = Session::new();
+    let daemon = Daemon::start(session.dir.path(), "a a-token\nb b-token steps=100\n");
+    fs::write(session.dir.path().join("script.fix"), SCRIPT).unwrap();
+    let built = handles(&session.run_quietly(&["repl", "--script", "script.fix"]));
+    let (a, b) = (daemon.as_tenant("a-token"), daemon.as_tenant("b-token"));
+    session.run_quietly(&["label", "--remote", &a, "built", &built[0]]);
+    let labelled = session.run_quietly(&["label", "--remote", &a, "built"]);
+    assert_eq!(handles(&labelled), [built[0].clone()]);
+    // Another tenant has labels of its own (and an unknown token, none).
+    for other in [b, daemon.as_tenant("c-token")] {
+        let output = session
+            .command(&["label", "--remote", &other, "built"])
+            .output();
+        assert!(!output.unwrap().status.success());
+    }
+    // A tenant's worker executes its Encodes.
+    let evaluated = session.run_quietly(&["eval", "--quiet", "--worker", &a, &built[0]]);
+    let local = session.run_quietly(&["eval", "--quiet", &built[0]]);
+    assert_eq!(evaluated, local);
+}
+
+#[test]
+fn equivalences_persist_in_the_repository() {
+    let session = Session::new();
+    let put = |contents: &str| {
+        let path = session.dir.path().join("blob");
+        fs::write(&path, contents).unwrap();
+        let stdout = session.run_quietly(&["put", path.to_str().unwrap()]);
+        stdout.trim().to_string()
+    };
+    let slow = put("a slow implementation, too large to be a Literal");
+    let fast = put("a fast implementation, too large to be a Literal");
+    let equivalent = || session.run_quietly(&["equivalent", &slow, &fast]);
+    assert_eq!(equivalent(), "false\n");
+    session.run_quietly(&["equate", &slow, &fast]);
+    assert_eq!(equivalent(), "true\n");
+    session.run_quietly(&["forget", "--equivalences"]);
+    assert_eq!(equivalent(), "false\n");
+}
+
+#[test]
+fn fetches_from_a_named_remote() {
+    let session = Session::new();
+    let upstream = session.dir.path().join("upstream");
+    let in_upstream = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_fixmodel"))
+            .arg("--repository")
+            .arg(&upstream)
+            .args(args)
+            .current_dir(session.dir.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    in_upstream(&["init"]);
+    fs::write(session.dir.path().join("script.fix"), SCRIPT).unwrap();
+    let built = handles(&in_upstream(&["repl", "--script", "script.fix"]));
+    in_upstream(&["label", "built", &built[0]]);
+    // An unknown remote isn't taken for a path.
+    let output = session.command(&["fetch", "upstream", "built"]).output();
+    assert!(!output.unwrap().status.success());
+    session.run_quietly(&["remote", "origin", "upstream"]);
+    let remotes = session.run_quietly(&["remote"]);
+    assert_eq!(remotes, format!("origin {}\n", upstream.display()));
+    let fetched = session.run_quietly(&["fetch", "origin", "built"]);
+    assert_eq!(handles(&fetched), [built[0].clone()]);
+    assert!(session.run_quietly(&["fsck"]).is_empty());
+    assert!(session.run_quietly(&["fsck", &built[0]]).is_empty());
+    session.run_quietly(&["unremote", "origin"]);
+    assert!(session.run_quietly(&["remote"]).is_empty());
+}
+
+#[test]
+fn fix_bench_runs_the_bench_command() {
+    let output = Command::new(env!("CARGO_BIN_EXE_fix-bench"))
+        .args(["--width", "10", "--depth", "5", "store", "deep"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let workloads: Vec<_> = stdout.lines().filter_map(|x| x.split(' ').next()).collect();
+    assert_eq!(workloads, ["store", "deep"]);
+    let output = Command::new(env!("CARGO_BIN_EXE_fix-bench"))
+        .arg("no-such-workload")
+        .output();
+    assert!(!output.unwrap().status.success());
+}
+
+// Interrupting an evaluation (here, one waiting on a worker that never answers) cancels it.
+#[cfg(unix)]
+#[test]
+fn an_interrupt_cancels_an_evaluation() {
+    let session = Session::new();
+    fs::write(session.dir.path().join("script.fix"), SCRIPT).unwrap();
+    let built = handles(&session.run_quietly(&["repl", "--script", "script.fix"]));
+    let silent = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = silent.local_addr().unwrap().to_string();
+    let evaluation = session
+        .command(&["eval", "--quiet", "--worker", &address, &built[0]])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    // (Once it's waiting on the worker.)
+    let _connection = silent.accept().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let interrupted = Command::new("kill")
+        .args(["-INT", &evaluation.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(interrupted.success());
+    let output = evaluation.wait_with_output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("fixmodel: cancelled"));
+}
+
+// What's stored with a --tier is written through to it, and what a repository doesn't hold
+// is loaded from it (here, a sled database, which can also be fetched from).
+#[cfg(feature = "sled")]
+#[test]
+fn stores_through_a_tier_and_loads_from_it() {
+    let session = Session::new();
+    let tier = format!("sled:{}", session.dir.path().join("tier").display());
+    let in_repository = |repository: &str, args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_fixmodel"))
+            .arg("--repository")
+            .arg(session.dir.path().join(repository))
+            .args(args)
+            .current_dir(session.dir.path())
+            .output()
+            .unwrap()
+    };
+    let succeeding = |repository: &str, args: &[&str]| {
+        let output = in_repository(repository, args);
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let contents = "a Blob stored through to a tier, too large to be a Literal";
+    fs::write(session.dir.path().join("blob"), contents).unwrap();
+    let stored = succeeding("repository", &["--tier", &tier, "put", "blob"]);
+    let stored = stored.trim();
+    succeeding("other", &["init"]);
+    let get = |flags: &[&str]| succeeding("other", &[flags, &["get", stored]].concat());
+    assert!(!in_repository("other", &["get", stored]).status.success());
+    assert_eq!(get(&["--tier", &tier]), contents);
+    assert_eq!(get(&["--tier", &tier, "--cache", "16"]), contents);
+    succeeding("third", &["init"]);
+    succeeding("third", &["remote", "tier", &tier]);
+    assert_eq!(
+        succeeding("third", &["fetch", "tier", stored]).trim(),
+        stored
+    );
+    assert_eq!(succeeding("third", &["get", stored]), contents);
+}
+
+#[cfg(feature = "git")]
+#[test]
+fn imports_a_git_revision() {
+    let session = Session::new();
+    let work = session.dir.path().join("work");
+    fs::create_dir(&work).unwrap();
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(&work)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success());
+    };
+    git(&["init", "-q"]);
+    fs::write(work.join("hello.txt"), "hello, git\n").unwrap();
+    git(&["add", "-A"]);
+    git(&["commit", "-q", "-m", "first"]);
+    let imported = session.run_quietly(&["git-import", work.to_str().unwrap(), "HEAD"]);
+    let shown = session.run_quietly(&["show", imported.trim(), "3"]);
+    assert!(shown.contains("\"hello.txt\"") && shown.contains("\"hello, git\\n\""));
+    assert!(session.run_quietly(&["fsck"]).is_empty());
+}
+
+// A Tree of the Handles listed, with --unique leaving out those equal to an earlier one.
+#[test]
+fn stores_a_tree_of_handles() {
+    let session = Session::new();
+    let put = |contents: &str| {
+        let file = session.dir.path().join("contents");
+        fs::write(&file, contents).unwrap();
+        session.run_quietly(&["put", file.to_str().unwrap()])
+    };
+    let (a, b) = (put("a Blob, stored twice"), put("another Blob"));
+    let list = |name: &str, handles: &[&str]| {
+        fs::write(session.dir.path().join(name), handles.concat()).unwrap();
+    };
+    list("repeated", &[&a, &b, &a]);
+    list("once", &[&a, &b]);
+    let repeated = session.run_quietly(&["tree", "repeated"]);
+    let unique = session.run_quietly(&["tree", "--unique", "repeated"]);
+    assert_ne!(repeated, unique);
+    assert_eq!(unique, session.run_quietly(&["tree", "once"]));
+    assert_eq!(session.run_quietly(&["select", repeated.trim(), "/2"]), a);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn evaluates_on_tokio() {
+    let session = Session::new();
+    fs::write(session.dir.path().join("script.fix"), SCRIPT).unwrap();
+    let built = handles(&session.run_quietly(&["repl", "--script", "script.fix"]));
+    let eval = |options: &[&str]| {
+        let args = [&["eval", "--quiet"], options, &[built[0].as_str()]].concat();
+        session.run_quietly(&args)
+    };
+    assert_eq!(eval(&["--async"]), eval(&[]));
+}
+
+#[test]
+fn prints_blobs_as_values() {
+    let session = Session::new();
+    let put = |contents: &[u8]| {
+        let file = session.dir.path().join("contents");
+        fs::write(&file, contents).unwrap();
+        session.run_quietly(&["put", file.to_str().unwrap()])
+    };
+    let (word, flag) = (put(&[1, 1, 0, 0]), put(&[1]));
+    let value = |kind: &str, h: &str| session.run_quietly(&["get", "--as", kind, h.trim()]);
+    assert_eq!(value("u32", &word), "257\n");
+    assert_eq!(value("string", &word), "\u{1}\u{1}\0\0\n");
+    assert_eq!(value("bool", &flag), "true\n");
+    assert_eq!(value("i8", &flag), "1\n");
+    // (A Blob of the wrong size isn't one.)
+    let output = session
+        .command(&["get", "--as", "u16", word.trim()])
+        .output();
+    assert!(!output.unwrap().status.success());
+}
+
+#[test]
+fn sets_a_label_only_if_it_is_unchanged() {
+    let session = Session::new();
+    fs::write(session.dir.path().join("script.fix"), SCRIPT).unwrap();
+    let built = handles(&session.run_quietly(&["repl", "--script", "script.fix"]));
+    let (first, second) = (&built[0], &built[1]);
+    session.run_quietly(&["label", "head", first]);
+    session.run_quietly(&["label", "--if", first, "head", second]);
+    let output = session
+        .command(&["label", "--if", first, "head", first])
+        .output();
+    assert!(!output.unwrap().status.success());
+    assert_eq!(
+        session.run_quietly(&["label", "head"]),
+        format!("{second}\n")
+    );
+}
+
+// A worker that never answers is given up on after --worker-timeout, and the Encode executed
+// locally.
+#[test]
+fn gives_up_on_a_silent_worker() {
+    let session = Session::new();
+    fs::write(session.dir.path().join("script.fix"), SCRIPT).unwrap();
+    let built = handles(&session.run_quietly(&["repl", "--script", "script.fix"]));
+    let silent = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = silent.local_addr().unwrap().to_string();
+    let options = ["eval", "--quiet", "--worker", &address];
+    let evaluated =
+        session.run_quietly(&[&options[..], &["--worker-timeout", "200", &built[0]]].concat());
+    let local = session.run_quietly(&["eval", "--quiet", &built[0]]);
+    assert_eq!(evaluated, local);
+    // (A lease has to outlast a heartbeat.)
+    let output = session
+        .command(&[&options[..], &["--lease", "500", &built[0]]].concat())
+        .output();
+    assert!(!output.unwrap().status.success());
+}
+
+#[cfg(feature = "ipfs")]
+#[test]
+fn looks_up_the_object_a_cid_names() {
+    let session = Session::new();
+    let file = session.dir.path().join("contents");
+    fs::write(&file, "a Blob too long to be a Literal, named by a CID").unwrap();
+    let stored = session.run_quietly(&["put", file.to_str().unwrap()]);
+    let cid = session.run_quietly(&["cid", stored.trim()]);
+    let found = session.run_quietly(&["lookup-cid", cid.trim()]);
+    assert_ne!(found, stored);
+    assert_eq!(session.run_quietly(&["cid", found.trim()]), cid);
+}